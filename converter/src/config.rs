@@ -1,13 +1,61 @@
+use std::path::PathBuf;
+
 pub struct Config {
     pub mapping: ConfigMapping,
     pub style: ConfigStyle,
+    pub routing: ConfigRouting,
+    pub output: ConfigOutput,
 }
 
 pub struct ConfigMapping {
     pub tile_res: u32,
     pub zoom: u8,
+    /// Vector tiles are cut at every zoom level from this one up to `zoom`, so the PSP can zoom
+    /// out. Raster tiles are only rendered at `zoom`.
+    pub min_zoom: u8,
+    pub format: TileFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileFormat {
+    /// Rendered here with the style below, QOI images
+    Raster,
+    /// Shapes the PSP draws itself, so it can turn the map and switch to night colors.
+    /// The style's colors and icons don't apply to these.
+    Vector,
 }
 
 pub struct ConfigStyle {
+    /// Goes into the manifest, so it's clear which look a region was rendered with
+    pub name: String,
     pub road_color: [u8; 3],
+    /// PNG with square icons laid out left to right in `PoiCategory` order.
+    /// Without a sheet POIs get drawn as plain colored squares.
+    pub poi_icon_sheet: Option<String>,
+    /// Size of a single icon in the sheet, in pixels
+    pub poi_icon_size: u32,
+    /// POI icons only get drawn at this zoom level or higher
+    pub poi_min_zoom: u8,
+}
+
+pub struct ConfigRouting {
+    /// Preprocess the graph for fast routing on the PSP, takes a while on big regions
+    pub contraction_hierarchy: bool,
+    /// How many random routes to check the hierarchy against plain Dijkstra with
+    pub verify_samples: usize,
+    /// How many random routes to print turn-by-turn directions for, to check them by eye
+    pub direction_samples: usize,
+}
+
+pub struct ConfigOutput {
+    /// Folder that holds all converted regions
+    pub folder: String,
+    /// Name of the region, every file for it ends up in a subfolder with this name
+    pub region: String,
+}
+
+impl ConfigOutput {
+    pub fn region_folder(&self) -> PathBuf {
+        PathBuf::from(&self.folder).join(&self.region)
+    }
 }
@@ -6,6 +6,9 @@ pub mod config;
 pub mod mapper;
 pub mod reader;
 pub mod render;
+pub mod routing;
+pub mod vector;
+pub mod writer;
 
 fn main() {
     println!("Hello, world!");
@@ -17,17 +20,69 @@ fn main() {
         mapping: ConfigMapping {
             tile_res: 64,
             zoom: 19,
+            min_zoom: 14,
+            format: TileFormat::Raster,
         },
         style: ConfigStyle {
+            name: String::from("default"),
             road_color: [96; 3],
+            poi_icon_sheet: Some(String::from("style/poi_icons.png")),
+            poi_icon_size: 8,
+            poi_min_zoom: 16,
+        },
+        routing: ConfigRouting {
+            contraction_hierarchy: true,
+            verify_samples: 100,
+            direction_samples: 5,
+        },
+        output: ConfigOutput {
+            folder: String::from("output"),
+            region: String::from("small"),
         },
     };
 
     let map = reader::read_osm_pbf(filename);
     println!("Roads: {}", map.roads.len());
+    println!("POIs: {}", map.pois.len());
+    println!("Addresses: {}", map.addresses.len());
+    println!("Objects: {}", map.objects.len());
+
+    writer::write_pois(&config, &map.pois);
+    writer::write_address_index(&config, &map.addresses);
 
-    let map_tiles = mapper::map_to_tiles(&config, map);
-    println!("Tiles: {}", map_tiles.tiles.len());
+    let graph = routing::build_graph(&map.roads, &map.restrictions);
+    let hierarchy = config
+        .routing
+        .contraction_hierarchy
+        .then(|| routing::build_hierarchy(&graph));
+    let graph_data = writer::encode_routing_graph(&graph, hierarchy.as_ref());
+    if hierarchy.is_some() {
+        let mismatches = routing::verify_hierarchy(&graph_data, config.routing.verify_samples);
+        // Wrong routes on the PSP are worse than no graph at all
+        assert_eq!(
+            mismatches, 0,
+            "The contraction hierarchy doesn't match plain Dijkstra, not writing graph.bin!"
+        );
+    }
+    writer::write_routing_graph(&config, &graph_data);
+    routing::print_directions(&graph_data, config.routing.direction_samples);
+
+    let extent = map.extent;
+    let tiles = match config.mapping.format {
+        TileFormat::Raster => {
+            let map_tiles = mapper::map_to_tiles(&config, map);
+            println!("Tiles: {}", map_tiles.tiles.len());
+            render::render_tiles(&config, map_tiles)
+        }
+        TileFormat::Vector => {
+            let tiles = vector::build_tiles(&config, &map);
+            println!("Tiles: {}", tiles.len());
+            tiles
+        }
+    };
+    writer::write_tile_pack(&config, extent, &tiles);
 
-    render::render_result_to_folder(&config, map_tiles);
+    // Last, it has the checksums of everything else
+    let source_timestamp = reader::source_timestamp(filename);
+    writer::write_manifest(&config, extent, source_timestamp);
 }
@@ -1,14 +1,8 @@
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Copy)]
-pub struct GlobalLocation {
-    pub tile_x: u32,
-    pub tile_y: u32,
-    pub x: u32,
-    pub y: u32,
-
-    pub lat: f64,
-}
+pub use nav_core::tile::GlobalLocation;
+
+use crate::reader::PoiCategory;
 
 #[derive(Debug, Clone)]
 pub struct Road {
@@ -17,6 +11,12 @@ pub struct Road {
     pub speedlimit: u8,
 }
 
+#[derive(Debug, Clone)]
+pub struct Poi {
+    pub location: GlobalLocation,
+    pub category: PoiCategory,
+}
+
 pub struct Tile {
     pub location: (u32, u32),
     pub road_indices: Vec<usize>,
@@ -37,4 +37,5 @@ pub struct MapTiles {
     pub tiles: HashMap<u64, Tile>,
 
     pub roads: Vec<Road>,
+    pub pois: Vec<Poi>,
 }
@@ -4,14 +4,13 @@ use std::collections::HashMap;
 
 use crate::{
     config::Config,
-    reader::{Map, Road as ReaderRoad},
+    reader::{Map, Poi as ReaderPoi, Road as ReaderRoad},
 };
 
 mod data;
 pub use data::*;
 
-mod math;
-pub use math::*;
+pub use nav_core::tile::coord_to_tile;
 
 pub fn map_to_tiles(config: &Config, map: Map) -> MapTiles {
     let max_tiles_x = 2u64.pow(config.mapping.zoom as u32);
@@ -30,11 +29,25 @@ pub fn map_to_tiles(config: &Config, map: Map) -> MapTiles {
         );
     }
 
+    let pois = map
+        .pois
+        .iter()
+        .map(|poi| map_poi(config.mapping.zoom, config.mapping.tile_res, poi))
+        .collect();
+
     MapTiles {
         zoom: config.mapping.zoom,
         tiles,
 
         roads: mapped_roads,
+        pois,
+    }
+}
+
+fn map_poi(zoom: u8, tile_res: u32, poi: &ReaderPoi) -> Poi {
+    Poi {
+        location: coord_to_tile(poi.coord, zoom, tile_res),
+        category: poi.category,
     }
 }
 
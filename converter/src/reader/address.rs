@@ -0,0 +1,19 @@
+//! Picks addresses out of the `addr:*` tags of nodes and building ways
+
+use std::collections::HashMap;
+
+use super::AddressTags;
+
+/// Returns the address if the tags have at least a street and a house number,
+/// anything less is useless for searching
+pub(super) fn parse(tags: &HashMap<String, String>) -> Option<AddressTags> {
+    let street = tags.get("addr:street")?;
+    let housenumber = tags.get("addr:housenumber")?;
+
+    Some(AddressTags {
+        street: street.clone(),
+        housenumber: housenumber.clone(),
+        postcode: tags.get("addr:postcode").cloned().unwrap_or_default(),
+        city: tags.get("addr:city").cloned().unwrap_or_default(),
+    })
+}
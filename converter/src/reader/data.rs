@@ -1,3 +1,5 @@
+pub use nav_core::coord::Coord;
+
 pub(super) struct Node {
     pub id: i64,
     pub coord: Coord,
@@ -7,82 +9,162 @@ pub(super) struct RoadRaw {
     pub id: i64,
     pub nodes: Vec<i64>,
     pub kind: String,
+    pub name: String,
+    pub road_ref: String,
     pub width: f32,
     pub speedlimit: u8,
+    pub oneway: Oneway,
+    pub roundabout: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Oneway {
+    #[default]
+    No,
+    /// Only in the direction the nodes are in
+    Forward,
+    /// Only against the direction the nodes are in
+    Backward,
 }
 
 #[derive(Default)]
 pub struct Road {
+    /// OSM way id
+    pub id: i64,
     /// (min, max)
     pub extent: (Coord, Coord),
     pub points: Vec<Coord>,
+    /// OSM node id of every point
+    pub node_ids: Vec<i64>,
+    /// Value of the `highway` tag
+    pub kind: String,
+    /// Empty if not tagged
+    pub name: String,
+    /// Road number like `A10`, empty if not tagged
+    pub road_ref: String,
     /// In meters
     pub width: f32,
+    /// In km/h, 0 if not tagged
     pub speedlimit: u8,
+    pub oneway: Oneway,
+    /// Part of a roundabout, counted as one junction when giving directions
+    pub roundabout: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ObjectKind {
+    /// Landuse like residential or industrial
     Generic,
     Grass,
     Water,
     Building,
 }
 
+pub(super) struct ObjectRaw {
+    pub nodes: Vec<i64>,
+    pub kind: ObjectKind,
+}
+
 pub struct Object {
     pub kind: ObjectKind,
+    /// Outline, closes itself, the first point isn't repeated at the end
     pub shape: Vec<Coord>,
 }
 
-pub struct Map {
-    pub roads: Vec<Road>,
-    pub objects: Vec<Object>,
-    /// (min, max)
-    pub extent: (Coord, Coord),
+pub(super) struct PoiRaw {
+    pub nodes: Vec<i64>,
+    pub category: PoiCategory,
+    pub name: String,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
-pub struct Coord {
-    pub lat: f64,
-    pub lon: f64,
+pub(super) struct AddressRaw {
+    pub nodes: Vec<i64>,
+    pub tags: AddressTags,
 }
 
-impl Coord {
-    pub fn min_each(self, other: Self) -> Self {
-        Self {
-            lat: self.lat.min(other.lat),
-            lon: self.lon.min(other.lon),
-        }
-    }
+#[derive(Debug, Clone, Default)]
+pub struct AddressTags {
+    pub street: String,
+    pub housenumber: String,
+    /// Empty if not tagged
+    pub postcode: String,
+    /// Empty if not tagged
+    pub city: String,
+}
 
-    pub fn max_each(self, other: Self) -> Self {
-        Self {
-            lat: self.lat.max(other.lat),
-            lon: self.lon.max(other.lon),
-        }
-    }
+#[derive(Debug, Clone)]
+pub struct Address {
+    pub tags: AddressTags,
+    pub coord: Coord,
+}
 
-    /// Returns the distance between 2 coordinates in meters
-    pub fn distance_to(&self, other: Self) -> f64 {
-        const R: f64 = 6371e3;
+/// Categories are stored as a single byte in the POI file,
+/// so the discriminants must stay stable!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum PoiCategory {
+    Fuel = 0,
+    Parking = 1,
+    Hospital = 2,
+    Restaurant = 3,
+    Cafe = 4,
+    FastFood = 5,
+    Pharmacy = 6,
+    Toilets = 7,
+    Atm = 8,
+    Police = 9,
+    ChargingStation = 10,
+    Shop = 11,
+    Tourism = 12,
+}
 
-        let lat1 = self.lat.max(other.lat);
-        let lat2 = self.lat.min(other.lat);
+impl PoiCategory {
+    pub const COUNT: usize = 13;
+}
 
-        let lon1 = self.lon.max(other.lon);
-        let lon2 = self.lon.min(other.lon);
+#[derive(Debug, Clone)]
+pub struct Poi {
+    pub category: PoiCategory,
+    /// Empty if the element had no `name` tag
+    pub name: String,
+    pub coord: Coord,
+}
 
-        let phi1 = lat1 * std::f64::consts::PI / 180f64;
-        let phi2 = lat2 * std::f64::consts::PI / 180f64;
-        let delta_phi = (lat2 - lat1) * std::f64::consts::PI / 180f64;
-        let delta_lambda = (lon2 - lon1) * std::f64::consts::PI / 180f64;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestrictionKind {
+    /// `no_left_turn`, `no_u_turn`, ...: the turn onto `to_way` is forbidden
+    No,
+    /// `only_straight_on`, ...: the turn onto `to_way` is the only one allowed
+    Only,
+}
 
-        let half_sin_delta_phi = (delta_phi / 2f64).sin();
-        let half_sin_delta_lambda = (delta_lambda / 2f64).sin();
+/// The turn a restriction is about, from the second half of its tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Turn {
+    Left,
+    Right,
+    Straight,
+    UTurn,
+}
 
-        let a = half_sin_delta_phi * half_sin_delta_phi
-            + phi1.cos() * phi2.cos() * half_sin_delta_lambda * half_sin_delta_lambda;
-        let c = 2f64 * a.sqrt().atan2((1f64 - a).sqrt());
+/// Only restrictions with a single via node are supported
+#[derive(Debug, Clone, Copy)]
+pub struct TurnRestriction {
+    pub kind: RestrictionKind,
+    pub from_way: i64,
+    pub via_node: i64,
+    pub to_way: i64,
+    /// None for `no_entry`, `no_exit` and anything else that isn't a single turn.
+    /// Tells the two sides apart when a way runs through the via node instead of ending there.
+    pub turn: Option<Turn>,
+}
 
-        R * c
-    }
+pub struct Map {
+    pub roads: Vec<Road>,
+    pub objects: Vec<Object>,
+    pub pois: Vec<Poi>,
+    pub addresses: Vec<Address>,
+    pub restrictions: Vec<TurnRestriction>,
+    /// (min, max)
+    pub extent: (Coord, Coord),
 }
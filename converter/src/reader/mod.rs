@@ -1,15 +1,32 @@
 use std::collections::HashMap;
 
-use osmpbf::{Element, IndexedReader};
+use osmpbf::{BlobDecode, BlobReader, Element, ElementReader, IndexedReader};
 
 mod data;
 pub use data::*;
 
+mod address;
+mod object;
+mod poi;
+mod restriction;
+
+/// When the data was exported, from the file's header. Not every tool writes it.
+pub fn source_timestamp(filename: &str) -> Option<i64> {
+    let mut blobs = BlobReader::from_path(filename).ok()?;
+    match blobs.next()?.ok()?.decode().ok()? {
+        BlobDecode::OsmHeader(header) => header.osmosis_replication_timestamp(),
+        _ => None,
+    }
+}
+
 pub fn read_osm_pbf(filename: &str) -> Map {
     let mut reader = IndexedReader::from_path(filename).expect("Failed to open the file!");
 
     let mut nodes = HashMap::new();
     let mut data_roads = Vec::new();
+    let mut data_pois = Vec::new();
+    let mut data_addresses = Vec::new();
+    let mut data_objects = Vec::new();
 
     let mut coord_min: Option<Coord> = None;
     let mut coord_max: Option<Coord> = None;
@@ -36,6 +53,32 @@ pub fn read_osm_pbf(filename: &str) -> Map {
                         .map(|(key, value)| (key.to_string(), value.to_string()))
                         .collect::<HashMap<String, String>>();
 
+                    if let Some((category, name)) = poi::classify(&tags) {
+                        data_pois.push(PoiRaw {
+                            nodes: way.refs().collect(),
+                            category,
+                            name,
+                        });
+                    }
+
+                    // Only closed ways are areas
+                    let nodes = way.refs().collect::<Vec<_>>();
+                    if nodes.len() >= 4
+                        && nodes.first() == nodes.last()
+                        && let Some(kind) = object::classify(&tags)
+                    {
+                        data_objects.push(ObjectRaw { nodes, kind });
+                    }
+
+                    if tags.contains_key("building")
+                        && let Some(address) = address::parse(&tags)
+                    {
+                        data_addresses.push(AddressRaw {
+                            nodes: way.refs().collect(),
+                            tags: address,
+                        });
+                    }
+
                     if let Some(road_kind) = tags.get("highway") {
                         let car_allowed = !(tags.get("motorcar") == Some(&String::from("no"))
                             || tags.get("motor_vehicle") == Some(&String::from("no")));
@@ -54,17 +97,40 @@ pub fn read_osm_pbf(filename: &str) -> Map {
                                 })
                                 .flatten()
                                 .unwrap_or(1f32);
+                            // `maxspeed` is the actual OSM tag, `speedlimit` is kept as a fallback
                             let speedlimit = tags
-                                .get("speedlimit")
+                                .get("maxspeed")
+                                .or(tags.get("speedlimit"))
                                 .map(|s| s.parse::<f32>().ok()) // We parse as f32 in case it says like 100.0 or something lol
                                 .flatten()
                                 .unwrap_or(0f32) as u8;
+                            let oneway = match tags.get("oneway").map(|s| s.as_str()) {
+                                Some("yes" | "true" | "1") => Oneway::Forward,
+                                Some("-1" | "reverse") => Oneway::Backward,
+                                Some("no") => Oneway::No,
+                                // Motorways and roundabouts are oneway without saying so
+                                _ if road_kind == "motorway"
+                                    || tags.get("junction").map(|s| s.as_str())
+                                        == Some("roundabout") =>
+                                {
+                                    Oneway::Forward
+                                }
+                                _ => Oneway::No,
+                            };
+                            let roundabout = matches!(
+                                tags.get("junction").map(|s| s.as_str()),
+                                Some("roundabout" | "circular")
+                            );
                             let data = RoadRaw {
                                 id,
                                 nodes,
                                 kind: road_kind.clone(),
+                                name: tags.get("name").cloned().unwrap_or_default(),
+                                road_ref: tags.get("ref").cloned().unwrap_or_default(),
                                 width,
                                 speedlimit,
+                                oneway,
+                                roundabout,
                             };
                             data_roads.push(data);
 
@@ -132,12 +198,13 @@ pub fn read_osm_pbf(filename: &str) -> Map {
     let roads: Vec<Road> = data_roads
         .into_iter()
         .filter_map(|data| {
-            let points: Vec<Coord> = data
+            // Node ids stay next to their points, the routing graph needs to know where roads meet
+            let (node_ids, points): (Vec<i64>, Vec<Coord>) = data
                 .nodes
                 .into_iter()
                 .filter_map(|i| nodes.get(&i))
-                .map(|node| node.coord)
-                .collect();
+                .map(|node| (node.id, node.coord))
+                .unzip();
             if points.len() < 2 {
                 return None;
             }
@@ -148,15 +215,83 @@ pub fn read_osm_pbf(filename: &str) -> Map {
                 road_max = road_max.max_each(*p);
             }
             Some(Road {
+                id: data.id,
                 extent: (road_min, road_max),
                 points,
+                node_ids,
+                kind: data.kind,
+                name: data.name,
+                road_ref: data.road_ref,
                 width: data.width,
                 speedlimit: data.speedlimit,
+                oneway: data.oneway,
+                roundabout: data.roundabout,
             })
         })
         .collect();
 
-    let objects = Vec::new(); // TODO: Gotta do this still lol
+    let objects: Vec<Object> = data_objects
+        .into_iter()
+        .filter_map(|data| {
+            let mut shape: Vec<Coord> = data
+                .nodes
+                .into_iter()
+                .filter_map(|i| nodes.get(&i))
+                .map(|node| node.coord)
+                .collect();
+            if shape.len() > 1 && shape.first() == shape.last() {
+                shape.pop();
+            }
+            (shape.len() >= 3).then_some(Object {
+                kind: data.kind,
+                shape,
+            })
+        })
+        .collect();
+
+    let mut pois: Vec<Poi> = data_pois
+        .into_iter()
+        .filter_map(|data| {
+            let points: Vec<Coord> = data
+                .nodes
+                .into_iter()
+                .filter_map(|i| nodes.get(&i))
+                .map(|node| node.coord)
+                .collect();
+            Some(Poi {
+                category: data.category,
+                name: data.name,
+                coord: poi::centroid(&points)?,
+            })
+        })
+        .collect();
+    println!("POIs from ways: {}", pois.len());
+
+    // Buildings get found by their centroid, same as POIs
+    let mut addresses: Vec<Address> = data_addresses
+        .into_iter()
+        .filter_map(|data| {
+            let points: Vec<Coord> = data
+                .nodes
+                .into_iter()
+                .filter_map(|i| nodes.get(&i))
+                .map(|node| node.coord)
+                .collect();
+            Some(Address {
+                tags: data.tags,
+                coord: poi::centroid(&points)?,
+            })
+        })
+        .collect();
+    println!("Addresses from buildings: {}", addresses.len());
+
+    // `read_ways_and_deps` only hands us nodes that are part of a way and no relations,
+    // so standalone POI and address nodes and turn restrictions need a separate pass over the file
+    let mut restrictions = Vec::new();
+    read_tagged_elements(filename, &mut pois, &mut addresses, &mut restrictions);
+    println!("POIs total: {}", pois.len());
+    println!("Addresses total: {}", addresses.len());
+    println!("Turn restrictions: {}", restrictions.len());
 
     let coord_min = coord_min.unwrap();
     let coord_max = coord_max.unwrap();
@@ -166,6 +301,89 @@ pub fn read_osm_pbf(filename: &str) -> Map {
     Map {
         roads,
         objects,
+        pois,
+        addresses,
+        restrictions,
         extent: (coord_min, coord_max),
     }
 }
+
+fn read_tagged_elements(
+    filename: &str,
+    pois: &mut Vec<Poi>,
+    addresses: &mut Vec<Address>,
+    restrictions: &mut Vec<TurnRestriction>,
+) {
+    let reader = ElementReader::from_path(filename).expect("Failed to open the file!");
+
+    let mut handle_node = |tags: HashMap<String, String>, coord: Coord| {
+        if let Some((category, name)) = poi::classify(&tags) {
+            pois.push(Poi {
+                category,
+                name,
+                coord,
+            });
+        }
+        if let Some(tags) = address::parse(&tags) {
+            addresses.push(Address { tags, coord });
+        }
+    };
+
+    reader
+        .for_each(|element| match element {
+            Element::Node(node) => {
+                // Most nodes have no tags at all, so skip those before allocating anything
+                if node.tags().next().is_none() {
+                    return;
+                }
+                let tags = node
+                    .tags()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<HashMap<String, String>>();
+                let coord = Coord {
+                    lat: node.lat(),
+                    lon: node.lon(),
+                };
+                handle_node(tags, coord);
+            }
+            Element::DenseNode(node) => {
+                if node.tags().next().is_none() {
+                    return;
+                }
+                let tags = node
+                    .tags()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<HashMap<String, String>>();
+                let coord = Coord {
+                    lat: node.lat(),
+                    lon: node.lon(),
+                };
+                handle_node(tags, coord);
+            }
+            Element::Relation(relation) => {
+                let tags = relation
+                    .tags()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<HashMap<String, String>>();
+                if tags.get("type").map(|s| s.as_str()) != Some("restriction") {
+                    return;
+                }
+
+                let members = relation
+                    .members()
+                    .map(|member| {
+                        (
+                            member.role().unwrap_or_default().to_string(),
+                            member.member_type,
+                            member.member_id,
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                if let Some(restriction) = restriction::parse(&tags, &members) {
+                    restrictions.push(restriction);
+                }
+            }
+            _ => {}
+        })
+        .unwrap();
+}
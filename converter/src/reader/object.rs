@@ -0,0 +1,34 @@
+//! Picks areas to draw out of the tags of closed ways. Multipolygon relations aren't read, so
+//! big lakes and forests made of several ways are missing.
+
+use std::collections::HashMap;
+
+use super::ObjectKind;
+
+/// Returns the kind if the tags describe an area worth drawing
+pub(super) fn classify(tags: &HashMap<String, String>) -> Option<ObjectKind> {
+    let tag = |key: &str| tags.get(key).map(|s| s.as_str());
+
+    if tag("building").is_some_and(|value| value != "no") {
+        return Some(ObjectKind::Building);
+    }
+    if tag("natural") == Some("water")
+        || tag("waterway") == Some("riverbank")
+        || matches!(tag("landuse"), Some("reservoir" | "basin"))
+    {
+        return Some(ObjectKind::Water);
+    }
+    if matches!(
+        tag("landuse"),
+        Some("grass" | "meadow" | "forest" | "village_green" | "recreation_ground")
+    ) || matches!(tag("leisure"), Some("park" | "garden" | "pitch"))
+        || matches!(tag("natural"), Some("wood" | "grassland" | "scrub"))
+    {
+        return Some(ObjectKind::Grass);
+    }
+    matches!(
+        tag("landuse"),
+        Some("residential" | "commercial" | "industrial" | "retail")
+    )
+    .then_some(ObjectKind::Generic)
+}
@@ -0,0 +1,60 @@
+//! Picks points of interest out of the tags of nodes and ways
+
+use std::collections::HashMap;
+
+use super::{Coord, PoiCategory};
+
+/// Returns the category and name if the tags describe something we care about
+pub(super) fn classify(tags: &HashMap<String, String>) -> Option<(PoiCategory, String)> {
+    let category = match tags.get("amenity").map(|s| s.as_str()) {
+        Some("fuel") => Some(PoiCategory::Fuel),
+        Some("parking") => Some(PoiCategory::Parking),
+        Some("hospital") => Some(PoiCategory::Hospital),
+        Some("restaurant") => Some(PoiCategory::Restaurant),
+        Some("cafe") => Some(PoiCategory::Cafe),
+        Some("fast_food") => Some(PoiCategory::FastFood),
+        Some("pharmacy") => Some(PoiCategory::Pharmacy),
+        Some("toilets") => Some(PoiCategory::Toilets),
+        Some("atm") => Some(PoiCategory::Atm),
+        Some("police") => Some(PoiCategory::Police),
+        Some("charging_station") => Some(PoiCategory::ChargingStation),
+        _ => None,
+    }
+    .or_else(|| tags.contains_key("shop").then_some(PoiCategory::Shop))
+    .or_else(|| {
+        tags.get("tourism")
+            .filter(|s| s.as_str() != "yes")
+            .map(|_| PoiCategory::Tourism)
+    })?;
+
+    let name = tags.get("name").cloned().unwrap_or_default();
+
+    Some((category, name))
+}
+
+/// Average of all points, good enough for the small shapes POIs tend to be
+pub(super) fn centroid(points: &[Coord]) -> Option<Coord> {
+    // Closed ways repeat their first node at the end, which would skew the average
+    let points = match points {
+        [first, rest @ .., last]
+            if rest.len() > 1 && first.lat == last.lat && first.lon == last.lon =>
+        {
+            &points[..points.len() - 1]
+        }
+        _ => points,
+    };
+
+    if points.is_empty() {
+        return None;
+    }
+
+    let mut sum = Coord::default();
+    for p in points {
+        sum.lat += p.lat;
+        sum.lon += p.lon;
+    }
+    Some(Coord {
+        lat: sum.lat / points.len() as f64,
+        lon: sum.lon / points.len() as f64,
+    })
+}
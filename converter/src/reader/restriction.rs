@@ -0,0 +1,59 @@
+//! Parses `type=restriction` relations
+
+use std::collections::HashMap;
+
+use osmpbf::RelMemberType;
+
+use super::{RestrictionKind, Turn, TurnRestriction};
+
+/// `members` are (role, type, id). Returns None for anything we can't or don't need to
+/// handle: restrictions for other vehicles, ones that exempt cars, or ones with a via way.
+pub(super) fn parse(
+    tags: &HashMap<String, String>,
+    members: &[(String, RelMemberType, i64)],
+) -> Option<TurnRestriction> {
+    let value = tags
+        .get("restriction:motorcar")
+        .or(tags.get("restriction"))?;
+    let (kind, turn) = if let Some(turn) = value.strip_prefix("no_") {
+        (RestrictionKind::No, turn)
+    } else if let Some(turn) = value.strip_prefix("only_") {
+        (RestrictionKind::Only, turn)
+    } else {
+        return None;
+    };
+    let turn = match turn {
+        "left_turn" => Some(Turn::Left),
+        "right_turn" => Some(Turn::Right),
+        "straight_on" => Some(Turn::Straight),
+        "u_turn" => Some(Turn::UTurn),
+        _ => None,
+    };
+
+    if tags
+        .get("except")
+        .is_some_and(|except| except.split(';').any(|v| v.trim() == "motorcar"))
+    {
+        return None;
+    }
+
+    let member = |role: &str, member_type: RelMemberType| {
+        let mut matching = members
+            .iter()
+            .filter(|(r, t, _)| r == role && *t == member_type);
+        let (_, _, id) = matching.next()?;
+        // More than one from/via/to is either broken data or needs splitting up we don't do
+        if matching.next().is_some() {
+            return None;
+        }
+        Some(*id)
+    };
+
+    Some(TurnRestriction {
+        kind,
+        from_way: member("from", RelMemberType::Way)?,
+        via_node: member("via", RelMemberType::Node)?,
+        to_way: member("to", RelMemberType::Way)?,
+        turn,
+    })
+}
@@ -1,14 +1,22 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, RgbImage};
+use nav_core::tile::TileKey;
 use skia_safe::{
-    Canvas, Color, Data, EncodedImageFormat, Paint, PaintStyle, PathBuilder, Surface, surfaces,
+    AlphaType, Canvas, Color, ColorType, ImageInfo, Paint, PaintStyle, PathBuilder, surfaces,
 };
 // use skia_rs_safe::core::{Color, Rect};
 
 use crate::Config;
 use crate::mapper::*;
 
+mod poi;
+pub use poi::*;
+
 const EARTH_CIRCUMFERENCE_METERS: f64 = 40_075_016_686f64;
 
-pub fn render_result_to_folder(config: &Config, map_tiles: MapTiles) {
+/// Renders the map and cuts it into QOI encoded tiles
+pub fn render_tiles(config: &Config, map_tiles: MapTiles) -> Vec<(TileKey, Vec<u8>)> {
     let mut tile_x_min = u32::MAX;
     let mut tile_y_min = u32::MAX;
     let mut tile_x_max = 0;
@@ -46,12 +54,50 @@ pub fn render_result_to_folder(config: &Config, map_tiles: MapTiles) {
         draw_road_debug(config, canvas, tile_x_min, tile_y_min, road);
     }
 
-    let image = surface.image_snapshot();
-    let mut context = surface.direct_context();
-    let d = image
-        .encode(context.as_mut(), EncodedImageFormat::PNG, None)
-        .unwrap();
-    std::fs::write("tmp.png", d.as_bytes()).unwrap();
+    // Labels will have to be placed before this, icons only go where there's room left
+    let mut placement = Placement::default();
+    if map_tiles.zoom >= config.style.poi_min_zoom {
+        let sheet = load_icon_sheet(config);
+        draw_pois(
+            config,
+            canvas,
+            sheet.as_ref(),
+            tile_x_min,
+            tile_y_min,
+            &map_tiles.pois,
+            &mut placement,
+        );
+    }
+
+    let mut pixels = vec![0u8; (img_width * img_height * 4) as usize];
+    let info = ImageInfo::new(
+        (img_width as i32, img_height as i32),
+        ColorType::RGBA8888,
+        AlphaType::Unpremul,
+        None,
+    );
+    assert!(
+        surface.read_pixels(&info, &mut pixels, img_width as usize * 4, (0, 0)),
+        "Failed to read the rendered map!"
+    );
+
+    let res = config.mapping.tile_res;
+    let (columns, rows) = (img_width / res, img_height / res);
+    let mut tiles = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            let tile = RgbImage::from_fn(res, res, |x, y| {
+                let at = (((row * res + y) * img_width + column * res + x) * 4) as usize;
+                image::Rgb([pixels[at], pixels[at + 1], pixels[at + 2]])
+            });
+            let mut encoded = Vec::new();
+            tile.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Qoi)
+                .expect("Failed to encode a tile!");
+            let key = TileKey::new(map_tiles.zoom, tile_x_min + column, tile_y_min + row);
+            tiles.push((key, encoded));
+        }
+    }
+    tiles
 }
 
 fn draw_road_debug(config: &Config, canvas: &Canvas, offset_x: u32, offset_y: u32, road: Road) {
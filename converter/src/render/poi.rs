@@ -0,0 +1,164 @@
+//! Draws POI icons on top of the roads
+
+use skia_safe::{Canvas, Color, Data, Image, Paint, PaintStyle, Rect, SrcRectConstraint};
+
+use crate::Config;
+use crate::mapper::Poi;
+use crate::reader::PoiCategory;
+
+/// Screen space box of something that has already been drawn, in pixels
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedBox {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+impl PlacedBox {
+    fn overlaps(&self, other: &Self) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+}
+
+/// Keeps track of everything placed on the map so labels and icons don't end up on top of each other.
+/// Whatever gets placed first wins, so labels should be placed before icons.
+#[derive(Default)]
+pub struct Placement {
+    placed: Vec<PlacedBox>,
+}
+
+impl Placement {
+    /// Returns false (and places nothing) if the box would overlap something already placed
+    pub fn try_place(&mut self, b: PlacedBox) -> bool {
+        if self.placed.iter().any(|other| other.overlaps(&b)) {
+            return false;
+        }
+        self.placed.push(b);
+        true
+    }
+}
+
+pub fn load_icon_sheet(config: &Config) -> Option<Image> {
+    let path = config.style.poi_icon_sheet.as_ref()?;
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("Failed to read POI icon sheet {path}: {e}, falling back to squares");
+            return None;
+        }
+    };
+    let image = Image::from_encoded(Data::new_copy(&bytes));
+    if image.is_none() {
+        println!("Failed to decode POI icon sheet {path}, falling back to squares");
+    }
+    image
+}
+
+/// `sheet` is what [`load_icon_sheet`] returned, POIs outside of the canvas are left out
+pub fn draw_pois(
+    config: &Config,
+    canvas: &Canvas,
+    sheet: Option<&Image>,
+    offset_x: u32,
+    offset_y: u32,
+    pois: &[Poi],
+    placement: &mut Placement,
+) {
+    let size = config.style.poi_icon_size as i32;
+    let canvas_size = canvas.base_layer_size();
+    let tile_res = config.mapping.tile_res as i64;
+
+    // Enum order doubles as priority, so a fuel station wins over a souvenir shop
+    let mut pois = pois.iter().collect::<Vec<_>>();
+    pois.sort_by_key(|poi| poi.category);
+
+    let mut skipped = 0;
+    for poi in pois {
+        let loc = poi.location;
+        // POIs don't have to be near any road, so they can lie outside the rendered tiles
+        let x = (loc.tile_x as i64 - offset_x as i64) * tile_res + loc.x as i64;
+        let y = (loc.tile_y as i64 - offset_y as i64) * tile_res + loc.y as i64;
+        if !(0..canvas_size.width as i64).contains(&x)
+            || !(0..canvas_size.height as i64).contains(&y)
+        {
+            continue;
+        }
+        let (x, y) = (x as i32, y as i32);
+
+        let placed = PlacedBox {
+            x: x - size / 2,
+            y: y - size / 2,
+            w: size,
+            h: size,
+        };
+        if !placement.try_place(placed) {
+            skipped += 1;
+            continue;
+        }
+
+        match sheet {
+            Some(sheet) => draw_icon(canvas, sheet, size, poi.category, placed),
+            None => draw_square(canvas, poi.category, placed),
+        }
+    }
+
+    if skipped > 0 {
+        println!("Skipped {skipped} POI icons because of collisions");
+    }
+}
+
+fn draw_icon(canvas: &Canvas, sheet: &Image, size: i32, category: PoiCategory, placed: PlacedBox) {
+    let src = Rect::from_xywh(
+        (category as i32 * size) as f32,
+        0.0,
+        size as f32,
+        size as f32,
+    );
+    let dst = Rect::from_xywh(
+        placed.x as f32,
+        placed.y as f32,
+        placed.w as f32,
+        placed.h as f32,
+    );
+
+    // No anti aliasing, we want crisp pixels
+    let paint = Paint::default();
+    canvas.draw_image_rect(sheet, Some((&src, SrcRectConstraint::Strict)), dst, &paint);
+}
+
+fn draw_square(canvas: &Canvas, category: PoiCategory, placed: PlacedBox) {
+    let [r, g, b] = category_color(category);
+    let rect = Rect::from_xywh(
+        placed.x as f32,
+        placed.y as f32,
+        placed.w as f32,
+        placed.h as f32,
+    );
+
+    let mut paint = Paint::default();
+    paint.set_style(PaintStyle::Fill);
+    paint.set_color(Color::from_rgb(r, g, b));
+    canvas.draw_rect(rect, &paint);
+
+    paint.set_style(PaintStyle::Stroke);
+    paint.set_color(Color::BLACK);
+    canvas.draw_rect(rect, &paint);
+}
+
+fn category_color(category: PoiCategory) -> [u8; 3] {
+    match category {
+        PoiCategory::Fuel => [224, 64, 64],
+        PoiCategory::Parking => [64, 96, 224],
+        PoiCategory::Hospital | PoiCategory::Pharmacy => [240, 240, 240],
+        PoiCategory::Restaurant | PoiCategory::Cafe | PoiCategory::FastFood => [224, 160, 64],
+        PoiCategory::Toilets | PoiCategory::Atm => [160, 160, 160],
+        PoiCategory::Police => [32, 32, 160],
+        PoiCategory::ChargingStation => [64, 192, 96],
+        PoiCategory::Shop => [192, 96, 192],
+        PoiCategory::Tourism => [96, 192, 224],
+    }
+}
@@ -0,0 +1,486 @@
+//! Contraction hierarchy preprocessing, see [`nav_core::graph::ch`] for how it gets queried.
+//!
+//! Nodes get contracted one by one, least important first. Contracting a node removes it from
+//! the graph, adding a shortcut between two of its neighbours whenever the path through it was
+//! the only shortest one. The order a node got contracted in is its rank.
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use nav_core::graph::{Graph, Metric, NO_CHILD, ch::ChQuery, dijkstra::Dijkstra, travel_time_ds};
+
+use super::{RoutingGraph, random_node_pairs};
+
+/// Witness searches give up after this many nodes and just add the shortcut.
+/// Too many shortcuts is only a bit slower, a missing one breaks routing.
+const WITNESS_SETTLE_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ChChildren {
+    /// Index into [`RoutingGraph::edges`]
+    Original(u32),
+    /// Indices into [`ContractionHierarchy::edges`], in driving order
+    Shortcut(usize, usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChEdge {
+    pub from: u32,
+    pub to: u32,
+    /// Travel time in deciseconds
+    pub weight: u32,
+    pub children: ChChildren,
+}
+
+pub struct ContractionHierarchy {
+    /// Per node, the order it was contracted in
+    pub ranks: Vec<u32>,
+    pub edges: Vec<ChEdge>,
+}
+
+struct Contractor {
+    edges: Vec<ChEdge>,
+    /// Per node, indices into `edges` going out of / coming into it, to uncontracted nodes only
+    outgoing: Vec<Vec<usize>>,
+    incoming: Vec<Vec<usize>>,
+    contracted: Vec<bool>,
+    /// How many neighbours already got contracted, keeps contraction spread out evenly
+    contracted_neighbours: Vec<u32>,
+
+    // Witness search buffers
+    dist: Vec<u32>,
+    touched: Vec<u32>,
+    heap: BinaryHeap<Reverse<(u32, u32)>>,
+}
+
+impl Contractor {
+    fn new(graph: &RoutingGraph) -> Self {
+        let n = graph.nodes.len();
+        let mut contractor = Self {
+            edges: Vec::new(),
+            outgoing: vec![Vec::new(); n],
+            incoming: vec![Vec::new(); n],
+            contracted: vec![false; n],
+            contracted_neighbours: vec![0; n],
+            dist: vec![u32::MAX; n],
+            touched: Vec::new(),
+            heap: BinaryHeap::new(),
+        };
+
+        for (i, edge) in graph.edges.iter().enumerate() {
+            if edge.source == edge.target {
+                continue;
+            }
+            let weight = travel_time_ds(edge.length_dm(), edge.speed_kmh);
+            contractor.add_edge(ChEdge {
+                from: edge.source,
+                to: edge.target,
+                weight,
+                children: ChChildren::Original(i as u32),
+            });
+        }
+
+        contractor
+    }
+
+    /// Adds the edge unless there already is one between the same nodes that's at least as good
+    fn add_edge(&mut self, edge: ChEdge) {
+        let existing = self.outgoing[edge.from as usize]
+            .iter()
+            .position(|&e| self.edges[e].to == edge.to);
+        if let Some(pos) = existing {
+            let old = self.outgoing[edge.from as usize][pos];
+            if self.edges[old].weight <= edge.weight {
+                return;
+            }
+            // The old edge stays in `edges`, shortcuts made earlier can still point at it
+            self.outgoing[edge.from as usize].swap_remove(pos);
+            self.incoming[edge.to as usize].retain(|&e| e != old);
+        }
+
+        let index = self.edges.len();
+        self.edges.push(edge);
+        self.outgoing[edge.from as usize].push(index);
+        self.incoming[edge.to as usize].push(index);
+    }
+
+    /// Dijkstra from `start` that ignores `skip`, for checking whether there's a path
+    /// that's at least as short as going through the node being contracted
+    fn witness_search(&mut self, start: u32, skip: u32, max_cost: u32) {
+        for n in self.touched.drain(..) {
+            self.dist[n as usize] = u32::MAX;
+        }
+        self.heap.clear();
+
+        self.dist[start as usize] = 0;
+        self.touched.push(start);
+        self.heap.push(Reverse((0, start)));
+
+        let mut settled = 0;
+        while let Some(Reverse((cost, node))) = self.heap.pop() {
+            if cost > self.dist[node as usize] {
+                continue;
+            }
+            settled += 1;
+            if cost > max_cost || settled > WITNESS_SETTLE_LIMIT {
+                break;
+            }
+
+            for &e in &self.outgoing[node as usize] {
+                let edge = self.edges[e];
+                if edge.to == skip {
+                    continue;
+                }
+                let next = cost + edge.weight;
+                let dist = &mut self.dist[edge.to as usize];
+                if next < *dist {
+                    if *dist == u32::MAX {
+                        self.touched.push(edge.to);
+                    }
+                    *dist = next;
+                    self.heap.push(Reverse((next, edge.to)));
+                }
+            }
+        }
+    }
+
+    /// Shortcuts contracting `node` would need
+    fn shortcuts(&mut self, node: u32) -> Vec<ChEdge> {
+        let mut shortcuts = Vec::new();
+
+        let incoming = self.incoming[node as usize].clone();
+        let outgoing = self.outgoing[node as usize].clone();
+        for &in_edge in &incoming {
+            let from = self.edges[in_edge].from;
+            let in_weight = self.edges[in_edge].weight;
+
+            let max_cost = outgoing
+                .iter()
+                .map(|&e| in_weight + self.edges[e].weight)
+                .max()
+                .unwrap_or(0);
+            self.witness_search(from, node, max_cost);
+
+            for &out_edge in &outgoing {
+                let to = self.edges[out_edge].to;
+                if to == from {
+                    continue;
+                }
+                let weight = in_weight + self.edges[out_edge].weight;
+                if self.dist[to as usize] > weight {
+                    shortcuts.push(ChEdge {
+                        from,
+                        to,
+                        weight,
+                        children: ChChildren::Shortcut(in_edge, out_edge),
+                    });
+                }
+            }
+        }
+
+        shortcuts
+    }
+
+    fn priority(&mut self, node: u32) -> i64 {
+        let shortcuts = self.shortcuts(node).len() as i64;
+        let removed =
+            (self.incoming[node as usize].len() + self.outgoing[node as usize].len()) as i64;
+        2 * (shortcuts - removed) + self.contracted_neighbours[node as usize] as i64
+    }
+
+    fn contract(&mut self, node: u32) {
+        for shortcut in self.shortcuts(node) {
+            self.add_edge(shortcut);
+        }
+
+        self.contracted[node as usize] = true;
+        let incoming = std::mem::take(&mut self.incoming[node as usize]);
+        let outgoing = std::mem::take(&mut self.outgoing[node as usize]);
+        for e in incoming {
+            let from = self.edges[e].from;
+            self.outgoing[from as usize].retain(|&e| self.edges[e].to != node);
+            self.contracted_neighbours[from as usize] += 1;
+        }
+        for e in outgoing {
+            let to = self.edges[e].to;
+            self.incoming[to as usize].retain(|&e| self.edges[e].from != node);
+            self.contracted_neighbours[to as usize] += 1;
+        }
+    }
+}
+
+pub fn build_hierarchy(graph: &RoutingGraph) -> ContractionHierarchy {
+    let n = graph.nodes.len();
+    let mut contractor = Contractor::new(graph);
+
+    let mut queue = BinaryHeap::new();
+    for node in 0..n as u32 {
+        queue.push(Reverse((contractor.priority(node), node)));
+    }
+
+    // Priorities go stale as neighbours get contracted, so they get recomputed
+    // when a node comes up and it's put back if it's no longer the best choice
+    let mut ranks = vec![0; n];
+    let mut rank = 0;
+    while let Some(Reverse((_, node))) = queue.pop() {
+        if contractor.contracted[node as usize] {
+            continue;
+        }
+        let priority = contractor.priority(node);
+        if let Some(Reverse((next, _))) = queue.peek()
+            && priority > *next
+        {
+            queue.push(Reverse((priority, node)));
+            continue;
+        }
+
+        contractor.contract(node);
+        ranks[node as usize] = rank;
+        rank += 1;
+
+        if rank % 100_000 == 0 {
+            println!("Contracted {rank} / {n} nodes");
+        }
+    }
+
+    println!(
+        "Contraction hierarchy: {} edges ({} shortcuts)",
+        contractor.edges.len(),
+        contractor
+            .edges
+            .iter()
+            .filter(|e| matches!(e.children, ChChildren::Shortcut(..)))
+            .count()
+    );
+
+    ContractionHierarchy {
+        ranks,
+        edges: contractor.edges,
+    }
+}
+
+/// Compares hierarchy queries against plain Dijkstra on `samples` random node pairs of the
+/// encoded graph, to catch preprocessing bugs before they end up on a Memory Stick.
+/// Returns the number of mismatches.
+pub fn verify_hierarchy(data: &[u8], samples: usize) -> usize {
+    let graph = Graph::parse(data).expect("Failed to parse the graph we just encoded!");
+    let Some(hierarchy) = graph.hierarchy() else {
+        println!("No contraction hierarchy to verify");
+        return 0;
+    };
+    let n = graph.node_count();
+    if n == 0 {
+        return 0;
+    }
+
+    let mut dijkstra = Dijkstra::new(n);
+    let mut query = ChQuery::new(n);
+
+    let mut mismatches = 0;
+    for (source, target) in random_node_pairs(n, samples) {
+        let expected = dijkstra
+            .run(
+                &graph,
+                Metric::Time,
+                &[(source, 0)],
+                &[(target, 0)],
+                usize::MAX,
+            )
+            .unwrap();
+        let actual = query.run(hierarchy, &[(source, 0)], &[(target, 0)]);
+
+        let ok = match (&expected, &actual) {
+            (None, None) => true,
+            (Some(expected), Some(actual)) => {
+                // The unpacked path has to be a real, connected path with the same cost
+                let mut node = source;
+                let mut cost = 0;
+                let mut connected = true;
+                for &e in &actual.edges {
+                    connected &= graph.edge_source(e) == node;
+                    let edge = graph.edge(e);
+                    cost += Metric::Time.weight(&edge);
+                    node = edge.target;
+                }
+                connected && node == target && cost == actual.cost && actual.cost == expected.cost
+            }
+            _ => false,
+        };
+        if !ok {
+            mismatches += 1;
+            println!(
+                "Hierarchy mismatch {source} -> {target}: dijkstra {:?}, hierarchy {:?}",
+                expected.map(|p| p.cost),
+                actual.map(|p| p.cost)
+            );
+        }
+    }
+
+    println!("Verified contraction hierarchy on {samples} routes, {mismatches} mismatches");
+    mismatches
+}
+
+/// Upward edges grouped by their lower ranked node, in the layout [`nav_core::graph::ch`] reads.
+/// Returns (first edge per node + 1, records as [other, weight, a, b, flags]).
+pub fn upward_edges(
+    hierarchy: &ContractionHierarchy,
+    node_count: usize,
+) -> (Vec<u32>, Vec<[u32; 5]>) {
+    use nav_core::graph::{CH_FLAG_BACKWARD, CH_FLAG_FORWARD};
+
+    let owner = |edge: &ChEdge| {
+        if hierarchy.ranks[edge.from as usize] < hierarchy.ranks[edge.to as usize] {
+            edge.from
+        } else {
+            edge.to
+        }
+    };
+
+    let mut first = vec![0u32; node_count + 1];
+    for edge in &hierarchy.edges {
+        first[owner(edge) as usize + 1] += 1;
+    }
+    for i in 0..node_count {
+        first[i + 1] += first[i];
+    }
+
+    // Final position of every edge, shortcuts refer to their children by it
+    let mut next = first.clone();
+    let positions = hierarchy
+        .edges
+        .iter()
+        .map(|edge| {
+            let owner = owner(edge) as usize;
+            next[owner] += 1;
+            next[owner] - 1
+        })
+        .collect::<Vec<_>>();
+
+    let mut records = vec![[0u32; 5]; hierarchy.edges.len()];
+    for (edge, &position) in hierarchy.edges.iter().zip(&positions) {
+        let (other, flags) = if owner(edge) == edge.from {
+            (edge.to, CH_FLAG_FORWARD)
+        } else {
+            (edge.from, CH_FLAG_BACKWARD)
+        };
+        let (a, b) = match edge.children {
+            ChChildren::Original(e) => (e, NO_CHILD),
+            ChChildren::Shortcut(a, b) => (positions[a], positions[b]),
+        };
+        records[position as usize] = [other, edge.weight, a, b, flags];
+    }
+
+    (first, records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::Coord;
+    use crate::routing::{GraphEdge, GraphNode, Segment};
+    use crate::writer::encode_routing_graph;
+    use nav_core::graph::RoadClass;
+
+    fn add_road(graph: &mut RoutingGraph, a: u32, b: u32, speed_kmh: u8, both_ways: bool) {
+        let points = vec![graph.nodes[a as usize].coord, graph.nodes[b as usize].coord];
+        let length = points[0].distance_to(points[1]);
+        let segment = graph.segments.len() as u32;
+        graph.segments.push(Segment {
+            points,
+            name: String::new(),
+            road_ref: String::new(),
+        });
+        let edge = GraphEdge {
+            source: a,
+            target: b,
+            length,
+            segment,
+            reversed: false,
+            roundabout: false,
+            speed_kmh,
+            class: RoadClass::Residential,
+            way_id: segment as i64,
+        };
+        if both_ways {
+            graph.edges.push(GraphEdge {
+                source: b,
+                target: a,
+                reversed: true,
+                ..edge.clone()
+            });
+        }
+        graph.edges.push(edge);
+    }
+
+    /// Streets in a `size` by `size` grid with different speeds, every other row one way
+    fn grid(size: usize) -> RoutingGraph {
+        let mut graph = RoutingGraph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            segments: Vec::new(),
+        };
+        let node = |row: usize, column: usize| (row * size + column) as u32;
+        for row in 0..size {
+            for column in 0..size {
+                graph.nodes.push(GraphNode {
+                    osm_id: node(row, column) as i64,
+                    coord: Coord {
+                        lat: 52.5 + row as f64 * 0.001,
+                        lon: 13.4 + column as f64 * 0.0015,
+                    },
+                });
+            }
+        }
+        for row in 0..size {
+            for column in 0..size {
+                let speed = [30, 50, 30, 70, 20][(row * 3 + column) % 5];
+                if column + 1 < size {
+                    let both_ways = row % 2 == 0;
+                    add_road(
+                        &mut graph,
+                        node(row, column),
+                        node(row, column + 1),
+                        speed,
+                        both_ways,
+                    );
+                }
+                if row + 1 < size {
+                    add_road(
+                        &mut graph,
+                        node(row, column),
+                        node(row + 1, column),
+                        speed,
+                        true,
+                    );
+                }
+            }
+        }
+        graph.edges.sort_by_key(|edge| edge.source);
+        graph
+    }
+
+    #[test]
+    fn hierarchy_matches_dijkstra() {
+        let graph = grid(8);
+        let hierarchy = build_hierarchy(&graph);
+        let data = encode_routing_graph(&graph, Some(&hierarchy));
+        assert_eq!(verify_hierarchy(&data, 1000), 0);
+    }
+
+    #[test]
+    fn hierarchy_has_shortcuts_only_between_higher_ranks() {
+        let graph = grid(6);
+        let hierarchy = build_hierarchy(&graph);
+        for edge in &hierarchy.edges {
+            if let ChChildren::Shortcut(a, b) = edge.children {
+                let middle = hierarchy.edges[a].to;
+                assert_eq!(middle, hierarchy.edges[b].from);
+                assert_eq!(
+                    edge.weight,
+                    hierarchy.edges[a].weight + hierarchy.edges[b].weight
+                );
+                assert!(hierarchy.ranks[middle as usize] < hierarchy.ranks[edge.from as usize]);
+                assert!(hierarchy.ranks[middle as usize] < hierarchy.ranks[edge.to as usize]);
+            }
+        }
+    }
+}
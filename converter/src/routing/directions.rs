@@ -0,0 +1,53 @@
+//! Prints turn-by-turn directions for a few random routes, worked out by the same
+//! [`nav_core::maneuver`] code the PSP runs. Reading them next to a map is a quick way to spot
+//! bad instructions without copying anything to a Memory Stick.
+
+use nav_core::graph::{Graph, Metric};
+use nav_core::maneuver::{Maneuver, maneuvers};
+use nav_core::route::Router;
+
+use super::random_node_pairs;
+
+/// Search memory for the sample routes, nothing like the PSP's limits
+const ROUTER_MEMORY: usize = 256 * 1024 * 1024;
+
+/// Directions for `samples` routes between random nodes of the encoded graph
+pub fn print_directions(data: &[u8], samples: usize) {
+    let graph = Graph::parse(data).expect("Failed to parse the graph we just encoded!");
+    if graph.node_count() == 0 {
+        return;
+    }
+    let mut router = Router::new(&graph, ROUTER_MEMORY).expect("No memory for a router!");
+
+    for (source, target) in random_node_pairs(graph.node_count(), samples) {
+        let (from, to) = (graph.node_coord(source), graph.node_coord(target));
+        let route = match router.route(&graph, from, to, Metric::Time) {
+            Ok(route) => route,
+            Err(e) => {
+                println!("No directions {source} -> {target}: {e:?}");
+                continue;
+            }
+        };
+
+        println!("Directions {source} -> {target}:");
+        for maneuver in maneuvers(&graph, &route) {
+            println!("  {}", describe(&graph, &maneuver));
+        }
+    }
+}
+
+fn describe(graph: &Graph, maneuver: &Maneuver) -> String {
+    let road = match (
+        graph.segment_name(maneuver.segment),
+        graph.segment_ref(maneuver.segment),
+    ) {
+        (Some(name), Some(road_ref)) => format!("{name} ({road_ref})"),
+        (Some(name), None) => name.to_string(),
+        (None, Some(road_ref)) => road_ref.to_string(),
+        (None, None) => String::from("unnamed road"),
+    };
+    format!(
+        "after {:>6.0} m: {:?} onto {road}",
+        maneuver.distance_m, maneuver.kind
+    )
+}
@@ -0,0 +1,196 @@
+//! Turns the roads into a routing graph.
+//! Every point shared by two or more roads (and every road end) becomes a node,
+//! everything in between becomes the geometry of the edges connecting them.
+
+use std::collections::HashMap;
+
+use nav_core::graph::RoadClass;
+
+use crate::reader::{Coord, Oneway, Road, TurnRestriction};
+
+mod ch;
+pub use ch::*;
+
+mod directions;
+pub use directions::*;
+
+mod restrictions;
+
+pub struct GraphNode {
+    pub osm_id: i64,
+    pub coord: Coord,
+}
+
+#[derive(Clone)]
+pub struct GraphEdge {
+    pub source: u32,
+    pub target: u32,
+    /// In meters
+    pub length: f64,
+    /// Index into [`RoutingGraph::segments`]
+    pub segment: u32,
+    /// Driving against the direction of the segment's points
+    pub reversed: bool,
+    pub roundabout: bool,
+    pub speed_kmh: u8,
+    pub class: RoadClass,
+    /// OSM way this edge is part of
+    pub way_id: i64,
+}
+
+impl GraphEdge {
+    /// Length as it gets stored in the graph file
+    pub fn length_dm(&self) -> u32 {
+        (self.length * 10.0).round() as u32
+    }
+}
+
+pub struct RoutingGraph {
+    pub nodes: Vec<GraphNode>,
+    /// Sorted by source node
+    pub edges: Vec<GraphEdge>,
+    /// Geometry of the edges, shared by both directions of a road
+    pub segments: Vec<Segment>,
+}
+
+/// A piece of road between two nodes
+pub struct Segment {
+    pub points: Vec<Coord>,
+    /// Empty if not tagged
+    pub name: String,
+    /// Empty if not tagged
+    pub road_ref: String,
+}
+
+/// `count` pairs of nodes, picked at random but the same ones on every run
+fn random_node_pairs(node_count: usize, count: usize) -> Vec<(u32, u32)> {
+    // Xorshift, no need to pull in a crate for picking some nodes
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut random_node = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % node_count as u64) as u32
+    };
+    (0..count).map(|_| (random_node(), random_node())).collect()
+}
+
+/// Returns None for anything a car can't drive on
+pub fn road_class(kind: &str) -> Option<RoadClass> {
+    Some(match kind {
+        "motorway" | "motorway_link" => RoadClass::Motorway,
+        "trunk" | "trunk_link" => RoadClass::Trunk,
+        "primary" | "primary_link" => RoadClass::Primary,
+        "secondary" | "secondary_link" => RoadClass::Secondary,
+        "tertiary" | "tertiary_link" => RoadClass::Tertiary,
+        "unclassified" | "road" => RoadClass::Unclassified,
+        "residential" => RoadClass::Residential,
+        "living_street" => RoadClass::LivingStreet,
+        "service" => RoadClass::Service,
+        "track" => RoadClass::Track,
+        _ => return None,
+    })
+}
+
+pub fn build_graph(roads: &[Road], restrictions: &[TurnRestriction]) -> RoutingGraph {
+    let roads = roads
+        .iter()
+        .filter_map(|road| Some((road, road_class(&road.kind)?)))
+        .collect::<Vec<_>>();
+
+    // A node that shows up more than once is where roads meet (or a road meets itself)
+    let mut uses = HashMap::<i64, u32>::new();
+    for (road, _) in &roads {
+        for id in &road.node_ids {
+            *uses.entry(*id).or_default() += 1;
+        }
+    }
+
+    let mut nodes = Vec::new();
+    let mut node_index = HashMap::<i64, u32>::new();
+    let mut index_of = |osm_id: i64, coord: Coord| {
+        *node_index.entry(osm_id).or_insert_with(|| {
+            nodes.push(GraphNode { osm_id, coord });
+            nodes.len() as u32 - 1
+        })
+    };
+
+    let mut edges = Vec::new();
+    let mut segments = Vec::new();
+
+    for (road, class) in roads {
+        let speed_kmh = if road.speedlimit > 0 {
+            road.speedlimit
+        } else {
+            class.default_speed_kmh()
+        };
+
+        let last = road.node_ids.len() - 1;
+        let mut start = 0;
+        for i in 1..=last {
+            let is_node = i == last || uses[&road.node_ids[i]] > 1;
+            if !is_node {
+                continue;
+            }
+
+            let points = road.points[start..=i].to_vec();
+            let length: f64 = points.windows(2).map(|w| w[0].distance_to(w[1])).sum();
+            let a = index_of(road.node_ids[start], road.points[start]);
+            let b = index_of(road.node_ids[i], road.points[i]);
+            start = i;
+
+            // Duplicate nodes in the source data, not a real road
+            if a == b && length < 0.1 {
+                continue;
+            }
+
+            let segment = segments.len() as u32;
+            segments.push(Segment {
+                points,
+                name: road.name.clone(),
+                road_ref: road.road_ref.clone(),
+            });
+
+            let edge = |source, target, reversed| GraphEdge {
+                source,
+                target,
+                length,
+                segment,
+                reversed,
+                roundabout: road.roundabout,
+                speed_kmh,
+                class,
+                way_id: road.id,
+            };
+            if road.oneway != Oneway::Backward {
+                edges.push(edge(a, b, false));
+            }
+            if road.oneway != Oneway::Forward {
+                edges.push(edge(b, a, true));
+            }
+        }
+    }
+
+    let copies = restrictions::apply_restrictions(
+        &mut nodes,
+        &mut edges,
+        &segments,
+        &node_index,
+        restrictions,
+    );
+    println!("Turn restrictions: {copies} intersection copies");
+
+    edges.sort_by_key(|edge| edge.source);
+
+    println!(
+        "Routing graph: {} nodes, {} edges",
+        nodes.len(),
+        edges.len()
+    );
+
+    RoutingGraph {
+        nodes,
+        edges,
+        segments,
+    }
+}
@@ -0,0 +1,390 @@
+//! Bakes turn restrictions into the graph itself.
+//!
+//! Every edge arriving over a restricted `from` way gets its own copy of the via node,
+//! and that copy only gets the outgoing edges that are allowed after arriving that way.
+//! The result is a plain graph where forbidden turns simply don't exist, so any router
+//! (including the contraction hierarchy) respects them without knowing about them.
+
+use std::collections::{HashMap, HashSet};
+
+use nav_core::geometry::{LocalProjection, bearing, bearing_delta};
+use nav_core::maneuver::ManeuverKind;
+
+use crate::reader::{Coord, RestrictionKind, Turn, TurnRestriction};
+
+use super::{GraphEdge, GraphNode, Segment};
+
+/// Returns how many via node copies were made
+pub(super) fn apply_restrictions(
+    nodes: &mut Vec<GraphNode>,
+    edges: &mut Vec<GraphEdge>,
+    segments: &[Segment],
+    node_index: &HashMap<i64, u32>,
+    restrictions: &[TurnRestriction],
+) -> usize {
+    let mut by_via = HashMap::<u32, Vec<&TurnRestriction>>::new();
+    for restriction in restrictions {
+        // Via nodes in the middle of a road aren't intersections, nothing to restrict there
+        if let Some(via) = node_index.get(&restriction.via_node) {
+            by_via.entry(*via).or_default().push(restriction);
+        }
+    }
+
+    let mut incoming = HashMap::<u32, Vec<usize>>::new();
+    let mut outgoing = HashMap::<u32, Vec<usize>>::new();
+    for (i, edge) in edges.iter().enumerate() {
+        if by_via.contains_key(&edge.target) {
+            incoming.entry(edge.target).or_default().push(i);
+        }
+        if by_via.contains_key(&edge.source) {
+            outgoing.entry(edge.source).or_default().push(i);
+        }
+    }
+
+    // First decide on all the copies and redirect the restricted edges to them.
+    // Copying the allowed edges has to wait until every edge points at its final target,
+    // otherwise restrictions on neighbouring intersections get lost on the copies.
+    let mut copies = Vec::new();
+    for (via, via_restrictions) in &by_via {
+        let Some(incoming) = incoming.get(via) else {
+            continue;
+        };
+        let no_outgoing = Vec::new();
+        let outgoing = outgoing.get(via).unwrap_or(&no_outgoing);
+        let via_coord = nodes[*via as usize].coord;
+
+        // A way that doesn't end at the via node reaches it from two sides
+        let mut sides = HashMap::<i64, HashSet<u32>>::new();
+        for &e in incoming.iter().chain(outgoing) {
+            sides
+                .entry(edges[e].way_id)
+                .or_default()
+                .insert(edges[e].segment);
+        }
+        let through = |way| sides.get(&way).is_some_and(|sides| sides.len() > 1);
+
+        for &from in incoming {
+            let from_edge = &edges[from];
+            let hits = |r: &TurnRestriction, to: usize| {
+                let to_edge = &edges[to];
+                if to_edge.way_id != r.to_way {
+                    return false;
+                }
+                // `no_u_turn` has the same from and to way, only the way back is meant
+                if r.from_way == r.to_way {
+                    return to_edge.segment == from_edge.segment;
+                }
+                // Only the tagged turn tells which side of a through way is meant
+                match r.turn {
+                    Some(turn) if through(r.from_way) || through(r.to_way) => {
+                        turn_between(segments, via_coord, from_edge, to_edge) == turn
+                    }
+                    _ => true,
+                }
+            };
+
+            let applicable = via_restrictions
+                .iter()
+                .copied()
+                .filter(|r| {
+                    r.from_way == from_edge.way_id
+                        && (!through(r.from_way) || outgoing.iter().any(|&to| hits(r, to)))
+                })
+                .collect::<Vec<_>>();
+            if applicable.is_empty() {
+                continue;
+            }
+
+            let has_only = applicable.iter().any(|r| r.kind == RestrictionKind::Only);
+            let allowed = outgoing
+                .iter()
+                .copied()
+                .filter(|&to| {
+                    let allowed_by_only = !has_only
+                        || applicable
+                            .iter()
+                            .any(|r| r.kind == RestrictionKind::Only && hits(r, to));
+                    let banned = applicable
+                        .iter()
+                        .any(|r| r.kind == RestrictionKind::No && hits(r, to));
+                    allowed_by_only && !banned
+                })
+                .collect::<Vec<_>>();
+
+            let copy = nodes.len() as u32;
+            nodes.push(GraphNode {
+                osm_id: nodes[*via as usize].osm_id,
+                coord: via_coord,
+            });
+            edges[from].target = copy;
+            copies.push((copy, allowed));
+        }
+    }
+
+    let copy_count = copies.len();
+    for (copy, allowed) in copies {
+        for to in allowed {
+            let mut edge = edges[to].clone();
+            edge.source = copy;
+            edges.push(edge);
+        }
+    }
+
+    copy_count
+}
+
+/// Which way a driver turns at `via` going from `from` onto `to`,
+/// judged by the first piece of road on either side of it
+fn turn_between(segments: &[Segment], via: Coord, from: &GraphEdge, to: &GraphEdge) -> Turn {
+    // The via node is the last point when arriving forwards or leaving backwards
+    let next_to_via = |edge: &GraphEdge, arriving: bool| {
+        let points = &segments[edge.segment as usize].points;
+        if arriving != edge.reversed {
+            points[points.len() - 2]
+        } else {
+            points[1]
+        }
+    };
+
+    let projection = LocalProjection::new(via.into());
+    let via = (0.0, 0.0);
+    let arriving = bearing(projection.to_local(next_to_via(from, true).into()), via);
+    let leaving = bearing(via, projection.to_local(next_to_via(to, false).into()));
+    match ManeuverKind::from_bearing_delta(bearing_delta(arriving, leaving)) {
+        ManeuverKind::Left | ManeuverKind::SharpLeft => Turn::Left,
+        ManeuverKind::Right | ManeuverKind::SharpRight => Turn::Right,
+        ManeuverKind::UTurn => Turn::UTurn,
+        _ => Turn::Straight,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nav_core::graph::ch::ChQuery;
+    use nav_core::graph::dijkstra::Dijkstra;
+    use nav_core::graph::{Graph, Metric};
+
+    use super::*;
+    use crate::reader::{Oneway, Road};
+    use crate::routing::{RoutingGraph, build_graph, build_hierarchy};
+    use crate::writer::encode_routing_graph;
+
+    // An intersection with an arm in every direction
+    const VIA: i64 = 0;
+    const WEST: i64 = 1;
+    const EAST: i64 = 2;
+    const NORTH: i64 = 3;
+    const SOUTH: i64 = 4;
+
+    /// Roughly 100 m per step in either direction
+    fn coord(id: i64) -> Coord {
+        let (x, y) = match id {
+            VIA => (0.0, 0.0),
+            WEST => (-1.0, 0.0),
+            EAST => (1.0, 0.0),
+            NORTH => (0.0, 1.0),
+            SOUTH => (0.0, -1.0),
+            // Corners of a ring road far out, so every forbidden turn has a way around
+            5 => (-3.0, 3.0),
+            6 => (3.0, 3.0),
+            7 => (3.0, -3.0),
+            8 => (-3.0, -3.0),
+            // The u-turn fixture's own nodes
+            10 => (-2.0, 0.0),
+            11 => (-1.0, -1.0),
+            12 => (0.0, 5.0),
+            _ => unreachable!(),
+        };
+        Coord {
+            lat: 52.5 + y * 0.001,
+            lon: 13.4 + x * 0.0015,
+        }
+    }
+
+    fn road(id: i64, node_ids: &[i64]) -> Road {
+        Road {
+            id,
+            points: node_ids.iter().map(|&id| coord(id)).collect(),
+            node_ids: node_ids.to_vec(),
+            kind: "residential".into(),
+            ..Default::default()
+        }
+    }
+
+    fn restriction(
+        kind: RestrictionKind,
+        turn: Turn,
+        from_way: i64,
+        to_way: i64,
+    ) -> TurnRestriction {
+        TurnRestriction {
+            kind,
+            from_way,
+            via_node: VIA,
+            to_way,
+            turn: Some(turn),
+        }
+    }
+
+    /// Ways 1 to 4 end at the intersection, way 9 is the ring road around it
+    fn crossing(through: bool) -> Vec<Road> {
+        let mut roads = if through {
+            // Way 5 runs from west to east right through the intersection
+            vec![road(5, &[WEST, VIA, EAST])]
+        } else {
+            vec![road(1, &[WEST, VIA]), road(2, &[VIA, EAST])]
+        };
+        roads.push(road(3, &[NORTH, VIA]));
+        roads.push(road(4, &[VIA, SOUTH]));
+        roads.push(road(9, &[WEST, 5, NORTH, 6, EAST, 7, SOUTH, 8, WEST]));
+        roads
+    }
+
+    /// OSM nodes passed on the fastest route from `from` to `to`, with Dijkstra and through
+    /// the contraction hierarchy
+    fn routes(graph: &RoutingGraph, from: i64, to: i64) -> [Vec<i64>; 2] {
+        let node = |id| graph.nodes.iter().position(|n| n.osm_id == id).unwrap() as u32;
+        let (from, to) = (node(from), node(to));
+
+        let hierarchy = build_hierarchy(graph);
+        let data = encode_routing_graph(graph, Some(&hierarchy));
+        let parsed = Graph::parse(&data).unwrap();
+        let n = parsed.node_count();
+        let dijkstra = Dijkstra::new(n)
+            .run(&parsed, Metric::Time, &[(from, 0)], &[(to, 0)], usize::MAX)
+            .unwrap()
+            .expect("No route");
+        let ch = ChQuery::new(n)
+            .run(parsed.hierarchy().unwrap(), &[(from, 0)], &[(to, 0)])
+            .expect("No route through the hierarchy");
+        assert_eq!(dijkstra.cost, ch.cost);
+
+        [dijkstra, ch].map(|path| {
+            let mut passed = vec![graph.nodes[from as usize].osm_id];
+            for e in path.edges {
+                passed.push(graph.nodes[parsed.edge(e).target as usize].osm_id);
+            }
+            passed
+        })
+    }
+
+    fn turns(path: &[i64], turn: [i64; 3]) -> bool {
+        path.windows(3).any(|w| w == turn)
+    }
+
+    #[test]
+    fn no_turn() {
+        let restrictions = [restriction(RestrictionKind::No, Turn::Left, 1, 3)];
+        let graph = build_graph(&crossing(false), &restrictions);
+        for path in routes(&graph, WEST, NORTH) {
+            assert!(!turns(&path, [WEST, VIA, NORTH]), "{path:?}");
+        }
+        for path in routes(&graph, WEST, EAST) {
+            assert_eq!(path, [WEST, VIA, EAST]);
+        }
+        for path in routes(&graph, WEST, SOUTH) {
+            assert_eq!(path, [WEST, VIA, SOUTH]);
+        }
+        // Only arriving over the from way is restricted
+        for path in routes(&graph, EAST, NORTH) {
+            assert_eq!(path, [EAST, VIA, NORTH]);
+        }
+    }
+
+    #[test]
+    fn only_turn() {
+        let restrictions = [restriction(RestrictionKind::Only, Turn::Straight, 1, 2)];
+        let graph = build_graph(&crossing(false), &restrictions);
+        for to in [NORTH, SOUTH] {
+            for path in routes(&graph, WEST, to) {
+                assert!(!turns(&path, [WEST, VIA, to]), "{path:?}");
+            }
+        }
+        for path in routes(&graph, WEST, EAST) {
+            assert_eq!(path, [WEST, VIA, EAST]);
+        }
+        for path in routes(&graph, NORTH, SOUTH) {
+            assert_eq!(path, [NORTH, VIA, SOUTH]);
+        }
+    }
+
+    #[test]
+    fn no_u_turn() {
+        // Way 20 only leads onto way 21 towards the intersection, and turning onto way 22
+        // right away is forbidden, so turning around at the intersection is the short way
+        // to node 11. The long way goes up way 23 and back down way 24.
+        let mut one_way = road(20, &[10, WEST]);
+        one_way.oneway = Oneway::Forward;
+        let roads = [
+            one_way,
+            road(21, &[WEST, VIA]),
+            road(22, &[WEST, 11]),
+            road(23, &[VIA, 12]),
+            road(24, &[12, 11]),
+        ];
+        let mut restrictions = vec![TurnRestriction {
+            kind: RestrictionKind::No,
+            from_way: 20,
+            via_node: WEST,
+            to_way: 22,
+            turn: None,
+        }];
+
+        let graph = build_graph(&roads, &restrictions);
+        for path in routes(&graph, 10, 11) {
+            assert_eq!(path, [10, WEST, VIA, WEST, 11]);
+        }
+
+        restrictions.push(restriction(RestrictionKind::No, Turn::UTurn, 21, 21));
+        let graph = build_graph(&roads, &restrictions);
+        for path in routes(&graph, 10, 11) {
+            assert_eq!(path, [10, WEST, VIA, 12, 11]);
+        }
+    }
+
+    #[test]
+    fn from_way_through_the_via_node() {
+        // Turning left from the west is right from the east, which stays allowed
+        let restrictions = [restriction(RestrictionKind::No, Turn::Left, 5, 3)];
+        let graph = build_graph(&crossing(true), &restrictions);
+        for path in routes(&graph, WEST, NORTH) {
+            assert!(!turns(&path, [WEST, VIA, NORTH]), "{path:?}");
+        }
+        for path in routes(&graph, EAST, NORTH) {
+            assert_eq!(path, [EAST, VIA, NORTH]);
+        }
+        for path in routes(&graph, WEST, EAST) {
+            assert_eq!(path, [WEST, VIA, EAST]);
+        }
+
+        // Same for a way running through as the to way: only the right turn onto it is meant
+        let restrictions = [restriction(RestrictionKind::No, Turn::Right, 3, 5)];
+        let graph = build_graph(&crossing(true), &restrictions);
+        for path in routes(&graph, NORTH, WEST) {
+            assert!(!turns(&path, [NORTH, VIA, WEST]), "{path:?}");
+        }
+        for path in routes(&graph, NORTH, EAST) {
+            assert_eq!(path, [NORTH, VIA, EAST]);
+        }
+    }
+
+    #[test]
+    fn restrictions_sharing_a_via_node() {
+        let restrictions = [
+            restriction(RestrictionKind::No, Turn::Left, 1, 3),
+            restriction(RestrictionKind::No, Turn::Right, 1, 4),
+            restriction(RestrictionKind::No, Turn::Left, 3, 2),
+        ];
+        let graph = build_graph(&crossing(false), &restrictions);
+        for (from, to) in [(WEST, NORTH), (WEST, SOUTH), (NORTH, EAST)] {
+            for path in routes(&graph, from, to) {
+                assert!(!turns(&path, [from, VIA, to]), "{path:?}");
+            }
+        }
+        for (from, to) in [(WEST, EAST), (NORTH, SOUTH), (NORTH, WEST), (EAST, NORTH)] {
+            for path in routes(&graph, from, to) {
+                assert_eq!(path, [from, VIA, to]);
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Cuts shapes down to a square, so no tile has to carry more of a road or area than it shows
+
+type Point = (f64, f64);
+
+/// Pieces of the line inside the square from `min` to `max` on both axes. A line that leaves
+/// and comes back in turns into several pieces.
+pub(super) fn clip_line(points: &[Point], min: f64, max: f64) -> Vec<Vec<Point>> {
+    let mut pieces = Vec::new();
+    let mut current = Vec::new();
+    for pair in points.windows(2) {
+        let Some((start, end, entered, left)) = clip_segment(pair[0], pair[1], min, max) else {
+            if current.len() >= 2 {
+                pieces.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            continue;
+        };
+        if entered || current.is_empty() {
+            if current.len() >= 2 {
+                pieces.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            current.push(start);
+        }
+        current.push(end);
+        if left {
+            pieces.push(std::mem::take(&mut current));
+        }
+    }
+    if current.len() >= 2 {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Liang-Barsky. Returns the part of the segment inside, and whether it had to be cut at the
+/// start and at the end.
+fn clip_segment(a: Point, b: Point, min: f64, max: f64) -> Option<(Point, Point, bool, bool)> {
+    let d = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    for (p, q) in [
+        (-d.0, a.0 - min),
+        (d.0, max - a.0),
+        (-d.1, a.1 - min),
+        (d.1, max - a.1),
+    ] {
+        if p == 0.0 {
+            // Parallel to this edge, and outside of it
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    // Points that don't get cut stay exactly as they were
+    let at = |t: f64| (a.0 + t * d.0, a.1 + t * d.1);
+    let start = if t0 > 0.0 { at(t0) } else { a };
+    let end = if t1 < 1.0 { at(t1) } else { b };
+    Some((start, end, t0 > 0.0, t1 < 1.0))
+}
+
+/// The part of the area inside the square, Sutherland-Hodgman. Empty if there's nothing left.
+pub(super) fn clip_polygon(points: &[Point], min: f64, max: f64) -> Vec<Point> {
+    let mut ring = points.to_vec();
+    // Axis, bound, and whether inside is above it
+    for (axis, bound, above) in [
+        (0, min, true),
+        (0, max, false),
+        (1, min, true),
+        (1, max, false),
+    ] {
+        let value = |p: Point| if axis == 0 { p.0 } else { p.1 };
+        let inside = |p: Point| (value(p) >= bound) == above || value(p) == bound;
+        let mut clipped = Vec::with_capacity(ring.len() + 4);
+        for (i, &a) in ring.iter().enumerate() {
+            let b = ring[(i + 1) % ring.len()];
+            if inside(a) {
+                clipped.push(a);
+            }
+            if inside(a) != inside(b) {
+                let t = (bound - value(a)) / (value(b) - value(a));
+                clipped.push((a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1)));
+            }
+        }
+        ring = clipped;
+        if ring.is_empty() {
+            break;
+        }
+    }
+    ring
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Twice the area, positive or negative depending on which way the ring goes
+    fn area(ring: &[Point]) -> f64 {
+        (0..ring.len())
+            .map(|i| {
+                let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+                a.0 * b.1 - b.0 * a.1
+            })
+            .sum::<f64>()
+            .abs()
+            / 2.0
+    }
+
+    #[test]
+    fn segments_get_cut_where_they_cross() {
+        let clip = |a, b| clip_segment(a, b, 0.0, 10.0);
+        assert_eq!(
+            clip((1.0, 1.0), (2.0, 3.0)),
+            Some(((1.0, 1.0), (2.0, 3.0), false, false))
+        );
+        // Entering, leaving, and both
+        assert_eq!(
+            clip((-5.0, 5.0), (5.0, 5.0)),
+            Some(((0.0, 5.0), (5.0, 5.0), true, false))
+        );
+        assert_eq!(
+            clip((5.0, 5.0), (5.0, 15.0)),
+            Some(((5.0, 5.0), (5.0, 10.0), false, true))
+        );
+        assert_eq!(
+            clip((-5.0, 0.0), (15.0, 10.0)),
+            Some(((0.0, 2.5), (10.0, 7.5), true, true))
+        );
+        // Along an edge counts as inside
+        assert_eq!(
+            clip((0.0, 2.0), (0.0, 8.0)),
+            Some(((0.0, 2.0), (0.0, 8.0), false, false))
+        );
+
+        assert_eq!(clip((-1.0, 0.0), (-1.0, 10.0)), None);
+        assert_eq!(clip((11.0, 11.0), (20.0, 5.0)), None);
+        // Passing by a corner
+        assert_eq!(clip((-2.0, 1.0), (1.0, -2.0)), None);
+    }
+
+    #[test]
+    fn lines_split_into_the_pieces_inside() {
+        let clip = |points: &[Point]| clip_line(points, 0.0, 10.0);
+        let inside = [(1.0, 1.0), (5.0, 2.0), (9.0, 9.0)];
+        assert_eq!(clip(&inside), [inside.to_vec()]);
+        assert!(clip(&[(-5.0, -5.0), (-1.0, 20.0), (20.0, 20.0)]).is_empty());
+
+        // In, along a few points, and out again
+        assert_eq!(
+            clip(&[(-5.0, 2.0), (2.0, 2.0), (3.0, 3.0), (20.0, 3.0)]),
+            [vec![(0.0, 2.0), (2.0, 2.0), (3.0, 3.0), (10.0, 3.0)]]
+        );
+        // Out and back in turns into two pieces
+        assert_eq!(
+            clip(&[(5.0, 5.0), (15.0, 5.0), (15.0, 8.0), (5.0, 8.0)]),
+            [vec![(5.0, 5.0), (10.0, 5.0)], vec![(10.0, 8.0), (5.0, 8.0)]]
+        );
+        // Right through without a point inside
+        assert_eq!(
+            clip(&[
+                (-5.0, 5.0),
+                (15.0, 5.0),
+                (15.0, 20.0),
+                (-5.0, 20.0),
+                (-5.0, 6.0),
+                (15.0, 6.0)
+            ]),
+            [vec![(0.0, 5.0), (10.0, 5.0)], vec![(0.0, 6.0), (10.0, 6.0)]]
+        );
+    }
+
+    #[test]
+    fn rings_keep_what_is_inside() {
+        let clip = |points: &[Point]| clip_polygon(points, 0.0, 10.0);
+        let inside = [(1.0, 1.0), (9.0, 1.0), (5.0, 9.0)];
+        assert_eq!(clip(&inside), inside);
+        assert!(clip(&[(11.0, 11.0), (20.0, 11.0), (20.0, 20.0)]).is_empty());
+        assert!(clip(&[(-5.0, 3.0), (-1.0, 3.0), (-1.0, 20.0)]).is_empty());
+
+        // Reaching over a corner of the square takes the corner in
+        let ring = clip(&[(5.0, 5.0), (15.0, 5.0), (15.0, 15.0), (5.0, 15.0)]);
+        assert_eq!(area(&ring), 25.0);
+        assert!(ring.contains(&(10.0, 10.0)));
+        assert!(
+            ring.iter()
+                .all(|p| (0.0..=10.0).contains(&p.0) && (0.0..=10.0).contains(&p.1))
+        );
+
+        // Around all of it leaves just the square
+        let ring = clip(&[(-5.0, -5.0), (15.0, -5.0), (15.0, 15.0), (-5.0, 15.0)]);
+        assert_eq!(area(&ring), 100.0);
+        for corner in [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)] {
+            assert!(ring.contains(&corner), "{ring:?}");
+        }
+
+        // A triangle poking out of one side loses its tip
+        let ring = clip(&[(2.0, 2.0), (8.0, 2.0), (5.0, -4.0)]);
+        assert_eq!(area(&ring), 18.0 - 8.0);
+    }
+}
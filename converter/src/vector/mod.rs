@@ -0,0 +1,415 @@
+//! Cuts the map into vector tiles for the PSP to draw itself, the other way to get tiles next
+//! to [`crate::render`]. What goes into a tile is described in `nav_core::vector_tile`.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use nav_core::graph::RoadClass;
+use nav_core::tile::{TileKey, tile_position};
+use nav_core::vector_tile::{AreaKind, BUFFER, EXTENT, TileBuilder};
+
+use crate::config::Config;
+use crate::reader::{Coord, Map, ObjectKind};
+use crate::routing::road_class;
+
+mod clip;
+mod simplify;
+
+/// What a tile's shapes get cut down to, in tile steps
+const CLIP_MIN: f64 = -BUFFER as f64;
+const CLIP_MAX: f64 = (EXTENT + BUFFER) as f64;
+
+/// Roads with less than this on a tile don't get their name on it, in tile steps
+const MIN_LABEL_LENGTH: f64 = EXTENT as f64 / 4.0;
+
+/// POI names make way for every road name
+const POI_LABEL_PRIORITY: u8 = u8::MAX;
+
+/// Zoomed out further, POI names would cover up the roads
+const POI_MIN_ZOOM: u8 = 16;
+
+/// Detail smaller than this gets simplified away, in tile steps.
+/// That's a pixel when the PSP draws tiles at 256 pixels.
+const SIMPLIFY_TOLERANCE: f64 = EXTENT as f64 / 256.0;
+
+/// Everything that goes into one tile, put in drawing order before encoding
+#[derive(Default)]
+struct TileFeatures {
+    areas: Vec<(AreaKind, Vec<(i32, i32)>)>,
+    lines: Vec<(RoadClass, Vec<(i32, i32)>)>,
+    /// Lower priority comes first, and gets the space when labels overlap on the PSP
+    labels: Vec<(u8, (i32, i32), String)>,
+}
+
+/// Every zoom level from `config.mapping.min_zoom` to `config.mapping.zoom`.
+/// Further out, small roads, buildings and POI names are left out and shapes get simpler.
+pub fn build_tiles(config: &Config, map: &Map) -> Vec<(TileKey, Vec<u8>)> {
+    let max_zoom = config.mapping.zoom;
+    (config.mapping.min_zoom.min(max_zoom)..=max_zoom)
+        .flat_map(|zoom| build_zoom(map, zoom))
+        .collect()
+}
+
+fn build_zoom(map: &Map, zoom: u8) -> Vec<(TileKey, Vec<u8>)> {
+    let mut tiles = BTreeMap::<(u32, u32), TileFeatures>::new();
+
+    for object in &map.objects {
+        let kind = area_kind(object.kind);
+        if zoom < area_min_zoom(kind) {
+            continue;
+        }
+        let points = simplified(&object.shape, zoom);
+        for (tile, origin) in covered_tiles(&points, zoom) {
+            let local = to_local(&points, origin);
+            let ring = quantize(&clip::clip_polygon(&local, CLIP_MIN, CLIP_MAX));
+            if ring.len() >= 3 {
+                tiles.entry(tile).or_default().areas.push((kind, ring));
+            }
+        }
+    }
+
+    for road in &map.roads {
+        // Same roads as the routing graph has
+        let Some(class) = road_class(&road.kind).filter(|&class| zoom >= road_min_zoom(class))
+        else {
+            continue;
+        };
+        let name = match road.name.is_empty() {
+            true => &road.road_ref,
+            false => &road.name,
+        };
+        let points = simplified(&road.points, zoom);
+        for (tile, origin) in covered_tiles(&points, zoom) {
+            let pieces = clip::clip_line(&to_local(&points, origin), CLIP_MIN, CLIP_MAX);
+            let lines = pieces
+                .iter()
+                .map(|piece| quantize(piece))
+                .filter(|line| line.len() >= 2)
+                .collect::<Vec<_>>();
+            if lines.is_empty() {
+                continue;
+            }
+            let features = tiles.entry(tile).or_default();
+            if !name.is_empty()
+                && let Some(position) = label_position(&pieces)
+            {
+                features.labels.push((class as u8, position, name.clone()));
+            }
+            features
+                .lines
+                .extend(lines.into_iter().map(|line| (class, line)));
+        }
+    }
+
+    let pois = match zoom >= POI_MIN_ZOOM {
+        true => &map.pois[..],
+        false => &[],
+    };
+    for poi in pois.iter().filter(|poi| !poi.name.is_empty()) {
+        let (x, y) = tile_position(poi.coord, zoom);
+        let tile = (x.floor() as u32, y.floor() as u32);
+        let position = (
+            ((x - x.floor()) * EXTENT as f64) as i32,
+            ((y - y.floor()) * EXTENT as f64) as i32,
+        );
+        let features = tiles.entry(tile).or_default();
+        features
+            .labels
+            .push((POI_LABEL_PRIORITY, position, poi.name.clone()));
+    }
+
+    tiles
+        .into_iter()
+        .map(|((x, y), mut features)| {
+            features.areas.sort_by_key(|(kind, _)| *kind);
+            // Small roads first, so the big ones end up on top
+            features.lines.sort_by_key(|(class, _)| Reverse(*class));
+            features.labels.sort_by_key(|(priority, ..)| *priority);
+
+            let mut builder = TileBuilder::new();
+            for (kind, ring) in &features.areas {
+                builder.area(*kind, ring);
+            }
+            for (class, line) in &features.lines {
+                builder.line(*class, line);
+            }
+            for (_, position, text) in &features.labels {
+                builder.label(*position, text);
+            }
+            (TileKey::new(zoom, x, y), builder.finish())
+        })
+        .collect()
+}
+
+fn area_kind(kind: ObjectKind) -> AreaKind {
+    match kind {
+        ObjectKind::Generic => AreaKind::Generic,
+        ObjectKind::Grass => AreaKind::Grass,
+        ObjectKind::Water => AreaKind::Water,
+        ObjectKind::Building => AreaKind::Building,
+    }
+}
+
+/// Lowest zoom level the roads of `class` go into, further out they'd only be clutter
+fn road_min_zoom(class: RoadClass) -> u8 {
+    match class {
+        RoadClass::Motorway
+        | RoadClass::Trunk
+        | RoadClass::Primary
+        | RoadClass::Secondary
+        | RoadClass::Tertiary => 0,
+        RoadClass::Unclassified | RoadClass::Residential | RoadClass::LivingStreet => 15,
+        RoadClass::Service | RoadClass::Track => 16,
+    }
+}
+
+/// Lowest zoom level the areas of `kind` go into
+fn area_min_zoom(kind: AreaKind) -> u8 {
+    match kind {
+        AreaKind::Generic | AreaKind::Grass | AreaKind::Water => 0,
+        AreaKind::Building => 16,
+    }
+}
+
+/// Into tile units at `zoom`, without the detail too small to see there
+fn simplified(points: &[Coord], zoom: u8) -> Vec<(f64, f64)> {
+    let points = points
+        .iter()
+        .map(|&p| tile_position(p, zoom))
+        .collect::<Vec<_>>();
+    simplify::simplify(&points, SIMPLIFY_TOLERANCE / EXTENT as f64)
+}
+
+/// Every tile the shape's bounding box reaches into, buffer included, with its top left corner
+fn covered_tiles(points: &[(f64, f64)], zoom: u8) -> Vec<((u32, u32), (f64, f64))> {
+    let buffer = BUFFER as f64 / EXTENT as f64;
+    let last = ((1u64 << zoom) - 1) as f64;
+    let (mut min, mut max) = ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN));
+    for &(x, y) in points {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    let range = |min: f64, max: f64| {
+        let first = (min - buffer).floor().clamp(0.0, last) as u32;
+        let last = (max + buffer).floor().clamp(0.0, last) as u32;
+        first..=last
+    };
+
+    let mut tiles = Vec::new();
+    for y in range(min.1, max.1) {
+        for x in range(min.0, max.0) {
+            tiles.push(((x, y), (x as f64, y as f64)));
+        }
+    }
+    tiles
+}
+
+/// From tile units into steps from `origin`
+fn to_local(points: &[(f64, f64)], origin: (f64, f64)) -> Vec<(f64, f64)> {
+    points
+        .iter()
+        .map(|&(x, y)| {
+            (
+                (x - origin.0) * EXTENT as f64,
+                (y - origin.1) * EXTENT as f64,
+            )
+        })
+        .collect()
+}
+
+/// Rounds to whole steps, points that end up on top of each other are dropped
+fn quantize(points: &[(f64, f64)]) -> Vec<(i32, i32)> {
+    let mut quantized: Vec<(i32, i32)> = Vec::with_capacity(points.len());
+    for &(x, y) in points {
+        let point = (x.round() as i32, y.round() as i32);
+        if quantized.last() != Some(&point) {
+            quantized.push(point);
+        }
+    }
+    quantized
+}
+
+/// Halfway along the longest piece, if that's inside the tile itself. Otherwise the
+/// neighbouring tile gets the label, or nobody if the road is too short here.
+fn label_position(pieces: &[Vec<(f64, f64)>]) -> Option<(i32, i32)> {
+    let length = |piece: &[(f64, f64)]| {
+        piece
+            .windows(2)
+            .map(|pair| (pair[1].0 - pair[0].0).hypot(pair[1].1 - pair[0].1))
+            .sum::<f64>()
+    };
+    let piece = pieces
+        .iter()
+        .max_by(|a, b| length(a).total_cmp(&length(b)))?;
+    let mut remaining = length(piece) / 2.0;
+    if remaining * 2.0 < MIN_LABEL_LENGTH {
+        return None;
+    }
+
+    for pair in piece.windows(2) {
+        let segment = (pair[1].0 - pair[0].0).hypot(pair[1].1 - pair[0].1);
+        if segment >= remaining {
+            let t = remaining / segment;
+            let x = pair[0].0 + t * (pair[1].0 - pair[0].0);
+            let y = pair[0].1 + t * (pair[1].1 - pair[0].1);
+            let inside = |v: f64| (0.0..EXTENT as f64).contains(&v);
+            return (inside(x) && inside(y)).then_some((x as i32, y as i32));
+        }
+        remaining -= segment;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use nav_core::vector_tile::{Feature, VectorTile};
+
+    use super::*;
+    use crate::reader::{Object, Poi, PoiCategory, Road};
+
+    #[test]
+    fn labels_halfway_along_the_longest_piece() {
+        assert_eq!(label_position(&[]), None);
+        // Too short to carry a name
+        assert_eq!(label_position(&[vec![(0.0, 100.0), (1000.0, 100.0)]]), None);
+        assert_eq!(
+            label_position(&[vec![(0.0, 100.0), (2000.0, 100.0)]]),
+            Some((1000, 100))
+        );
+        // Over more than one segment
+        assert_eq!(
+            label_position(&[vec![(0.0, 0.0), (600.0, 0.0), (600.0, 1400.0)]]),
+            Some((600, 400))
+        );
+        assert_eq!(
+            label_position(&[
+                vec![(0.0, 10.0), (1500.0, 10.0)],
+                vec![(0.0, 20.0), (3000.0, 20.0)],
+                vec![(0.0, 30.0), (100.0, 30.0)],
+            ]),
+            Some((1500, 20))
+        );
+        // Halfway is in the buffer, that's the neighbour's to label
+        assert_eq!(
+            label_position(&[vec![(-100.0, 0.0), (-100.0, 2000.0)]]),
+            None
+        );
+    }
+
+    #[test]
+    fn covers_the_tiles_the_buffer_reaches_into() {
+        let tiles = |points: &[(f64, f64)], zoom| {
+            covered_tiles(points, zoom)
+                .into_iter()
+                .map(|(tile, origin)| {
+                    assert_eq!(origin, (tile.0 as f64, tile.1 as f64));
+                    tile
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tiles(&[(10.5, 20.5)], 5), [(10, 20)]);
+        // Within the buffer of the tiles to the left and above
+        assert_eq!(tiles(&[(10.03, 20.5)], 5), [(9, 20), (10, 20)]);
+        assert_eq!(
+            tiles(&[(10.97, 20.97)], 5),
+            [(10, 20), (11, 20), (10, 21), (11, 21)]
+        );
+        assert_eq!(
+            tiles(&[(1.5, 1.5), (3.5, 1.2)], 3),
+            [(1, 1), (2, 1), (3, 1)]
+        );
+        // Nothing past the edges of the world
+        assert_eq!(tiles(&[(0.01, 0.01)], 1), [(0, 0)]);
+        assert_eq!(tiles(&[(1.99, 1.99)], 1), [(1, 1)]);
+    }
+
+    /// Features of every tile at `zoom`, as (line classes with their point counts, area kinds, labels)
+    fn features(map: &Map, zoom: u8) -> (Vec<(RoadClass, usize)>, Vec<AreaKind>, Vec<String>) {
+        let (mut lines, mut areas, mut labels) = (Vec::new(), Vec::new(), Vec::new());
+        for (key, data) in build_zoom(map, zoom) {
+            assert_eq!(key.zoom, zoom);
+            for feature in VectorTile::parse(&data).unwrap().features() {
+                match feature {
+                    Feature::Line { class, points } => lines.push((class, points.count())),
+                    Feature::Area { kind, .. } => areas.push(kind),
+                    Feature::Label { text, .. } => labels.push(text.to_string()),
+                }
+            }
+        }
+        (lines, areas, labels)
+    }
+
+    #[test]
+    fn leaves_out_small_things_further_out() {
+        let coord = |lat, lon| Coord { lat, lon };
+        // About a kilometer east, wiggling a meter either side every ten meters
+        let wiggly = (0..=100)
+            .map(|i| {
+                let wiggle = if i % 2 == 0 { 0.000_01 } else { -0.000_01 };
+                coord(52.5 + wiggle, 13.4 + i as f64 * 0.000_15)
+            })
+            .collect::<Vec<_>>();
+        let road = |kind: &str, points: Vec<Coord>| Road {
+            kind: kind.into(),
+            name: format!("{kind} road"),
+            points,
+            ..Default::default()
+        };
+        let map = Map {
+            roads: vec![
+                road("primary", wiggly),
+                road("residential", vec![coord(52.5, 13.4), coord(52.51, 13.4)]),
+            ],
+            objects: vec![Object {
+                kind: ObjectKind::Building,
+                shape: vec![
+                    coord(52.501, 13.401),
+                    coord(52.501, 13.4012),
+                    coord(52.5012, 13.4012),
+                ],
+            }],
+            pois: vec![Poi {
+                category: PoiCategory::Cafe,
+                name: "Cafe".into(),
+                coord: coord(52.502, 13.402),
+            }],
+            addresses: Vec::new(),
+            restrictions: Vec::new(),
+            extent: (coord(52.5, 13.4), coord(52.51, 13.415)),
+        };
+
+        let (lines, areas, labels) = features(&map, 14);
+        assert!(!lines.is_empty());
+        // Straightened out, it doesn't wiggle by a pixel this far out
+        assert!(
+            lines
+                .iter()
+                .all(|&(class, points)| class == RoadClass::Primary && points == 2),
+            "{lines:?}"
+        );
+        assert!(areas.is_empty());
+        assert!(
+            labels.iter().all(|label| label == "primary road"),
+            "{labels:?}"
+        );
+
+        let (lines, areas, labels) = features(&map, 16);
+        assert!(
+            lines
+                .iter()
+                .any(|&(class, _)| class == RoadClass::Residential)
+        );
+        assert_eq!(areas, [AreaKind::Building]);
+        assert!(labels.contains(&"residential road".to_string()));
+        assert!(labels.contains(&"Cafe".to_string()));
+
+        // Every wiggle is there up close
+        let (lines, ..) = features(&map, 19);
+        let primary = lines
+            .iter()
+            .filter(|(class, _)| *class == RoadClass::Primary)
+            .map(|(_, points)| points)
+            .sum::<usize>();
+        assert!(primary > 100, "{primary}");
+    }
+}
@@ -0,0 +1,92 @@
+//! Leaves out points that barely change a shape, Douglas-Peucker
+
+type Point = (f64, f64);
+
+/// Drops every point that is less than `tolerance` off the line that remains.
+/// The first and the last point always stay.
+pub(super) fn simplify(points: &[Point], tolerance: f64) -> Vec<Point> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    // Ranges still to look at. No recursion, long roads and coastlines have thousands of points
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        let (mut farthest, mut max_distance) = (first, 0.0);
+        for i in first + 1..last {
+            let distance = distance_to_segment(points[i], points[first], points[last]);
+            if distance > max_distance {
+                (farthest, max_distance) = (i, distance);
+            }
+        }
+        if max_distance > tolerance {
+            keep[farthest] = true;
+            ranges.push((first, farthest));
+            ranges.push((farthest, last));
+        }
+    }
+
+    points
+        .iter()
+        .zip(keep)
+        .filter_map(|(&point, keep)| keep.then_some(point))
+        .collect()
+}
+
+fn distance_to_segment(p: Point, a: Point, b: Point) -> f64 {
+    let d = (b.0 - a.0, b.1 - a.1);
+    let len_sq = d.0 * d.0 + d.1 * d.1;
+    let t = if len_sq > 0.0 {
+        (((p.0 - a.0) * d.0 + (p.1 - a.1) * d.1) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (a.0 + t * d.0 - p.0).hypot(a.1 + t * d.1 - p.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_what_is_within_the_tolerance() {
+        let straight = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (5.0, 5.0)];
+        assert_eq!(simplify(&straight, 0.1), [(0.0, 0.0), (5.0, 5.0)]);
+
+        let bumpy = [(0.0, 0.0), (1.0, 0.4), (2.0, 0.0), (3.0, 5.0), (4.0, 0.0)];
+        assert_eq!(
+            simplify(&bumpy, 1.0),
+            [(0.0, 0.0), (2.0, 0.0), (3.0, 5.0), (4.0, 0.0)]
+        );
+        assert_eq!(simplify(&bumpy, 0.1), bumpy);
+        assert_eq!(simplify(&bumpy, 10.0), [(0.0, 0.0), (4.0, 0.0)]);
+    }
+
+    #[test]
+    fn rings_keep_their_corners() {
+        let ring = [
+            (0.0, 0.0),
+            (5.0, 0.0),
+            (10.0, 0.0),
+            (10.0, 10.0),
+            (0.0, 10.0),
+        ];
+        assert_eq!(
+            simplify(&ring, 1.0),
+            [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]
+        );
+    }
+
+    #[test]
+    fn short_lines_stay() {
+        assert!(simplify(&[], 1.0).is_empty());
+        assert_eq!(simplify(&[(1.0, 2.0)], 1.0), [(1.0, 2.0)]);
+        assert_eq!(
+            simplify(&[(1.0, 2.0), (1.1, 2.0)], 1.0),
+            [(1.0, 2.0), (1.1, 2.0)]
+        );
+    }
+}
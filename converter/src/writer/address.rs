@@ -0,0 +1,263 @@
+//! Address index layout (all little-endian):
+//!
+//! | Size                | Content                                              |
+//! |---------------------|------------------------------------------------------|
+//! | 4                   | Magic `PADR`                                         |
+//! | 2                   | Version                                              |
+//! | 2                   | Reserved                                             |
+//! | 4                   | Street count                                         |
+//! | 4                   | Address count                                        |
+//! | 4                   | Size of the string table in bytes                    |
+//! | 20 * street count   | Streets, see [`ADDRESS_STREET_SIZE`]                 |
+//! | 24 * address count  | Addresses, see [`ADDRESS_RECORD_SIZE`]               |
+//! | string table size   | Deduplicated UTF-8 strings                           |
+//!
+//! Streets are sorted by their search key (the lowercased name), so a prefix search
+//! is a binary search for the first key that starts with the typed text.
+//! Every street points at a contiguous run of addresses, sorted by house number.
+
+use crate::config::Config;
+use crate::reader::Address;
+
+use super::{StringTable, degrees_to_fixed, write_region_file};
+
+pub const ADDRESS_MAGIC: [u8; 4] = *b"PADR";
+pub const ADDRESS_VERSION: u16 = 1;
+/// key offset (u32), name offset (u32), key length (u16), name length (u16),
+/// first address (u32), address count (u32)
+pub const ADDRESS_STREET_SIZE: usize = 20;
+/// lat (i32), lon (i32), housenumber offset (u32), postcode offset (u32), city offset (u32),
+/// housenumber length (u8), postcode length (u8), city length (u8), reserved (u8)
+pub const ADDRESS_RECORD_SIZE: usize = 24;
+
+pub fn write_address_index(config: &Config, addresses: &[Address]) {
+    let data = encode_address_index(addresses);
+    write_region_file(config, "addresses.bin", &data);
+}
+
+/// Search key for a street, this is what the typed text gets compared against
+pub fn street_key(street: &str) -> String {
+    street.trim().to_lowercase()
+}
+
+/// Sorts "2" before "10", and "10" before "10a"
+fn housenumber_order(housenumber: &str) -> (u32, &str) {
+    let digits = housenumber
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(housenumber.len());
+    let number = housenumber[..digits].parse().unwrap_or(u32::MAX);
+    (number, &housenumber[digits..])
+}
+
+pub fn encode_address_index(addresses: &[Address]) -> Vec<u8> {
+    let mut sorted = addresses
+        .iter()
+        .map(|address| (street_key(&address.tags.street), address))
+        .collect::<Vec<_>>();
+    sorted.sort_by(|(key_a, a), (key_b, b)| {
+        key_a
+            .cmp(key_b)
+            .then_with(|| {
+                housenumber_order(&a.tags.housenumber).cmp(&housenumber_order(&b.tags.housenumber))
+            })
+            .then_with(|| a.tags.city.cmp(&b.tags.city))
+    });
+    // The same building often gets tagged on both a node and its way
+    sorted.dedup_by(|(key_a, a), (key_b, b)| {
+        key_a == key_b
+            && a.tags.housenumber == b.tags.housenumber
+            && a.tags.city == b.tags.city
+            && a.coord.distance_to(b.coord) < 50.0
+    });
+
+    let mut strings = StringTable::default();
+    let mut streets = Vec::new();
+    let mut records = Vec::with_capacity(sorted.len() * ADDRESS_RECORD_SIZE);
+
+    let mut street_count = 0u32;
+    let mut i = 0;
+    while i < sorted.len() {
+        let key = &sorted[i].0;
+        let first = i;
+        while i < sorted.len() && sorted[i].0 == *key {
+            let address = sorted[i].1;
+            let (housenumber_offset, housenumber_len) =
+                strings.insert(&address.tags.housenumber, u8::MAX as usize);
+            let (postcode_offset, postcode_len) =
+                strings.insert(&address.tags.postcode, u8::MAX as usize);
+            let (city_offset, city_len) = strings.insert(&address.tags.city, u8::MAX as usize);
+
+            records.extend_from_slice(&degrees_to_fixed(address.coord.lat).to_le_bytes());
+            records.extend_from_slice(&degrees_to_fixed(address.coord.lon).to_le_bytes());
+            records.extend_from_slice(&housenumber_offset.to_le_bytes());
+            records.extend_from_slice(&postcode_offset.to_le_bytes());
+            records.extend_from_slice(&city_offset.to_le_bytes());
+            records.push(housenumber_len as u8);
+            records.push(postcode_len as u8);
+            records.push(city_len as u8);
+            records.push(0);
+            i += 1;
+        }
+
+        // Display the name as it was first tagged, search on the key
+        let (key_offset, key_len) = strings.insert(key, u16::MAX as usize);
+        let (name_offset, name_len) =
+            strings.insert(&sorted[first].1.tags.street, u16::MAX as usize);
+        streets.extend_from_slice(&key_offset.to_le_bytes());
+        streets.extend_from_slice(&name_offset.to_le_bytes());
+        streets.extend_from_slice(&(key_len as u16).to_le_bytes());
+        streets.extend_from_slice(&(name_len as u16).to_le_bytes());
+        streets.extend_from_slice(&(first as u32).to_le_bytes());
+        streets.extend_from_slice(&((i - first) as u32).to_le_bytes());
+        street_count += 1;
+    }
+
+    let mut data = Vec::with_capacity(20 + streets.len() + records.len() + strings.data.len());
+    data.extend_from_slice(&ADDRESS_MAGIC);
+    data.extend_from_slice(&ADDRESS_VERSION.to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes());
+    data.extend_from_slice(&street_count.to_le_bytes());
+    data.extend_from_slice(&(sorted.len() as u32).to_le_bytes());
+    data.extend_from_slice(&(strings.data.len() as u32).to_le_bytes());
+    data.extend_from_slice(&streets);
+    data.extend_from_slice(&records);
+    data.extend_from_slice(&strings.data);
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::{AddressTags, Coord};
+
+    fn address(street: &str, housenumber: &str, city: &str, lat: f64, lon: f64) -> Address {
+        Address {
+            tags: AddressTags {
+                street: street.to_string(),
+                housenumber: housenumber.to_string(),
+                postcode: String::from("10117"),
+                city: city.to_string(),
+            },
+            coord: Coord { lat, lon },
+        }
+    }
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(data[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    /// House number, city, lat
+    type DecodedAddress = (String, String, i32);
+
+    /// (key, name, addresses) per street, the way the PSP reads them
+    fn decode(data: &[u8]) -> Vec<(String, String, Vec<DecodedAddress>)> {
+        assert_eq!(data[0..4], ADDRESS_MAGIC);
+        assert_eq!(u16_at(data, 4), ADDRESS_VERSION);
+        let (street_count, address_count) = (u32_at(data, 8) as usize, u32_at(data, 12) as usize);
+        let records = 20 + street_count * ADDRESS_STREET_SIZE;
+        let strings = records + address_count * ADDRESS_RECORD_SIZE;
+        assert_eq!(data.len(), strings + u32_at(data, 16) as usize);
+        let string = |offset: u32, length: usize| {
+            let bytes = &data[strings + offset as usize..][..length];
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        (0..street_count)
+            .map(|s| {
+                let at = 20 + s * ADDRESS_STREET_SIZE;
+                let key = string(u32_at(data, at), u16_at(data, at + 8) as usize);
+                let name = string(u32_at(data, at + 4), u16_at(data, at + 10) as usize);
+                let (first, count) = (u32_at(data, at + 12) as usize, u32_at(data, at + 16));
+                let addresses = (first..first + count as usize)
+                    .map(|a| {
+                        let at = records + a * ADDRESS_RECORD_SIZE;
+                        (
+                            string(u32_at(data, at + 8), data[at + 20] as usize),
+                            string(u32_at(data, at + 16), data[at + 22] as usize),
+                            u32_at(data, at) as i32,
+                        )
+                    })
+                    .collect();
+                (key, name, addresses)
+            })
+            .collect()
+    }
+
+    fn numbers(addresses: &[DecodedAddress]) -> Vec<&str> {
+        addresses
+            .iter()
+            .map(|(number, ..)| number.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn streets_sorted_by_key_and_houses_by_number() {
+        let data = encode_address_index(&[
+            address("Unter den Linden", "10a", "Berlin", 52.517, 13.389),
+            address("Friedrichstraße", "2", "Berlin", 52.51, 13.39),
+            address("unter den linden ", "2", "Berlin", 52.516, 13.396),
+            address("Unter den Linden", "10", "Berlin", 52.517, 13.39),
+            address("Unter den Linden", "4-6", "Berlin", 52.5165, 13.394),
+            address("Friedrichstraße", "", "Berlin", 52.52, 13.39),
+        ]);
+        let streets = decode(&data);
+        assert_eq!(streets.len(), 2);
+
+        let (key, name, addresses) = &streets[0];
+        assert_eq!(
+            (key.as_str(), name.as_str()),
+            ("friedrichstraße", "Friedrichstraße")
+        );
+        // No number at all goes last
+        assert_eq!(numbers(addresses), ["2", ""]);
+
+        let (key, name, addresses) = &streets[1];
+        assert_eq!(key, "unter den linden");
+        // As the first one in sorted order was tagged
+        assert_eq!(name, "unter den linden ");
+        assert_eq!(numbers(addresses), ["2", "4-6", "10", "10a"]);
+    }
+
+    #[test]
+    fn a_node_and_its_building_count_once() {
+        let data = encode_address_index(&[
+            address("Hauptstraße", "1", "Berlin", 52.5, 13.4),
+            // The building's center, 20 m away from the entrance node
+            address("Hauptstraße", "1", "Berlin", 52.5, 13.4003),
+            address("Hauptstraße", "3", "Berlin", 52.5, 13.401),
+        ]);
+        let streets = decode(&data);
+        assert_eq!(u32_at(&data, 12), 2);
+        assert_eq!(numbers(&streets[0].2), ["1", "3"]);
+        assert_eq!(streets[0].2[0].2, 525_000_000);
+    }
+
+    #[test]
+    fn same_numbers_far_apart_are_kept() {
+        let data = encode_address_index(&[
+            // Same street and number, without a city to tell the villages apart
+            address("Dorfstraße", "1", "", 52.5, 13.4),
+            address("Dorfstraße", "1", "", 52.5, 13.401),
+            // Or in different cities right next to each other
+            address("Grenzweg", "7", "Berlin", 52.4, 13.2),
+            address("Grenzweg", "7", "Potsdam", 52.4, 13.2),
+        ]);
+        let streets = decode(&data);
+        assert_eq!(u32_at(&data, 12), 4);
+        assert_eq!(numbers(&streets[0].2), ["1", "1"]);
+        let cities = streets[1].2.iter().map(|(_, city, _)| city.as_str());
+        assert_eq!(cities.collect::<Vec<_>>(), ["Berlin", "Potsdam"]);
+    }
+
+    #[test]
+    fn house_number_order() {
+        let mut numbers = ["10a", "", "2", "10", "1b", "1"];
+        numbers.sort_by_key(|number| housenumber_order(number));
+        assert_eq!(numbers, ["1", "1b", "2", "10", "10a", ""]);
+    }
+}
@@ -0,0 +1,108 @@
+//! See [`nav_core::graph`] for the layout
+
+use nav_core::{container, graph::*};
+
+use crate::config::Config;
+use crate::routing::{ContractionHierarchy, RoutingGraph, upward_edges};
+
+use super::{StringTable, degrees_to_fixed, write_region_file};
+
+/// Takes the graph already encoded, so it can be checked before it's written
+pub fn write_routing_graph(config: &Config, data: &[u8]) {
+    write_region_file(config, "graph.bin", data);
+}
+
+pub fn encode_routing_graph(
+    graph: &RoutingGraph,
+    hierarchy: Option<&ContractionHierarchy>,
+) -> Vec<u8> {
+    let mut nodes = Vec::with_capacity(graph.nodes.len() * NODE_SIZE);
+    for node in &graph.nodes {
+        nodes.extend_from_slice(&degrees_to_fixed(node.coord.lat).to_le_bytes());
+        nodes.extend_from_slice(&degrees_to_fixed(node.coord.lon).to_le_bytes());
+    }
+
+    // Edges are sorted by source, so every node's edges are one contiguous run
+    let mut adjacency = Vec::with_capacity((graph.nodes.len() + 1) * 4);
+    let mut edge_index = 0;
+    for n in 0..=graph.nodes.len() {
+        while edge_index < graph.edges.len() && (graph.edges[edge_index].source as usize) < n {
+            edge_index += 1;
+        }
+        adjacency.extend_from_slice(&(edge_index as u32).to_le_bytes());
+    }
+
+    let mut edges = Vec::with_capacity(graph.edges.len() * EDGE_SIZE);
+    for edge in &graph.edges {
+        let mut flags = 0;
+        if edge.reversed {
+            flags |= EDGE_FLAG_REVERSED;
+        }
+        if edge.roundabout {
+            flags |= EDGE_FLAG_ROUNDABOUT;
+        }
+        edges.extend_from_slice(&edge.target.to_le_bytes());
+        edges.extend_from_slice(&edge.length_dm().to_le_bytes());
+        edges.extend_from_slice(&edge.segment.to_le_bytes());
+        edges.push(edge.speed_kmh);
+        edges.push(edge.class as u8);
+        edges.push(flags);
+        edges.push(0);
+    }
+
+    let mut segments = Vec::with_capacity((graph.segments.len() + 1) * 4);
+    let mut points = Vec::new();
+    let mut names = Vec::with_capacity(graph.segments.len() * SEGMENT_NAME_SIZE);
+    let mut strings = StringTable::default();
+    for segment in &graph.segments {
+        segments.extend_from_slice(&((points.len() / POINT_SIZE) as u32).to_le_bytes());
+
+        let (name_offset, name_len) = strings.insert(&segment.name, u16::MAX as usize);
+        let (ref_offset, ref_len) = strings.insert(&segment.road_ref, u16::MAX as usize);
+        names.extend_from_slice(&name_offset.to_le_bytes());
+        names.extend_from_slice(&ref_offset.to_le_bytes());
+        names.extend_from_slice(&(name_len as u16).to_le_bytes());
+        names.extend_from_slice(&(ref_len as u16).to_le_bytes());
+
+        for p in &segment.points {
+            points.extend_from_slice(&degrees_to_fixed(p.lat).to_le_bytes());
+            points.extend_from_slice(&degrees_to_fixed(p.lon).to_le_bytes());
+        }
+    }
+    segments.extend_from_slice(&((points.len() / POINT_SIZE) as u32).to_le_bytes());
+
+    let mut sections: Vec<([u8; 4], Vec<u8>)> = vec![
+        (SECTION_NODES, nodes),
+        (SECTION_ADJACENCY, adjacency),
+        (SECTION_EDGES, edges),
+        (SECTION_SEGMENTS, segments),
+        (SECTION_SEGMENT_POINTS, points),
+        (SECTION_SEGMENT_NAMES, names),
+        (SECTION_STRINGS, strings.data),
+    ];
+
+    if let Some(hierarchy) = hierarchy {
+        let ranks = hierarchy
+            .ranks
+            .iter()
+            .flat_map(|rank| rank.to_le_bytes())
+            .collect();
+        let (first, records) = upward_edges(hierarchy, graph.nodes.len());
+        let ch_adjacency = first.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let ch_edges = records
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+
+        sections.push((SECTION_RANKS, ranks));
+        sections.push((SECTION_CH_ADJACENCY, ch_adjacency));
+        sections.push((SECTION_CH_EDGES, ch_edges));
+    }
+
+    let sections = sections
+        .iter()
+        .map(|(tag, data)| (*tag, data.as_slice()))
+        .collect::<Vec<_>>();
+    container::build(MAGIC, VERSION, &sections)
+}
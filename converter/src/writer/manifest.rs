@@ -0,0 +1,55 @@
+use std::fs;
+use std::io::Read;
+
+use nav_core::manifest::{self, FILE_NAME, Manifest, ManifestFile, crc32};
+use nav_core::tile_pack::{HEADER_SIZE, PackHeader};
+
+use crate::config::Config;
+use crate::reader::Coord;
+
+use super::write_region_file;
+
+/// Describes everything in the region folder, has to come after all other files are written
+pub fn write_manifest(config: &Config, extent: (Coord, Coord), source_timestamp: Option<i64>) {
+    let folder = config.output.region_folder();
+    let mut files = fs::read_dir(&folder)
+        .expect("Failed to list the region folder!")
+        .map(|entry| entry.expect("Failed to list the region folder!"))
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name != FILE_NAME)
+        .map(|name| {
+            let data = fs::read(folder.join(&name)).expect("Failed to read back a region file!");
+            ManifestFile {
+                name,
+                size: data.len() as u64,
+                crc32: crc32(&data),
+            }
+        })
+        .collect::<Vec<_>>();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+
+    // The zoom levels and format go by whatever ended up in the pack
+    let mut header = [0; HEADER_SIZE];
+    let header = fs::File::open(folder.join("tiles.pack"))
+        .and_then(|mut file| file.read_exact(&mut header))
+        .ok()
+        .and_then(|()| PackHeader::parse(&header).ok());
+    let (min_zoom, max_zoom) = header
+        .map(|header| (header.min_zoom, header.max_zoom))
+        .unwrap_or((config.mapping.zoom, config.mapping.zoom));
+    let format = header.map_or(1, |header| manifest::format_for(header.content));
+
+    let manifest = Manifest {
+        format,
+        region: config.output.region.clone(),
+        extent: (extent.0.into(), extent.1.into()),
+        min_zoom,
+        max_zoom,
+        source_timestamp,
+        converter_version: String::from(env!("CARGO_PKG_VERSION")),
+        style: config.style.name.clone(),
+        files,
+    };
+    write_region_file(config, FILE_NAME, manifest.to_text().as_bytes());
+}
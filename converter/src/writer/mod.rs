@@ -0,0 +1,63 @@
+//! Writes the converted data out in the binary formats the PSP reads.
+//! Everything is little-endian, which is what the PSP's Allegrex runs in.
+
+use std::{collections::HashMap, fs};
+
+use crate::config::Config;
+
+mod address;
+pub use address::*;
+
+mod graph;
+pub use graph::*;
+
+mod manifest;
+pub use manifest::*;
+
+mod poi;
+pub use poi::*;
+
+mod tiles;
+pub use tiles::*;
+
+/// Makes sure the region folder exists and writes `data` to `name` inside it
+fn write_region_file(config: &Config, name: &str, data: &[u8]) {
+    let folder = config.output.region_folder();
+    fs::create_dir_all(&folder).expect("Failed to create the region folder!");
+
+    let path = folder.join(name);
+    fs::write(&path, data).expect("Failed to write region file!");
+    println!("Wrote {} ({} bytes)", path.display(), data.len());
+}
+
+/// Streets, house numbers and especially cities repeat a lot, so only store each string once
+#[derive(Default)]
+pub(super) struct StringTable {
+    pub data: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl StringTable {
+    /// Returns (offset, length in bytes)
+    pub fn insert(&mut self, s: &str, max_len: usize) -> (u32, usize) {
+        let s = truncate_utf8(s, max_len);
+        if let Some(offset) = self.offsets.get(s) {
+            return (*offset, s.len());
+        }
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(s.as_bytes());
+        self.offsets.insert(s.to_string(), offset);
+        (offset, s.len())
+    }
+}
+
+fn truncate_utf8(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
@@ -0,0 +1,199 @@
+//! POI file layout (all little-endian):
+//!
+//! | Size                | Content                                              |
+//! |---------------------|------------------------------------------------------|
+//! | 4                   | Magic `PPOI`                                         |
+//! | 2                   | Version                                              |
+//! | 2                   | Category count                                       |
+//! | 4                   | POI count                                            |
+//! | 4                   | Size of the name table in bytes                      |
+//! | 8 * category count  | Per category: index of its first POI, POI count      |
+//! | 16 * POI count      | Records, see [`POI_RECORD_SIZE`]                     |
+//! | name table size     | UTF-8 names, referenced by offset + length           |
+//!
+//! Records are grouped by category and sorted by latitude within a category,
+//! so finding the nearest POI of one kind only touches that category's records
+//! and can start from a binary search on latitude.
+
+use crate::config::Config;
+use crate::reader::{Poi, PoiCategory};
+
+use super::{truncate_utf8, write_region_file};
+
+pub const POI_MAGIC: [u8; 4] = *b"PPOI";
+pub const POI_VERSION: u16 = 1;
+/// lat (i32), lon (i32), name offset (u32), name length (u16), category (u8), reserved (u8)
+pub const POI_RECORD_SIZE: usize = 16;
+
+/// Degrees are stored as fixed point with 7 decimals, same precision as OSM itself
+pub fn degrees_to_fixed(deg: f64) -> i32 {
+    (deg * 1e7).round() as i32
+}
+
+pub fn write_pois(config: &Config, pois: &[Poi]) {
+    let data = encode_pois(pois);
+    write_region_file(config, "pois.bin", &data);
+}
+
+pub fn encode_pois(pois: &[Poi]) -> Vec<u8> {
+    let mut sorted = pois.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| {
+        a.category
+            .cmp(&b.category)
+            .then(a.coord.lat.total_cmp(&b.coord.lat))
+    });
+
+    let mut categories = [(0u32, 0u32); PoiCategory::COUNT];
+    for (i, poi) in sorted.iter().enumerate() {
+        let entry = &mut categories[poi.category as usize];
+        if entry.1 == 0 {
+            entry.0 = i as u32;
+        }
+        entry.1 += 1;
+    }
+
+    let mut names = Vec::new();
+    let mut records = Vec::with_capacity(sorted.len() * POI_RECORD_SIZE);
+    for poi in &sorted {
+        // Names longer than this are nonsense anyway
+        let name = truncate_utf8(&poi.name, u16::MAX as usize);
+        let name_offset = names.len() as u32;
+        names.extend_from_slice(name.as_bytes());
+
+        records.extend_from_slice(&degrees_to_fixed(poi.coord.lat).to_le_bytes());
+        records.extend_from_slice(&degrees_to_fixed(poi.coord.lon).to_le_bytes());
+        records.extend_from_slice(&name_offset.to_le_bytes());
+        records.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        records.push(poi.category as u8);
+        records.push(0);
+    }
+
+    let mut data = Vec::with_capacity(16 + categories.len() * 8 + records.len() + names.len());
+    data.extend_from_slice(&POI_MAGIC);
+    data.extend_from_slice(&POI_VERSION.to_le_bytes());
+    data.extend_from_slice(&(PoiCategory::COUNT as u16).to_le_bytes());
+    data.extend_from_slice(&(sorted.len() as u32).to_le_bytes());
+    data.extend_from_slice(&(names.len() as u32).to_le_bytes());
+    for (first, count) in categories {
+        data.extend_from_slice(&first.to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+    }
+    data.extend_from_slice(&records);
+    data.extend_from_slice(&names);
+
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::Coord;
+
+    fn poi(category: PoiCategory, lat: f64, lon: f64, name: &str) -> Poi {
+        Poi {
+            category,
+            name: name.to_string(),
+            coord: Coord { lat, lon },
+        }
+    }
+
+    fn u16_at(data: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes(data[at..at + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    fn i32_at(data: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn reads_back_what_the_psp_gets() {
+        // "é" is two bytes and would end one byte past the limit
+        let long_name = format!("{}é", "a".repeat(u16::MAX as usize - 1));
+        let pois = [
+            poi(PoiCategory::Cafe, 52.52, 13.405, "Café Kranzler"),
+            poi(PoiCategory::Fuel, 52.5, 13.4, "Aral"),
+            poi(PoiCategory::Cafe, 52.51, 13.39, ""),
+            poi(PoiCategory::Fuel, -33.868_812_3, 151.209_3, &long_name),
+        ];
+        let data = encode_pois(&pois);
+
+        assert_eq!(data[0..4], POI_MAGIC);
+        assert_eq!(u16_at(&data, 4), POI_VERSION);
+        assert_eq!(u16_at(&data, 6) as usize, PoiCategory::COUNT);
+        assert_eq!(u32_at(&data, 8), 4);
+        let names_size = u32_at(&data, 12) as usize;
+
+        let category = |category: PoiCategory| {
+            let at = 16 + category as usize * 8;
+            (u32_at(&data, at), u32_at(&data, at + 4))
+        };
+        assert_eq!(category(PoiCategory::Fuel), (0, 2));
+        assert_eq!(category(PoiCategory::Cafe), (2, 2));
+        assert_eq!(category(PoiCategory::Parking).1, 0);
+
+        let records = 16 + PoiCategory::COUNT * 8;
+        let names = records + 4 * POI_RECORD_SIZE;
+        assert_eq!(data.len(), names + names_size);
+        let record = |i: usize| {
+            let at = records + i * POI_RECORD_SIZE;
+            let (offset, length) = (u32_at(&data, at + 8) as usize, u16_at(&data, at + 12));
+            let name = std::str::from_utf8(&data[names + offset..][..length as usize]).unwrap();
+            (
+                data[at + 14],
+                i32_at(&data, at),
+                i32_at(&data, at + 4),
+                name.to_string(),
+            )
+        };
+
+        // By category, then south to north
+        let truncated = "a".repeat(u16::MAX as usize - 1);
+        assert_eq!(
+            record(0),
+            (
+                PoiCategory::Fuel as u8,
+                -338_688_123,
+                1_512_093_000,
+                truncated
+            )
+        );
+        assert_eq!(
+            record(1),
+            (
+                PoiCategory::Fuel as u8,
+                525_000_000,
+                134_000_000,
+                "Aral".to_string()
+            )
+        );
+        assert_eq!(
+            record(2),
+            (
+                PoiCategory::Cafe as u8,
+                525_100_000,
+                133_900_000,
+                String::new()
+            )
+        );
+        assert_eq!(
+            record(3),
+            (
+                PoiCategory::Cafe as u8,
+                525_200_000,
+                134_050_000,
+                "Café Kranzler".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn no_pois() {
+        let data = encode_pois(&[]);
+        assert_eq!(u32_at(&data, 8), 0);
+        assert_eq!(data.len(), 16 + PoiCategory::COUNT * 8);
+    }
+}
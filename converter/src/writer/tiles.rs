@@ -0,0 +1,23 @@
+//! Packs the rendered or cut tiles into one file, the layout is described in `nav_core::tile_pack`
+
+use nav_core::tile::TileKey;
+use nav_core::tile_pack::{self, TileContent};
+
+use crate::config::{Config, TileFormat};
+use crate::reader::Coord;
+
+use super::write_region_file;
+
+pub fn write_tile_pack(config: &Config, extent: (Coord, Coord), tiles: &[(TileKey, Vec<u8>)]) {
+    let tiles = tiles
+        .iter()
+        .map(|(key, data)| (*key, data.as_slice()))
+        .collect::<Vec<_>>();
+    let extent = (extent.0.into(), extent.1.into());
+    let content = match config.mapping.format {
+        TileFormat::Raster => TileContent::Qoi,
+        TileFormat::Vector => TileContent::Vector,
+    };
+    let data = tile_pack::build(config.mapping.tile_res as u16, content, extent, &tiles);
+    write_region_file(config, "tiles.pack", &data);
+}
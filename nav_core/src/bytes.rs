@@ -0,0 +1,23 @@
+//! Little-endian helpers for reading our file formats straight from a byte slice.
+//! Callers are expected to have checked the bounds already.
+
+pub(crate) fn read_u8(data: &[u8], offset: usize) -> u8 {
+    data[offset]
+}
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+pub(crate) fn read_i32(data: &[u8], offset: usize) -> i32 {
+    read_u32(data, offset) as i32
+}
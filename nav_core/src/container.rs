@@ -0,0 +1,124 @@
+//! A tiny sectioned container that our binary formats are built on:
+//!
+//! | Size               | Content                                        |
+//! |--------------------|------------------------------------------------|
+//! | 4                  | Magic, identifies the format                   |
+//! | 2                  | Format version                                 |
+//! | 2                  | Section count                                  |
+//! | 12 * section count | Per section: tag (4 bytes), offset, length     |
+//! | ...                | Section data, each section starts 4-aligned    |
+//!
+//! Sections are looked up by tag, so new sections can be added without breaking
+//! readers that don't know about them.
+
+use alloc::vec::Vec;
+
+use crate::bytes::*;
+
+const HEADER_SIZE: usize = 8;
+const SECTION_ENTRY_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerError {
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The section table points outside of the file
+    BadSectionTable,
+}
+
+pub struct Container<'a> {
+    data: &'a [u8],
+    version: u16,
+    section_count: usize,
+}
+
+impl<'a> Container<'a> {
+    /// Checks the header and section table, accepting versions `min_version..=max_version`
+    pub fn parse(
+        data: &'a [u8],
+        magic: [u8; 4],
+        min_version: u16,
+        max_version: u16,
+    ) -> Result<Self, ContainerError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ContainerError::TooShort);
+        }
+        if data[0..4] != magic {
+            return Err(ContainerError::BadMagic);
+        }
+        let version = read_u16(data, 4);
+        if version < min_version || version > max_version {
+            return Err(ContainerError::UnsupportedVersion(version));
+        }
+        let section_count = read_u16(data, 6) as usize;
+        if data.len() < HEADER_SIZE + section_count * SECTION_ENTRY_SIZE {
+            return Err(ContainerError::TooShort);
+        }
+
+        let container = Self {
+            data,
+            version,
+            section_count,
+        };
+        for i in 0..section_count {
+            let (offset, len) = container.section_range(i);
+            if offset.checked_add(len).is_none_or(|end| end > data.len()) {
+                return Err(ContainerError::BadSectionTable);
+            }
+        }
+
+        Ok(container)
+    }
+
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    pub fn section(&self, tag: [u8; 4]) -> Option<&'a [u8]> {
+        (0..self.section_count)
+            .find(|i| {
+                let entry = HEADER_SIZE + i * SECTION_ENTRY_SIZE;
+                self.data[entry..entry + 4] == tag
+            })
+            .map(|i| {
+                let (offset, len) = self.section_range(i);
+                &self.data[offset..offset + len]
+            })
+    }
+
+    fn section_range(&self, i: usize) -> (usize, usize) {
+        let entry = HEADER_SIZE + i * SECTION_ENTRY_SIZE;
+        (
+            read_u32(self.data, entry + 4) as usize,
+            read_u32(self.data, entry + 8) as usize,
+        )
+    }
+}
+
+/// Builds a container from `(tag, data)` pairs, used by the converter
+pub fn build(magic: [u8; 4], version: u16, sections: &[([u8; 4], &[u8])]) -> Vec<u8> {
+    let table_end = HEADER_SIZE + sections.len() * SECTION_ENTRY_SIZE;
+    let total = table_end + sections.iter().map(|(_, s)| s.len() + 3).sum::<usize>();
+
+    let mut data = Vec::with_capacity(total);
+    data.extend_from_slice(&magic);
+    data.extend_from_slice(&version.to_le_bytes());
+    data.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+
+    let mut offset = table_end;
+    for (tag, section) in sections {
+        offset = offset.next_multiple_of(4);
+        data.extend_from_slice(tag);
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+        offset += section.len();
+    }
+
+    for (_, section) in sections {
+        data.resize(data.len().next_multiple_of(4), 0);
+        data.extend_from_slice(section);
+    }
+
+    data
+}
@@ -0,0 +1,128 @@
+//! Coordinates in plain degrees, for when precision matters more than size. The converter works
+//! with these, the graph and the PSP mostly with [`FixedCoord`].
+
+use core::f64::consts::PI;
+
+use crate::graph::FixedCoord;
+
+/// Mean earth radius in meters
+const EARTH_RADIUS: f64 = 6371e3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Coord {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Coord {
+    pub fn min_each(self, other: Self) -> Self {
+        Self {
+            lat: self.lat.min(other.lat),
+            lon: self.lon.min(other.lon),
+        }
+    }
+
+    pub fn max_each(self, other: Self) -> Self {
+        Self {
+            lat: self.lat.max(other.lat),
+            lon: self.lon.max(other.lon),
+        }
+    }
+
+    /// Great circle distance in meters, using the haversine formula
+    pub fn distance_to(&self, other: Self) -> f64 {
+        let phi1 = self.lat * PI / 180.0;
+        let phi2 = other.lat * PI / 180.0;
+        let delta_phi = (other.lat - self.lat) * PI / 180.0;
+        let delta_lambda = (other.lon - self.lon) * PI / 180.0;
+
+        let half_sin_delta_phi = libm::sin(delta_phi / 2.0);
+        let half_sin_delta_lambda = libm::sin(delta_lambda / 2.0);
+
+        let a = half_sin_delta_phi * half_sin_delta_phi
+            + libm::cos(phi1) * libm::cos(phi2) * half_sin_delta_lambda * half_sin_delta_lambda;
+        let c = 2.0 * libm::atan2(libm::sqrt(a), libm::sqrt(1.0 - a));
+
+        EARTH_RADIUS * c
+    }
+}
+
+impl From<FixedCoord> for Coord {
+    fn from(coord: FixedCoord) -> Self {
+        Self {
+            lat: coord.lat_degrees(),
+            lon: coord.lon_degrees(),
+        }
+    }
+}
+
+impl From<Coord> for FixedCoord {
+    fn from(coord: Coord) -> Self {
+        FixedCoord::from_degrees(coord.lat, coord.lon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coord(lat: f64, lon: f64) -> Coord {
+        Coord { lat, lon }
+    }
+
+    #[test]
+    fn known_distances() {
+        let berlin = coord(52.52, 13.405);
+        let paris = coord(48.8566, 2.3522);
+        let london = coord(51.5074, -0.1278);
+        let new_york = coord(40.7128, -74.006);
+        let sydney = coord(-33.8688, 151.2093);
+        let san_francisco = coord(37.7749, -122.4194);
+        for (a, b, meters) in [
+            (berlin, paris, 877_463.3),
+            (london, new_york, 5_570_222.2),
+            (sydney, san_francisco, 11_947_660.5),
+            (coord(0.0, 0.0), coord(1.0, 0.0), 111_194.9),
+            (coord(0.0, 0.0), coord(0.0, 180.0), 20_015_086.8),
+            (coord(90.0, 0.0), coord(-90.0, 0.0), 20_015_086.8),
+            // Across the date line the short way
+            (coord(0.0, 179.5), coord(0.0, -179.5), 111_194.9),
+        ] {
+            assert!((a.distance_to(b) - meters).abs() < 1.0, "{a:?} {b:?}");
+            assert!((b.distance_to(a) - meters).abs() < 1.0, "{b:?} {a:?}");
+        }
+        assert_eq!(berlin.distance_to(berlin), 0.0);
+    }
+
+    #[test]
+    fn fixed_coords_round_trip() {
+        for (lat, lon) in [
+            (0, 0),
+            (525_200_000, 134_050_000),
+            (-338_688_000, 1_512_093_000),
+            (1, -1),
+            (900_000_000, 1_800_000_000),
+            (-900_000_000, -1_800_000_000),
+        ] {
+            let fixed = FixedCoord { lat, lon };
+            assert_eq!(FixedCoord::from(Coord::from(fixed)), fixed);
+        }
+
+        // Degrees lose what's below the fixed step, no more
+        for (lat, lon) in [
+            (52.520_008_3, 13.404_954_4),
+            (-85.051_128_8, 179.999_999_99),
+        ] {
+            let back = Coord::from(FixedCoord::from(coord(lat, lon)));
+            assert!((back.lat - lat).abs() <= 0.5e-7, "{lat}");
+            assert!((back.lon - lon).abs() <= 0.5e-7, "{lon}");
+        }
+    }
+
+    #[test]
+    fn bounds_of_coordinates() {
+        let (a, b) = (coord(52.0, 14.0), coord(53.0, 13.0));
+        assert_eq!(a.min_each(b), coord(52.0, 13.0));
+        assert_eq!(a.max_each(b), coord(53.0, 14.0));
+    }
+}
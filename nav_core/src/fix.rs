@@ -0,0 +1,411 @@
+//! Makes sense of what the GPS receiver reports.
+//!
+//! The PSP's receiver hands over a struct of floats whether it has a fix or not, so a reading
+//! is only turned into a [`Fix`] after checking it. [`RawGpsData`] mirrors that struct, which
+//! keeps all of this independent of `psp::sys`.
+
+use crate::graph::FixedCoord;
+use crate::tracking::TrackPosition;
+
+/// Typical GPS error in meters for an HDOP of 1, what the HDOP gets multiplied with
+const METERS_PER_HDOP: f32 = 5.0;
+
+/// Readings worse than this are too far off to navigate with
+const MAX_HDOP: f32 = 20.0;
+
+/// GPS didn't exist before, an earlier year is a receiver that doesn't know the time yet
+const MIN_YEAR: u16 = 1980;
+
+/// Field for field what `sceUsbGpsGetData` fills in, minus the unknown ones
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RawGpsData {
+    pub year: i32,
+    pub month: i32,
+    pub date: i32,
+    pub hour: i32,
+    pub minute: i32,
+    pub second: i32,
+    pub hdop: f32,
+    /// Degrees
+    pub latitude: f32,
+    pub longitude: f32,
+    /// Meters above sea level
+    pub altitude: f32,
+    /// km/h
+    pub speed: f32,
+    /// Degrees, 0 is north, clockwise
+    pub bearing: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Satellite {
+    /// PRN number
+    pub id: u8,
+    /// Degrees above the horizon
+    pub elevation: u8,
+    /// Degrees, 0 is north, clockwise
+    pub azimuth: u16,
+    /// Signal to noise ratio in dB-Hz, 0 if not tracked
+    pub snr: u8,
+    /// Used for the current fix
+    pub used: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FixQuality {
+    /// Latitude and longitude only, the altitude is made up
+    TwoD,
+    ThreeD,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UtcTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl UtcTime {
+    pub fn is_valid(&self) -> bool {
+        self.year >= MIN_YEAR
+            && (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            // Leap seconds
+            && self.second <= 60
+    }
+
+    /// Seconds since 1970-01-01
+    pub fn unix_seconds(&self) -> i64 {
+        // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+        let (month, day) = (self.month as i64, self.day as i64);
+        let year = self.year as i64 - if month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days * 86_400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+    }
+
+    /// The other way around from [`Self::unix_seconds`]
+    pub fn from_unix_seconds(seconds: i64) -> Self {
+        // Civil from days, same source as above
+        let (days, second_of_day) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8,
+            hour: (second_of_day / 3600) as u8,
+            minute: (second_of_day / 60 % 60) as u8,
+            second: (second_of_day % 60) as u8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixError {
+    /// The receiver is still searching, everything is zero
+    NoFix,
+    /// Position out of range or not a number
+    BadPosition,
+    BadTime,
+    /// Fewer than 3 satellites, or an HDOP too bad to use
+    BadAccuracy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    pub coord: FixedCoord,
+    /// Meters above sea level, only meaningful for [`FixQuality::ThreeD`]
+    pub altitude_m: f32,
+    pub speed_mps: f32,
+    /// Degrees, 0 is north, clockwise
+    pub bearing: f32,
+    pub hdop: f32,
+    pub quality: FixQuality,
+    pub time: UtcTime,
+    pub satellites_used: u8,
+}
+
+impl Fix {
+    /// Checks a reading and turns it into a fix. `satellites` is what the receiver reported
+    /// along with it, only the number of satellites used matters here.
+    pub fn from_raw(raw: &RawGpsData, satellites: &[Satellite]) -> Result<Self, FixError> {
+        if raw.latitude == 0.0 && raw.longitude == 0.0 {
+            return Err(FixError::NoFix);
+        }
+        let position_ok = raw.latitude.is_finite()
+            && raw.longitude.is_finite()
+            && raw.latitude.abs() <= 90.0
+            && raw.longitude.abs() <= 180.0;
+        if !position_ok {
+            return Err(FixError::BadPosition);
+        }
+
+        let time = UtcTime {
+            year: raw.year.clamp(0, u16::MAX as i32) as u16,
+            month: raw.month.clamp(0, 255) as u8,
+            day: raw.date.clamp(0, 255) as u8,
+            hour: raw.hour.clamp(0, 255) as u8,
+            minute: raw.minute.clamp(0, 255) as u8,
+            second: raw.second.clamp(0, 255) as u8,
+        };
+        if !time.is_valid() {
+            return Err(FixError::BadTime);
+        }
+
+        let satellites_used = satellites.iter().filter(|s| s.used).count().min(255) as u8;
+        let quality = match satellites_used {
+            0..=2 => return Err(FixError::BadAccuracy),
+            3 => FixQuality::TwoD,
+            _ => FixQuality::ThreeD,
+        };
+        if !(raw.hdop > 0.0 && raw.hdop <= MAX_HDOP) {
+            return Err(FixError::BadAccuracy);
+        }
+
+        Ok(Self {
+            coord: FixedCoord::from_degrees(raw.latitude as f64, raw.longitude as f64),
+            altitude_m: raw.altitude,
+            speed_mps: raw.speed.max(0.0) / 3.6,
+            bearing: normalize_bearing(raw.bearing),
+            hdop: raw.hdop,
+            quality,
+            time,
+            satellites_used,
+        })
+    }
+
+    /// Rough horizontal accuracy in meters
+    pub fn accuracy_m(&self) -> f32 {
+        self.hdop * METERS_PER_HDOP
+    }
+
+    /// For [`crate::tracking::Tracker`], `time_ms` being any millisecond clock
+    pub fn track_position(&self, time_ms: u32) -> TrackPosition {
+        TrackPosition {
+            coord: self.coord,
+            heading: self.bearing,
+            speed: self.speed_mps,
+            time_ms,
+        }
+    }
+}
+
+fn normalize_bearing(bearing: f32) -> f32 {
+    let bearing = libm::fmodf(bearing, 360.0);
+    if bearing < 0.0 {
+        bearing + 360.0
+    } else {
+        bearing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> UtcTime {
+        UtcTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    /// A good reading in Berlin, driving south-west at 36 km/h
+    fn raw() -> RawGpsData {
+        RawGpsData {
+            year: 2024,
+            month: 6,
+            date: 15,
+            hour: 14,
+            minute: 30,
+            second: 5,
+            hdop: 1.2,
+            latitude: 52.52,
+            longitude: 13.405,
+            altitude: 34.0,
+            speed: 36.0,
+            bearing: 225.0,
+        }
+    }
+
+    fn used(count: usize) -> [Satellite; 6] {
+        core::array::from_fn(|i| Satellite {
+            id: i as u8 + 1,
+            snr: 40,
+            used: i < count,
+            ..Satellite::default()
+        })
+    }
+
+    #[test]
+    fn times_need_every_field_in_range() {
+        assert!(time(2024, 2, 29, 23, 59, 59).is_valid());
+        // A leap second
+        assert!(time(2016, 12, 31, 23, 59, 60).is_valid());
+
+        assert!(!UtcTime::default().is_valid());
+        // What the receiver reports before it knows the time
+        assert!(!time(0, 1, 1, 0, 0, 0).is_valid());
+        assert!(!time(1979, 12, 31, 0, 0, 0).is_valid());
+        assert!(!time(2024, 0, 1, 0, 0, 0).is_valid());
+        assert!(!time(2024, 13, 1, 0, 0, 0).is_valid());
+        assert!(!time(2024, 1, 0, 0, 0, 0).is_valid());
+        assert!(!time(2024, 1, 32, 0, 0, 0).is_valid());
+        assert!(!time(2024, 1, 1, 24, 0, 0).is_valid());
+        assert!(!time(2024, 1, 1, 0, 60, 0).is_valid());
+        assert!(!time(2024, 1, 1, 0, 0, 61).is_valid());
+    }
+
+    #[test]
+    fn counts_seconds_since_1970() {
+        assert_eq!(time(1970, 1, 1, 0, 0, 0).unix_seconds(), 0);
+        assert_eq!(time(1969, 12, 31, 23, 59, 59).unix_seconds(), -1);
+        assert_eq!(time(2000, 1, 1, 0, 0, 0).unix_seconds(), 946_684_800);
+        assert_eq!(time(2024, 2, 29, 12, 34, 56).unix_seconds(), 1_709_210_096);
+        // Not a leap year
+        assert_eq!(time(2100, 3, 1, 0, 0, 0).unix_seconds(), 4_107_542_400);
+        assert_eq!(
+            time(2100, 3, 1, 0, 0, 0).unix_seconds() - time(2100, 2, 28, 0, 0, 0).unix_seconds(),
+            86_400
+        );
+        assert!(time(2024, 6, 15, 14, 30, 5) < time(2024, 6, 15, 14, 30, 6));
+    }
+
+    #[test]
+    fn turns_seconds_back_into_a_date() {
+        for t in [
+            time(1970, 1, 1, 0, 0, 0),
+            time(1999, 12, 31, 23, 59, 59),
+            time(2000, 2, 29, 0, 0, 1),
+            time(2024, 2, 29, 12, 34, 56),
+            time(2024, 3, 1, 0, 0, 0),
+            time(2100, 2, 28, 18, 0, 0),
+            time(2100, 3, 1, 6, 7, 8),
+        ] {
+            assert_eq!(UtcTime::from_unix_seconds(t.unix_seconds()), t);
+        }
+        assert_eq!(
+            UtcTime::from_unix_seconds(-1),
+            time(1969, 12, 31, 23, 59, 59)
+        );
+    }
+
+    #[test]
+    fn turns_a_good_reading_into_a_fix() {
+        let fix = Fix::from_raw(&raw(), &used(5)).unwrap();
+        assert_eq!(
+            fix.coord,
+            FixedCoord::from_degrees(52.52f32 as f64, 13.405f32 as f64)
+        );
+        assert_eq!(fix.altitude_m, 34.0);
+        assert!((fix.speed_mps - 10.0).abs() < 1e-5);
+        assert_eq!(fix.bearing, 225.0);
+        assert_eq!(fix.quality, FixQuality::ThreeD);
+        assert_eq!(fix.time, time(2024, 6, 15, 14, 30, 5));
+        assert_eq!(fix.satellites_used, 5);
+        assert!((fix.accuracy_m() - 6.0).abs() < 1e-5);
+
+        let position = fix.track_position(1234);
+        assert_eq!(position.coord, fix.coord);
+        assert_eq!((position.heading, position.speed), (225.0, fix.speed_mps));
+        assert_eq!(position.time_ms, 1234);
+
+        let two_d = Fix::from_raw(&raw(), &used(3)).unwrap();
+        assert_eq!(two_d.quality, FixQuality::TwoD);
+    }
+
+    #[test]
+    fn cleans_up_speed_and_bearing() {
+        let fix = |speed, bearing| {
+            let raw = RawGpsData {
+                speed,
+                bearing,
+                ..raw()
+            };
+            let fix = Fix::from_raw(&raw, &used(4)).unwrap();
+            (fix.speed_mps, fix.bearing)
+        };
+        assert_eq!(fix(-3.6, -90.0), (0.0, 270.0));
+        assert_eq!(fix(0.0, 360.0), (0.0, 0.0));
+        assert_eq!(fix(0.0, 725.0), (0.0, 5.0));
+    }
+
+    #[test]
+    fn refuses_bad_readings() {
+        let check = |raw: RawGpsData, satellites: usize| Fix::from_raw(&raw, &used(satellites));
+
+        // Still searching, everything is zero
+        assert_eq!(
+            Fix::from_raw(&RawGpsData::default(), &[]),
+            Err(FixError::NoFix)
+        );
+        let position = |latitude, longitude| RawGpsData {
+            latitude,
+            longitude,
+            ..raw()
+        };
+        assert_eq!(check(position(0.0, 0.0), 5), Err(FixError::NoFix));
+        assert_eq!(
+            check(position(f32::NAN, 13.0), 5),
+            Err(FixError::BadPosition)
+        );
+        assert_eq!(
+            check(position(52.0, f32::INFINITY), 5),
+            Err(FixError::BadPosition)
+        );
+        assert_eq!(check(position(90.5, 13.0), 5), Err(FixError::BadPosition));
+        assert_eq!(check(position(52.0, -180.5), 5), Err(FixError::BadPosition));
+        // The equator and the date line are fine
+        assert!(check(position(0.0, 180.0), 5).is_ok());
+
+        // A position but no time yet
+        let no_time = RawGpsData {
+            year: 0,
+            month: 0,
+            date: 0,
+            hour: 0,
+            minute: 0,
+            second: 0,
+            ..raw()
+        };
+        assert_eq!(check(no_time, 5), Err(FixError::BadTime));
+        let out_of_range = RawGpsData {
+            month: 300,
+            ..raw()
+        };
+        assert_eq!(check(out_of_range, 5), Err(FixError::BadTime));
+
+        assert_eq!(check(raw(), 2), Err(FixError::BadAccuracy));
+        let hdop = |hdop| RawGpsData { hdop, ..raw() };
+        assert_eq!(check(hdop(0.0), 5), Err(FixError::BadAccuracy));
+        assert_eq!(check(hdop(25.0), 5), Err(FixError::BadAccuracy));
+        assert_eq!(check(hdop(f32::NAN), 5), Err(FixError::BadAccuracy));
+        assert!(check(hdop(MAX_HDOP), 5).is_ok());
+    }
+}
@@ -0,0 +1,212 @@
+//! Web Mercator without `f64`, for the PSP. Its FPU only does `f32`, `f64` trig is slow soft
+//! float, and `f32` alone is off by meters at zoom 19.
+//!
+//! Longitude maps linearly, so x is plain integer math. For y the projected latitude is kept in
+//! a fixed point table every [`STEP_E7`], and only the little bit past the table entry is worked
+//! out in `f32`, with a Taylor series around the entry. That bit is small enough for `f32` to
+//! stay well within a pixel. Matches [`crate::tile::coord_to_tile`] to a pixel up to zoom 19.
+
+use alloc::vec::Vec;
+use core::f64::consts::PI;
+
+use crate::graph::FixedCoord;
+use crate::tile::GlobalLocation;
+
+/// Latitude between table entries, in degrees * 1e7
+const STEP_E7: i32 = 1_000_000;
+
+/// Web Mercator stops here, the map is square at this latitude
+const MAX_LAT_E7: i32 = 850_511_287;
+
+/// Fraction bits of the projected latitude in the table
+const TABLE_BITS: u32 = 30;
+
+/// Fraction bits of positions within the unit square
+const UNIT_BITS: u32 = 32;
+
+/// Fraction bits of world pixels, see [`FixedProjection::world_pixel`]
+pub const SUBPIXEL_BITS: u32 = 4;
+
+/// `1 / 2π` with [`UNIT_BITS`] fraction bits
+const INV_TWO_PI: i64 = ((1u64 << UNIT_BITS) as f64 / (2.0 * PI)) as i64;
+
+struct Entry {
+    /// `asinh(tan(lat))` with [`TABLE_BITS`] fraction bits
+    y: i64,
+    sec: f32,
+    tan: f32,
+}
+
+pub struct FixedProjection {
+    table: Vec<Entry>,
+}
+
+impl Default for FixedProjection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FixedProjection {
+    /// Fills the table, once, using `f64`. Takes a moment on the PSP, so keep it around.
+    pub fn new() -> Self {
+        let table = (0..=MAX_LAT_E7 / STEP_E7 + 1)
+            .map(|i| {
+                let lat = (i * STEP_E7) as f64 / 1e7 * PI / 180.0;
+                let y = libm::asinh(libm::tan(lat));
+                Entry {
+                    y: libm::round(y * (1u64 << TABLE_BITS) as f64) as i64,
+                    sec: (1.0 / libm::cos(lat)) as f32,
+                    tan: libm::tan(lat) as f32,
+                }
+            })
+            .collect();
+        Self { table }
+    }
+
+    /// Where `coord` is in the unit square, x east and y south, with [`UNIT_BITS`] fraction bits
+    fn unit_position(&self, coord: FixedCoord) -> (i64, i64) {
+        // 0 to 360 degrees from the date line
+        let lon = (coord.lon as i64 + 1_800_000_000).clamp(0, 3_600_000_000);
+        let x = (lon << (UNIT_BITS - 1)) / 1_800_000_000;
+
+        let lat = coord.lat.clamp(-MAX_LAT_E7, MAX_LAT_E7);
+        let (index, rest) = (
+            lat.unsigned_abs() / STEP_E7 as u32,
+            lat.unsigned_abs() % STEP_E7 as u32,
+        );
+        let entry = &self.table[index as usize];
+
+        // Taylor series of asinh(tan(lat)) around the entry, the derivatives being sec,
+        // sec tan, sec (2 tan² + 1) and sec tan (6 tan² + 5)
+        let d = rest as f32 / 1e7 * (core::f32::consts::PI / 180.0);
+        let (sec, tan) = (entry.sec, entry.tan);
+        let tan2 = tan * tan;
+        let delta = d
+            * sec
+            * (1.0
+                + d * (tan / 2.0
+                    + d * ((2.0 * tan2 + 1.0) / 6.0 + d * tan * (6.0 * tan2 + 5.0) / 24.0)));
+        let mut y = entry.y + (delta * (1u64 << TABLE_BITS) as f32) as i64;
+        if lat < 0 {
+            y = -y;
+        }
+
+        // Onto the unit square, the equator being in the middle
+        let y = (1i64 << (UNIT_BITS - 1)) - ((y * INV_TWO_PI) >> TABLE_BITS);
+        (x, y)
+    }
+
+    /// Pixel position in the whole world at `zoom` with tiles of `tile_res` pixels, with
+    /// [`SUBPIXEL_BITS`] fraction bits. `tile_res << zoom` has to stay below `2^31`.
+    pub fn world_pixel(&self, coord: FixedCoord, zoom: u8, tile_res: u32) -> (i64, i64) {
+        let size = (tile_res as i64) << zoom;
+        let (x, y) = self.unit_position(coord);
+        let shift = UNIT_BITS - SUBPIXEL_BITS;
+        ((x * size) >> shift, (y * size) >> shift)
+    }
+
+    /// Same as [`crate::tile::coord_to_tile`], except that the east and south edge of the map
+    /// at 180° and -85.05° are in the last tile rather than one past it
+    pub fn coord_to_tile(&self, coord: FixedCoord, zoom: u8, tile_res: u32) -> GlobalLocation {
+        let last = ((tile_res as i64) << zoom) - 1;
+        let (x, y) = self.world_pixel(coord, zoom, tile_res);
+        let (x, y) = (
+            (x >> SUBPIXEL_BITS).min(last) as u32,
+            (y >> SUBPIXEL_BITS).min(last) as u32,
+        );
+        GlobalLocation {
+            tile_x: x / tile_res,
+            tile_y: y / tile_res,
+            x: x % tile_res,
+            y: y % tile_res,
+            lat: coord.lat_degrees(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coord::Coord;
+    use crate::tile;
+
+    const MAX_LAT: f64 = MAX_LAT_E7 as f64 / 1e7;
+
+    /// Latitudes every so often across the whole map, both edges included
+    fn latitudes() -> impl Iterator<Item = f64> {
+        (0..=460)
+            .map(|i| -MAX_LAT + i as f64 * 2.0 * MAX_LAT / 460.0)
+            .chain([-MAX_LAT, -85.05, 0.0, 52.520_008_3, 85.05, MAX_LAT])
+    }
+
+    fn longitudes() -> impl Iterator<Item = f64> {
+        (0..=36).map(|i| -180.0 + i as f64 * 10.0).chain([
+            -180.0,
+            -179.999_999_9,
+            13.404_954_4,
+            179.999_999_9,
+            180.0,
+        ])
+    }
+
+    #[test]
+    fn matches_floating_point_within_a_pixel_at_zoom_19() {
+        let projection = FixedProjection::new();
+        let (zoom, tile_res) = (19, 256);
+        for lat in latitudes() {
+            for lon in longitudes() {
+                let fixed = FixedCoord::from_degrees(lat, lon);
+                // The same point, rounded to the fixed step
+                let (x, y) = tile::tile_position(Coord::from(fixed), zoom);
+                let (x, y) = (x * tile_res as f64, y * tile_res as f64);
+
+                let (fixed_x, fixed_y) = projection.world_pixel(fixed, zoom, tile_res);
+                let subpixel = (1 << SUBPIXEL_BITS) as f64;
+                let (fixed_x, fixed_y) = (fixed_x as f64 / subpixel, fixed_y as f64 / subpixel);
+                assert!((fixed_x - x).abs() < 1.0, "{lat} {lon}: {fixed_x} {x}");
+                assert!((fixed_y - y).abs() < 1.0, "{lat} {lon}: {fixed_y} {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn the_edges_of_the_map_are_in_the_last_tile() {
+        let projection = FixedProjection::new();
+        for zoom in [0, 1, 10, 19] {
+            let last = (1u32 << zoom) - 1;
+            let corner =
+                projection.coord_to_tile(FixedCoord::from_degrees(-90.0, 180.0), zoom, 256);
+            assert_eq!((corner.tile_x, corner.tile_y), (last, last));
+            assert_eq!((corner.x, corner.y), (255, 255));
+
+            let corner =
+                projection.coord_to_tile(FixedCoord::from_degrees(90.0, -180.0), zoom, 256);
+            assert_eq!(
+                (corner.tile_x, corner.tile_y, corner.x, corner.y),
+                (0, 0, 0, 0)
+            );
+
+            let east = projection.coord_to_tile(FixedCoord::from_degrees(0.0, 180.0), zoom, 512);
+            assert_eq!((east.tile_x, east.x), (last, 511));
+        }
+    }
+
+    #[test]
+    fn same_tiles_as_floating_point() {
+        let projection = FixedProjection::new();
+        for (lat, lon) in [(52.52, 13.405), (-33.8688, 151.2093), (64.1466, -21.9426)] {
+            let fixed = FixedCoord::from_degrees(lat, lon);
+            for zoom in [0, 8, 15] {
+                let expected = tile::coord_to_tile(Coord::from(fixed), zoom, 256);
+                let location = projection.coord_to_tile(fixed, zoom, 256);
+                assert_eq!(
+                    (location.tile_x, location.tile_y),
+                    (expected.tile_x, expected.tile_y)
+                );
+                assert!(location.x.abs_diff(expected.x) <= 1);
+                assert!(location.y.abs_diff(expected.y) <= 1);
+            }
+        }
+    }
+}
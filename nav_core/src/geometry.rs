@@ -0,0 +1,81 @@
+//! Flat-earth math for distances of up to a few kilometers, in `f32` since that's
+//! what the PSP's FPU does natively. Good to well under a meter at city scale.
+
+use crate::graph::FixedCoord;
+
+/// Meters per degree of latitude (and of longitude at the equator)
+const METERS_PER_DEGREE: f32 = 111_319.49;
+
+/// Maps coordinates around an origin onto a plane in meters, x east and y north
+#[derive(Debug, Clone, Copy)]
+pub struct LocalProjection {
+    origin: FixedCoord,
+    meters_per_lat_e7: f32,
+    meters_per_lon_e7: f32,
+}
+
+impl LocalProjection {
+    pub fn new(origin: FixedCoord) -> Self {
+        let lat_radians = (origin.lat as f32 / 1e7).to_radians();
+        Self {
+            origin,
+            meters_per_lat_e7: METERS_PER_DEGREE / 1e7,
+            meters_per_lon_e7: METERS_PER_DEGREE / 1e7 * libm::cosf(lat_radians),
+        }
+    }
+
+    pub fn to_local(&self, coord: FixedCoord) -> (f32, f32) {
+        (
+            coord.lon.wrapping_sub(self.origin.lon) as f32 * self.meters_per_lon_e7,
+            coord.lat.wrapping_sub(self.origin.lat) as f32 * self.meters_per_lat_e7,
+        )
+    }
+
+    pub fn to_coord(&self, (x, y): (f32, f32)) -> FixedCoord {
+        FixedCoord {
+            lat: self.origin.lat + (y / self.meters_per_lat_e7) as i32,
+            lon: self.origin.lon + (x / self.meters_per_lon_e7) as i32,
+        }
+    }
+}
+
+/// Closest point to `p` on the line from `a` to `b`.
+/// Returns (how far along the line it is from 0 to 1, distance to it).
+pub fn project_onto_line(p: (f32, f32), a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq > 0.0 {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (cx, cy) = (a.0 + dx * t, a.1 + dy * t);
+    (t, distance(p, (cx, cy)))
+}
+
+pub fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    libm::sqrtf(dx * dx + dy * dy)
+}
+
+/// Compass bearing from `a` to `b` in degrees, 0 is north and 90 is east
+pub fn bearing(a: (f32, f32), b: (f32, f32)) -> f32 {
+    let degrees = libm::atan2f(b.0 - a.0, b.1 - a.1).to_degrees();
+    if degrees < 0.0 {
+        degrees + 360.0
+    } else {
+        degrees
+    }
+}
+
+/// Signed change in bearing from `from` to `to`, in -180..=180, positive is a right turn
+pub fn bearing_delta(from: f32, to: f32) -> f32 {
+    let mut delta = to - from;
+    while delta > 180.0 {
+        delta -= 360.0;
+    }
+    while delta < -180.0 {
+        delta += 360.0;
+    }
+    delta
+}
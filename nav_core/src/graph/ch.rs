@@ -0,0 +1,466 @@
+//! Contraction hierarchy queries.
+//!
+//! The converter ranks every node by importance and adds shortcut edges, so that a shortest
+//! path can always be found by only ever going *up* in rank from both ends. Every edge is
+//! stored once, at its lower ranked end, and searching from both ends only has to look at
+//! those upward edges. That touches a few hundred nodes instead of most of the city.
+//!
+//! Weights are travel times ([`Metric::Time`](super::Metric::Time)).
+
+use alloc::{collections::BinaryHeap, vec::Vec};
+use core::cmp::Reverse;
+
+use super::{
+    CH_EDGE_SIZE, CH_FLAG_BACKWARD, CH_FLAG_FORWARD, GraphError, NO_CHILD, Path,
+    SECTION_CH_ADJACENCY, SECTION_CH_EDGES, SECTION_RANKS, adjacency_owner,
+};
+use crate::bytes::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChEdge {
+    pub id: u32,
+    pub other: u32,
+    pub weight: u32,
+    pub a: u32,
+    pub b: u32,
+    pub flags: u32,
+}
+
+impl ChEdge {
+    pub fn is_shortcut(&self) -> bool {
+        self.b != NO_CHILD
+    }
+}
+
+pub struct Hierarchy<'a> {
+    ranks: &'a [u8],
+    adjacency: &'a [u8],
+    edges: &'a [u8],
+}
+
+impl<'a> Hierarchy<'a> {
+    pub(super) fn parse(
+        ranks: &'a [u8],
+        adjacency: &'a [u8],
+        edges: &'a [u8],
+        node_count: usize,
+        edge_count: usize,
+        validate: bool,
+    ) -> Result<Self, GraphError> {
+        let hierarchy = Self {
+            ranks,
+            adjacency,
+            edges,
+        };
+        if !validate {
+            return Ok(hierarchy);
+        }
+
+        if ranks.len() != node_count * 4 {
+            return Err(GraphError::BadSection(SECTION_RANKS));
+        }
+        if adjacency.len() != (node_count + 1) * 4 {
+            return Err(GraphError::BadSection(SECTION_CH_ADJACENCY));
+        }
+        if !edges.len().is_multiple_of(CH_EDGE_SIZE) {
+            return Err(GraphError::BadSection(SECTION_CH_EDGES));
+        }
+
+        let mut prev = 0;
+        for n in 0..=node_count {
+            let first = read_u32(adjacency, n * 4) as usize;
+            if first < prev || first > hierarchy.edge_count() {
+                return Err(GraphError::BadSection(SECTION_CH_ADJACENCY));
+            }
+            prev = first;
+        }
+        if prev != hierarchy.edge_count() {
+            return Err(GraphError::BadSection(SECTION_CH_ADJACENCY));
+        }
+
+        for e in 0..hierarchy.edge_count() as u32 {
+            let edge = hierarchy.edge(e);
+            let children_ok = if edge.is_shortcut() {
+                // Children cost less than the shortcut, so unpacking always gets down to
+                // original edges instead of going round in circles
+                let cheaper = |child: u32| {
+                    (child as usize) < hierarchy.edge_count()
+                        && hierarchy.edge(child).weight < edge.weight
+                };
+                cheaper(edge.a)
+                    && cheaper(edge.b)
+                    && hierarchy
+                        .edge(edge.a)
+                        .weight
+                        .checked_add(hierarchy.edge(edge.b).weight)
+                        == Some(edge.weight)
+            } else {
+                (edge.a as usize) < edge_count
+            };
+            if edge.other as usize >= node_count || !children_ok {
+                return Err(GraphError::BadSection(SECTION_CH_EDGES));
+            }
+        }
+
+        Ok(hierarchy)
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len() / CH_EDGE_SIZE
+    }
+
+    pub fn rank(&self, node: u32) -> u32 {
+        read_u32(self.ranks, node as usize * 4)
+    }
+
+    pub fn edge(&self, id: u32) -> ChEdge {
+        let offset = id as usize * CH_EDGE_SIZE;
+        ChEdge {
+            id,
+            other: read_u32(self.edges, offset),
+            weight: read_u32(self.edges, offset + 4),
+            a: read_u32(self.edges, offset + 8),
+            b: read_u32(self.edges, offset + 12),
+            flags: read_u32(self.edges, offset + 16),
+        }
+    }
+
+    /// Upward edges stored at `node`
+    pub fn edges(&self, node: u32) -> impl Iterator<Item = ChEdge> + '_ {
+        let node = node as usize;
+        let first = read_u32(self.adjacency, node * 4);
+        let last = read_u32(self.adjacency, (node + 1) * 4);
+        (first..last).map(|e| self.edge(e))
+    }
+
+    /// The (lower ranked) node an upward edge is stored at
+    pub fn edge_owner(&self, id: u32) -> u32 {
+        adjacency_owner(self.adjacency, id)
+    }
+
+    /// Appends the original edges an upward edge stands for, in driving order
+    pub fn unpack(&self, id: u32, out: &mut Vec<u32>) {
+        // Shortcuts of shortcuts can nest deep, so no recursion on the PSP's small stack
+        let mut stack = alloc::vec![id];
+        while let Some(id) = stack.pop() {
+            let edge = self.edge(id);
+            if edge.is_shortcut() {
+                stack.push(edge.b);
+                stack.push(edge.a);
+            } else {
+                out.push(edge.a);
+            }
+        }
+    }
+}
+
+const INFINITY: u32 = u32::MAX;
+const NONE: u32 = u32::MAX;
+
+/// Buffers for one direction of the search
+struct Side {
+    dist: Vec<u32>,
+    /// Upward edge the node was reached over
+    parent: Vec<u32>,
+    touched: Vec<u32>,
+    heap: BinaryHeap<Reverse<(u32, u32)>>,
+}
+
+impl Side {
+    fn new(node_count: usize) -> Self {
+        Self {
+            dist: alloc::vec![INFINITY; node_count],
+            parent: alloc::vec![NONE; node_count],
+            touched: Vec::new(),
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        for n in self.touched.drain(..) {
+            self.dist[n as usize] = INFINITY;
+            self.parent[n as usize] = NONE;
+        }
+        self.heap.clear();
+    }
+
+    fn relax(&mut self, node: u32, cost: u32, parent: u32) {
+        let n = node as usize;
+        if cost < self.dist[n] {
+            if self.dist[n] == INFINITY {
+                self.touched.push(node);
+            }
+            self.dist[n] = cost;
+            self.parent[n] = parent;
+            self.heap.push(Reverse((cost, node)));
+        }
+    }
+
+    fn min_key(&self) -> u32 {
+        self.heap
+            .peek()
+            .map(|Reverse((d, _))| *d)
+            .unwrap_or(INFINITY)
+    }
+}
+
+/// Reusable query state, allocated once for the whole graph
+/// (16 bytes per node, plus whatever the searches touch).
+pub struct ChQuery {
+    forward: Side,
+    backward: Side,
+}
+
+impl ChQuery {
+    pub fn new(node_count: usize) -> Self {
+        Self {
+            forward: Side::new(node_count),
+            backward: Side::new(node_count),
+        }
+    }
+
+    /// Bytes [`ChQuery::new`] allocates up front for a graph with `node_count` nodes
+    pub fn memory_needed(node_count: usize) -> usize {
+        node_count * 16
+    }
+
+    /// Fastest path from any of `sources` to any of `targets`, given as (node, cost to get there).
+    /// Starting costs let a route start or end halfway along an edge.
+    pub fn run(
+        &mut self,
+        hierarchy: &Hierarchy,
+        sources: &[(u32, u32)],
+        targets: &[(u32, u32)],
+    ) -> Option<Path> {
+        self.forward.reset();
+        self.backward.reset();
+
+        for &(node, cost) in sources {
+            self.forward.relax(node, cost, NONE);
+        }
+        for &(node, cost) in targets {
+            self.backward.relax(node, cost, NONE);
+        }
+
+        let mut best = INFINITY;
+        let mut meeting = NONE;
+
+        loop {
+            let forward_min = self.forward.min_key();
+            let backward_min = self.backward.min_key();
+            // Neither side can find anything better than what we already have
+            if forward_min >= best && backward_min >= best {
+                break;
+            }
+
+            let is_forward = forward_min <= backward_min;
+            let (side, other, flag) = if is_forward {
+                (&mut self.forward, &self.backward, CH_FLAG_FORWARD)
+            } else {
+                (&mut self.backward, &self.forward, CH_FLAG_BACKWARD)
+            };
+
+            let Some(Reverse((cost, node))) = side.heap.pop() else {
+                break;
+            };
+            if cost > side.dist[node as usize] {
+                continue;
+            }
+
+            let other_cost = other.dist[node as usize];
+            if other_cost != INFINITY && cost.saturating_add(other_cost) < best {
+                best = cost + other_cost;
+                meeting = node;
+            }
+
+            for edge in hierarchy.edges(node) {
+                if edge.flags & flag != 0 {
+                    side.relax(edge.other, cost.saturating_add(edge.weight), edge.id);
+                }
+            }
+        }
+
+        if meeting == NONE {
+            return None;
+        }
+
+        // Walk back down both sides of the meeting node
+        let mut upward = Vec::new();
+        let mut node = meeting;
+        while self.forward.parent[node as usize] != NONE {
+            let edge = self.forward.parent[node as usize];
+            upward.push(edge);
+            node = hierarchy.edge_owner(edge);
+        }
+        let source = node;
+        upward.reverse();
+
+        let mut node = meeting;
+        while self.backward.parent[node as usize] != NONE {
+            let edge = self.backward.parent[node as usize];
+            upward.push(edge);
+            node = hierarchy.edge_owner(edge);
+        }
+        let target = node;
+
+        let mut edges = Vec::new();
+        for edge in upward {
+            hierarchy.unpack(edge, &mut edges);
+        }
+
+        Some(Path {
+            cost: best,
+            source,
+            target,
+            edges,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::Container;
+    use crate::graph::fixture::GraphFixture;
+    use crate::graph::{Graph, GraphError, MAGIC, Metric, VERSION, dijkstra::Dijkstra};
+
+    /// A 5 by 5 grid of streets with different speeds and some one ways, and a node that
+    /// isn't connected to anything
+    fn grid() -> GraphFixture {
+        let mut fixture = GraphFixture::new();
+        let mut nodes = [[0; 5]; 5];
+        for (row, nodes) in nodes.iter_mut().enumerate() {
+            for (column, node) in nodes.iter_mut().enumerate() {
+                *node = fixture.node(52.5 + row as f64 * 0.001, 13.4 + column as f64 * 0.0015);
+            }
+        }
+        for row in 0..5 {
+            for column in 0..5 {
+                let speed = [30, 50, 30, 70, 20][(row + column) % 5];
+                if column < 4 {
+                    let (a, b) = (nodes[row][column], nodes[row][column + 1]);
+                    match row % 2 {
+                        0 => fixture.road(a, b, speed, ""),
+                        _ => _ = fixture.edge(a, b, speed),
+                    }
+                }
+                if row < 4 {
+                    let (a, b) = (nodes[row][column], nodes[row + 1][column]);
+                    match column {
+                        1 => _ = fixture.edge(b, a, speed),
+                        _ => fixture.road(a, b, speed, ""),
+                    }
+                }
+            }
+        }
+        fixture.node(52.6, 13.5);
+        fixture
+    }
+
+    /// Total weight of a path, checking that its edges actually connect `source` to `target`
+    fn walk(graph: &Graph, edges: &[u32], source: u32, target: u32) -> u32 {
+        let mut node = source;
+        let mut cost = 0;
+        for &e in edges {
+            assert_eq!(graph.edge_source(e), node);
+            let edge = graph.edge(e);
+            cost += Metric::Time.weight(&edge);
+            node = edge.target;
+        }
+        assert_eq!(node, target);
+        cost
+    }
+
+    #[test]
+    fn matches_dijkstra_between_all_nodes() {
+        let data = grid().build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let hierarchy = graph.hierarchy().unwrap();
+        let n = graph.node_count();
+        let mut dijkstra = Dijkstra::new(n);
+        let mut query = ChQuery::new(n);
+
+        for source in 0..n as u32 {
+            for target in 0..n as u32 {
+                let expected = dijkstra
+                    .run(&graph, Metric::Time, &[(source, 0)], &[(target, 0)], n)
+                    .unwrap();
+                let actual = query.run(hierarchy, &[(source, 0)], &[(target, 0)]);
+                match (expected, actual) {
+                    (None, None) => {}
+                    (Some(expected), Some(actual)) => {
+                        assert_eq!(actual.cost, expected.cost, "{source} -> {target}");
+                        assert_eq!(walk(&graph, &actual.edges, source, target), actual.cost);
+                    }
+                    (expected, actual) => {
+                        panic!("{source} -> {target}: {expected:?} vs {actual:?}")
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_dijkstra_with_start_costs() {
+        let data = grid().build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let n = graph.node_count();
+        let sources = [(0, 40), (6, 0)];
+        let targets = [(24, 5), (18, 60)];
+
+        let expected = Dijkstra::new(n)
+            .run(&graph, Metric::Time, &sources, &targets, n)
+            .unwrap()
+            .unwrap();
+        let actual = ChQuery::new(n)
+            .run(graph.hierarchy().unwrap(), &sources, &targets)
+            .unwrap();
+        assert_eq!(actual.cost, expected.cost);
+        let start = sources
+            .iter()
+            .find(|(node, _)| *node == actual.source)
+            .unwrap();
+        let end = targets
+            .iter()
+            .find(|(node, _)| *node == actual.target)
+            .unwrap();
+        let walked = walk(&graph, &actual.edges, actual.source, actual.target);
+        assert_eq!(start.1 + walked + end.1, actual.cost);
+    }
+
+    /// Runs `change` on the first shortcut record of the grid's hierarchy
+    fn with_broken_shortcut(change: impl FnOnce(&mut [u32; 5], u32)) -> Result<(), GraphError> {
+        let mut data = grid().build(true);
+        let container = Container::parse(&data, MAGIC, VERSION, VERSION).unwrap();
+        let section = container.section(SECTION_CH_EDGES).unwrap();
+        let start = section.as_ptr() as usize - data.as_ptr() as usize;
+        let count = section.len() / CH_EDGE_SIZE;
+
+        let id = (0..count)
+            .find(|&e| read_u32(section, e * CH_EDGE_SIZE + 12) != NO_CHILD)
+            .unwrap();
+        let offset = start + id * CH_EDGE_SIZE;
+        let mut record = [0; 5];
+        for (i, value) in record.iter_mut().enumerate() {
+            *value = read_u32(&data, offset + i * 4);
+        }
+        change(&mut record, id as u32);
+        for (i, value) in record.iter().enumerate() {
+            data[offset + i * 4..offset + i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        Graph::parse(&data).map(|_| ())
+    }
+
+    #[test]
+    fn rejects_shortcut_cycles() {
+        let broken = Err(GraphError::BadSection(SECTION_CH_EDGES));
+        assert_eq!(with_broken_shortcut(|_, _| {}), Ok(()));
+        assert_eq!(with_broken_shortcut(|record, id| record[2] = id), broken);
+        assert_eq!(with_broken_shortcut(|record, id| record[3] = id), broken);
+        assert_eq!(with_broken_shortcut(|record, _| record[1] += 1), broken);
+        assert_eq!(
+            with_broken_shortcut(|record, _| record[3] = u32::MAX - 1),
+            broken
+        );
+    }
+}
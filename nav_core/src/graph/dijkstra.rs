@@ -0,0 +1,125 @@
+//! Plain Dijkstra on the full graph. Works with any [`Metric`] and any graph,
+//! but on a city sized graph it's too slow for the PSP to use for long routes.
+
+use alloc::{collections::BinaryHeap, vec::Vec};
+use core::cmp::Reverse;
+
+use super::{Graph, Metric, Path};
+
+const INFINITY: u32 = u32::MAX;
+const NONE: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DijkstraError {
+    /// Gave up after settling this many nodes
+    SearchLimit(usize),
+}
+
+/// Reusable search state, allocated once for the whole graph (8 bytes per node)
+pub struct Dijkstra {
+    dist: Vec<u32>,
+    /// Edge the node was reached over
+    parent: Vec<u32>,
+    touched: Vec<u32>,
+    heap: BinaryHeap<Reverse<(u32, u32)>>,
+}
+
+impl Dijkstra {
+    pub fn new(node_count: usize) -> Self {
+        Self {
+            dist: alloc::vec![INFINITY; node_count],
+            parent: alloc::vec![NONE; node_count],
+            touched: Vec::new(),
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    /// Bytes [`Dijkstra::new`] allocates up front for a graph with `node_count` nodes
+    pub fn memory_needed(node_count: usize) -> usize {
+        node_count * 8
+    }
+
+    fn relax(&mut self, node: u32, cost: u32, parent: u32) {
+        let n = node as usize;
+        if cost < self.dist[n] {
+            if self.dist[n] == INFINITY {
+                self.touched.push(node);
+            }
+            self.dist[n] = cost;
+            self.parent[n] = parent;
+            self.heap.push(Reverse((cost, node)));
+        }
+    }
+
+    /// Best path from any of `sources` to any of `targets`, given as (node, cost to get there).
+    /// Gives up after settling `max_settled` nodes, which bounds both time and memory.
+    pub fn run(
+        &mut self,
+        graph: &Graph,
+        metric: Metric,
+        sources: &[(u32, u32)],
+        targets: &[(u32, u32)],
+        max_settled: usize,
+    ) -> Result<Option<Path>, DijkstraError> {
+        for n in self.touched.drain(..) {
+            self.dist[n as usize] = INFINITY;
+            self.parent[n as usize] = NONE;
+        }
+        self.heap.clear();
+
+        for &(node, cost) in sources {
+            self.relax(node, cost, NONE);
+        }
+
+        let mut best = INFINITY;
+        let mut best_target = NONE;
+        let mut settled = 0;
+
+        while let Some(Reverse((cost, node))) = self.heap.pop() {
+            if cost > self.dist[node as usize] {
+                continue;
+            }
+            if cost >= best {
+                break;
+            }
+
+            // Targets can have an extra cost, so only stop once nothing cheaper can show up
+            for &(target, target_cost) in targets {
+                if target == node && cost.saturating_add(target_cost) < best {
+                    best = cost + target_cost;
+                    best_target = node;
+                }
+            }
+
+            settled += 1;
+            if settled > max_settled {
+                return Err(DijkstraError::SearchLimit(max_settled));
+            }
+
+            for edge in graph.edges(node) {
+                let weight = metric.weight(&edge);
+                self.relax(edge.target, cost.saturating_add(weight), edge.id);
+            }
+        }
+
+        if best_target == NONE {
+            return Ok(None);
+        }
+
+        let mut edges = Vec::new();
+        let mut node = best_target;
+        while self.parent[node as usize] != NONE {
+            let edge = self.parent[node as usize];
+            edges.push(edge);
+            node = graph.edge_source(edge);
+        }
+        edges.reverse();
+
+        Ok(Some(Path {
+            cost: best,
+            source: node,
+            target: best_target,
+            edges,
+        }))
+    }
+}
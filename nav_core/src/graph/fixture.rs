@@ -0,0 +1,234 @@
+//! Small graphs for tests, encoded the same way the converter writes real ones. Every edge is
+//! its own straight geometry segment, the hierarchy adds a shortcut for every path through a
+//! contracted node. That's a lot more shortcuts than the converter makes, but just as correct.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use super::*;
+use crate::container;
+use crate::coord::Coord;
+
+#[derive(Debug, Clone)]
+pub(crate) struct FixtureEdge {
+    pub from: u32,
+    pub to: u32,
+    pub speed_kmh: u8,
+    pub class: RoadClass,
+    pub flags: u8,
+    pub name: &'static str,
+    /// Drives the geometry of this earlier edge backwards
+    reverse_of: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GraphFixture {
+    nodes: Vec<FixedCoord>,
+    edges: Vec<FixtureEdge>,
+}
+
+impl GraphFixture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn node(&mut self, lat: f64, lon: f64) -> u32 {
+        self.nodes.push(FixedCoord::from_degrees(lat, lon));
+        self.nodes.len() as u32 - 1
+    }
+
+    /// A one way road, residential unless changed through the returned edge
+    pub fn edge(&mut self, from: u32, to: u32, speed_kmh: u8) -> &mut FixtureEdge {
+        self.edges.push(FixtureEdge {
+            from,
+            to,
+            speed_kmh,
+            class: RoadClass::Residential,
+            flags: 0,
+            name: "",
+            reverse_of: None,
+        });
+        self.edges.last_mut().unwrap()
+    }
+
+    /// A two way road, both directions share their geometry like they do in real graphs
+    pub fn road(&mut self, a: u32, b: u32, speed_kmh: u8, name: &'static str) {
+        self.edge(a, b, speed_kmh).name = name;
+        let forward = self.edges.len() - 1;
+        let backward = self.edge(b, a, speed_kmh);
+        backward.name = name;
+        backward.reverse_of = Some(forward);
+    }
+
+    /// Encodes the graph, with a hierarchy that contracts the nodes in the order they were added
+    pub fn build(&self, hierarchy: bool) -> Vec<u8> {
+        let mut nodes = Vec::new();
+        for node in &self.nodes {
+            nodes.extend_from_slice(&node.lat.to_le_bytes());
+            nodes.extend_from_slice(&node.lon.to_le_bytes());
+        }
+
+        // Geometry first, in the order the edges were added
+        let (mut segments, mut points, mut names, mut strings) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let mut segment_of = Vec::new();
+        for edge in &self.edges {
+            if let Some(forward) = edge.reverse_of {
+                segment_of.push(segment_of[forward]);
+                continue;
+            }
+            segment_of.push(segments.len() as u32 / 4);
+            segments.extend_from_slice(&(points.len() as u32 / POINT_SIZE as u32).to_le_bytes());
+            for node in [edge.from, edge.to] {
+                let point = self.nodes[node as usize];
+                points.extend_from_slice(&point.lat.to_le_bytes());
+                points.extend_from_slice(&point.lon.to_le_bytes());
+            }
+            names.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+            names.extend_from_slice(&0u32.to_le_bytes());
+            names.extend_from_slice(&(edge.name.len() as u16).to_le_bytes());
+            names.extend_from_slice(&0u16.to_le_bytes());
+            strings.extend_from_slice(edge.name.as_bytes());
+        }
+        segments.extend_from_slice(&(points.len() as u32 / POINT_SIZE as u32).to_le_bytes());
+
+        let mut order = (0..self.edges.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&e| self.edges[e].from);
+        let edges = order
+            .iter()
+            .map(|&e| self.edges[e].clone())
+            .collect::<Vec<_>>();
+        let adjacency = first_edges(self.nodes.len(), edges.iter().map(|edge| edge.from));
+
+        let mut encoded = Vec::new();
+        let mut lengths = Vec::new();
+        for (edge, &e) in edges.iter().zip(&order) {
+            let (from, to) = (self.nodes[edge.from as usize], self.nodes[edge.to as usize]);
+            let length_m = Coord::from(from).distance_to(Coord::from(to));
+            let length_dm = libm::round(length_m * 10.0) as u32;
+            let mut flags = edge.flags;
+            if edge.reverse_of.is_some() {
+                flags |= EDGE_FLAG_REVERSED;
+            }
+            lengths.push(length_dm);
+            encoded.extend_from_slice(&edge.to.to_le_bytes());
+            encoded.extend_from_slice(&length_dm.to_le_bytes());
+            encoded.extend_from_slice(&segment_of[e].to_le_bytes());
+            encoded.extend_from_slice(&[edge.speed_kmh, edge.class as u8, flags, 0]);
+        }
+
+        let mut sections: Vec<([u8; 4], &[u8])> = alloc::vec![
+            (SECTION_NODES, &nodes),
+            (SECTION_ADJACENCY, &adjacency),
+            (SECTION_EDGES, &encoded),
+            (SECTION_SEGMENTS, &segments),
+            (SECTION_SEGMENT_POINTS, &points),
+            (SECTION_SEGMENT_NAMES, &names),
+            (SECTION_STRINGS, &strings),
+        ];
+        let (ranks, ch_adjacency, ch_edges) = contract(self.nodes.len(), &edges, &lengths);
+        if hierarchy {
+            sections.push((SECTION_RANKS, &ranks));
+            sections.push((SECTION_CH_ADJACENCY, &ch_adjacency));
+            sections.push((SECTION_CH_EDGES, &ch_edges));
+        }
+        container::build(MAGIC, VERSION, &sections)
+    }
+}
+
+/// The edge from `from` to `to`
+pub(crate) fn edge_between(graph: &Graph, from: u32, to: u32) -> u32 {
+    graph
+        .edges(from)
+        .find(|edge| edge.target == to)
+        .expect("No such edge in the fixture")
+        .id
+}
+
+/// Per node + 1, the index of its first entry in a list sorted by owner
+fn first_edges(node_count: usize, owners: impl Iterator<Item = u32>) -> Vec<u8> {
+    let mut first = alloc::vec![0u32; node_count + 1];
+    for owner in owners {
+        first[owner as usize + 1] += 1;
+    }
+    for n in 0..node_count {
+        first[n + 1] += first[n];
+    }
+    first.iter().flat_map(|f| f.to_le_bytes()).collect()
+}
+
+/// Returns the `RANK`, `CHAD` and `CHED` sections, node `n` gets rank `n`
+fn contract(
+    node_count: usize,
+    edges: &[FixtureEdge],
+    lengths: &[u32],
+) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    // [from, to, weight, a, b], b is NO_CHILD for original edges
+    let mut all = Vec::<[u32; 5]>::new();
+    // Best edge between two nodes that aren't contracted yet
+    let mut best = BTreeMap::<(u32, u32), usize>::new();
+    let add = |all: &mut Vec<[u32; 5]>, best: &mut BTreeMap<_, _>, edge: [u32; 5]| {
+        let index = all.len();
+        all.push(edge);
+        let entry = best.entry((edge[0], edge[1])).or_insert(index);
+        if all[*entry][2] > edge[2] {
+            *entry = index;
+        }
+    };
+
+    for (id, edge) in edges.iter().enumerate() {
+        if edge.from != edge.to {
+            let weight = travel_time_ds(lengths[id], edge.speed_kmh);
+            add(
+                &mut all,
+                &mut best,
+                [edge.from, edge.to, weight, id as u32, NO_CHILD],
+            );
+        }
+    }
+
+    for node in 0..node_count as u32 {
+        let mut shortcuts = Vec::new();
+        for (&(from, _), &a) in best.iter().filter(|((f, t), _)| *t == node && *f > node) {
+            for (&(_, to), &b) in best.iter().filter(|((f, t), _)| *f == node && *t > node) {
+                if from != to {
+                    shortcuts.push([from, to, all[a][2] + all[b][2], a as u32, b as u32]);
+                }
+            }
+        }
+        for shortcut in shortcuts {
+            add(&mut all, &mut best, shortcut);
+        }
+    }
+
+    let ranks = (0..node_count as u32)
+        .flat_map(|n| n.to_le_bytes())
+        .collect::<Vec<_>>();
+
+    // Stored at the lower ranked end, which is the lower node index
+    let owner = |edge: &[u32; 5]| edge[0].min(edge[1]);
+    let mut order = (0..all.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&e| owner(&all[e]));
+    let mut position = alloc::vec![0u32; all.len()];
+    for (i, &e) in order.iter().enumerate() {
+        position[e] = i as u32;
+    }
+
+    let mut records = Vec::new();
+    for &e in &order {
+        let [from, to, weight, a, b] = all[e];
+        let (other, flags) = match from < to {
+            true => (to, CH_FLAG_FORWARD),
+            false => (from, CH_FLAG_BACKWARD),
+        };
+        let (a, b) = match b {
+            NO_CHILD => (a, NO_CHILD),
+            _ => (position[a as usize], position[b as usize]),
+        };
+        for value in [other, weight, a, b, flags] {
+            records.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    let adjacency = first_edges(node_count, order.iter().map(|&e| owner(&all[e])));
+    (ranks, adjacency, records)
+}
@@ -0,0 +1,565 @@
+//! The routing graph the converter writes and the PSP routes on.
+//!
+//! Intersections (and dead ends) are nodes, the road between two of them is a directed edge.
+//! A two-way road becomes two edges sharing the same geometry. Edges are stored grouped by
+//! their source node, so the outgoing edges of node `n` are `first_edge[n]..first_edge[n + 1]`.
+//!
+//! Turn restrictions are part of the topology: an intersection with restrictions gets an extra
+//! node (at the same coordinate) for every restricted way of arriving there, which only has the
+//! turns that are allowed after arriving that way. Routers don't have to know about them.
+//!
+//! The file is a [`container`](crate::container) with these sections:
+//!
+//! | Tag    | Content                                                              |
+//! |--------|----------------------------------------------------------------------|
+//! | `NODE` | Per node: lat, lon (i32, degrees * 1e7)                              |
+//! | `ADJO` | Per node + 1: index of the node's first outgoing edge (u32)          |
+//! | `EDGE` | Per edge: see [`EDGE_SIZE`]                                          |
+//! | `SEGM` | Per geometry segment + 1: index of its first point (u32)             |
+//! | `SPTS` | Geometry points: lat, lon (i32, degrees * 1e7), including both ends  |
+//! | `SNAM` | Per geometry segment: road name and ref, see [`SEGMENT_NAME_SIZE`]   |
+//! | `STRS` | UTF-8 strings the names point into                                   |
+//!
+//! The names are optional, graphs from before they were added simply have no names.
+//!
+//! Optionally, a contraction hierarchy for fast routing on the PSP (see [`ch`]):
+//!
+//! | Tag    | Content                                                              |
+//! |--------|----------------------------------------------------------------------|
+//! | `RANK` | Per node: its position in the contraction order (u32)                |
+//! | `CHAD` | Per node + 1: index of the node's first upward edge (u32)            |
+//! | `CHED` | Per upward edge: see [`CH_EDGE_SIZE`]                                |
+//!
+//! Everything is read in place from the file's bytes, nothing gets copied into
+//! bigger structs, which matters with 32 MB of RAM.
+
+use alloc::vec::Vec;
+
+use crate::bytes::*;
+use crate::container::{Container, ContainerError};
+
+pub mod ch;
+pub mod dijkstra;
+#[cfg(test)]
+pub(crate) mod fixture;
+
+pub use ch::Hierarchy;
+
+pub const MAGIC: [u8; 4] = *b"PRNG";
+pub const VERSION: u16 = 1;
+
+pub const SECTION_NODES: [u8; 4] = *b"NODE";
+pub const SECTION_ADJACENCY: [u8; 4] = *b"ADJO";
+pub const SECTION_EDGES: [u8; 4] = *b"EDGE";
+pub const SECTION_SEGMENTS: [u8; 4] = *b"SEGM";
+pub const SECTION_SEGMENT_POINTS: [u8; 4] = *b"SPTS";
+pub const SECTION_SEGMENT_NAMES: [u8; 4] = *b"SNAM";
+pub const SECTION_STRINGS: [u8; 4] = *b"STRS";
+pub const SECTION_RANKS: [u8; 4] = *b"RANK";
+pub const SECTION_CH_ADJACENCY: [u8; 4] = *b"CHAD";
+pub const SECTION_CH_EDGES: [u8; 4] = *b"CHED";
+
+pub const NODE_SIZE: usize = 8;
+/// target (u32), length in decimeters (u32), geometry segment (u32),
+/// speed in km/h (u8), road class (u8), flags (u8), reserved (u8)
+pub const EDGE_SIZE: usize = 16;
+pub const POINT_SIZE: usize = 8;
+/// name offset (u32), ref offset (u32), name length (u16), ref length (u16), into `STRS`
+pub const SEGMENT_NAME_SIZE: usize = 12;
+/// other node (u32), weight (u32), child a (u32), child b (u32), flags (u32).
+/// For original edges child a is the edge id and child b is [`NO_CHILD`],
+/// shortcuts point at the two upward edges they replace, in driving order.
+pub const CH_EDGE_SIZE: usize = 20;
+pub const NO_CHILD: u32 = u32::MAX;
+
+/// The edge runs against the direction its geometry was stored in
+pub const EDGE_FLAG_REVERSED: u8 = 1 << 0;
+/// Part of a roundabout
+pub const EDGE_FLAG_ROUNDABOUT: u8 = 1 << 1;
+
+/// The upward edge goes from the node it's stored at to `other`
+pub const CH_FLAG_FORWARD: u32 = 1 << 0;
+/// The upward edge goes from `other` to the node it's stored at
+pub const CH_FLAG_BACKWARD: u32 = 1 << 1;
+
+/// Stored as a single byte, so the discriminants must stay stable!
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum RoadClass {
+    Motorway = 0,
+    Trunk = 1,
+    Primary = 2,
+    Secondary = 3,
+    Tertiary = 4,
+    Unclassified = 5,
+    Residential = 6,
+    LivingStreet = 7,
+    Service = 8,
+    Track = 9,
+}
+
+impl RoadClass {
+    pub const COUNT: usize = 10;
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => Self::Motorway,
+            1 => Self::Trunk,
+            2 => Self::Primary,
+            3 => Self::Secondary,
+            4 => Self::Tertiary,
+            5 => Self::Unclassified,
+            6 => Self::Residential,
+            7 => Self::LivingStreet,
+            8 => Self::Service,
+            9 => Self::Track,
+            _ => return None,
+        })
+    }
+
+    /// Used when a road has no speed limit tagged
+    pub fn default_speed_kmh(self) -> u8 {
+        match self {
+            Self::Motorway => 110,
+            Self::Trunk => 90,
+            Self::Primary => 70,
+            Self::Secondary => 60,
+            Self::Tertiary => 50,
+            Self::Unclassified => 40,
+            Self::Residential => 30,
+            Self::LivingStreet => 10,
+            Self::Service => 15,
+            Self::Track => 10,
+        }
+    }
+}
+
+/// Coordinate in degrees * 1e7, same precision OSM stores them in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FixedCoord {
+    pub lat: i32,
+    pub lon: i32,
+}
+
+impl FixedCoord {
+    /// Rounded to the nearest step, the same as the converter writes them
+    pub fn from_degrees(lat: f64, lon: f64) -> Self {
+        Self {
+            lat: libm::round(lat * 1e7) as i32,
+            lon: libm::round(lon * 1e7) as i32,
+        }
+    }
+
+    pub fn lat_degrees(&self) -> f64 {
+        self.lat as f64 / 1e7
+    }
+
+    pub fn lon_degrees(&self) -> f64 {
+        self.lon as f64 / 1e7
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub id: u32,
+    pub target: u32,
+    pub length_dm: u32,
+    pub segment: u32,
+    pub speed_kmh: u8,
+    pub class: u8,
+    pub flags: u8,
+}
+
+/// Travel time in deciseconds, the converter needs the exact same numbers for the hierarchy
+pub fn travel_time_ds(length_dm: u32, speed_kmh: u8) -> u32 {
+    let speed = speed_kmh.max(1) as u64;
+    // dm / (km/h) = 0.36 s, so this is dm * 3.6 / speed in deciseconds
+    (length_dm as u64 * 36 / (speed * 10)).max(1) as u32
+}
+
+impl Edge {
+    /// Travel time in deciseconds at the edge's speed
+    pub fn travel_time_ds(&self) -> u32 {
+        travel_time_ds(self.length_dm, self.speed_kmh)
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.flags & EDGE_FLAG_REVERSED != 0
+    }
+
+    pub fn is_roundabout(&self) -> bool {
+        self.flags & EDGE_FLAG_ROUNDABOUT != 0
+    }
+}
+
+/// What a route minimizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Shortest, in decimeters
+    Distance,
+    /// Fastest, in deciseconds. This is what the contraction hierarchy is built for.
+    Time,
+}
+
+impl Metric {
+    pub fn weight(&self, edge: &Edge) -> u32 {
+        match self {
+            Self::Distance => edge.length_dm,
+            Self::Time => edge.travel_time_ds(),
+        }
+    }
+}
+
+/// A route through the graph, from node to node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    /// In the unit of the [`Metric`] used, including the start costs the search started with
+    pub cost: u32,
+    pub source: u32,
+    pub target: u32,
+    /// Edge ids in driving order
+    pub edges: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphError {
+    Container(ContainerError),
+    MissingSection([u8; 4]),
+    /// A section has a size that doesn't fit the others
+    BadSection([u8; 4]),
+}
+
+impl From<ContainerError> for GraphError {
+    fn from(e: ContainerError) -> Self {
+        Self::Container(e)
+    }
+}
+
+pub struct Graph<'a> {
+    nodes: &'a [u8],
+    adjacency: &'a [u8],
+    edges: &'a [u8],
+    segments: &'a [u8],
+    points: &'a [u8],
+    /// Empty if the graph has no names
+    names: &'a [u8],
+    strings: &'a [u8],
+    hierarchy: Option<Hierarchy<'a>>,
+}
+
+impl<'a> Graph<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, GraphError> {
+        Self::parse_impl(data, true)
+    }
+
+    /// Skips walking every node and edge, only for data that already went through
+    /// [`Graph::parse`] once. Broken data panics on access instead of being reported.
+    pub fn parse_unchecked(data: &'a [u8]) -> Self {
+        Self::parse_impl(data, false).expect("Graph data changed after it was validated")
+    }
+
+    fn parse_impl(data: &'a [u8], validate: bool) -> Result<Self, GraphError> {
+        let container = Container::parse(data, MAGIC, VERSION, VERSION)?;
+        let section = |tag| {
+            container
+                .section(tag)
+                .ok_or(GraphError::MissingSection(tag))
+        };
+
+        let graph = Self {
+            nodes: section(SECTION_NODES)?,
+            adjacency: section(SECTION_ADJACENCY)?,
+            edges: section(SECTION_EDGES)?,
+            segments: section(SECTION_SEGMENTS)?,
+            points: section(SECTION_SEGMENT_POINTS)?,
+            names: container.section(SECTION_SEGMENT_NAMES).unwrap_or_default(),
+            strings: container.section(SECTION_STRINGS).unwrap_or_default(),
+            hierarchy: None,
+        };
+        if validate {
+            graph.validate()?;
+        }
+
+        // Older converter runs (or ones with the hierarchy turned off) don't have these
+        let hierarchy = match (
+            container.section(SECTION_RANKS),
+            container.section(SECTION_CH_ADJACENCY),
+            container.section(SECTION_CH_EDGES),
+        ) {
+            (Some(ranks), Some(adjacency), Some(edges)) => Some(Hierarchy::parse(
+                ranks,
+                adjacency,
+                edges,
+                graph.node_count(),
+                graph.edge_count(),
+                validate,
+            )?),
+            _ => None,
+        };
+
+        Ok(Self { hierarchy, ..graph })
+    }
+
+    /// Checks everything the accessors rely on, so they can index without checks later
+    fn validate(&self) -> Result<(), GraphError> {
+        let bad = |tag| Err(GraphError::BadSection(tag));
+
+        if !self.nodes.len().is_multiple_of(NODE_SIZE) {
+            return bad(SECTION_NODES);
+        }
+        if self.adjacency.len() != (self.node_count() + 1) * 4 {
+            return bad(SECTION_ADJACENCY);
+        }
+        if !self.edges.len().is_multiple_of(EDGE_SIZE) {
+            return bad(SECTION_EDGES);
+        }
+        if !self.points.len().is_multiple_of(POINT_SIZE) || !self.segments.len().is_multiple_of(4) {
+            return bad(SECTION_SEGMENT_POINTS);
+        }
+        if self.segments.is_empty() {
+            return bad(SECTION_SEGMENTS);
+        }
+
+        let mut prev = 0;
+        for n in 0..=self.node_count() {
+            let first = read_u32(self.adjacency, n * 4) as usize;
+            if first < prev || first > self.edge_count() {
+                return bad(SECTION_ADJACENCY);
+            }
+            prev = first;
+        }
+        if prev != self.edge_count() {
+            return bad(SECTION_ADJACENCY);
+        }
+
+        // Every segment has both ends of its edges at least
+        let mut prev = None;
+        for s in 0..=self.segment_count() {
+            let first = read_u32(self.segments, s * 4) as usize;
+            if prev.is_some_and(|prev| first < prev + 2) || first > self.points.len() / POINT_SIZE {
+                return bad(SECTION_SEGMENTS);
+            }
+            prev = Some(first);
+        }
+
+        if !self.names.is_empty() {
+            if self.names.len() != self.segment_count() * SEGMENT_NAME_SIZE {
+                return bad(SECTION_SEGMENT_NAMES);
+            }
+            for s in 0..self.segment_count() {
+                let offset = s * SEGMENT_NAME_SIZE;
+                for (start, len) in [(offset, offset + 8), (offset + 4, offset + 10)] {
+                    let start = read_u32(self.names, start) as usize;
+                    let end = start + read_u16(self.names, len) as usize;
+                    if end > self.strings.len()
+                        || str::from_utf8(&self.strings[start..end]).is_err()
+                    {
+                        return bad(SECTION_STRINGS);
+                    }
+                }
+            }
+        }
+
+        for e in 0..self.edge_count() {
+            let edge = self.edge(e as u32);
+            if edge.target as usize >= self.node_count()
+                || edge.segment as usize >= self.segment_count()
+            {
+                return bad(SECTION_EDGES);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn hierarchy(&self) -> Option<&Hierarchy<'a>> {
+        self.hierarchy.as_ref()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len() / NODE_SIZE
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.len() / EDGE_SIZE
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len() / 4 - 1
+    }
+
+    pub fn node_coord(&self, node: u32) -> FixedCoord {
+        let offset = node as usize * NODE_SIZE;
+        FixedCoord {
+            lat: read_i32(self.nodes, offset),
+            lon: read_i32(self.nodes, offset + 4),
+        }
+    }
+
+    /// Range of edge ids going out of `node`
+    pub fn edge_range(&self, node: u32) -> core::ops::Range<u32> {
+        let node = node as usize;
+        read_u32(self.adjacency, node * 4)..read_u32(self.adjacency, (node + 1) * 4)
+    }
+
+    pub fn edges(&self, node: u32) -> impl Iterator<Item = Edge> + '_ {
+        self.edge_range(node).map(|e| self.edge(e))
+    }
+
+    pub fn edge(&self, id: u32) -> Edge {
+        let offset = id as usize * EDGE_SIZE;
+        Edge {
+            id,
+            target: read_u32(self.edges, offset),
+            length_dm: read_u32(self.edges, offset + 4),
+            segment: read_u32(self.edges, offset + 8),
+            speed_kmh: read_u8(self.edges, offset + 12),
+            class: read_u8(self.edges, offset + 13),
+            flags: read_u8(self.edges, offset + 14),
+        }
+    }
+
+    /// Road name of a geometry segment, None if it has none
+    pub fn segment_name(&self, segment: u32) -> Option<&'a str> {
+        self.segment_string(segment, 0, 8)
+    }
+
+    /// Road number (like `A10`) of a geometry segment, None if it has none
+    pub fn segment_ref(&self, segment: u32) -> Option<&'a str> {
+        self.segment_string(segment, 4, 10)
+    }
+
+    fn segment_string(&self, segment: u32, offset_at: usize, len_at: usize) -> Option<&'a str> {
+        if self.names.is_empty() {
+            return None;
+        }
+        let entry = segment as usize * SEGMENT_NAME_SIZE;
+        let start = read_u32(self.names, entry + offset_at) as usize;
+        let len = read_u16(self.names, entry + len_at) as usize;
+        if len == 0 {
+            return None;
+        }
+        str::from_utf8(&self.strings[start..start + len]).ok()
+    }
+
+    /// Node the edge starts at, found with a binary search over the adjacency offsets
+    pub fn edge_source(&self, id: u32) -> u32 {
+        adjacency_owner(self.adjacency, id)
+    }
+
+    /// Points of the edge's road in driving direction, including both end nodes
+    pub fn edge_points(&self, edge: &Edge) -> EdgePoints<'a> {
+        let seg = edge.segment as usize;
+        EdgePoints {
+            points: self.points,
+            front: read_u32(self.segments, seg * 4) as usize,
+            back: read_u32(self.segments, (seg + 1) * 4) as usize,
+            reversed: edge.is_reversed(),
+        }
+    }
+}
+
+/// Finds the node whose run of edges in a `first edge` table contains `id`
+pub(crate) fn adjacency_owner(adjacency: &[u8], id: u32) -> u32 {
+    let (mut lo, mut hi) = (0, adjacency.len() / 4 - 1);
+    while lo + 1 < hi {
+        let mid = (lo + hi) / 2;
+        if read_u32(adjacency, mid * 4) <= id {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    lo as u32
+}
+
+pub struct EdgePoints<'a> {
+    points: &'a [u8],
+    front: usize,
+    /// One past the last point
+    back: usize,
+    reversed: bool,
+}
+
+impl EdgePoints<'_> {
+    fn point(&self, p: usize) -> FixedCoord {
+        FixedCoord {
+            lat: read_i32(self.points, p * POINT_SIZE),
+            lon: read_i32(self.points, p * POINT_SIZE + 4),
+        }
+    }
+}
+
+impl Iterator for EdgePoints<'_> {
+    type Item = FixedCoord;
+
+    fn next(&mut self) -> Option<FixedCoord> {
+        if self.front >= self.back {
+            return None;
+        }
+        if self.reversed {
+            self.back -= 1;
+            Some(self.point(self.back))
+        } else {
+            self.front += 1;
+            Some(self.point(self.front - 1))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for EdgePoints<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::fixture::GraphFixture;
+    use super::*;
+
+    /// Two roads in a row, their segments start at points 0 and 2
+    fn two_roads() -> Vec<u8> {
+        let mut fixture = GraphFixture::new();
+        let a = fixture.node(52.5, 13.4);
+        let b = fixture.node(52.5, 13.401);
+        let c = fixture.node(52.501, 13.401);
+        fixture.road(a, b, 50, "");
+        fixture.road(b, c, 50, "");
+        fixture.build(false)
+    }
+
+    /// Moves where the second segment starts
+    fn with_second_segment_at(first: u32) -> Result<(), GraphError> {
+        let mut data = two_roads();
+        let container = Container::parse(&data, MAGIC, VERSION, VERSION).unwrap();
+        let section = container.section(SECTION_SEGMENTS).unwrap();
+        assert_eq!(read_u32(section, 4), 2);
+        let at = section.as_ptr() as usize - data.as_ptr() as usize + 4;
+        data[at..at + 4].copy_from_slice(&first.to_le_bytes());
+        Graph::parse(&data).map(|_| ())
+    }
+
+    #[test]
+    fn segments_need_two_points() {
+        assert_eq!(with_second_segment_at(2), Ok(()));
+        let broken = Err(GraphError::BadSection(SECTION_SEGMENTS));
+        // The first one gets one point
+        assert_eq!(with_second_segment_at(1), broken);
+        // Then the second one
+        assert_eq!(with_second_segment_at(3), broken);
+        // Or none at all
+        assert_eq!(with_second_segment_at(0), broken);
+        assert_eq!(with_second_segment_at(4), broken);
+    }
+
+    #[test]
+    fn every_edge_has_points_to_follow() {
+        let data = two_roads();
+        let graph = Graph::parse(&data).unwrap();
+        for id in 0..graph.edge_count() as u32 {
+            assert_eq!(graph.edge_points(&graph.edge(id)).len(), 2);
+        }
+    }
+}
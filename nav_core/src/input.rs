@@ -0,0 +1,359 @@
+//! Turns controller readings into what they mean for the map.
+//!
+//! Only [`actions`] and [`menu_action`] decide anything, from two readings in a row, so the
+//! mapping works the same anywhere. Reading the controller itself is up to the PSP side.
+
+/// Button bits, the same ones `sceCtrlReadBufferPositive` reports
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons(pub u32);
+
+impl Buttons {
+    pub const SELECT: Self = Self(0x1);
+    pub const START: Self = Self(0x8);
+    pub const UP: Self = Self(0x10);
+    pub const RIGHT: Self = Self(0x20);
+    pub const DOWN: Self = Self(0x40);
+    pub const LEFT: Self = Self(0x80);
+    pub const L: Self = Self(0x100);
+    pub const R: Self = Self(0x200);
+    pub const TRIANGLE: Self = Self(0x1000);
+    pub const CIRCLE: Self = Self(0x2000);
+    pub const CROSS: Self = Self(0x4000);
+    pub const SQUARE: Self = Self(0x8000);
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// One reading of the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Controls {
+    pub buttons: Buttons,
+    /// Analog nub, 0 is left or up, 128 is the middle
+    pub analog_x: u8,
+    pub analog_y: u8,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            buttons: Buttons::default(),
+            analog_x: 128,
+            analog_y: 128,
+        }
+    }
+}
+
+impl Controls {
+    /// Down now but not in `previous`
+    pub fn pressed(&self, previous: &Controls, button: Buttons) -> bool {
+        self.buttons.contains(button) && !previous.buttons.contains(button)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputConfig {
+    /// Nub movement from the middle that gets ignored. Worn nubs rarely sit at 128.
+    pub dead_zone: u8,
+    /// Pixels per frame with the nub all the way out, before holding it speeds things up
+    pub pan_speed: f32,
+    /// Panning speeds up to this many times `pan_speed` while the nub stays out...
+    pub max_acceleration: f32,
+    /// ...over this long
+    pub acceleration_ms: u32,
+    pub zoom_in: Buttons,
+    pub zoom_out: Buttons,
+    pub recenter: Buttons,
+    pub toggle_follow: Buttons,
+    pub toggle_heading_up: Buttons,
+    pub toggle_night: Buttons,
+    /// Route from the GPS position to the middle of the map
+    pub navigate_here: Buttons,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            dead_zone: 32,
+            pan_speed: 6.0,
+            max_acceleration: 4.0,
+            acceleration_ms: 1500,
+            zoom_in: Buttons::R,
+            zoom_out: Buttons::L,
+            recenter: Buttons::CROSS,
+            toggle_follow: Buttons::SQUARE,
+            toggle_heading_up: Buttons::TRIANGLE,
+            toggle_night: Buttons::SELECT,
+            navigate_here: Buttons::CIRCLE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Actions {
+    /// Pixels to move the map center by, x east and y south
+    pub pan: (f32, f32),
+    /// Zoom levels to step, in is positive
+    pub zoom: i8,
+    /// Jump back to the GPS position and follow it again
+    pub recenter: bool,
+    pub toggle_follow: bool,
+    /// Turn the map so the direction of travel is up, or back to north up
+    pub toggle_heading_up: bool,
+    pub toggle_night: bool,
+    pub navigate_here: bool,
+}
+
+impl Actions {
+    pub fn is_panning(&self) -> bool {
+        self.pan != (0.0, 0.0)
+    }
+}
+
+/// What `current` asks for, given the reading before it. `pan_held_ms` is how long the nub has
+/// been out of the dead zone without a break.
+pub fn actions(
+    config: &InputConfig,
+    previous: &Controls,
+    current: &Controls,
+    pan_held_ms: u32,
+) -> Actions {
+    let pressed = |button| current.pressed(previous, button);
+
+    let acceleration = if config.acceleration_ms == 0 {
+        config.max_acceleration
+    } else {
+        let held = pan_held_ms.min(config.acceleration_ms) as f32 / config.acceleration_ms as f32;
+        1.0 + (config.max_acceleration - 1.0).max(0.0) * held
+    };
+    let speed = config.pan_speed * acceleration;
+    let pan = (
+        axis(current.analog_x, config.dead_zone) * speed,
+        axis(current.analog_y, config.dead_zone) * speed,
+    );
+
+    Actions {
+        pan,
+        zoom: pressed(config.zoom_in) as i8 - pressed(config.zoom_out) as i8,
+        recenter: pressed(config.recenter),
+        toggle_follow: pressed(config.toggle_follow),
+        toggle_heading_up: pressed(config.toggle_heading_up),
+        toggle_night: pressed(config.toggle_night),
+        navigate_here: pressed(config.navigate_here),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    Up,
+    Down,
+    Select,
+    Back,
+}
+
+/// For lists: the D-pad moves, cross picks and circle goes back. One action per press.
+pub fn menu_action(previous: &Controls, current: &Controls) -> Option<MenuAction> {
+    let pressed = |button| current.pressed(previous, button);
+    [
+        (Buttons::UP, MenuAction::Up),
+        (Buttons::DOWN, MenuAction::Down),
+        (Buttons::CROSS, MenuAction::Select),
+        (Buttons::CIRCLE, MenuAction::Back),
+    ]
+    .into_iter()
+    .find(|&(button, _)| pressed(button))
+    .map(|(_, action)| action)
+}
+
+/// Nub position from -1 to 1 past the dead zone, squared so small movements stay fine
+fn axis(value: u8, dead_zone: u8) -> f32 {
+    let offset = value as f32 - 128.0;
+    let range = 128.0 - dead_zone as f32;
+    if offset.abs() <= dead_zone as f32 || range <= 0.0 {
+        return 0.0;
+    }
+    let amount = ((offset.abs() - dead_zone as f32) / range).min(1.0);
+    amount * amount * offset.signum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nub(x: u8, y: u8) -> Controls {
+        Controls {
+            analog_x: x,
+            analog_y: y,
+            ..Controls::default()
+        }
+    }
+
+    fn holding(buttons: &[Buttons]) -> Controls {
+        Controls {
+            buttons: Buttons(buttons.iter().fold(0, |bits, button| bits | button.0)),
+            ..Controls::default()
+        }
+    }
+
+    fn pan(config: &InputConfig, x: u8, y: u8, held_ms: u32) -> (f32, f32) {
+        actions(config, &Controls::default(), &nub(x, y), held_ms).pan
+    }
+
+    #[test]
+    fn the_dead_zone_is_ignored_up_to_its_edge() {
+        let config = InputConfig::default();
+        assert_eq!(pan(&config, 128, 128, 0), (0.0, 0.0));
+        assert_eq!(pan(&config, 128 + 32, 128 - 32, 0), (0.0, 0.0));
+
+        let (x, y) = pan(&config, 128 + 33, 128 - 33, 0);
+        assert!(x > 0.0 && x < 0.01, "{x}");
+        assert!(y < 0.0 && y > -0.01, "{y}");
+
+        // All the way out is full speed, the nub only gets to 127 from the middle going right
+        assert_eq!(pan(&config, 0, 128, 0), (-config.pan_speed, 0.0));
+        let (x, _) = pan(&config, 255, 128, 0);
+        assert!(
+            (x - config.pan_speed).abs() < config.pan_speed * 0.03,
+            "{x}"
+        );
+    }
+
+    #[test]
+    fn small_movements_stay_fine() {
+        let config = InputConfig::default();
+        let (half, _) = pan(&config, 128 + 32 + 48, 128, 0);
+        assert!((half - config.pan_speed / 4.0).abs() < 1e-4, "{half}");
+    }
+
+    #[test]
+    fn a_dead_zone_over_the_whole_range_never_pans() {
+        for dead_zone in [128, 129, 200, 255] {
+            let config = InputConfig {
+                dead_zone,
+                ..InputConfig::default()
+            };
+            for (x, y) in [(0, 0), (255, 255), (0, 255), (128, 128)] {
+                assert_eq!(pan(&config, x, y, 0), (0.0, 0.0), "dead zone {dead_zone}");
+            }
+        }
+        let config = InputConfig {
+            dead_zone: 0,
+            ..InputConfig::default()
+        };
+        assert_eq!(pan(&config, 128, 128, 0), (0.0, 0.0));
+        assert!(pan(&config, 129, 128, 0).0 > 0.0);
+    }
+
+    #[test]
+    fn holding_the_nub_out_speeds_up_panning() {
+        let config = InputConfig::default();
+        let speed = |held_ms| -pan(&config, 0, 128, held_ms).0;
+        assert_eq!(speed(0), config.pan_speed);
+        assert_eq!(speed(config.acceleration_ms / 2), config.pan_speed * 2.5);
+        assert_eq!(speed(config.acceleration_ms), config.pan_speed * 4.0);
+        assert_eq!(speed(u32::MAX), config.pan_speed * 4.0);
+
+        let instant = InputConfig {
+            acceleration_ms: 0,
+            ..config
+        };
+        assert_eq!(-pan(&instant, 0, 128, 0).0, config.pan_speed * 4.0);
+
+        // Less than 1 doesn't slow down
+        let none = InputConfig {
+            max_acceleration: 0.5,
+            ..config
+        };
+        assert_eq!(-pan(&none, 0, 128, 10_000).0, config.pan_speed);
+    }
+
+    #[test]
+    fn buttons_act_once_per_press() {
+        let config = InputConfig::default();
+        let up = Controls::default();
+        let down = holding(&[Buttons::R, Buttons::CROSS, Buttons::SELECT]);
+
+        let pressed = actions(&config, &up, &down, 0);
+        assert_eq!(pressed.zoom, 1);
+        assert!(pressed.recenter && pressed.toggle_night);
+        assert!(!pressed.toggle_follow && !pressed.toggle_heading_up && !pressed.navigate_here);
+
+        assert_eq!(actions(&config, &down, &down, 0), Actions::default());
+        assert_eq!(actions(&config, &down, &up, 0), Actions::default());
+
+        let zoom_out = actions(&config, &up, &holding(&[Buttons::L]), 0);
+        assert_eq!(zoom_out.zoom, -1);
+        // Holding one while pressing the other still counts the press
+        let zoom_in = actions(
+            &config,
+            &holding(&[Buttons::L]),
+            &holding(&[Buttons::L, Buttons::R]),
+            0,
+        );
+        assert_eq!(zoom_in.zoom, 1);
+    }
+
+    #[test]
+    fn zooming_in_and_out_at_once_cancels_out() {
+        let config = InputConfig::default();
+        let both = holding(&[Buttons::L, Buttons::R]);
+        assert_eq!(actions(&config, &Controls::default(), &both, 0).zoom, 0);
+    }
+
+    #[test]
+    fn buttons_can_be_remapped() {
+        let config = InputConfig {
+            navigate_here: Buttons::START,
+            zoom_in: Buttons::UP,
+            ..InputConfig::default()
+        };
+        let pressed = actions(
+            &config,
+            &Controls::default(),
+            &holding(&[Buttons::START, Buttons::UP]),
+            0,
+        );
+        assert!(pressed.navigate_here);
+        assert_eq!(pressed.zoom, 1);
+        let circle = actions(
+            &config,
+            &Controls::default(),
+            &holding(&[Buttons::CIRCLE]),
+            0,
+        );
+        assert!(!circle.navigate_here);
+    }
+
+    #[test]
+    fn menus_act_once_per_press() {
+        let up = Controls::default();
+        for (button, action) in [
+            (Buttons::UP, MenuAction::Up),
+            (Buttons::DOWN, MenuAction::Down),
+            (Buttons::CROSS, MenuAction::Select),
+            (Buttons::CIRCLE, MenuAction::Back),
+        ] {
+            let down = holding(&[button]);
+            assert_eq!(menu_action(&up, &down), Some(action));
+            assert_eq!(menu_action(&down, &down), None);
+            assert_eq!(menu_action(&down, &up), None);
+        }
+        assert_eq!(
+            menu_action(&up, &holding(&[Buttons::L, Buttons::SQUARE])),
+            None
+        );
+        // The nub doesn't move through lists
+        assert_eq!(menu_action(&up, &nub(0, 0)), None);
+    }
+
+    #[test]
+    fn one_menu_action_for_several_buttons() {
+        let up = Controls::default();
+        let several = holding(&[Buttons::CIRCLE, Buttons::DOWN]);
+        assert_eq!(menu_action(&up, &several), Some(MenuAction::Down));
+        // Pressing another one while holding the first
+        let circle = holding(&[Buttons::CIRCLE]);
+        assert_eq!(menu_action(&circle, &several), Some(MenuAction::Down));
+    }
+}
@@ -0,0 +1,27 @@
+//! Code shared between the converter and the PSP app.
+//! Everything in here has to stay `no_std` (with `alloc`), since it runs on the PSP too.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod container;
+pub mod coord;
+pub mod fix;
+pub mod fixed_projection;
+pub mod geometry;
+pub mod graph;
+pub mod input;
+pub mod maneuver;
+pub mod manifest;
+pub mod matcher;
+pub mod route;
+pub mod source;
+pub mod tile;
+pub mod tile_cache;
+pub mod tile_image;
+pub mod tile_pack;
+pub mod tracking;
+pub mod vector_tile;
+
+mod bytes;
@@ -0,0 +1,451 @@
+//! Turns a route into turn-by-turn instructions.
+//!
+//! At every node the route passes, the bearing of the road going in is compared with the
+//! bearing of the road going out. Nodes without any other road to take never get an
+//! instruction, and neither does going straight on along the same road.
+
+use alloc::vec::Vec;
+
+use crate::geometry::{LocalProjection, bearing, bearing_delta, distance};
+use crate::graph::{Edge, FixedCoord, Graph};
+use crate::route::Route;
+
+/// Bearings are measured over this much road, so a little kink right at the node doesn't count
+const BEARING_DISTANCE_M: f32 = 20.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManeuverKind {
+    Depart,
+    /// Straight on, but the road changes name
+    Continue,
+    SlightLeft,
+    SlightRight,
+    Left,
+    Right,
+    SharpLeft,
+    SharpRight,
+    UTurn,
+    /// Two roads going roughly the same way, take the left one
+    KeepLeft,
+    KeepRight,
+    /// Take exit `exit` (counting from 1) of the roundabout
+    Roundabout {
+        exit: u8,
+    },
+    Arrive,
+}
+
+impl ManeuverKind {
+    /// Turn for a change in bearing in degrees, positive is to the right
+    pub fn from_bearing_delta(delta: f32) -> Self {
+        let left = delta < 0.0;
+        match delta.abs() {
+            d if d < 20.0 => Self::Continue,
+            d if d < 45.0 => pick(left, Self::SlightLeft, Self::SlightRight),
+            d if d < 135.0 => pick(left, Self::Left, Self::Right),
+            d if d < 170.0 => pick(left, Self::SharpLeft, Self::SharpRight),
+            _ => Self::UTurn,
+        }
+    }
+}
+
+fn pick(left: bool, a: ManeuverKind, b: ManeuverKind) -> ManeuverKind {
+    if left { a } else { b }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Maneuver {
+    pub kind: ManeuverKind,
+    pub coord: FixedCoord,
+    /// Meters from the previous maneuver to this one
+    pub distance_m: f32,
+    /// Meters from the start of the route to this one
+    pub position_m: f32,
+    /// Index into the route's edges of the edge driven after this maneuver
+    /// (the last edge for [`ManeuverKind::Arrive`])
+    pub edge_index: usize,
+    /// Geometry segment of the road driven after this maneuver, for
+    /// [`Graph::segment_name`] and [`Graph::segment_ref`]
+    pub segment: u32,
+}
+
+/// Bearing of the edge leaving its source (`at_end == false`) or arriving at its target
+fn edge_bearing(graph: &Graph, edge: &Edge, at_end: bool) -> f32 {
+    let points = graph.edge_points(edge);
+    let mut points: Vec<FixedCoord> = points.collect();
+    if at_end {
+        // Walk back from the node so both cases measure away from it
+        points.reverse();
+    }
+
+    let projection = LocalProjection::new(points[0]);
+    let origin = (0.0, 0.0);
+    let mut far = origin;
+    for p in &points[1..] {
+        far = projection.to_local(*p);
+        if distance(origin, far) >= BEARING_DISTANCE_M {
+            break;
+        }
+    }
+
+    if at_end {
+        bearing(far, origin)
+    } else {
+        bearing(origin, far)
+    }
+}
+
+fn same_road(graph: &Graph, a: &Edge, b: &Edge) -> bool {
+    let name = |e: &Edge| (graph.segment_name(e.segment), graph.segment_ref(e.segment));
+    let (a, b) = (name(a), name(b));
+    // Two unnamed roads might as well be different ones
+    a == b && (a.0.is_some() || a.1.is_some())
+}
+
+/// Counts the roads out of a roundabout at `node`, other than continuing around it
+fn roundabout_exits(graph: &Graph, node: u32) -> u8 {
+    graph.edges(node).filter(|e| !e.is_roundabout()).count() as u8
+}
+
+pub fn maneuvers(graph: &Graph, route: &Route) -> Vec<Maneuver> {
+    let edges = route
+        .path
+        .edges
+        .iter()
+        .map(|&e| graph.edge(e))
+        .collect::<Vec<_>>();
+    let mut result = Vec::new();
+    let Some(first) = edges.first() else {
+        return result;
+    };
+
+    result.push(Maneuver {
+        kind: ManeuverKind::Depart,
+        coord: route.start.coord,
+        distance_m: 0.0,
+        position_m: 0.0,
+        edge_index: 0,
+        segment: first.segment,
+    });
+
+    // Distance driven so far, the first edge only from where the route starts on it
+    let length = |e: &Edge| e.length_dm as f32 / 10.0;
+    let mut position = length(first) * (1.0 - route.start_fraction());
+    let mut last_position = 0.0;
+    // (index of the maneuver that entered it, exits passed) while going around a roundabout.
+    // A route can start on a roundabout, then there's nothing to fill in once it's left.
+    let mut roundabout: Option<(Option<usize>, u8)> = None;
+
+    for i in 1..edges.len() {
+        let (from, to) = (&edges[i - 1], &edges[i]);
+        let node = from.target;
+        let coord = graph.node_coord(node);
+
+        let mut push = |result: &mut Vec<Maneuver>, kind| {
+            result.push(Maneuver {
+                kind,
+                coord,
+                distance_m: position - last_position,
+                position_m: position,
+                edge_index: i,
+                segment: to.segment,
+            });
+            last_position = position;
+        };
+
+        match (from.is_roundabout(), to.is_roundabout(), &mut roundabout) {
+            // Entering, the exit number gets filled in once we leave
+            (false, true, _) => {
+                roundabout = Some((Some(result.len()), 0));
+                push(&mut result, ManeuverKind::Roundabout { exit: 0 });
+            }
+            (true, true, Some((_, exits))) => {
+                *exits += roundabout_exits(graph, node);
+            }
+            (true, true, None) => {
+                roundabout = Some((None, roundabout_exits(graph, node)));
+            }
+            (true, false, state) => {
+                let exits = state.map_or(0, |(_, exits)| exits);
+                let kind = ManeuverKind::Roundabout { exit: exits + 1 };
+                match state {
+                    Some((Some(index), _)) => result[*index].kind = kind,
+                    // Started on it, so leaving is the first thing to tell
+                    _ => push(&mut result, kind),
+                }
+                roundabout = None;
+            }
+            _ => {
+                let incoming = edge_bearing(graph, from, true);
+                let delta = bearing_delta(incoming, edge_bearing(graph, to, false));
+
+                // Anything but turning around onto the road we came from
+                let alternatives = graph
+                    .edges(node)
+                    .filter(|e| e.id != to.id && e.segment != from.segment)
+                    .map(|e| bearing_delta(incoming, edge_bearing(graph, &e, false)))
+                    .collect::<Vec<_>>();
+                // Going back the way we came, at a dead end or after a reroute, is always worth a mention
+                let turning_around = to.segment == from.segment;
+                if alternatives.is_empty() && !turning_around {
+                    position += length(to);
+                    continue;
+                }
+
+                // Another road going roughly the same way makes this a fork
+                let fork = alternatives
+                    .iter()
+                    .find(|alt| delta.abs() < 45.0 && alt.abs() < 45.0)
+                    .map(|alt| {
+                        if delta < *alt {
+                            ManeuverKind::KeepLeft
+                        } else {
+                            ManeuverKind::KeepRight
+                        }
+                    });
+
+                let kind = match (fork, ManeuverKind::from_bearing_delta(delta)) {
+                    _ if turning_around => Some(ManeuverKind::UTurn),
+                    (Some(keep), _) => Some(keep),
+                    (None, ManeuverKind::Continue) if same_road(graph, from, to) => None,
+                    (None, kind) => Some(kind),
+                };
+                if let Some(kind) = kind {
+                    push(&mut result, kind);
+                }
+            }
+        }
+
+        position += length(to);
+    }
+
+    let last = edges[edges.len() - 1];
+    position -= length(&last) * (1.0 - route.end_fraction());
+    result.push(Maneuver {
+        kind: ManeuverKind::Arrive,
+        coord: route.end.coord,
+        distance_m: position - last_position,
+        position_m: position,
+        edge_index: edges.len() - 1,
+        segment: last.segment,
+    });
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::ManeuverKind::*;
+    use super::*;
+    use crate::graph::fixture::{GraphFixture, edge_between};
+    use crate::graph::{EDGE_FLAG_ROUNDABOUT, Metric, Path};
+    use crate::route::Snap;
+
+    /// Adds a node `x` meters east and `y` meters north of a fixed point
+    fn node(fixture: &mut GraphFixture, x: f64, y: f64) -> u32 {
+        let meters_per_degree = 111_319.49;
+        let lat = 52.5 + y / meters_per_degree;
+        let lon = 13.4 + x / (meters_per_degree * libm::cos(52.5f64.to_radians()));
+        fixture.node(lat, lon)
+    }
+
+    /// The route through `nodes`, from the first to the last one
+    fn route(graph: &Graph, nodes: &[u32]) -> Route {
+        let edges = nodes
+            .windows(2)
+            .map(|pair| edge_between(graph, pair[0], pair[1]))
+            .collect::<Vec<_>>();
+        let snap = |edge: u32, fraction, node| Snap {
+            edge,
+            fraction,
+            distance_m: 0.0,
+            coord: graph.node_coord(node),
+        };
+        Route {
+            metric: Metric::Time,
+            start: snap(edges[0], 0.0, nodes[0]),
+            end: snap(edges[edges.len() - 1], 1.0, nodes[nodes.len() - 1]),
+            path: Path {
+                cost: 0,
+                source: nodes[0],
+                target: nodes[nodes.len() - 1],
+                edges,
+            },
+        }
+    }
+
+    fn kinds(graph: &Graph, nodes: &[u32]) -> Vec<ManeuverKind> {
+        maneuvers(graph, &route(graph, nodes))
+            .iter()
+            .map(|maneuver| maneuver.kind)
+            .collect()
+    }
+
+    #[test]
+    fn bearing_changes() {
+        assert_eq!(ManeuverKind::from_bearing_delta(0.0), Continue);
+        assert_eq!(ManeuverKind::from_bearing_delta(-19.0), Continue);
+        assert_eq!(ManeuverKind::from_bearing_delta(30.0), SlightRight);
+        assert_eq!(ManeuverKind::from_bearing_delta(-30.0), SlightLeft);
+        assert_eq!(ManeuverKind::from_bearing_delta(90.0), Right);
+        assert_eq!(ManeuverKind::from_bearing_delta(-90.0), Left);
+        assert_eq!(ManeuverKind::from_bearing_delta(150.0), SharpRight);
+        assert_eq!(ManeuverKind::from_bearing_delta(-150.0), SharpLeft);
+        assert_eq!(ManeuverKind::from_bearing_delta(180.0), UTurn);
+    }
+
+    /// A crossing 100 m north of the start, `Main` going on straight north
+    ///
+    /// ```text
+    ///         4
+    ///         |
+    ///  3 ---- 1 ---- 2
+    ///         |
+    ///         0
+    /// ```
+    fn crossing(north_name: &'static str) -> Vec<u8> {
+        let mut fixture = GraphFixture::new();
+        let start = node(&mut fixture, 0.0, 0.0);
+        let middle = node(&mut fixture, 0.0, 100.0);
+        let east = node(&mut fixture, 100.0, 100.0);
+        let west = node(&mut fixture, -100.0, 100.0);
+        let north = node(&mut fixture, 0.0, 200.0);
+        fixture.road(start, middle, 50, "Main");
+        fixture.road(middle, east, 50, "East");
+        fixture.road(middle, west, 50, "West");
+        fixture.road(middle, north, 50, north_name);
+        fixture.build(false)
+    }
+
+    #[test]
+    fn turns_at_a_crossing() {
+        let data = crossing("Main");
+        let graph = Graph::parse(&data).unwrap();
+        assert_eq!(kinds(&graph, &[0, 1, 2]), [Depart, Right, Arrive]);
+        assert_eq!(kinds(&graph, &[0, 1, 3]), [Depart, Left, Arrive]);
+        // Straight on along the same road needs no instruction
+        assert_eq!(kinds(&graph, &[0, 1, 4]), [Depart, Arrive]);
+
+        let maneuvers = maneuvers(&graph, &route(&graph, &[0, 1, 2]));
+        assert_eq!(maneuvers[1].coord, graph.node_coord(1));
+        assert_eq!(maneuvers[1].edge_index, 1);
+        assert_eq!(graph.segment_name(maneuvers[1].segment), Some("East"));
+        assert!((maneuvers[1].distance_m - 100.0).abs() < 0.5);
+        assert!((maneuvers[2].distance_m - 100.0).abs() < 0.5);
+        assert!((maneuvers[2].position_m - 200.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn continues_when_the_name_changes() {
+        let data = crossing("North");
+        let graph = Graph::parse(&data).unwrap();
+        assert_eq!(kinds(&graph, &[0, 1, 4]), [Depart, Continue, Arrive]);
+    }
+
+    #[test]
+    fn nothing_to_say_without_a_junction() {
+        let mut fixture = GraphFixture::new();
+        let start = node(&mut fixture, 0.0, 0.0);
+        let bend = node(&mut fixture, 0.0, 100.0);
+        let end = node(&mut fixture, 100.0, 100.0);
+        fixture.road(start, bend, 50, "Main");
+        fixture.road(bend, end, 50, "Other");
+        let data = fixture.build(false);
+        let graph = Graph::parse(&data).unwrap();
+        assert_eq!(kinds(&graph, &[0, 1, 2]), [Depart, Arrive]);
+    }
+
+    #[test]
+    fn forks() {
+        let mut fixture = GraphFixture::new();
+        let start = node(&mut fixture, 0.0, 0.0);
+        let fork = node(&mut fixture, 0.0, 100.0);
+        let right = node(&mut fixture, 30.0, 200.0);
+        let left = node(&mut fixture, -30.0, 200.0);
+        fixture.road(start, fork, 50, "Main");
+        fixture.road(fork, right, 50, "Right");
+        fixture.road(fork, left, 50, "Left");
+        let data = fixture.build(false);
+        let graph = Graph::parse(&data).unwrap();
+        assert_eq!(kinds(&graph, &[0, 1, 3]), [Depart, KeepLeft, Arrive]);
+        assert_eq!(kinds(&graph, &[0, 1, 2]), [Depart, KeepRight, Arrive]);
+    }
+
+    #[test]
+    fn turns_around_at_a_dead_end() {
+        let mut fixture = GraphFixture::new();
+        let start = node(&mut fixture, 0.0, 0.0);
+        let end = node(&mut fixture, 0.0, 100.0);
+        fixture.road(start, end, 50, "Main");
+        let data = fixture.build(false);
+        let graph = Graph::parse(&data).unwrap();
+        assert_eq!(kinds(&graph, &[0, 1, 0]), [Depart, UTurn, Arrive]);
+    }
+
+    /// A roundabout driven counterclockwise, with a road out of it to every side
+    ///
+    /// ```text
+    ///         6
+    ///         |
+    ///         2
+    ///  7 -- 3   1 -- 5
+    ///         0
+    ///         |
+    ///         4
+    /// ```
+    fn roundabout() -> Vec<u8> {
+        let mut fixture = GraphFixture::new();
+        let ring = [(0.0, 100.0), (50.0, 150.0), (0.0, 200.0), (-50.0, 150.0)]
+            .map(|(x, y)| node(&mut fixture, x, y));
+        let arms = [(0.0, 0.0), (150.0, 150.0), (0.0, 300.0), (-150.0, 150.0)]
+            .map(|(x, y)| node(&mut fixture, x, y));
+        for i in 0..4 {
+            fixture.edge(ring[i], ring[(i + 1) % 4], 30).flags = EDGE_FLAG_ROUNDABOUT;
+            fixture.road(ring[i], arms[i], 50, ["South", "East", "North", "West"][i]);
+        }
+        fixture.build(false)
+    }
+
+    #[test]
+    fn counts_roundabout_exits() {
+        let data = roundabout();
+        let graph = Graph::parse(&data).unwrap();
+        assert_eq!(
+            kinds(&graph, &[4, 0, 1, 5]),
+            [Depart, Roundabout { exit: 1 }, Arrive]
+        );
+        assert_eq!(
+            kinds(&graph, &[4, 0, 1, 2, 6]),
+            [Depart, Roundabout { exit: 2 }, Arrive]
+        );
+        assert_eq!(
+            kinds(&graph, &[4, 0, 1, 2, 3, 7]),
+            [Depart, Roundabout { exit: 3 }, Arrive]
+        );
+
+        // Told where the roundabout starts, not where it's left
+        let maneuvers = maneuvers(&graph, &route(&graph, &[4, 0, 1, 2, 6]));
+        assert_eq!(maneuvers[1].coord, graph.node_coord(0));
+    }
+
+    #[test]
+    fn leaves_a_roundabout_the_route_started_on() {
+        let data = roundabout();
+        let graph = Graph::parse(&data).unwrap();
+        assert_eq!(
+            kinds(&graph, &[1, 2, 6]),
+            [Depart, Roundabout { exit: 1 }, Arrive]
+        );
+        assert_eq!(
+            kinds(&graph, &[0, 1, 2, 3, 7]),
+            [Depart, Roundabout { exit: 3 }, Arrive]
+        );
+
+        let maneuvers = maneuvers(&graph, &route(&graph, &[0, 1, 2, 6]));
+        assert_eq!(maneuvers[1].kind, Roundabout { exit: 2 });
+        assert_eq!(maneuvers[1].coord, graph.node_coord(2));
+        assert_eq!(graph.segment_name(maneuvers[1].segment), Some("North"));
+    }
+}
@@ -0,0 +1,373 @@
+//! Describes a converted region, `manifest.txt` next to its other files. Plain text, one
+//! `key value` per line, so it can be checked by eye:
+//!
+//! ```text
+//! format 2
+//! region small
+//! extent 473000000 85000000 474000000 86000000
+//! zoom 19 19
+//! source_timestamp 1700000000
+//! converter 0.1.0
+//! style default
+//! file tiles.pack 1234567 9a8b7c6d
+//! ```
+//!
+//! `extent` is min lat, min lon, max lat, max lon in degrees * 1e7, `source_timestamp` is
+//! when the OSM data was exported, in seconds since 1970. Every `file` line has the file's
+//! size in bytes and CRC32. Unknown keys are skipped, so new ones can be added without
+//! bumping the format.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::graph::FixedCoord;
+use crate::tile_pack::TileContent;
+
+/// Version of the region's files as a whole. Bumped whenever an older PSP app can't read what
+/// the converter writes anymore, the app reads every version up to this one.
+///
+/// 2: the tile pack can hold vector tiles
+pub const FORMAT_VERSION: u16 = 2;
+
+pub const FILE_NAME: &str = "manifest.txt";
+
+/// The oldest format that has `content`, so regions that don't need anything newer stay
+/// readable for older PSP apps
+pub fn format_for(content: TileContent) -> u16 {
+    match content {
+        TileContent::Qoi => 1,
+        TileContent::Vector => 2,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestError {
+    NotUtf8,
+    /// Line `n` (from 1) isn't `key value`, or its value doesn't parse
+    BadLine(usize),
+    /// A required key is missing
+    Missing(&'static str),
+}
+
+/// A day in UTC, for showing how old the data is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    /// The day `seconds` since 1970 fall on
+    pub fn from_unix_seconds(seconds: i64) -> Self {
+        // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+        let days = seconds.div_euclid(86_400) + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        Self {
+            year: year_of_era + era * 400 + if month <= 2 { 1 } else { 0 },
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestFile {
+    pub name: String,
+    pub size: u64,
+    pub crc32: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub format: u16,
+    pub region: String,
+    /// (min, max)
+    pub extent: (FixedCoord, FixedCoord),
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    /// Seconds since 1970, if the source file said
+    pub source_timestamp: Option<i64>,
+    pub converter_version: String,
+    pub style: String,
+    pub files: Vec<ManifestFile>,
+}
+
+impl Manifest {
+    pub fn parse(data: &[u8]) -> Result<Self, ManifestError> {
+        let text = core::str::from_utf8(data).map_err(|_| ManifestError::NotUtf8)?;
+
+        let mut format = None;
+        let mut region = None;
+        let mut extent = None;
+        let mut zoom = None;
+        let mut source_timestamp = None;
+        let mut converter_version = None;
+        let mut style = None;
+        let mut files = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = ManifestError::BadLine(i + 1);
+            let (key, value) = line.split_once(' ').ok_or(bad)?;
+            let value = value.trim();
+            let numbers = |count| {
+                let numbers = value
+                    .split_whitespace()
+                    .map(|n| n.parse::<i64>().ok())
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|numbers| numbers.len() == count);
+                numbers.ok_or(bad)
+            };
+            match key {
+                "format" => format = Some(value.parse().map_err(|_| bad)?),
+                "region" => region = Some(String::from(value)),
+                "extent" => {
+                    let n = numbers(4)?;
+                    let coord = |lat: i64, lon: i64| {
+                        Some(FixedCoord {
+                            lat: i32::try_from(lat).ok()?,
+                            lon: i32::try_from(lon).ok()?,
+                        })
+                    };
+                    extent = Some(coord(n[0], n[1]).zip(coord(n[2], n[3])).ok_or(bad)?);
+                }
+                "zoom" => {
+                    let n = numbers(2)?;
+                    let level = |n: i64| u8::try_from(n).ok().filter(|&z| z <= 30);
+                    zoom = Some(level(n[0]).zip(level(n[1])).ok_or(bad)?);
+                }
+                "source_timestamp" => source_timestamp = Some(numbers(1)?[0]),
+                "converter" => converter_version = Some(String::from(value)),
+                "style" => style = Some(String::from(value)),
+                "file" => {
+                    let mut parts = value.split_whitespace();
+                    let (Some(name), Some(size), Some(crc32), None) =
+                        (parts.next(), parts.next(), parts.next(), parts.next())
+                    else {
+                        return Err(bad);
+                    };
+                    files.push(ManifestFile {
+                        name: String::from(name),
+                        size: size.parse().map_err(|_| bad)?,
+                        crc32: u32::from_str_radix(crc32, 16).map_err(|_| bad)?,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let (min_zoom, max_zoom) = zoom.ok_or(ManifestError::Missing("zoom"))?;
+        Ok(Self {
+            format: format.ok_or(ManifestError::Missing("format"))?,
+            region: region.ok_or(ManifestError::Missing("region"))?,
+            extent: extent.ok_or(ManifestError::Missing("extent"))?,
+            min_zoom,
+            max_zoom,
+            source_timestamp,
+            converter_version: converter_version.unwrap_or_default(),
+            style: style.unwrap_or_default(),
+            files,
+        })
+    }
+
+    /// Whether this app can read the region
+    pub fn is_compatible(&self) -> bool {
+        (1..=FORMAT_VERSION).contains(&self.format)
+    }
+
+    /// The day the OSM data was exported, if the source file said
+    pub fn source_date(&self) -> Option<Date> {
+        self.source_timestamp.map(Date::from_unix_seconds)
+    }
+
+    pub fn file(&self, name: &str) -> Option<&ManifestFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        // Writing to a String can't fail
+        let _ = self.write(&mut text);
+        text
+    }
+
+    fn write(&self, text: &mut String) -> core::fmt::Result {
+        let (min, max) = self.extent;
+        writeln!(text, "format {}", self.format)?;
+        writeln!(text, "region {}", self.region)?;
+        writeln!(
+            text,
+            "extent {} {} {} {}",
+            min.lat, min.lon, max.lat, max.lon
+        )?;
+        writeln!(text, "zoom {} {}", self.min_zoom, self.max_zoom)?;
+        if let Some(timestamp) = self.source_timestamp {
+            writeln!(text, "source_timestamp {timestamp}")?;
+        }
+        writeln!(text, "converter {}", self.converter_version)?;
+        writeln!(text, "style {}", self.style)?;
+        for file in &self.files {
+            writeln!(text, "file {} {} {:08x}", file.name, file.size, file.crc32)?;
+        }
+        Ok(())
+    }
+}
+
+/// CRC-32 as used by zip and PNG, fed in pieces so big files don't have to fit in memory
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC_TABLE[((self.0 ^ byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i64, month: u8, day: u8) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn days_since_1970() {
+        for (seconds, expected) in [
+            (0, date(1970, 1, 1)),
+            (86_399, date(1970, 1, 1)),
+            (-1, date(1969, 12, 31)),
+            (-31_536_000, date(1969, 1, 1)),
+            (946_684_800, date(2000, 1, 1)),
+            // Every 400 years is a leap year after all
+            (951_782_400, date(2000, 2, 29)),
+            (1_700_000_000, date(2023, 11, 14)),
+            (1_709_210_096, date(2024, 2, 29)),
+            // Every 100 isn't
+            (4_107_456_000, date(2100, 2, 28)),
+            (4_107_542_400, date(2100, 3, 1)),
+        ] {
+            assert_eq!(Date::from_unix_seconds(seconds), expected, "{seconds}");
+        }
+    }
+
+    #[test]
+    fn every_day_follows_the_one_before() {
+        let mut previous = Date::from_unix_seconds(-400 * 366 * 86_400);
+        for day in -400 * 366 + 1..400 * 366 {
+            let next = Date::from_unix_seconds(day * 86_400);
+            let follows = match (next.month, next.day) {
+                (1, 1) => {
+                    next.year == previous.year + 1 && (previous.month, previous.day) == (12, 31)
+                }
+                (_, 1) => next.year == previous.year && next.month == previous.month + 1,
+                _ => {
+                    (next.year, next.month) == (previous.year, previous.month)
+                        && next.day == previous.day + 1
+                }
+            };
+            assert!(follows, "{previous:?} {next:?}");
+            assert!((28..=31).contains(&previous.day) || next.day != 1);
+            previous = next;
+        }
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let manifest = Manifest {
+            format: FORMAT_VERSION,
+            region: String::from("small"),
+            extent: (
+                FixedCoord::from_degrees(47.3, 8.5),
+                FixedCoord::from_degrees(47.4, 8.6),
+            ),
+            min_zoom: 12,
+            max_zoom: 19,
+            source_timestamp: Some(1_700_000_000),
+            converter_version: String::from("0.1.0"),
+            style: String::from("default"),
+            files: alloc::vec![ManifestFile {
+                name: String::from("tiles.pack"),
+                size: 1_234_567,
+                crc32: 0x9a8b_7c6d,
+            }],
+        };
+        let parsed = Manifest::parse(manifest.to_text().as_bytes()).unwrap();
+        assert_eq!(parsed, manifest);
+        assert_eq!(parsed.source_date(), Some(date(2023, 11, 14)));
+        assert_eq!(parsed.file("tiles.pack").unwrap().size, 1_234_567);
+    }
+
+    #[test]
+    fn only_vector_tiles_need_the_new_format() {
+        assert_eq!(format_for(TileContent::Qoi), 1);
+        assert_eq!(format_for(TileContent::Vector), FORMAT_VERSION);
+    }
+
+    #[test]
+    fn standard_checksums() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+        let mut pieces = Crc32::new();
+        pieces.update(b"1234");
+        pieces.update(b"56789");
+        assert_eq!(pieces.finish(), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,408 @@
+//! Keeps the GPS position on the road.
+//!
+//! Every fix is compared with the roads around it: close by, pointing the way we're driving
+//! and connected to the road of the previous fix all make a road more likely. The scores
+//! carry over from fix to fix (Viterbi-style, without looking back), so a single fix that
+//! wobbles closer to a side street doesn't pull the marker onto it.
+
+use alloc::vec::Vec;
+
+use crate::geometry::{LocalProjection, bearing_delta};
+use crate::graph::Graph;
+use crate::route::{Snap, snap_to_edge, twin};
+use crate::tracking::TrackPosition;
+
+/// Grid cell size for finding nearby edges, about 275 m north to south
+const CELL_SIZE_E7: i32 = 25_000;
+
+/// One edge in one grid cell
+type GridEntry = (u64, u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatcherError {
+    /// The grid for this graph doesn't fit in the memory budget
+    OverBudget { needed: usize, budget: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatcherConfig {
+    /// Roads further away than this aren't considered at all
+    pub search_radius_m: f32,
+    /// Typical GPS error, how much being further from a road counts against it
+    pub distance_sigma_m: f32,
+    /// How much driving at an angle to a road counts against it
+    pub heading_sigma_deg: f32,
+    /// Below this speed the GPS heading is mostly noise and gets ignored
+    pub min_heading_speed_mps: f32,
+    /// Score for jumping to a road that isn't connected to the previous one
+    pub jump_penalty: f32,
+}
+
+impl Default for MatcherConfig {
+    fn default() -> Self {
+        Self {
+            search_radius_m: 50.0,
+            distance_sigma_m: 10.0,
+            heading_sigma_deg: 30.0,
+            min_heading_speed_mps: 3.0,
+            jump_penalty: 4.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchedPosition {
+    /// On the road, `snap.distance_m` is how far the fix was from it
+    pub snap: Snap,
+    /// Direction of the road at the matched position, in driving direction
+    pub bearing: f32,
+}
+
+#[derive(Clone, Copy)]
+struct Candidate {
+    matched: MatchedPosition,
+    source: u32,
+    target: u32,
+    /// Log-likelihood, relative to the best candidate
+    score: f32,
+}
+
+pub struct MapMatcher {
+    config: MatcherConfig,
+    /// (cell, edge) for every cell an edge's bounding box touches, sorted by cell
+    cells: Vec<GridEntry>,
+    candidates: Vec<Candidate>,
+    previous: Vec<Candidate>,
+}
+
+fn cell(lat: i32, lon: i32) -> (i32, i32) {
+    (lat.div_euclid(CELL_SIZE_E7), lon.div_euclid(CELL_SIZE_E7))
+}
+
+fn cell_key((y, x): (i32, i32)) -> u64 {
+    ((y as u32 as u64) << 32) | x as u32 as u64
+}
+
+/// Every grid cell the bounding box of an edge touches
+fn edge_cells(graph: &Graph, id: u32) -> impl Iterator<Item = u64> {
+    let (mut min, mut max) = ((i32::MAX, i32::MAX), (i32::MIN, i32::MIN));
+    for p in graph.edge_points(&graph.edge(id)) {
+        min = (min.0.min(p.lat), min.1.min(p.lon));
+        max = (max.0.max(p.lat), max.1.max(p.lon));
+    }
+    let (min, max) = (cell(min.0, min.1), cell(max.0, max.1));
+    (min.0..=max.0).flat_map(move |y| (min.1..=max.1).map(move |x| cell_key((y, x))))
+}
+
+impl MapMatcher {
+    /// Bytes the grid of edges takes for `graph`
+    pub fn memory_needed(graph: &Graph) -> usize {
+        let entries: usize = (0..graph.edge_count() as u32)
+            .map(|id| edge_cells(graph, id).count())
+            .sum();
+        entries * core::mem::size_of::<GridEntry>()
+    }
+
+    /// Builds the grid of edges, takes a moment on a large graph so do it once.
+    /// `memory_budget` is in bytes, the grid gets allocated only if it fits.
+    pub fn new(
+        graph: &Graph,
+        config: MatcherConfig,
+        memory_budget: usize,
+    ) -> Result<Self, MatcherError> {
+        let needed = Self::memory_needed(graph);
+        if needed > memory_budget {
+            return Err(MatcherError::OverBudget {
+                needed,
+                budget: memory_budget,
+            });
+        }
+
+        let mut cells = Vec::with_capacity(needed / core::mem::size_of::<GridEntry>());
+        for id in 0..graph.edge_count() as u32 {
+            cells.extend(edge_cells(graph, id).map(|key| (key, id)));
+        }
+        cells.sort_unstable();
+
+        Ok(Self {
+            config,
+            cells,
+            candidates: Vec::new(),
+            previous: Vec::new(),
+        })
+    }
+
+    /// Forgets the previous fixes, e.g. after the GPS lost its fix for a while
+    pub fn reset(&mut self) {
+        self.candidates.clear();
+    }
+
+    /// Matches a fix onto the most likely road. `None` when there's no road nearby,
+    /// then the raw position is the best there is.
+    pub fn update(&mut self, graph: &Graph, position: TrackPosition) -> Option<MatchedPosition> {
+        let config = self.config;
+        let projection = LocalProjection::new(position.coord);
+        let use_heading = position.speed >= config.min_heading_speed_mps;
+
+        core::mem::swap(&mut self.previous, &mut self.candidates);
+        self.candidates.clear();
+
+        // The search radius is well under a cell, so the cells around the fix's cell cover it
+        let (y, x) = cell(position.coord.lat, position.coord.lon);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let key = cell_key((y + dy, x + dx));
+                let first = self.cells.partition_point(|&(k, _)| k < key);
+                for &(_, id) in self.cells[first..].iter().take_while(|&&(k, _)| k == key) {
+                    // An edge can be in several of these cells
+                    if self.candidates.iter().any(|c| c.matched.snap.edge == id) {
+                        continue;
+                    }
+                    let edge = graph.edge(id);
+                    let Some((snap, bearing)) = snap_to_edge(graph, &projection, &edge) else {
+                        continue;
+                    };
+                    if snap.distance_m > config.search_radius_m {
+                        continue;
+                    }
+
+                    let mut score = -0.5 * square(snap.distance_m / config.distance_sigma_m);
+                    if use_heading {
+                        let delta = bearing_delta(bearing, position.heading);
+                        score -= 0.5 * square(delta / config.heading_sigma_deg);
+                    }
+
+                    self.candidates.push(Candidate {
+                        matched: MatchedPosition { snap, bearing },
+                        source: graph.edge_source(id),
+                        target: edge.target,
+                        score,
+                    });
+                }
+            }
+        }
+
+        if !self.previous.is_empty() {
+            for candidate in &mut self.candidates {
+                let best_transition = self
+                    .previous
+                    .iter()
+                    .map(|from| from.score + transition(graph, &config, from, candidate))
+                    .fold(f32::NEG_INFINITY, f32::max);
+                candidate.score += best_transition;
+            }
+        }
+
+        // Keep the scores relative, they'd drift off otherwise
+        let best = self
+            .candidates
+            .iter()
+            .copied()
+            .max_by(|a, b| a.score.total_cmp(&b.score))?;
+        for candidate in &mut self.candidates {
+            candidate.score -= best.score;
+        }
+
+        Some(best.matched)
+    }
+}
+
+/// Log-likelihood of having driven from one candidate to the next
+fn transition(graph: &Graph, config: &MatcherConfig, from: &Candidate, to: &Candidate) -> f32 {
+    let (a, b) = (&from.matched.snap, &to.matched.snap);
+    if a.edge == b.edge {
+        // Driving backwards along an edge is as unlikely as jumping
+        return if b.fraction + 0.05 >= a.fraction {
+            0.0
+        } else {
+            -config.jump_penalty
+        };
+    }
+    // The twin starts where the edge ends too, so check for turning around first
+    let turned_around = twin(graph, &graph.edge(a.edge)).is_some_and(|t| t.id == b.edge);
+    if turned_around {
+        return -config.jump_penalty * 0.5;
+    }
+    if to.source == from.target {
+        return 0.0;
+    }
+    // Somewhere on a road sharing a node, maybe missed the edge in between
+    if to.source == from.source || to.target == from.target || to.target == from.source {
+        return -1.0;
+    }
+    -config.jump_penalty
+}
+
+fn square(x: f32) -> f32 {
+    x * x
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::graph::FixedCoord;
+    use crate::graph::fixture::{GraphFixture, edge_between};
+
+    /// `x` meters east and `y` meters north of a fixed point
+    fn at(x: f64, y: f64) -> FixedCoord {
+        let meters_per_degree = 111_319.49;
+        FixedCoord::from_degrees(
+            52.5 + y / meters_per_degree,
+            13.4 + x / (meters_per_degree * libm::cos(52.5f64.to_radians())),
+        )
+    }
+
+    /// A main road 1 km east with a side street going north from its middle, and a separate
+    /// service road running alongside it 30 m to the north
+    fn network() -> (Vec<u8>, [u32; 6]) {
+        let mut fixture = GraphFixture::new();
+        let nodes = [
+            (0.0, 0.0),
+            (500.0, 0.0),
+            (1000.0, 0.0),
+            (500.0, 600.0),
+            (100.0, 30.0),
+            (400.0, 30.0),
+        ]
+        .map(|(x, y)| {
+            let coord = at(x, y);
+            fixture.node(coord.lat_degrees(), coord.lon_degrees())
+        });
+        fixture.road(nodes[0], nodes[1], 50, "Main Street");
+        fixture.road(nodes[1], nodes[2], 50, "Main Street");
+        fixture.road(nodes[1], nodes[3], 30, "Side Street");
+        fixture.road(nodes[4], nodes[5], 20, "");
+        (fixture.build(false), nodes)
+    }
+
+    fn position(x: f64, y: f64, heading: f32, speed: f32) -> TrackPosition {
+        TrackPosition {
+            coord: at(x, y),
+            heading,
+            speed,
+            time_ms: 0,
+        }
+    }
+
+    fn matcher(graph: &Graph) -> MapMatcher {
+        MapMatcher::new(graph, MatcherConfig::default(), usize::MAX).unwrap()
+    }
+
+    #[test]
+    fn snaps_onto_the_closest_road() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut matcher = matcher(&graph);
+
+        let matched = matcher
+            .update(&graph, position(700.0, 8.0, 0.0, 0.0))
+            .unwrap();
+        assert!(
+            [
+                edge_between(&graph, nodes[1], nodes[2]),
+                edge_between(&graph, nodes[2], nodes[1])
+            ]
+            .contains(&matched.snap.edge)
+        );
+        assert!((matched.snap.distance_m - 8.0).abs() < 0.5);
+        let local = LocalProjection::new(at(700.0, 0.0)).to_local(matched.snap.coord);
+        assert!(local.0.abs() < 0.5 && local.1.abs() < 0.5);
+
+        // Nothing within the search radius
+        assert_eq!(
+            matcher.update(&graph, position(800.0, 300.0, 0.0, 0.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn picks_the_direction_being_driven() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut matcher = matcher(&graph);
+
+        let east = matcher
+            .update(&graph, position(700.0, 5.0, 85.0, 12.0))
+            .unwrap();
+        assert_eq!(east.snap.edge, edge_between(&graph, nodes[1], nodes[2]));
+        assert!(bearing_delta(east.bearing, 90.0).abs() < 1.0);
+
+        matcher.reset();
+        let west = matcher
+            .update(&graph, position(700.0, 5.0, 275.0, 12.0))
+            .unwrap();
+        assert_eq!(west.snap.edge, edge_between(&graph, nodes[2], nodes[1]));
+        assert!(bearing_delta(west.bearing, 270.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn one_stray_fix_stays_on_the_road() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let main_street = edge_between(&graph, nodes[0], nodes[1]);
+        let mut matcher = matcher(&graph);
+
+        for x in [100.0, 150.0, 200.0] {
+            let matched = matcher
+                .update(&graph, position(x, 2.0, 90.0, 12.0))
+                .unwrap();
+            assert_eq!(matched.snap.edge, main_street);
+        }
+        // Closer to the service road, but that one isn't connected
+        let stray = position(250.0, 18.0, 90.0, 12.0);
+        let matched = matcher.update(&graph, stray).unwrap();
+        assert_eq!(matched.snap.edge, main_street);
+
+        // Without the fixes before it, it does end up there
+        matcher.reset();
+        let matched = matcher.update(&graph, stray).unwrap();
+        assert_eq!(matched.snap.edge, edge_between(&graph, nodes[4], nodes[5]));
+    }
+
+    #[test]
+    fn follows_a_turn_onto_a_connected_road() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut matcher = matcher(&graph);
+
+        let mut trace = (6..=10)
+            .map(|i| position(i as f64 * 50.0 - 5.0, -3.0, 90.0, 10.0))
+            .collect::<Vec<_>>();
+        trace.extend((1..=4).map(|i| position(497.0, i as f64 * 40.0, 0.0, 10.0)));
+        let edges = trace
+            .iter()
+            .map(|&p| matcher.update(&graph, p).unwrap().snap.edge)
+            .collect::<Vec<_>>();
+
+        assert!(
+            edges[..4]
+                .iter()
+                .all(|&edge| edge == edge_between(&graph, nodes[0], nodes[1]))
+        );
+        assert!(
+            edges[5..]
+                .iter()
+                .all(|&edge| edge == edge_between(&graph, nodes[1], nodes[3]))
+        );
+    }
+
+    #[test]
+    fn the_grid_has_to_fit() {
+        let (data, _) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let needed = MapMatcher::memory_needed(&graph);
+        // Every edge is in at least one cell
+        assert!(needed >= graph.edge_count() * core::mem::size_of::<GridEntry>());
+
+        assert!(MapMatcher::new(&graph, MatcherConfig::default(), needed).is_ok());
+        assert_eq!(
+            MapMatcher::new(&graph, MatcherConfig::default(), needed - 1).err(),
+            Some(MatcherError::OverBudget {
+                needed,
+                budget: needed - 1
+            })
+        );
+    }
+}
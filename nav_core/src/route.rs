@@ -0,0 +1,590 @@
+//! Point to point routing: snaps both ends onto the nearest road and searches the graph,
+//! with the contraction hierarchy when there is one and plain Dijkstra otherwise.
+//!
+//! All search memory is allocated when the [`Router`] is made, sized to fit a budget,
+//! so a route request can never run the PSP out of memory halfway through.
+
+use alloc::vec;
+
+use crate::geometry::{LocalProjection, bearing, bearing_delta, project_onto_line};
+use crate::graph::{
+    Edge, FixedCoord, Graph, Metric, Path,
+    ch::ChQuery,
+    dijkstra::{Dijkstra, DijkstraError},
+};
+
+/// Roughly what one settled node costs Dijkstra in heap and bookkeeping
+const BYTES_PER_SETTLED_NODE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+    /// Not even the search state for this graph fits in the memory budget
+    OverBudget { needed: usize, budget: usize },
+    /// No road close enough to the start
+    NoStart,
+    /// No road close enough to the destination
+    NoDestination,
+    /// The roads aren't connected (or only through forbidden turns)
+    NoRoute,
+    /// The route is too long to find within the memory budget
+    SearchLimit,
+    /// Only the contraction hierarchy fit in the memory budget, and it only does
+    /// [`Metric::Time`]
+    MetricUnavailable,
+}
+
+/// A position on an edge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snap {
+    pub edge: u32,
+    /// How far along the edge, in driving direction, from 0 to 1
+    pub fraction: f32,
+    /// Distance between the requested position and the road, in meters
+    pub distance_m: f32,
+    /// The snapped position on the road
+    pub coord: FixedCoord,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub metric: Metric,
+    pub start: Snap,
+    pub end: Snap,
+    /// Runs from the start of the first edge to the end of the last one, the parts of those
+    /// before `start` and after `end` aren't driven. The cost does account for that.
+    pub path: Path,
+}
+
+impl Route {
+    /// How far along the path's first edge the route starts. The start can be snapped onto
+    /// the other direction of the same road, then it's counted from the other end.
+    pub fn start_fraction(&self) -> f32 {
+        if self.path.edges.first() == Some(&self.start.edge) {
+            self.start.fraction
+        } else {
+            1.0 - self.start.fraction
+        }
+    }
+
+    /// How far along the path's last edge the route ends
+    pub fn end_fraction(&self) -> f32 {
+        if self.path.edges.last() == Some(&self.end.edge) {
+            self.end.fraction
+        } else {
+            1.0 - self.end.fraction
+        }
+    }
+}
+
+/// Finds the closest point on any road within `max_distance_m`.
+/// This looks at every edge, which is fine for the occasional route request.
+pub fn snap(graph: &Graph, coord: FixedCoord, max_distance_m: f32) -> Option<Snap> {
+    snap_impl(graph, coord, None, max_distance_m)
+}
+
+/// Like [`snap`], but only onto roads that can be driven in the direction of `heading`
+/// (degrees, 0 is north), give or take 90 degrees
+pub fn snap_heading(
+    graph: &Graph,
+    coord: FixedCoord,
+    heading: f32,
+    max_distance_m: f32,
+) -> Option<Snap> {
+    snap_impl(graph, coord, Some(heading), max_distance_m)
+}
+
+fn snap_impl(
+    graph: &Graph,
+    coord: FixedCoord,
+    heading: Option<f32>,
+    max_distance_m: f32,
+) -> Option<Snap> {
+    let projection = LocalProjection::new(coord);
+
+    let mut best: Option<Snap> = None;
+    for id in 0..graph.edge_count() as u32 {
+        let edge = graph.edge(id);
+
+        // Cheap rejection on the end nodes first, edges longer than this are rare
+        let a = projection.to_local(graph.node_coord(graph.edge_source(id)));
+        let b = projection.to_local(graph.node_coord(edge.target));
+        let reach = edge.length_dm as f32 / 10.0 + max_distance_m;
+        if a.0.abs().min(b.0.abs()) > reach || a.1.abs().min(b.1.abs()) > reach {
+            continue;
+        }
+
+        let Some((snap, piece_bearing)) = snap_to_edge(graph, &projection, &edge) else {
+            continue;
+        };
+        if heading.is_some_and(|heading| bearing_delta(piece_bearing, heading).abs() > 90.0) {
+            continue;
+        }
+        if snap.distance_m <= max_distance_m
+            && best.is_none_or(|best| snap.distance_m < best.distance_m)
+        {
+            best = Some(snap);
+        }
+    }
+
+    best
+}
+
+/// Closest point on one edge to the origin of `projection`, with the bearing of the
+/// edge there (in driving direction)
+pub fn snap_to_edge(
+    graph: &Graph,
+    projection: &LocalProjection,
+    edge: &Edge,
+) -> Option<(Snap, f32)> {
+    let p = (0.0, 0.0);
+
+    // Closest point on this edge as (distance, meters along the edge, position, bearing)
+    let mut closest: Option<(f32, f32, (f32, f32), f32)> = None;
+    let mut along = 0.0;
+    let mut points = graph.edge_points(edge).map(|c| projection.to_local(c));
+    let mut prev = points.next()?;
+    for next in points {
+        let piece = crate::geometry::distance(prev, next);
+        let (t, distance) = project_onto_line(p, prev, next);
+        if closest.is_none_or(|(d, ..)| distance < d) {
+            let point = (
+                prev.0 + (next.0 - prev.0) * t,
+                prev.1 + (next.1 - prev.1) * t,
+            );
+            closest = Some((distance, along + piece * t, point, bearing(prev, next)));
+        }
+        along += piece;
+        prev = next;
+    }
+
+    let (distance, closest_along, point, piece_bearing) = closest?;
+    let snap = Snap {
+        edge: edge.id,
+        fraction: if along > 0.0 {
+            (closest_along / along).clamp(0.0, 1.0)
+        } else {
+            0.0
+        },
+        distance_m: distance,
+        coord: projection.to_coord(point),
+    };
+    Some((snap, piece_bearing))
+}
+
+/// The edge for driving the same road the other way, if that's allowed
+pub fn twin(graph: &Graph, edge: &Edge) -> Option<Edge> {
+    let source = graph.edge_source(edge.id);
+    graph
+        .edges(edge.target)
+        .find(|e| e.segment == edge.segment && e.target == source)
+}
+
+fn partial_weight(metric: Metric, edge: &Edge, fraction: f32) -> u32 {
+    (metric.weight(edge) as f32 * fraction.clamp(0.0, 1.0)) as u32
+}
+
+pub struct Router {
+    ch: Option<ChQuery>,
+    dijkstra: Option<Dijkstra>,
+    max_settled: usize,
+    /// How far from a road the start and destination can be
+    pub snap_distance_m: f32,
+}
+
+impl Router {
+    /// Allocates the search state, `memory_budget` is in bytes and doesn't include the graph itself.
+    /// The hierarchy gets priority since it's the only thing fast enough for long routes, Dijkstra
+    /// (needed for shortest routes, or when there's no hierarchy) gets what's left.
+    pub fn new(graph: &Graph, memory_budget: usize) -> Result<Self, RouteError> {
+        let nodes = graph.node_count();
+        let mut left = memory_budget;
+
+        let ch = match graph.hierarchy() {
+            Some(_) if ChQuery::memory_needed(nodes) <= left => {
+                left -= ChQuery::memory_needed(nodes);
+                Some(ChQuery::new(nodes))
+            }
+            _ => None,
+        };
+
+        // Without room for at least a small search Dijkstra is pointless
+        let dijkstra_needed = Dijkstra::memory_needed(nodes) + 1024 * BYTES_PER_SETTLED_NODE;
+        let dijkstra = if dijkstra_needed <= left {
+            left -= Dijkstra::memory_needed(nodes);
+            Some(Dijkstra::new(nodes))
+        } else {
+            None
+        };
+
+        if ch.is_none() && dijkstra.is_none() {
+            return Err(RouteError::OverBudget {
+                needed: dijkstra_needed,
+                budget: memory_budget,
+            });
+        }
+
+        Ok(Self {
+            ch,
+            dijkstra,
+            max_settled: left / BYTES_PER_SETTLED_NODE,
+            snap_distance_m: 200.0,
+        })
+    }
+
+    pub fn supports(&self, metric: Metric) -> bool {
+        self.dijkstra.is_some() || (metric == Metric::Time && self.ch.is_some())
+    }
+
+    pub fn route(
+        &mut self,
+        graph: &Graph,
+        from: FixedCoord,
+        to: FixedCoord,
+        metric: Metric,
+    ) -> Result<Route, RouteError> {
+        let start = snap(graph, from, self.snap_distance_m).ok_or(RouteError::NoStart)?;
+        let end = snap(graph, to, self.snap_distance_m).ok_or(RouteError::NoDestination)?;
+        self.route_between(graph, start, end, metric)
+    }
+
+    /// Routes again from where the car is now to the destination of `route`.
+    /// With a heading the route starts in that direction, instead of turning around on the spot.
+    pub fn reroute(
+        &mut self,
+        graph: &Graph,
+        from: FixedCoord,
+        heading: Option<f32>,
+        route: &Route,
+    ) -> Result<Route, RouteError> {
+        let start = heading
+            .and_then(|heading| snap_heading(graph, from, heading, self.snap_distance_m))
+            .or_else(|| snap(graph, from, self.snap_distance_m))
+            .ok_or(RouteError::NoStart)?;
+        self.route_impl(graph, start, route.end, route.metric, heading.is_none())
+    }
+
+    /// Routes between two already snapped positions, rerouting can use a snap of its own
+    pub fn route_between(
+        &mut self,
+        graph: &Graph,
+        start: Snap,
+        end: Snap,
+        metric: Metric,
+    ) -> Result<Route, RouteError> {
+        self.route_impl(graph, start, end, metric, true)
+    }
+
+    fn route_impl(
+        &mut self,
+        graph: &Graph,
+        start: Snap,
+        end: Snap,
+        metric: Metric,
+        allow_turn_around: bool,
+    ) -> Result<Route, RouteError> {
+        let start_edge = graph.edge(start.edge);
+        let end_edge = graph.edge(end.edge);
+
+        // Start partway along an edge: either drive on to its end or turn around onto its twin
+        let mut sources = vec![(
+            start_edge.target,
+            partial_weight(metric, &start_edge, 1.0 - start.fraction),
+        )];
+        let start_twin = twin(graph, &start_edge).filter(|_| allow_turn_around);
+        if let Some(twin) = &start_twin {
+            sources.push((twin.target, partial_weight(metric, twin, start.fraction)));
+        }
+
+        // And arrive partway along an edge, from either of its ends
+        let mut targets = vec![(
+            graph.edge_source(end.edge),
+            partial_weight(metric, &end_edge, end.fraction),
+        )];
+        let end_twin = twin(graph, &end_edge);
+        if let Some(twin) = &end_twin {
+            targets.push((
+                graph.edge_source(twin.id),
+                partial_weight(metric, twin, 1.0 - end.fraction),
+            ));
+        }
+
+        let path = match (&mut self.ch, &mut self.dijkstra, metric) {
+            (Some(ch), _, Metric::Time) => ch.run(graph.hierarchy().unwrap(), &sources, &targets),
+            (_, Some(dijkstra), _) => dijkstra
+                .run(graph, metric, &sources, &targets, self.max_settled)
+                .map_err(|e| match e {
+                    DijkstraError::SearchLimit(_) => RouteError::SearchLimit,
+                })?,
+            _ => return Err(RouteError::MetricUnavailable),
+        };
+
+        // The search goes from node to node, add the partial edges at both ends
+        let mut best = path.map(|path| {
+            let first = if start_twin.is_some_and(|t| t.target == path.source)
+                && start_edge.target != path.source
+            {
+                start_twin.unwrap()
+            } else {
+                start_edge
+            };
+            let last = if end_twin.is_some_and(|t| graph.edge_source(t.id) == path.target)
+                && graph.edge_source(end.edge) != path.target
+            {
+                end_twin.unwrap()
+            } else {
+                end_edge
+            };
+
+            let mut edges = vec![first.id];
+            edges.extend_from_slice(&path.edges);
+            edges.push(last.id);
+            Path {
+                cost: path.cost,
+                source: graph.edge_source(first.id),
+                target: last.target,
+                edges,
+            }
+        });
+
+        // Both ends on the same road, possibly without ever reaching a node
+        let end_along_start = if end.edge == start_edge.id {
+            Some(end.fraction)
+        } else if start_twin.is_some_and(|t| t.id == end.edge) {
+            Some(1.0 - end.fraction)
+        } else {
+            None
+        };
+        if let Some(end_along_start) = end_along_start {
+            let direct = if start.fraction <= end_along_start {
+                Some((start_edge, end_along_start - start.fraction))
+            } else {
+                start_twin.map(|twin| (twin, start.fraction - end_along_start))
+            };
+            if let Some((edge, fraction)) = direct {
+                let cost = partial_weight(metric, &edge, fraction);
+                if best.as_ref().is_none_or(|best| cost < best.cost) {
+                    best = Some(Path {
+                        cost,
+                        source: graph.edge_source(edge.id),
+                        target: edge.target,
+                        edges: vec![edge.id],
+                    });
+                }
+            }
+        }
+
+        let path = best.ok_or(RouteError::NoRoute)?;
+        Ok(Route {
+            metric,
+            start,
+            end,
+            path,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::graph::fixture::{GraphFixture, edge_between};
+
+    /// Two streets 111 m apart and three cross streets. The top street bends up in the middle,
+    /// so it's longer, but it's much faster. There's also a road far away that isn't connected
+    /// to the others.
+    ///
+    /// ```text
+    ///       1
+    /// 0 --/ | \-- 2
+    /// |     |     |
+    /// 3 --- 4 --- 5       6 --- 7
+    /// ```
+    fn network() -> GraphFixture {
+        let mut fixture = GraphFixture::new();
+        for (lat, lon) in [
+            (52.501, 13.400),
+            (52.5015, 13.401),
+            (52.501, 13.402),
+            (52.500, 13.400),
+            (52.500, 13.401),
+            (52.500, 13.402),
+            (52.500, 13.420),
+            (52.500, 13.421),
+        ] {
+            fixture.node(lat, lon);
+        }
+        fixture.road(0, 1, 50, "Top");
+        fixture.road(1, 2, 50, "Top");
+        fixture.road(3, 4, 5, "Bottom");
+        fixture.road(4, 5, 5, "Bottom");
+        for (a, b) in [(0, 3), (1, 4), (2, 5)] {
+            fixture.road(a, b, 30, "Cross");
+        }
+        fixture.road(6, 7, 30, "Away");
+        fixture
+    }
+
+    fn at(lat: f64, lon: f64) -> FixedCoord {
+        FixedCoord::from_degrees(lat, lon)
+    }
+
+    /// Checks the edges follow each other and returns the nodes the route passes
+    fn nodes(graph: &Graph, route: &Route) -> Vec<u32> {
+        let mut nodes = alloc::vec![graph.edge_source(route.path.edges[0])];
+        for &e in &route.path.edges {
+            assert_eq!(graph.edge_source(e), *nodes.last().unwrap());
+            nodes.push(graph.edge(e).target);
+        }
+        nodes
+    }
+
+    #[test]
+    fn hierarchy_and_dijkstra_find_the_same_route() {
+        let (with_ch, without_ch) = (network().build(true), network().build(false));
+        let (from, to) = (at(52.5005, 13.4), at(52.5005, 13.402));
+        let mut costs = Vec::new();
+        for data in [&with_ch, &without_ch] {
+            let graph = Graph::parse(data).unwrap();
+            let mut router = Router::new(&graph, 1 << 20).unwrap();
+            let route = router.route(&graph, from, to, Metric::Time).unwrap();
+            assert_eq!(nodes(&graph, &route), [3, 0, 1, 2, 5]);
+            costs.push(route.path.cost);
+        }
+        assert_eq!(costs[0], costs[1]);
+    }
+
+    #[test]
+    fn shortest_and_fastest_route_differ() {
+        let data = network().build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let mut router = Router::new(&graph, 1 << 20).unwrap();
+        let (from, to) = (at(52.5005, 13.4), at(52.5005, 13.402));
+        let route = router.route(&graph, from, to, Metric::Distance).unwrap();
+        assert_eq!(nodes(&graph, &route), [0, 3, 4, 5, 2]);
+        // Half of each cross street and the whole bottom street, in decimeters
+        let expected = graph.edge(edge_between(&graph, 3, 4)).length_dm * 2
+            + graph.edge(edge_between(&graph, 0, 3)).length_dm;
+        assert!(route.path.cost.abs_diff(expected) <= 2);
+    }
+
+    #[test]
+    fn starts_and_ends_partway_along_roads() {
+        let data = network().build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let mut router = Router::new(&graph, 1 << 20).unwrap();
+        let route = router
+            .route(
+                &graph,
+                at(52.5, 13.4005),
+                at(52.5, 13.4015),
+                Metric::Distance,
+            )
+            .unwrap();
+        assert_eq!(nodes(&graph, &route), [3, 4, 5]);
+        assert!((0.45..0.55).contains(&route.start_fraction()));
+        assert!((0.45..0.55).contains(&route.end_fraction()));
+        let half = graph.edge(edge_between(&graph, 3, 4)).length_dm;
+        assert!(route.path.cost.abs_diff(half) <= 2);
+    }
+
+    #[test]
+    fn both_ends_on_the_same_road() {
+        let data = network().build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let mut router = Router::new(&graph, 1 << 20).unwrap();
+        let route = router
+            .route(&graph, at(52.5, 13.4008), at(52.5, 13.4002), Metric::Time)
+            .unwrap();
+        assert_eq!(route.path.edges, [edge_between(&graph, 4, 3)]);
+    }
+
+    #[test]
+    fn one_way_streets_are_driven_around() {
+        let mut fixture = GraphFixture::new();
+        for (lat, lon) in [(52.501, 13.400), (52.501, 13.401), (52.500, 13.401)] {
+            fixture.node(lat, lon);
+        }
+        fixture.edge(0, 1, 50);
+        fixture.road(1, 2, 30, "");
+        fixture.road(2, 0, 30, "");
+        let data = fixture.build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let mut router = Router::new(&graph, 1 << 20).unwrap();
+        let route = router
+            .route(
+                &graph,
+                at(52.501, 13.4007),
+                at(52.501, 13.4003),
+                Metric::Time,
+            )
+            .unwrap();
+        assert_eq!(nodes(&graph, &route), [0, 1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn reroute_keeps_going_the_way_the_car_faces() {
+        let data = network().build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let mut router = Router::new(&graph, 1 << 20).unwrap();
+        let route = router
+            .route(&graph, at(52.5, 13.4005), at(52.5, 13.4015), Metric::Time)
+            .unwrap();
+
+        // Facing west on the bottom street, away from the destination
+        let rerouted = router
+            .reroute(&graph, at(52.5, 13.4007), Some(270.0), &route)
+            .unwrap();
+        assert_eq!(rerouted.path.edges[0], edge_between(&graph, 4, 3));
+        assert_eq!(rerouted.end, route.end);
+    }
+
+    #[test]
+    fn reports_what_went_wrong() {
+        let data = network().build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let mut router = Router::new(&graph, 1 << 20).unwrap();
+        let mut route = |from, to| router.route(&graph, from, to, Metric::Time);
+
+        let (near, far, away) = (at(52.5, 13.4005), at(52.6, 13.4), at(52.5, 13.4205));
+        assert_eq!(route(far, near), Err(RouteError::NoStart));
+        assert_eq!(route(near, far), Err(RouteError::NoDestination));
+        assert_eq!(route(near, away), Err(RouteError::NoRoute));
+    }
+
+    #[test]
+    fn only_the_hierarchy_fits() {
+        let data = network().build(true);
+        let graph = Graph::parse(&data).unwrap();
+        let budget = ChQuery::memory_needed(graph.node_count());
+        let mut router = Router::new(&graph, budget).unwrap();
+        assert!(router.supports(Metric::Time));
+        assert!(!router.supports(Metric::Distance));
+
+        let (from, to) = (at(52.5, 13.4005), at(52.5, 13.4015));
+        assert!(router.route(&graph, from, to, Metric::Time).is_ok());
+        assert_eq!(
+            router.route(&graph, from, to, Metric::Distance),
+            Err(RouteError::MetricUnavailable)
+        );
+    }
+
+    #[test]
+    fn search_memory_has_to_fit() {
+        let data = network().build(false);
+        let graph = Graph::parse(&data).unwrap();
+        assert!(matches!(
+            Router::new(&graph, 100),
+            Err(RouteError::OverBudget { budget: 100, .. })
+        ));
+
+        let budget = Dijkstra::memory_needed(graph.node_count()) + 1024 * BYTES_PER_SETTLED_NODE;
+        let mut router = Router::new(&graph, budget).unwrap();
+        assert!(router.ch.is_none());
+        router.max_settled = 2;
+        assert_eq!(
+            router.route(&graph, at(52.5, 13.4005), at(52.501, 13.4015), Metric::Time),
+            Err(RouteError::SearchLimit)
+        );
+    }
+}
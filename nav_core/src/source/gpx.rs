@@ -0,0 +1,351 @@
+//! Reads the track points out of a GPX file, just enough XML for what GPS loggers write.
+//!
+//! GPX 1.0 has `<speed>` and `<course>` on track points, 1.1 dropped them. Without them
+//! speed and direction are worked out from the points around each one.
+
+use alloc::vec::Vec;
+use core::str;
+
+use crate::fix::{Fix, FixQuality, UtcTime};
+use crate::geometry::{LocalProjection, bearing, distance};
+use crate::graph::FixedCoord;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpxError {
+    NotUtf8,
+    NoTrackPoints,
+    /// Track point number `n` (from 0) lacks a position or a time, or has a broken one
+    BadTrackPoint(usize),
+}
+
+/// Every track point of every track, in file order
+pub fn parse(data: &[u8]) -> Result<Vec<Fix>, GpxError> {
+    let text = str::from_utf8(data).map_err(|_| GpxError::NotUtf8)?;
+
+    let mut fixes = Vec::new();
+    // Speed and course as found in the file, filled in afterwards where missing
+    let mut given = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<trkpt") {
+        rest = &rest[start..];
+        let n = fixes.len();
+        let tag_end = rest.find('>').ok_or(GpxError::BadTrackPoint(n))?;
+        let tag = &rest[..tag_end];
+        let body = if tag.ends_with('/') {
+            ""
+        } else {
+            let end = rest.find("</trkpt>").ok_or(GpxError::BadTrackPoint(n))?;
+            let body = &rest[tag_end + 1..end];
+            // Not closed before the next one starts
+            if body.contains("<trkpt") {
+                return Err(GpxError::BadTrackPoint(n));
+            }
+            body
+        };
+        rest = &rest[tag_end + 1..];
+
+        let coord = attribute(tag, "lat")
+            .zip(attribute(tag, "lon"))
+            .and_then(|(lat, lon)| Some((lat.parse::<f64>().ok()?, lon.parse::<f64>().ok()?)))
+            .filter(|(lat, lon)| lat.abs() <= 90.0 && lon.abs() <= 180.0)
+            .ok_or(GpxError::BadTrackPoint(n))?;
+        let time = element(body, "time")
+            .and_then(parse_time)
+            .ok_or(GpxError::BadTrackPoint(n))?;
+        let number = |name| element(body, name).and_then(|s| s.parse::<f32>().ok());
+        let altitude = number("ele");
+
+        fixes.push(Fix {
+            coord: FixedCoord::from_degrees(coord.0, coord.1),
+            altitude_m: altitude.unwrap_or(0.0),
+            speed_mps: 0.0,
+            bearing: 0.0,
+            hdop: number("hdop").unwrap_or(1.0),
+            quality: if altitude.is_some() {
+                FixQuality::ThreeD
+            } else {
+                FixQuality::TwoD
+            },
+            time,
+            satellites_used: number("sat").map(|n| n as u8).unwrap_or(0),
+        });
+        given.push((number("speed"), number("course")));
+    }
+
+    if fixes.is_empty() {
+        return Err(GpxError::NoTrackPoints);
+    }
+
+    for i in 0..fixes.len() {
+        // Going from this point to the next, the last one from the one before it
+        let (a, b) = match i + 1 < fixes.len() {
+            true => (fixes[i], fixes[i + 1]),
+            false if i > 0 => (fixes[i - 1], fixes[i]),
+            false => (fixes[i], fixes[i]),
+        };
+        let projection = LocalProjection::new(a.coord);
+        let (from, to) = ((0.0, 0.0), projection.to_local(b.coord));
+        let seconds = (b.time.unix_seconds() - a.time.unix_seconds()) as f32;
+
+        let (speed, course) = given[i];
+        fixes[i].speed_mps = speed.unwrap_or(if seconds > 0.0 {
+            distance(from, to) / seconds
+        } else {
+            0.0
+        });
+        fixes[i].bearing = course.unwrap_or_else(|| {
+            if from == to {
+                // Standing still, keep pointing the same way
+                if i > 0 { fixes[i - 1].bearing } else { 0.0 }
+            } else {
+                bearing(from, to)
+            }
+        });
+    }
+
+    Ok(fixes)
+}
+
+/// Value of `name="..."` (or with single quotes) in an opening tag
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    loop {
+        let at = rest.find(name)?;
+        let before = rest[..at].chars().next_back();
+        let after = rest[at + name.len()..].trim_start();
+        rest = &rest[at + name.len()..];
+        // Skip `lat` inside some other attribute's name, like `xlat`
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        let Some(value) = after.strip_prefix('=') else {
+            continue;
+        };
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &value[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+}
+
+/// Text of the first `<name>...</name>` child
+fn element<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = body;
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        let Some(after_name) = rest.strip_prefix(name) else {
+            continue;
+        };
+        // `<time>` shouldn't match `<timestamp>`
+        let Some(content) = after_name.trim_start().strip_prefix('>') else {
+            continue;
+        };
+        let end = content.find('<')?;
+        return Some(content[..end].trim());
+    }
+}
+
+/// `2024-02-29T12:34:56Z`, any fraction of a second is dropped. Times with an offset like
+/// `+02:00` are moved to UTC, ones without any are taken as UTC, which is what GPX prescribes.
+fn parse_time(s: &str) -> Option<UtcTime> {
+    let bytes = s.as_bytes();
+    if bytes.len() < 19
+        || bytes[4] != b'-'
+        || bytes[7] != b'-'
+        || bytes[10] != b'T'
+        || bytes[13] != b':'
+    {
+        return None;
+    }
+    let number =
+        |range: core::ops::Range<usize>| str::from_utf8(&bytes[range]).ok()?.parse::<u16>().ok();
+    let time = UtcTime {
+        year: number(0..4)?,
+        month: number(5..7)? as u8,
+        day: number(8..10)? as u8,
+        hour: number(11..13)? as u8,
+        minute: number(14..16)? as u8,
+        second: number(17..19)? as u8,
+    };
+    if !time.is_valid() {
+        return None;
+    }
+
+    let mut zone = &bytes[19..];
+    if let Some(fraction) = zone.strip_prefix(b".") {
+        let digits = fraction.iter().take_while(|b| b.is_ascii_digit()).count();
+        zone = &fraction[digits..];
+    }
+    let offset_minutes = match *zone {
+        [] | [b'Z'] => return Some(time),
+        [sign @ (b'+' | b'-'), h1, h2, b':', m1, m2] => {
+            let digits = [h1, h2, m1, m2];
+            if !digits.iter().all(u8::is_ascii_digit) {
+                return None;
+            }
+            let [h1, h2, m1, m2] = digits.map(|d| (d - b'0') as i64);
+            let minutes = (h1 * 10 + h2) * 60 + m1 * 10 + m2;
+            if sign == b'+' { minutes } else { -minutes }
+        }
+        _ => return None,
+    };
+    let time = UtcTime::from_unix_seconds(time.unix_seconds() - offset_minutes * 60);
+    time.is_valid().then_some(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::string::String;
+
+    use super::*;
+
+    fn gpx(points: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\"?>\n<gpx version=\"1.1\"><trk><trkseg>\n{points}</trkseg></trk></gpx>\n"
+        )
+    }
+
+    fn time(hour: u8, minute: u8, second: u8) -> UtcTime {
+        UtcTime {
+            year: 2024,
+            month: 6,
+            day: 15,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn speed_and_course_as_given_in_gpx_1_0() {
+        let data = gpx(concat!(
+            "<trkpt lat=\"52.5\" lon=\"13.4\"><ele>34.5</ele><time>2024-06-15T14:30:05Z</time>",
+            "<course>90.5</course><speed>12.5</speed><sat>7</sat><hdop>0.9</hdop></trkpt>\n",
+            "<trkpt lat=\"52.501\" lon=\"13.4\"><time>2024-06-15T14:30:15Z</time>",
+            "<speed>3</speed></trkpt>\n",
+        ));
+        let fixes = parse(data.as_bytes()).unwrap();
+        assert_eq!(fixes.len(), 2);
+
+        assert_eq!(fixes[0].coord, FixedCoord::from_degrees(52.5, 13.4));
+        assert_eq!(fixes[0].time, time(14, 30, 5));
+        assert_eq!(fixes[0].altitude_m, 34.5);
+        assert_eq!(fixes[0].quality, FixQuality::ThreeD);
+        assert_eq!(fixes[0].speed_mps, 12.5);
+        assert_eq!(fixes[0].bearing, 90.5);
+        assert_eq!(fixes[0].satellites_used, 7);
+        assert_eq!(fixes[0].hdop, 0.9);
+
+        // Without a course it's worked out from the point before
+        assert_eq!(fixes[1].quality, FixQuality::TwoD);
+        assert_eq!(fixes[1].speed_mps, 3.0);
+        assert!(fixes[1].bearing.abs() < 0.1, "{}", fixes[1].bearing);
+    }
+
+    #[test]
+    fn speed_and_course_worked_out_for_gpx_1_1() {
+        // About 111 m north every 10 s, then the same east, then standing still
+        let data = gpx(concat!(
+            "<trkpt lat=\"52.5\" lon=\"13.4\"><time>2024-06-15T14:30:00Z</time></trkpt>\n",
+            "<trkpt lat=\"52.501\" lon=\"13.4\"><time>2024-06-15T14:30:10Z</time></trkpt>\n",
+            "<trkpt lat=\"52.501\" lon=\"13.40164\"><time>2024-06-15T14:30:20Z</time></trkpt>\n",
+            "<trkpt lat=\"52.501\" lon=\"13.40164\"><time>2024-06-15T14:30:30Z</time></trkpt>\n",
+        ));
+        let fixes = parse(data.as_bytes()).unwrap();
+
+        assert!(
+            (fixes[0].speed_mps - 11.13).abs() < 0.1,
+            "{}",
+            fixes[0].speed_mps
+        );
+        assert!(fixes[0].bearing.abs() < 0.1, "{}", fixes[0].bearing);
+        assert!(
+            (fixes[1].speed_mps - 11.1).abs() < 0.1,
+            "{}",
+            fixes[1].speed_mps
+        );
+        assert!(
+            (fixes[1].bearing - 90.0).abs() < 0.1,
+            "{}",
+            fixes[1].bearing
+        );
+        // Standing still keeps the way it was going
+        assert_eq!(fixes[2].speed_mps, 0.0);
+        assert_eq!(fixes[2].bearing, fixes[1].bearing);
+        assert_eq!(fixes[3].speed_mps, 0.0);
+        assert_eq!(fixes[3].bearing, fixes[1].bearing);
+    }
+
+    #[test]
+    fn single_quotes_and_other_attributes() {
+        let data = gpx(
+            "<trkpt xlat='1' lon = '13.4' lat='52.5'><time>2024-06-15T14:30:05Z</time></trkpt>\n",
+        );
+        let fixes = parse(data.as_bytes()).unwrap();
+        assert_eq!(fixes[0].coord, FixedCoord::from_degrees(52.5, 13.4));
+    }
+
+    #[test]
+    fn bad_track_points_by_index() {
+        let good = "<trkpt lat=\"52.5\" lon=\"13.4\"><time>2024-06-15T14:30:05Z</time></trkpt>\n";
+        let bad = [
+            // Self-closing, so there's no time, and the next point's time isn't it either
+            "<trkpt lat=\"52.5\" lon=\"13.4\"/>\n",
+            "<trkpt lat=\"95\" lon=\"13.4\"><time>2024-06-15T14:30:06Z</time></trkpt>\n",
+            "<trkpt lat=\"52.5\"><time>2024-06-15T14:30:06Z</time></trkpt>\n",
+            "<trkpt lat=\"52.5\" lon=\"east\"><time>2024-06-15T14:30:06Z</time></trkpt>\n",
+            "<trkpt lat=\"52.5\" lon=\"13.4\"><time>2024-06-15T25:30:06Z</time></trkpt>\n",
+            "<trkpt lat=\"52.5\" lon=\"13.4\"><timestamp>2024-06-15T14:30:06Z</timestamp></trkpt>\n",
+            "<trkpt lat=\"52.5\" lon=\"13.4\"><time>2024-06-15T14:30:06Z</time>",
+        ];
+        for bad in bad {
+            let data = gpx(&format!("{good}{good}{bad}{good}"));
+            assert_eq!(
+                parse(data.as_bytes()),
+                Err(GpxError::BadTrackPoint(2)),
+                "{bad}"
+            );
+        }
+
+        assert_eq!(parse(&gpx("").into_bytes()), Err(GpxError::NoTrackPoints));
+        assert_eq!(parse(b"<trkpt \xff"), Err(GpxError::NotUtf8));
+    }
+
+    #[test]
+    fn times_with_an_offset_are_moved_to_utc() {
+        let utc = Some(time(14, 30, 5));
+        assert_eq!(parse_time("2024-06-15T14:30:05Z"), utc);
+        assert_eq!(parse_time("2024-06-15T14:30:05"), utc);
+        assert_eq!(parse_time("2024-06-15T14:30:05.750Z"), utc);
+        assert_eq!(parse_time("2024-06-15T16:30:05+02:00"), utc);
+        assert_eq!(parse_time("2024-06-15T16:30:05.5+02:00"), utc);
+        assert_eq!(parse_time("2024-06-15T13:00:05-01:30"), utc);
+        assert_eq!(parse_time("2024-06-15T14:30:05+00:00"), utc);
+        assert_eq!(
+            parse_time("2024-03-01T01:00:00+02:00"),
+            Some(UtcTime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 23,
+                minute: 0,
+                second: 0,
+            })
+        );
+
+        for bad in [
+            "2024-06-15T14:30:05+2:00",
+            "2024-06-15T14:30:05+0200",
+            "2024-06-15T14:30:05+02",
+            "2024-06-15T14:30:05 Z",
+            "2024-06-15T14:30:05ZZ",
+            "2024-06-15T14:30:05+0a:00",
+            "2024-06-15 14:30:05Z",
+            "2024-06-15T14:30",
+        ] {
+            assert_eq!(parse_time(bad), None, "{bad}");
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! Where positions come from: the GPS receiver on the PSP, or a recorded log played back.
+//!
+//! Navigation only ever talks to a [`PositionSource`], so it runs the same on a recording
+//! as on the road, be it on a PC, in an emulator or on a PSP without the receiver.
+
+use alloc::vec::Vec;
+
+use crate::fix::{Fix, FixError, Satellite};
+
+pub mod gpx;
+pub mod nmea;
+
+pub trait PositionSource {
+    /// The latest fix. `now_ms` is any millisecond clock, only used for playing logs back.
+    fn poll(&mut self, now_ms: u32) -> Result<Fix, FixError>;
+
+    /// Satellites seen for the latest fix, empty if the source doesn't know
+    fn satellites(&self) -> &[Satellite] {
+        &[]
+    }
+
+    /// A log that has been played back completely
+    fn is_finished(&self) -> bool {
+        false
+    }
+}
+
+/// Plays recorded fixes back at their original pace, or `speed` times that
+pub struct Replay {
+    fixes: Vec<Fix>,
+    /// Milliseconds after the first fix, for every fix
+    offsets_ms: Vec<u32>,
+    speed: f32,
+    looping: bool,
+    start_ms: Option<u32>,
+    index: usize,
+    finished: bool,
+}
+
+impl Replay {
+    /// `fixes` in the order they were recorded
+    pub fn new(fixes: Vec<Fix>, speed: f32, looping: bool) -> Self {
+        let first = fixes.first().map(|f| f.time.unix_seconds()).unwrap_or(0);
+        let offsets_ms = fixes
+            .iter()
+            .map(|f| ((f.time.unix_seconds() - first).max(0) as u32).saturating_mul(1000))
+            .collect();
+        Self {
+            fixes,
+            offsets_ms,
+            speed,
+            looping,
+            start_ms: None,
+            index: 0,
+            finished: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.fixes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fixes.is_empty()
+    }
+
+    /// Starts over from the first fix on the next poll
+    pub fn rewind(&mut self) {
+        self.start_ms = None;
+        self.index = 0;
+        self.finished = false;
+    }
+}
+
+impl PositionSource for Replay {
+    fn poll(&mut self, now_ms: u32) -> Result<Fix, FixError> {
+        if self.fixes.is_empty() {
+            return Err(FixError::NoFix);
+        }
+
+        let start = *self.start_ms.get_or_insert(now_ms);
+        let elapsed = (now_ms.wrapping_sub(start) as f32 * self.speed) as u32;
+        while self.index + 1 < self.fixes.len() && self.offsets_ms[self.index + 1] <= elapsed {
+            self.index += 1;
+        }
+
+        if self.index + 1 == self.fixes.len() {
+            if !self.looping {
+                self.finished = true;
+            } else if elapsed > self.offsets_ms[self.index] {
+                self.rewind();
+                self.start_ms = Some(now_ms);
+            }
+        }
+        Ok(self.fixes[self.index])
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::fix::{FixQuality, UtcTime};
+    use crate::graph::FixedCoord;
+
+    /// A fix `second`s into the recording, told apart by its latitude
+    fn fix(second: u8) -> Fix {
+        Fix {
+            coord: FixedCoord {
+                lat: 525_000_000 + second as i32,
+                lon: 134_000_000,
+            },
+            altitude_m: 0.0,
+            speed_mps: 0.0,
+            bearing: 0.0,
+            hdop: 1.0,
+            quality: FixQuality::TwoD,
+            time: UtcTime {
+                year: 2024,
+                month: 6,
+                day: 15,
+                hour: 14,
+                minute: 30,
+                second,
+            },
+            satellites_used: 0,
+        }
+    }
+
+    /// Second into the recording of the fix played at `now_ms`
+    fn played(replay: &mut Replay, now_ms: u32) -> i32 {
+        replay.poll(now_ms).unwrap().coord.lat - 525_000_000
+    }
+
+    #[test]
+    fn plays_at_the_recorded_pace() {
+        let mut replay = Replay::new(vec![fix(0), fix(1), fix(2), fix(4)], 1.0, false);
+        assert_eq!(played(&mut replay, 5000), 0);
+        assert_eq!(played(&mut replay, 5999), 0);
+        assert_eq!(played(&mut replay, 6000), 1);
+        assert_eq!(played(&mut replay, 8999), 2);
+        assert!(!replay.is_finished());
+        assert_eq!(played(&mut replay, 9000), 4);
+        assert!(replay.is_finished());
+        // Stays at the end
+        assert_eq!(played(&mut replay, 20_000), 4);
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn plays_faster_and_slower() {
+        let mut replay = Replay::new(vec![fix(0), fix(1), fix(2), fix(4)], 2.0, false);
+        assert_eq!(played(&mut replay, 1000), 0);
+        assert_eq!(played(&mut replay, 1499), 0);
+        assert_eq!(played(&mut replay, 1500), 1);
+        assert_eq!(played(&mut replay, 2000), 2);
+        assert!(!replay.is_finished());
+        assert_eq!(played(&mut replay, 3000), 4);
+        assert!(replay.is_finished());
+
+        let mut replay = Replay::new(vec![fix(0), fix(1), fix(2), fix(4)], 0.5, false);
+        assert_eq!(played(&mut replay, 0), 0);
+        assert_eq!(played(&mut replay, 1999), 0);
+        assert_eq!(played(&mut replay, 2000), 1);
+        assert_eq!(played(&mut replay, 7999), 2);
+        assert_eq!(played(&mut replay, 8000), 4);
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn survives_the_clock_wrapping() {
+        let mut replay = Replay::new(vec![fix(0), fix(1), fix(2)], 1.0, false);
+        let start = u32::MAX - 500;
+        assert_eq!(played(&mut replay, start), 0);
+        assert_eq!(played(&mut replay, start.wrapping_add(1000)), 1);
+        assert_eq!(played(&mut replay, start.wrapping_add(2000)), 2);
+    }
+
+    #[test]
+    fn loops_back_to_the_start() {
+        let mut replay = Replay::new(vec![fix(0), fix(1), fix(2)], 1.0, true);
+        assert_eq!(played(&mut replay, 0), 0);
+        assert_eq!(played(&mut replay, 2000), 2);
+        assert!(!replay.is_finished());
+        // The last fix is shown for as long as it was recorded, then it starts over
+        assert_eq!(played(&mut replay, 2001), 0);
+        assert!(!replay.is_finished());
+        assert_eq!(played(&mut replay, 3000), 0);
+        assert_eq!(played(&mut replay, 3001), 1);
+        assert_eq!(played(&mut replay, 4001), 2);
+        assert!(!replay.is_finished());
+    }
+
+    #[test]
+    fn rewinds() {
+        let mut replay = Replay::new(vec![fix(0), fix(1)], 1.0, false);
+        assert_eq!(played(&mut replay, 0), 0);
+        assert_eq!(played(&mut replay, 1000), 1);
+        assert!(replay.is_finished());
+
+        replay.rewind();
+        assert!(!replay.is_finished());
+        assert_eq!(played(&mut replay, 50_000), 0);
+        assert_eq!(played(&mut replay, 51_000), 1);
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn nothing_to_play() {
+        let mut replay = Replay::new(Vec::new(), 1.0, true);
+        assert!(replay.is_empty());
+        assert_eq!(replay.poll(0), Err(FixError::NoFix));
+    }
+}
@@ -0,0 +1,1168 @@
+//! NMEA 0183, what nearly every GPS receiver and logger speaks.
+//!
+//! A receiver sends a burst of sentences per fix: GGA and RMC for the position, GSA for the
+//! satellites it uses, GSV for all satellites in view and VTG for speed and course. They're
+//! put together into the same [`Fix`] and [`Satellite`]s the PSP's own receiver gives, checked
+//! by [`Fix::from_raw`] the same way.
+
+use alloc::vec::Vec;
+use core::str;
+
+use crate::fix::{Fix, FixError, RawGpsData, Satellite};
+use crate::graph::FixedCoord;
+use crate::source::PositionSource;
+
+/// Longest sentence allowed by the standard, including `$` and the line end
+const MAX_SENTENCE_LEN: usize = 82;
+
+/// More fields than any of the supported sentences have
+const MAX_FIELDS: usize = 24;
+
+const KNOTS_TO_KMH: f32 = 1.852;
+
+/// GGA only has the time of day. Without an RMC for the date the start of GPS time stands in,
+/// as (day, month, year), which still keeps the fixes of a day in order.
+const NO_DATE: (u8, u8, u16) = (6, 1, 1980);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmeaError {
+    /// Doesn't start with `$`
+    NoStart,
+    /// Longer than a sentence can be, or too many fields
+    TooLong,
+    MissingChecksum,
+    BadChecksum {
+        expected: u8,
+        found: u8,
+    },
+    /// Some other sentence, like `$GPZDA`
+    Unsupported,
+    /// Field number `n` (the sentence name is field 0) is missing or broken
+    BadField(usize),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NmeaTime {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sentence {
+    /// Fix data
+    Gga {
+        time: NmeaTime,
+        /// None without a fix
+        position: Option<(f64, f64)>,
+        /// 0 is no fix, 1 GPS, 2 DGPS and so on
+        quality: u8,
+        satellites: u8,
+        hdop: Option<f32>,
+        altitude: Option<f32>,
+    },
+    /// Recommended minimum data, the only one with a date
+    Rmc {
+        time: NmeaTime,
+        /// (day, month, year)
+        date: (u8, u8, u16),
+        valid: bool,
+        position: Option<(f64, f64)>,
+        speed_knots: Option<f32>,
+        course: Option<f32>,
+    },
+    /// Satellites used for the fix
+    Gsa {
+        /// 1 no fix, 2 2D, 3 3D
+        mode: u8,
+        used: [u8; 12],
+        used_count: u8,
+        hdop: Option<f32>,
+    },
+    /// Satellites in view, up to 4 per sentence
+    Gsv {
+        message_count: u8,
+        message: u8,
+        in_view: u8,
+        satellites: [Satellite; 4],
+        satellite_count: u8,
+    },
+    /// Course and speed over ground
+    Vtg {
+        course: Option<f32>,
+        speed_kmh: Option<f32>,
+    },
+}
+
+/// Parses one sentence, with or without the line end
+pub fn parse_sentence(line: &str) -> Result<Sentence, NmeaError> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.len() > MAX_SENTENCE_LEN {
+        return Err(NmeaError::TooLong);
+    }
+    let body = line.strip_prefix('$').ok_or(NmeaError::NoStart)?;
+    let (body, checksum) = body.split_once('*').ok_or(NmeaError::MissingChecksum)?;
+
+    let found = body.bytes().fold(0u8, |sum, b| sum ^ b);
+    let expected = (checksum.len() == 2 && checksum.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| u8::from_str_radix(checksum, 16).ok())
+        .flatten()
+        .ok_or(NmeaError::MissingChecksum)?;
+    if expected != found {
+        return Err(NmeaError::BadChecksum { expected, found });
+    }
+
+    let mut fields = [""; MAX_FIELDS];
+    let mut count = 0;
+    for field in body.split(',') {
+        *fields.get_mut(count).ok_or(NmeaError::TooLong)? = field;
+        count += 1;
+    }
+    let fields = Fields(&fields[..count]);
+
+    // Any talker (GP, GN, GL, ...), only the last three letters say what it is
+    let address = fields.0[0];
+    if address.len() != 5 || !address.is_ascii() {
+        return Err(NmeaError::Unsupported);
+    }
+    match &address[2..] {
+        "GGA" => Ok(Sentence::Gga {
+            time: fields.time(1)?,
+            position: fields.position(2)?,
+            quality: fields.number(6)?.unwrap_or(0),
+            satellites: fields.number(7)?.unwrap_or(0),
+            hdop: fields.number(8)?,
+            altitude: fields.number(9)?,
+        }),
+        "RMC" => Ok(Sentence::Rmc {
+            time: fields.time(1)?,
+            valid: fields.get(2)? == "A",
+            position: fields.position(3)?,
+            speed_knots: fields.number(7)?,
+            course: fields.number(8)?,
+            date: fields.date(9)?,
+        }),
+        "GSA" => {
+            let mut used = [0; 12];
+            let mut used_count = 0;
+            for i in 3..15 {
+                if let Some(prn) = fields.number(i)? {
+                    used[used_count] = prn;
+                    used_count += 1;
+                }
+            }
+            Ok(Sentence::Gsa {
+                mode: fields.number(2)?.unwrap_or(1),
+                used,
+                used_count: used_count as u8,
+                hdop: fields.number(16)?,
+            })
+        }
+        "GSV" => {
+            let mut satellites = [Satellite::default(); 4];
+            let mut satellite_count = 0;
+            // Groups of 4 fields per satellite, the last group can be cut short
+            for group in 0..4 {
+                let first = 4 + group * 4;
+                if first >= fields.0.len() {
+                    break;
+                }
+                let Some(id) = fields.number(first)? else {
+                    continue;
+                };
+                satellites[satellite_count] = Satellite {
+                    id,
+                    elevation: fields.number(first + 1)?.unwrap_or(0),
+                    azimuth: fields.number(first + 2)?.unwrap_or(0),
+                    snr: fields.number(first + 3)?.unwrap_or(0),
+                    used: false,
+                };
+                satellite_count += 1;
+            }
+            Ok(Sentence::Gsv {
+                message_count: fields.number(1)?.ok_or(NmeaError::BadField(1))?,
+                message: fields.number(2)?.ok_or(NmeaError::BadField(2))?,
+                in_view: fields.number(3)?.unwrap_or(0),
+                satellites,
+                satellite_count: satellite_count as u8,
+            })
+        }
+        "VTG" => Ok(Sentence::Vtg {
+            course: fields.number(1)?,
+            speed_kmh: fields.number(7)?,
+        }),
+        _ => Err(NmeaError::Unsupported),
+    }
+}
+
+struct Fields<'a>(&'a [&'a str]);
+
+impl Fields<'_> {
+    fn get(&self, i: usize) -> Result<&str, NmeaError> {
+        self.0.get(i).copied().ok_or(NmeaError::BadField(i))
+    }
+
+    /// None for a missing or empty field
+    fn optional(&self, i: usize) -> Option<&str> {
+        self.0.get(i).copied().filter(|s| !s.is_empty())
+    }
+
+    /// None for an empty field, an error for garbage
+    fn number<T: str::FromStr>(&self, i: usize) -> Result<Option<T>, NmeaError> {
+        self.optional(i)
+            .map(|s| s.parse().map_err(|_| NmeaError::BadField(i)))
+            .transpose()
+    }
+
+    /// `hhmmss` with optional fractions of a second
+    fn time(&self, i: usize) -> Result<NmeaTime, NmeaError> {
+        let s = self.get(i)?;
+        let digits = |range: core::ops::Range<usize>| {
+            s.get(range)
+                .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|d| d.parse::<u8>().ok())
+                .ok_or(NmeaError::BadField(i))
+        };
+        if s.is_empty() {
+            return Ok(NmeaTime::default());
+        }
+        Ok(NmeaTime {
+            hour: digits(0..2)?,
+            minute: digits(2..4)?,
+            second: digits(4..6)?,
+        })
+    }
+
+    /// `ddmmyy` as (day, month, year)
+    fn date(&self, i: usize) -> Result<(u8, u8, u16), NmeaError> {
+        let s = self.get(i)?;
+        let digits = |range: core::ops::Range<usize>| {
+            s.get(range)
+                .filter(|d| d.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|d| d.parse::<u8>().ok())
+                .ok_or(NmeaError::BadField(i))
+        };
+        if s.is_empty() {
+            return Ok((0, 0, 0));
+        }
+        let year = digits(4..6)? as u16;
+        // Two digit years, GPS didn't exist before 1980
+        let year = if year < 80 { 2000 + year } else { 1900 + year };
+        Ok((digits(0..2)?, digits(2..4)?, year))
+    }
+
+    /// `ddmm.mmmm,N,dddmm.mmmm,E` starting at field `i`, in degrees
+    fn position(&self, i: usize) -> Result<Option<(f64, f64)>, NmeaError> {
+        let (Some(lat), Some(lon)) = (self.optional(i), self.optional(i + 2)) else {
+            return Ok(None);
+        };
+        let lat = degrees(lat, 2).ok_or(NmeaError::BadField(i))?;
+        let lon = degrees(lon, 3).ok_or(NmeaError::BadField(i + 2))?;
+        let lat = match self.get(i + 1)? {
+            "N" => lat,
+            "S" => -lat,
+            _ => return Err(NmeaError::BadField(i + 1)),
+        };
+        let lon = match self.get(i + 3)? {
+            "E" => lon,
+            "W" => -lon,
+            _ => return Err(NmeaError::BadField(i + 3)),
+        };
+        Ok(Some((lat, lon)))
+    }
+}
+
+/// Degrees from `dddmm.mmmm`, with `degree_digits` digits before the minutes
+fn degrees(s: &str, degree_digits: usize) -> Option<f64> {
+    if !s.is_char_boundary(degree_digits) || !s[..degree_digits].bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let (degrees, minutes) = s.split_at(degree_digits);
+    // Whole minutes always have two digits, anything else has lost a digit somewhere
+    if minutes.find('.').unwrap_or(minutes.len()) != 2 {
+        return None;
+    }
+    let degrees = degrees.parse::<f64>().ok()?;
+    let minutes = minutes.parse::<f64>().ok()?;
+    if !(0.0..60.0).contains(&minutes) {
+        return None;
+    }
+    Some(degrees + minutes / 60.0)
+}
+
+/// Puts the sentences of one fix together
+#[derive(Default)]
+pub struct NmeaParser {
+    raw: RawGpsData,
+    /// Full precision, `RawGpsData` only has `f32` like the PSP's receiver
+    position: Option<(f64, f64)>,
+    time: Option<NmeaTime>,
+    has_rmc: bool,
+    has_gga: bool,
+    valid: bool,
+    /// Satellites used according to GSA, and according to GGA
+    used: Vec<u8>,
+    used_count: u8,
+    /// The previous sentence was a GSA
+    in_gsa: bool,
+    /// Satellites in view, collected over the GSV messages
+    in_view: Vec<Satellite>,
+    gsv_next: Vec<Satellite>,
+    /// The GSV message that continues `gsv_next`, `None` until the next first one
+    gsv_expected: Option<u8>,
+    satellites: Vec<Satellite>,
+    errors: usize,
+}
+
+impl NmeaParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sentences that couldn't be parsed so far
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Satellites of the latest fix, used ones marked
+    pub fn satellites(&self) -> &[Satellite] {
+        &self.satellites
+    }
+
+    /// Takes one sentence, returns a fix once it has all sentences of one. Without an RMC, a
+    /// GGA's fix comes out when the next fix starts, or with [`NmeaParser::flush`].
+    pub fn feed(&mut self, line: &str) -> Option<Result<Fix, FixError>> {
+        let sentence = parse_sentence(line);
+        if sentence.is_err() {
+            self.in_gsa = false;
+        }
+        let sentence = match sentence {
+            Ok(sentence) => sentence,
+            Err(NmeaError::Unsupported) => return None,
+            Err(_) => {
+                self.errors += 1;
+                return None;
+            }
+        };
+
+        let is_gsa = matches!(sentence, Sentence::Gsa { .. });
+        let mut finished = None;
+        match sentence {
+            Sentence::Gga {
+                time,
+                position,
+                quality,
+                satellites,
+                hdop,
+                altitude,
+            } => {
+                finished = self.start_epoch(time);
+                self.has_gga = true;
+                self.position = self.position.or(position);
+                self.valid &= quality > 0;
+                self.used_count = satellites;
+                self.raw.hdop = hdop.unwrap_or(self.raw.hdop);
+                self.raw.altitude = altitude.unwrap_or(0.0);
+            }
+            Sentence::Rmc {
+                time,
+                date: (day, month, year),
+                valid,
+                position,
+                speed_knots,
+                course,
+            } => {
+                finished = self.start_epoch(time);
+                self.has_rmc = true;
+                self.valid &= valid;
+                self.position = position.or(self.position);
+                self.raw.year = year as i32;
+                self.raw.month = month as i32;
+                self.raw.date = day as i32;
+                if let Some(speed) = speed_knots {
+                    self.raw.speed = speed * KNOTS_TO_KMH;
+                }
+                if let Some(course) = course {
+                    self.raw.bearing = course;
+                }
+            }
+            Sentence::Gsa {
+                used,
+                used_count,
+                hdop,
+                ..
+            } => {
+                // Multi-constellation receivers send one GSA per system, right after each other.
+                // They can come after the GGA and RMC, so a fix uses those of the fix before.
+                if !self.in_gsa {
+                    self.used.clear();
+                }
+                for &id in &used[..used_count as usize] {
+                    if !self.used.contains(&id) {
+                        self.used.push(id);
+                    }
+                }
+                if let Some(hdop) = hdop {
+                    self.raw.hdop = hdop;
+                }
+            }
+            Sentence::Gsv {
+                message_count,
+                message,
+                satellites,
+                satellite_count,
+                ..
+            } => {
+                if message == 1 {
+                    self.gsv_next.clear();
+                    self.gsv_expected = Some(1);
+                }
+                // A lost message would mix up two rounds, wait for the next one instead
+                if self.gsv_expected != Some(message) || message > message_count {
+                    self.gsv_expected = None;
+                } else {
+                    self.gsv_next
+                        .extend_from_slice(&satellites[..satellite_count as usize]);
+                    self.gsv_expected = Some(message + 1);
+                    if message == message_count {
+                        core::mem::swap(&mut self.in_view, &mut self.gsv_next);
+                        self.gsv_expected = None;
+                    }
+                }
+            }
+            Sentence::Vtg { course, speed_kmh } => {
+                if let Some(speed) = speed_kmh {
+                    self.raw.speed = speed;
+                }
+                if let Some(course) = course {
+                    self.raw.bearing = course;
+                }
+            }
+        }
+
+        self.in_gsa = is_gsa;
+
+        // RMC has the date, GGA the accuracy, a fix needs both
+        if self.has_rmc && self.has_gga {
+            self.has_rmc = false;
+            self.has_gga = false;
+            return Some(self.finish());
+        }
+        finished
+    }
+
+    /// The fix of a GGA still waiting for its RMC, for the end of a log
+    pub fn flush(&mut self) -> Option<Result<Fix, FixError>> {
+        let has_gga = core::mem::take(&mut self.has_gga);
+        self.has_rmc = false;
+        has_gga.then(|| self.finish())
+    }
+
+    /// The GGA and RMC of a fix carry the same time, anything else starts the next fix. Returns
+    /// the fix before if it only had a GGA.
+    fn start_epoch(&mut self, time: NmeaTime) -> Option<Result<Fix, FixError>> {
+        if self.time == Some(time) {
+            return None;
+        }
+        let finished = self.flush();
+        self.time = Some(time);
+        self.valid = true;
+        self.position = None;
+        self.raw.hour = time.hour as i32;
+        self.raw.minute = time.minute as i32;
+        self.raw.second = time.second as i32;
+        // Only this fix's RMC or VTG may say how fast we're going
+        self.raw.speed = 0.0;
+        self.raw.bearing = 0.0;
+        finished
+    }
+
+    fn finish(&mut self) -> Result<Fix, FixError> {
+        self.satellites.clear();
+        self.satellites
+            .extend(self.in_view.iter().map(|s| Satellite {
+                used: self.used.contains(&s.id),
+                ..*s
+            }));
+        // Used satellites GSV didn't mention, and any GGA counted that GSA didn't list
+        for &id in &self.used {
+            if !self.satellites.iter().any(|s| s.id == id) {
+                self.satellites.push(Satellite {
+                    id,
+                    used: true,
+                    ..Default::default()
+                });
+            }
+        }
+        let used = self.satellites.iter().filter(|s| s.used).count();
+        for _ in used..self.used_count as usize {
+            self.satellites.push(Satellite {
+                used: true,
+                ..Default::default()
+            });
+        }
+
+        let Some((lat, lon)) = self.position.filter(|_| self.valid) else {
+            return Err(FixError::NoFix);
+        };
+        self.raw.latitude = lat as f32;
+        self.raw.longitude = lon as f32;
+        let mut raw = self.raw;
+        if raw.year == 0 {
+            (raw.date, raw.month, raw.year) =
+                (NO_DATE.0 as i32, NO_DATE.1 as i32, NO_DATE.2 as i32);
+        }
+        let mut fix = Fix::from_raw(&raw, &self.satellites)?;
+        fix.coord = FixedCoord::from_degrees(lat, lon);
+        Ok(fix)
+    }
+}
+
+/// Every valid fix in a recorded log, for [`crate::source::Replay`]
+pub fn parse_log(data: &[u8]) -> Vec<Fix> {
+    let mut parser = NmeaParser::new();
+    // Lines that aren't UTF-8 are broken sentences anyway
+    let mut fixes = data
+        .split(|&b| b == b'\n')
+        .filter_map(|line| str::from_utf8(line).ok())
+        .filter_map(|line| parser.feed(line))
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>();
+    fixes.extend(parser.flush().and_then(Result::ok));
+    fixes
+}
+
+/// A receiver sending NMEA over a serial line, fed with whatever bytes came in
+#[derive(Default)]
+pub struct NmeaStream {
+    parser: NmeaParser,
+    line: Vec<u8>,
+    latest: Option<Result<Fix, FixError>>,
+}
+
+impl NmeaStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            match b {
+                b'\n' => {
+                    if let Ok(line) = str::from_utf8(&self.line)
+                        && let Some(fix) = self.parser.feed(line)
+                    {
+                        self.latest = Some(fix);
+                    }
+                    self.line.clear();
+                }
+                // A sentence starts over at `$`, whatever came before it got lost
+                b'$' => {
+                    self.line.clear();
+                    self.line.push(b);
+                }
+                // Never let a stream without line ends grow forever
+                _ if self.line.len() < MAX_SENTENCE_LEN => self.line.push(b),
+                _ => self.line.clear(),
+            }
+        }
+    }
+}
+
+impl PositionSource for NmeaStream {
+    fn poll(&mut self, _now_ms: u32) -> Result<Fix, FixError> {
+        self.latest.unwrap_or(Err(FixError::NoFix))
+    }
+
+    fn satellites(&self) -> &[Satellite] {
+        self.parser.satellites()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::fix::{FixQuality, UtcTime};
+
+    /// `body` with the `$` and its checksum around it
+    fn sentence(body: &str) -> String {
+        let checksum = body.bytes().fold(0u8, |sum, b| sum ^ b);
+        format!("${body}*{checksum:02X}\r\n")
+    }
+
+    fn feed_all(parser: &mut NmeaParser, bodies: &[&str]) -> Vec<Result<Fix, FixError>> {
+        bodies
+            .iter()
+            .filter_map(|body| parser.feed(&sentence(body)))
+            .collect()
+    }
+
+    /// `ddmm.mmmmm` for the position as fixed point, so it comes back the same
+    fn nmea_degrees(e7: i32, degree_digits: usize) -> String {
+        let e7 = e7.unsigned_abs() as u64;
+        let (degrees, rest) = (e7 / 10_000_000, e7 % 10_000_000);
+        // 1e-7 degrees are 6e-6 minutes, five decimals lose a bit
+        let minutes = rest as f64 * 60.0 / 1e7;
+        format!("{degrees:0degree_digits$}{minutes:08.5}")
+    }
+
+    /// The GGA and RMC a receiver would send for `fix`
+    fn gga_rmc(coord: FixedCoord, time: UtcTime, speed_knots: f32, course: f32) -> [String; 2] {
+        let position = format!(
+            "{},{},{},{}",
+            nmea_degrees(coord.lat, 2),
+            if coord.lat < 0 { 'S' } else { 'N' },
+            nmea_degrees(coord.lon, 3),
+            if coord.lon < 0 { 'W' } else { 'E' },
+        );
+        let clock = format!("{:02}{:02}{:02}.00", time.hour, time.minute, time.second);
+        [
+            format!("GPGGA,{clock},{position},1,07,1.1,35.5,M,47.0,M,,"),
+            format!(
+                "GPRMC,{clock},A,{position},{speed_knots:.1},{course:.1},{:02}{:02}{:02},,,A",
+                time.day,
+                time.month,
+                time.year % 100
+            ),
+        ]
+    }
+
+    /// A burst from a typical receiver
+    const BURST: [&str; 6] = [
+        "GPGSA,A,3,04,05,,09,12,,,24,,,,,2.5,1.3,2.1",
+        "GPGSV,2,1,06,04,40,083,46,05,17,308,41,09,07,344,39,12,22,228,45",
+        "GPGSV,2,2,06,24,60,120,50,31,05,010,",
+        "GPGGA,123519.00,4807.038,N,01131.000,E,1,05,0.9,545.4,M,46.9,M,,",
+        "GPRMC,123519.00,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W",
+        "GPVTG,084.4,T,,M,022.4,N,041.5,K",
+    ];
+
+    #[test]
+    fn parses_each_sentence() {
+        let Ok(Sentence::Gga {
+            time,
+            position: Some((lat, lon)),
+            quality: 1,
+            satellites: 5,
+            hdop: Some(hdop),
+            altitude: Some(altitude),
+        }) = parse_sentence(&sentence(BURST[3]))
+        else {
+            panic!("{:?}", parse_sentence(&sentence(BURST[3])));
+        };
+        assert_eq!(
+            time,
+            NmeaTime {
+                hour: 12,
+                minute: 35,
+                second: 19
+            }
+        );
+        assert!((lat - 48.1173).abs() < 1e-9 && (lon - (11.0 + 31.0 / 60.0)).abs() < 1e-9);
+        assert_eq!((hdop, altitude), (0.9, 545.4));
+
+        assert_eq!(
+            parse_sentence(&sentence(BURST[4])),
+            Ok(Sentence::Rmc {
+                time,
+                date: (23, 3, 1994),
+                valid: true,
+                position: Some((lat, lon)),
+                speed_knots: Some(22.4),
+                course: Some(84.4),
+            })
+        );
+        let Ok(Sentence::Gsa {
+            mode: 3,
+            used,
+            used_count: 5,
+            hdop: Some(1.3),
+        }) = parse_sentence(&sentence(BURST[0]))
+        else {
+            panic!();
+        };
+        assert_eq!(used[..5], [4, 5, 9, 12, 24]);
+        assert_eq!(
+            parse_sentence(&sentence(BURST[5])),
+            Ok(Sentence::Vtg {
+                course: Some(84.4),
+                speed_kmh: Some(41.5)
+            })
+        );
+        // Any talker
+        assert!(matches!(
+            parse_sentence(&sentence(&BURST[3].replace("GPGGA", "GNGGA"))),
+            Ok(Sentence::Gga { .. })
+        ));
+        assert_eq!(
+            parse_sentence(&sentence("GPZDA,123519.00,23,03,1994,00,00")),
+            Err(NmeaError::Unsupported)
+        );
+    }
+
+    #[test]
+    fn refuses_bad_checksums() {
+        let good = sentence(BURST[3]);
+        let (body, checksum) = good.trim_end().split_once('*').unwrap();
+        let found = u8::from_str_radix(checksum, 16).unwrap();
+
+        let wrong = format!("{body}*{:02X}", found ^ 0x5a);
+        assert_eq!(
+            parse_sentence(&wrong),
+            Err(NmeaError::BadChecksum {
+                expected: found ^ 0x5a,
+                found
+            })
+        );
+        // One character changed on the way
+        let flipped = good.replacen("4807", "4808", 1);
+        assert!(matches!(
+            parse_sentence(&flipped),
+            Err(NmeaError::BadChecksum { .. })
+        ));
+        assert!(parse_sentence(&format!("{body}*{}", checksum.to_ascii_lowercase())).is_ok());
+
+        for broken in [
+            String::from(body),
+            format!("{body}*"),
+            format!("{body}*{}", &checksum[..1]),
+            format!("{body}*{checksum}0"),
+            format!("{body}*G1"),
+            format!("{body}*+1"),
+        ] {
+            assert_eq!(parse_sentence(&broken), Err(NmeaError::MissingChecksum));
+        }
+        assert_eq!(parse_sentence(&good[1..]), Err(NmeaError::NoStart));
+        assert_eq!(parse_sentence(""), Err(NmeaError::NoStart));
+
+        let mut parser = NmeaParser::new();
+        assert_eq!(parser.feed(&wrong), None);
+        assert_eq!(parser.errors(), 1);
+    }
+
+    #[test]
+    fn truncated_and_empty_fields() {
+        // What receivers send before they have a fix
+        assert!(matches!(
+            parse_sentence(&sentence("GPGGA,,,,,,0,00,,,M,,M,,")),
+            Ok(Sentence::Gga {
+                position: None,
+                quality: 0,
+                hdop: None,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_sentence(&sentence("GPRMC,,V,,,,,,,,,,N")),
+            Ok(Sentence::Rmc {
+                valid: false,
+                date: (0, 0, 0),
+                position: None,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_sentence(&sentence("GPGGA")),
+            Err(NmeaError::BadField(1))
+        ));
+        // Cut off before the date
+        assert_eq!(
+            parse_sentence(&sentence("GPRMC,123519,A,4807.038,N,01131.000,E,022.4")),
+            Err(NmeaError::BadField(9))
+        );
+        // Half a position is none
+        assert!(matches!(
+            parse_sentence(&sentence("GPGGA,123519,4807.038,N,,,1,08")),
+            Ok(Sentence::Gga { position: None, .. })
+        ));
+
+        for (body, field) in [
+            (
+                "GPGGA,12a519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,,M,,",
+                1,
+            ),
+            ("GPGGA,1235,4807.038,N,01131.000,E,1,08,0.9,545.4,M,,M,,", 1),
+            (
+                "GPGGA,123519,4807.038,X,01131.000,E,1,08,0.9,545.4,M,,M,,",
+                3,
+            ),
+            (
+                "GPGGA,123519,4807.038,N,01131.000,,1,08,0.9,545.4,M,,M,,",
+                5,
+            ),
+            (
+                "GPGGA,123519,4860.000,N,01131.000,E,1,08,0.9,545.4,M,,M,,",
+                2,
+            ),
+            ("GPGGA,123519,48,N,01131.000,E,1,08,0.9,545.4,M,,M,,", 2),
+            (
+                "GPGGA,123519,4807.038,N,1131.000,E,1,08,0.9,545.4,M,,M,,",
+                4,
+            ),
+            (
+                "GPGGA,123519,4807.038,N,01131.000,E,1,-8,0.9,545.4,M,,M,,",
+                7,
+            ),
+            ("GPGGA,123519,4807.038,N,01131.000,E,1,08,x,545.4,M,,M,,", 8),
+            (
+                "GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,2303,,,A",
+                9,
+            ),
+            ("GPGSV,x,1,08", 1),
+            ("GPGSV,2,,08", 2),
+        ] {
+            assert_eq!(
+                parse_sentence(&sentence(body)),
+                Err(NmeaError::BadField(field)),
+                "{body}"
+            );
+        }
+    }
+
+    #[test]
+    fn overlong_lines() {
+        let long = format!("GPGGA,{}", "1".repeat(MAX_SENTENCE_LEN));
+        assert_eq!(parse_sentence(&sentence(&long)), Err(NmeaError::TooLong));
+        let many_fields = format!("GPGSA,A,3{}", ",".repeat(MAX_FIELDS));
+        assert_eq!(
+            parse_sentence(&sentence(&many_fields)),
+            Err(NmeaError::TooLong)
+        );
+
+        // Garbage without line ends, then a normal burst
+        let mut stream = NmeaStream::new();
+        stream.push_bytes(&[b'7'; 1000]);
+        stream.push_bytes(sentence(&long).as_bytes());
+        for body in BURST {
+            stream.push_bytes(sentence(body).as_bytes());
+        }
+        assert!(stream.poll(0).is_ok());
+        assert_eq!(stream.parser.errors(), 1);
+    }
+
+    #[test]
+    fn non_ascii_bytes() {
+        let odd = BURST[3].replace("N,", "Ñ,");
+        assert_eq!(parse_sentence(&sentence(&odd)), Err(NmeaError::BadField(3)));
+        let odd = BURST[3].replace("123519", "12é519");
+        assert_eq!(parse_sentence(&sentence(&odd)), Err(NmeaError::BadField(1)));
+        let odd = BURST[3].replace("4807", "4é07");
+        assert_eq!(parse_sentence(&sentence(&odd)), Err(NmeaError::BadField(2)));
+        assert_eq!(
+            parse_sentence(&sentence("GPGGé,1")),
+            Err(NmeaError::Unsupported)
+        );
+
+        // Not even UTF-8, in the middle of a stream
+        let mut stream = NmeaStream::new();
+        stream.push_bytes(b"$GPGGA,\xff\xfe,4807.038*00\r\n\x80\x81\r\n");
+        assert_eq!(stream.poll(0), Err(FixError::NoFix));
+        for body in BURST {
+            stream.push_bytes(sentence(body).as_bytes());
+        }
+        assert!(stream.poll(0).is_ok());
+        let log = [
+            b"\xc3\x28garbage\n".as_slice(),
+            sentence(BURST[3]).as_bytes(),
+        ]
+        .concat();
+        assert_eq!(parse_log(&log).len(), 1);
+    }
+
+    #[test]
+    fn odd_gsv_groups() {
+        let gsv = |body: &str| match parse_sentence(&sentence(body)) {
+            Ok(Sentence::Gsv {
+                satellites,
+                satellite_count,
+                ..
+            }) => satellites[..satellite_count as usize].to_vec(),
+            other => panic!("{other:?}"),
+        };
+        let satellite = |id, elevation, azimuth, snr| Satellite {
+            id,
+            elevation,
+            azimuth,
+            snr,
+            used: false,
+        };
+
+        // The last group cut short, one without an id and one that isn't tracked
+        assert_eq!(
+            gsv("GPGSV,1,1,03,07,10,200,30,,20,100,40,09,15"),
+            [satellite(7, 10, 200, 30), satellite(9, 15, 0, 0)]
+        );
+        assert_eq!(gsv("GPGSV,1,1,00"), []);
+        assert_eq!(gsv("GPGSV,1,1,01,11,,,"), [satellite(11, 0, 0, 0)]);
+        assert_eq!(
+            parse_sentence(&sentence("GPGSV,1,1,01,11,90,400,30")),
+            Ok(Sentence::Gsv {
+                message_count: 1,
+                message: 1,
+                in_view: 1,
+                satellites: [
+                    satellite(11, 90, 400, 30),
+                    Satellite::default(),
+                    Satellite::default(),
+                    Satellite::default()
+                ],
+                satellite_count: 1,
+            })
+        );
+        assert_eq!(
+            parse_sentence(&sentence("GPGSV,1,1,01,300,10,200,30")),
+            Err(NmeaError::BadField(4))
+        );
+
+        let in_view = |parser: &mut NmeaParser| {
+            feed_all(parser, &BURST[3..5]);
+            let mut ids = parser.satellites().iter().map(|s| s.id).collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        let mut parser = NmeaParser::new();
+        feed_all(&mut parser, &BURST[..3]);
+        assert_eq!(in_view(&mut parser), [4, 5, 9, 12, 24, 31]);
+
+        // The first message of the next round got lost, the old satellites stay
+        feed_all(&mut parser, &["GPGSV,2,2,06,25,60,120,50,26,05,010,"]);
+        assert_eq!(in_view(&mut parser), [4, 5, 9, 12, 24, 31]);
+        // Out of order, or numbered past the end
+        feed_all(
+            &mut parser,
+            &[
+                "GPGSV,3,1,09,40,40,083,46",
+                "GPGSV,3,3,09,41,40,083,46",
+                "GPGSV,3,2,09,42,40,083,46",
+                "GPGSV,1,2,01,43,40,083,46",
+            ],
+        );
+        assert_eq!(in_view(&mut parser), [4, 5, 9, 12, 24, 31]);
+        feed_all(
+            &mut parser,
+            &["GPGSV,2,1,02,40,40,083,46", "GPGSV,2,2,02,41,40,083,46"],
+        );
+        // The used ones GSV didn't mention are still there
+        assert_eq!(in_view(&mut parser), [4, 5, 9, 12, 24, 40, 41]);
+    }
+
+    #[test]
+    fn never_panics_on_garbage() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let alphabet = b"0123456789,.*$NSEWAV-\xc3\xa9\xff\r\n";
+
+        let mut parser = NmeaParser::new();
+        let mut stream = NmeaStream::new();
+        for round in 0..20_000 {
+            let mut bytes = sentence(BURST[round % BURST.len()]).into_bytes();
+            for _ in 0..1 + random() % 4 {
+                let at = random() as usize % bytes.len().max(1);
+                match random() % 3 {
+                    0 if at < bytes.len() => {
+                        bytes[at] = alphabet[random() as usize % alphabet.len()]
+                    }
+                    1 => bytes.truncate(at),
+                    _ => bytes.insert(at, alphabet[random() as usize % alphabet.len()]),
+                }
+            }
+            stream.push_bytes(&bytes);
+            if let Ok(line) = str::from_utf8(&bytes) {
+                let _ = parse_sentence(line);
+                // Past the checksum too
+                if let Some((body, _)) = line.trim_start_matches('$').split_once('*') {
+                    let _ = parser.feed(&sentence(body));
+                }
+            }
+        }
+        // Still works afterwards
+        let fixes = feed_all(&mut NmeaParser::new(), &BURST);
+        assert!(fixes.len() == 1 && fixes[0].is_ok());
+        assert!(parser.errors() > 0);
+    }
+
+    #[test]
+    fn puts_a_burst_together_into_a_fix() {
+        let mut parser = NmeaParser::new();
+        let fixes = feed_all(&mut parser, &BURST);
+        let [Ok(fix)] = fixes[..] else {
+            panic!("{fixes:?}");
+        };
+        assert_eq!(
+            fix.coord,
+            FixedCoord::from_degrees(48.1173, 11.0 + 31.0 / 60.0)
+        );
+        assert_eq!(
+            fix.time,
+            UtcTime {
+                year: 1994,
+                month: 3,
+                day: 23,
+                hour: 12,
+                minute: 35,
+                second: 19
+            }
+        );
+        assert!((fix.speed_mps - 22.4 * KNOTS_TO_KMH / 3.6).abs() < 1e-4);
+        assert_eq!(fix.bearing, 84.4);
+        // GGA's HDOP, it came after GSA's
+        assert_eq!((fix.hdop, fix.altitude_m), (0.9, 545.4));
+        assert_eq!((fix.quality, fix.satellites_used), (FixQuality::ThreeD, 5));
+        let used = parser.satellites().iter().filter(|s| s.used).count();
+        assert_eq!((parser.satellites().len(), used), (6, 5));
+    }
+
+    #[test]
+    fn gga_and_rmc_round_trip() {
+        let mut parser = NmeaParser::new();
+        let places = [
+            (52.520_008_1, 13.404_954_3),
+            (-33.856_784_4, 151.215_296_7),
+            (40.689_247_2, -74.044_502_1),
+            (-22.951_916_4, -43.210_487_3),
+            (0.000_001_2, -0.000_003_4),
+            (89.5, 179.999_999_9),
+        ];
+        for (i, (lat, lon)) in places.into_iter().enumerate() {
+            let coord = FixedCoord::from_degrees(lat, lon);
+            let time = UtcTime {
+                year: 2000 + i as u16 * 5,
+                month: 1 + i as u8,
+                day: 10 + i as u8,
+                hour: 23,
+                minute: 59,
+                second: i as u8 * 10,
+            };
+            let [gga, rmc] = gga_rmc(coord, time, 10.0 + i as f32, 45.0 * i as f32);
+            assert_eq!(parser.feed(&sentence(&gga)), None);
+            let fix = parser.feed(&sentence(&rmc)).unwrap().unwrap();
+
+            // Five decimals of minutes are good to about 2 cm
+            assert!((fix.coord.lat - coord.lat).abs() <= 2, "{fix:?} {coord:?}");
+            assert!((fix.coord.lon - coord.lon).abs() <= 2, "{fix:?} {coord:?}");
+            assert_eq!(fix.time, time);
+            assert!((fix.speed_mps - (10.0 + i as f32) * KNOTS_TO_KMH / 3.6).abs() < 1e-4);
+            assert_eq!(fix.bearing, 45.0 * i as f32);
+            assert_eq!((fix.hdop, fix.altitude_m), (1.1, 35.5));
+            assert_eq!(fix.satellites_used, 7);
+        }
+
+        // Either order
+        let coord = FixedCoord::from_degrees(48.0, 11.0);
+        let time = UtcTime {
+            year: 2024,
+            month: 6,
+            day: 1,
+            ..UtcTime::default()
+        };
+        let [gga, rmc] = gga_rmc(coord, time, 0.0, 0.0);
+        let fixes = feed_all(&mut parser, &[&rmc, &gga]);
+        assert!(matches!(fixes[..], [Ok(Fix { coord: c, .. })] if c == coord));
+    }
+
+    #[test]
+    fn no_fix_while_searching() {
+        let mut parser = NmeaParser::new();
+        let fixes = feed_all(
+            &mut parser,
+            &[
+                "GPGGA,123519.00,4807.038,N,01131.000,E,0,00,,,M,,M,,",
+                "GPRMC,123519.00,V,4807.038,N,01131.000,E,,,230394,,,N",
+            ],
+        );
+        assert_eq!(fixes, [Err(FixError::NoFix)]);
+    }
+
+    #[test]
+    fn speed_and_course_belong_to_one_fix() {
+        let mut parser = NmeaParser::new();
+        let fixes = feed_all(
+            &mut parser,
+            &[
+                BURST[3],
+                BURST[4],
+                BURST[5],
+                // Stopped, so the receiver leaves speed and course out
+                "GPGGA,123520.00,4807.038,N,01131.000,E,1,05,0.9,545.4,M,46.9,M,,",
+                "GPRMC,123520.00,A,4807.038,N,01131.000,E,,,230394,003.1,W",
+            ],
+        );
+        let speeds = fixes
+            .iter()
+            .map(|fix| fix.map(|fix| (fix.speed_mps, fix.bearing)))
+            .collect::<Vec<_>>();
+        assert!(speeds[0].unwrap().0 > 11.0);
+        assert_eq!(speeds[1], Ok((0.0, 0.0)));
+
+        // VTG within the fix does count
+        let fixes = feed_all(
+            &mut parser,
+            &[
+                "GPRMC,123521.00,A,4807.038,N,01131.000,E,,,230394,003.1,W",
+                "GPVTG,270.0,T,,M,,N,18.0,K",
+                "GPGGA,123521.00,4807.038,N,01131.000,E,1,05,0.9,545.4,M,46.9,M,,",
+            ],
+        );
+        assert_eq!(
+            fixes[0].map(|fix| (fix.speed_mps, fix.bearing)),
+            Ok((5.0, 270.0))
+        );
+    }
+
+    #[test]
+    fn fixes_from_gga_alone() {
+        let ggas = (0..5)
+            .map(|i| {
+                format!(
+                    "GPGGA,1235{:02}.00,4807.038,N,01131.000,E,1,06,1.0,500.0,M,,M,,",
+                    i * 10
+                )
+            })
+            .collect::<Vec<_>>();
+        let log = ggas.iter().map(|body| sentence(body)).collect::<String>();
+
+        let fixes = parse_log(log.as_bytes());
+        assert_eq!(fixes.len(), 5);
+        for (i, fix) in fixes.iter().enumerate() {
+            assert_eq!(
+                fix.time,
+                UtcTime {
+                    year: 1980,
+                    month: 1,
+                    day: 6,
+                    hour: 12,
+                    minute: 35,
+                    second: i as u8 * 10
+                }
+            );
+            assert_eq!(fix.satellites_used, 6);
+        }
+
+        // A stream gets each one once the next starts
+        let mut parser = NmeaParser::new();
+        let bodies = ggas.iter().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(feed_all(&mut parser, &bodies).len(), 4);
+        assert!(parser.flush().is_some_and(|fix| fix.is_ok()));
+        assert_eq!(parser.flush(), None);
+
+        // The date of an earlier RMC carries over to fixes that lost theirs
+        let mut parser = NmeaParser::new();
+        feed_all(&mut parser, &BURST);
+        let fixes = feed_all(&mut parser, &[&ggas[1], &ggas[2]]);
+        let [Ok(fix)] = fixes[..] else {
+            panic!("{fixes:?}");
+        };
+        assert_eq!((fix.time.year, fix.time.month, fix.time.day), (1994, 3, 23));
+        assert_eq!(fix.time.second, 10);
+    }
+}
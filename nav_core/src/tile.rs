@@ -0,0 +1,215 @@
+//! Map tiles, numbered the slippy map way: at zoom `z` the world is `2^z` by `2^z` tiles,
+//! `x` growing east from 180°W and `y` growing south from the top.
+//! See https://wiki.openstreetmap.org/wiki/Slippy_map_tilenames for the math.
+
+use core::f64::consts::PI;
+
+use crate::coord::Coord;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TileKey {
+    pub zoom: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileKey {
+    pub fn new(zoom: u8, x: u32, y: u32) -> Self {
+        Self { zoom, x, y }
+    }
+
+    /// Number of tiles in each direction at this zoom level
+    pub fn tiles_per_side(&self) -> u32 {
+        1 << self.zoom
+    }
+
+    /// The tile `dx` and `dy` tiles away, if it's on the map. Wraps around east to west.
+    pub fn offset(&self, dx: i32, dy: i32) -> Option<Self> {
+        let side = self.tiles_per_side() as i64;
+        let y = self.y as i64 + dy as i64;
+        if !(0..side).contains(&y) {
+            return None;
+        }
+        let x = (self.x as i64 + dx as i64).rem_euclid(side);
+        Some(Self::new(self.zoom, x as u32, y as u32))
+    }
+}
+
+/// A point on the map as a tile and a pixel within it, at some zoom level and tile resolution
+#[derive(Debug, Clone, Copy)]
+pub struct GlobalLocation {
+    pub tile_x: u32,
+    pub tile_y: u32,
+    pub x: u32,
+    pub y: u32,
+
+    pub lat: f64,
+}
+
+impl GlobalLocation {
+    /// Back to degrees, the top left corner of the pixel
+    pub fn to_coord(&self, zoom: u8, tile_res: u32) -> Coord {
+        let res = tile_res as f64;
+        tile_to_coord(
+            self.tile_x as f64 + self.x as f64 / res,
+            self.tile_y as f64 + self.y as f64 / res,
+            zoom,
+        )
+    }
+}
+
+/// Projects coordinates using web mercator projection
+fn to_web_mercator(coord: Coord) -> (f64, f64) {
+    (coord.lon, libm::asinh(libm::tan(coord.lat * PI / 180.0)))
+}
+
+/// Where `coord` lies in tiles at `zoom`, the fraction being the position within the tile
+pub fn tile_position(coord: Coord, zoom: u8) -> (f64, f64) {
+    let (x, y) = to_web_mercator(coord);
+
+    // Map projected coordinates onto a unit square
+    let x = 0.5 + x / 360.0;
+    let y = 0.5 - y / (2.0 * PI);
+
+    let n = (1u64 << zoom) as f64;
+    (x * n, y * n)
+}
+
+/// The other way around from [`tile_position`]
+pub fn tile_to_coord(x: f64, y: f64, zoom: u8) -> Coord {
+    let n = (1u64 << zoom) as f64;
+    let (x, y) = (x / n, y / n);
+    Coord {
+        lat: libm::atan(libm::sinh(PI * (1.0 - 2.0 * y))) * 180.0 / PI,
+        lon: x * 360.0 - 180.0,
+    }
+}
+
+/// Zoom level corresponds to https://wiki.openstreetmap.org/wiki/Slippy_map_tilenames#Zoom_levels
+pub fn coord_to_tile(coord: Coord, zoom: u8, tile_res: u32) -> GlobalLocation {
+    let (x, y) = tile_position(coord, zoom);
+    let (tile_x, tile_y) = (libm::floor(x), libm::floor(y));
+
+    // Get local tile space coordinate and transform into pixel space
+    GlobalLocation {
+        tile_x: tile_x as u32,
+        tile_y: tile_y as u32,
+        x: ((x - tile_x) * tile_res as f64) as u32,
+        y: ((y - tile_y) * tile_res as f64) as u32,
+        lat: coord.lat,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_LAT: f64 = 85.051_128_779_806_59;
+
+    fn coord(lat: f64, lon: f64) -> Coord {
+        Coord { lat, lon }
+    }
+
+    fn assert_close(a: Coord, b: Coord, degrees: f64) {
+        assert!(
+            (a.lat - b.lat).abs() <= degrees && (a.lon - b.lon).abs() <= degrees,
+            "{a:?} {b:?}"
+        );
+    }
+
+    #[test]
+    fn known_tiles() {
+        let berlin = coord_to_tile(coord(52.52, 13.405), 10, 256);
+        assert_eq!((berlin.tile_x, berlin.tile_y), (550, 335));
+        assert_eq!((berlin.x, berlin.y), (33, 211));
+
+        let sydney = coord_to_tile(coord(-33.8688, 151.2093), 12, 256);
+        assert_eq!((sydney.tile_x, sydney.tile_y), (3768, 2457));
+
+        let middle = coord_to_tile(coord(0.0, 0.0), 1, 256);
+        assert_eq!(
+            (middle.tile_x, middle.tile_y, middle.x, middle.y),
+            (1, 1, 0, 0)
+        );
+        let world = coord_to_tile(coord(45.0, -90.0), 0, 256);
+        assert_eq!((world.tile_x, world.tile_y), (0, 0));
+    }
+
+    #[test]
+    fn corners_of_the_map() {
+        for zoom in [0, 5, 19] {
+            let side = (1u64 << zoom) as f64;
+            assert_close(tile_to_coord(0.0, 0.0, zoom), coord(MAX_LAT, -180.0), 1e-9);
+            assert_close(
+                tile_to_coord(side, side, zoom),
+                coord(-MAX_LAT, 180.0),
+                1e-9,
+            );
+            assert_close(
+                tile_to_coord(side / 2.0, side / 2.0, zoom),
+                coord(0.0, 0.0),
+                1e-9,
+            );
+        }
+    }
+
+    #[test]
+    fn tile_positions_round_trip() {
+        for zoom in [0, 1, 10, 19, 24] {
+            for c in [
+                coord(52.52, 13.405),
+                coord(-33.8688, 151.2093),
+                coord(0.0, 0.0),
+                coord(MAX_LAT - 1e-6, -180.0),
+                coord(-MAX_LAT + 1e-6, 179.999_999),
+            ] {
+                let (x, y) = tile_position(c, zoom);
+                assert_close(tile_to_coord(x, y, zoom), c, 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn global_locations_round_trip_within_a_pixel() {
+        for (zoom, tile_res) in [(3, 256), (10, 256), (16, 512), (19, 256)] {
+            // Degrees across one pixel at the equator, less everywhere else
+            let pixel = 360.0 / ((1u64 << zoom) as f64 * tile_res as f64);
+            for c in [
+                coord(52.52, 13.405),
+                coord(-33.8688, 151.2093),
+                coord(64.1466, -21.9426),
+                coord(-MAX_LAT + 1e-6, -179.999_999),
+            ] {
+                let location = coord_to_tile(c, zoom, tile_res);
+                assert!(location.x < tile_res && location.y < tile_res);
+                let corner = location.to_coord(zoom, tile_res);
+                assert_close(corner, c, pixel);
+                // The top left corner of the pixel
+                assert!(
+                    corner.lon <= c.lon && corner.lat >= c.lat,
+                    "{corner:?} {c:?}"
+                );
+                // And back to the same pixel, give or take rounding
+                let again = coord_to_tile(
+                    coord(corner.lat - 1e-12, corner.lon + 1e-12),
+                    zoom,
+                    tile_res,
+                );
+                assert_eq!(
+                    (again.tile_x, again.tile_y, again.x, again.y),
+                    (location.tile_x, location.tile_y, location.x, location.y)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn offsets_wrap_east_to_west_only() {
+        let key = TileKey::new(2, 3, 0);
+        assert_eq!(key.offset(1, 0), Some(TileKey::new(2, 0, 0)));
+        assert_eq!(key.offset(-4, 1), Some(TileKey::new(2, 3, 1)));
+        assert_eq!(key.offset(0, -1), None);
+        assert_eq!(key.offset(0, 4), None);
+        assert_eq!(key.offset(0, 3), Some(TileKey::new(2, 3, 3)));
+    }
+}
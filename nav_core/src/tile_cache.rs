@@ -0,0 +1,368 @@
+//! Keeps decoded tiles around within a memory budget, throwing out whichever was used
+//! longest ago when a new one doesn't fit.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::tile::TileKey;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Share of lookups that found their tile, from 0 to 1
+    pub fn hit_rate(&self) -> f32 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f32 / total as f32,
+        }
+    }
+}
+
+struct Entry<T> {
+    value: T,
+    size: usize,
+    last_used: u64,
+}
+
+pub struct TileCache<T> {
+    budget: usize,
+    used: usize,
+    entries: BTreeMap<TileKey, Entry<T>>,
+    /// `last_used` of every entry, oldest first
+    by_age: BTreeMap<u64, TileKey>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+impl<T> TileCache<T> {
+    /// `budget` is in bytes, as counted by the sizes given to [`TileCache::insert`]
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            entries: BTreeMap::new(),
+            by_age: BTreeMap::new(),
+            clock: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn contains(&self, key: TileKey) -> bool {
+        self.entries.contains_key(&key)
+    }
+
+    /// Looks up a tile and marks it as just used, counting a hit or miss
+    pub fn get(&mut self, key: TileKey) -> Option<&T> {
+        let Some(entry) = self.entries.get_mut(&key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        self.stats.hits += 1;
+        self.clock += 1;
+        self.by_age.remove(&entry.last_used);
+        self.by_age.insert(self.clock, key);
+        entry.last_used = self.clock;
+        Some(&entry.value)
+    }
+
+    /// Looks up a tile without it counting as a use
+    pub fn peek(&self, key: TileKey) -> Option<&T> {
+        self.entries.get(&key).map(|entry| &entry.value)
+    }
+
+    /// Adds (or replaces) a tile that takes `size` bytes, evicting the least recently used
+    /// tiles until it fits. A tile bigger than the whole budget isn't kept, then this returns
+    /// the value back.
+    pub fn insert(&mut self, key: TileKey, value: T, size: usize) -> Result<(), T> {
+        if size > self.budget {
+            return Err(value);
+        }
+        self.remove(key);
+        while self.used + size > self.budget {
+            let Some((_, oldest)) = self.by_age.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.used -= entry.size;
+                self.stats.evictions += 1;
+            }
+        }
+
+        self.clock += 1;
+        self.by_age.insert(self.clock, key);
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                last_used: self.clock,
+            },
+        );
+        self.used += size;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: TileKey) -> Option<T> {
+        let entry = self.entries.remove(&key)?;
+        self.by_age.remove(&entry.last_used);
+        self.used -= entry.size;
+        Some(entry.value)
+    }
+
+    /// Drops every tile, e.g. when switching maps. The counters keep going.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.by_age.clear();
+        self.used = 0;
+    }
+
+    /// Tiles worth loading before they're needed: `depth` rows of three tiles in the direction
+    /// of `heading` (degrees, 0 is north) from `center`, nearest first, minus what's cached
+    pub fn prefetch_candidates(&self, center: TileKey, heading: f32, depth: i32) -> Vec<TileKey> {
+        let (dx, dy) = heading_step(heading);
+        let mut keys = Vec::new();
+        for step in 1..=depth {
+            let (ax, ay) = (dx * step, dy * step);
+            let row = if dx != 0 && dy != 0 {
+                // Going diagonally, the tiles next to the diagonal on either side
+                [(ax, ay), (ax, ay - dy), (ax - dx, ay)]
+            } else {
+                // Straight ahead and its neighbours left and right of it
+                [(ax, ay), (ax + dy, ay + dx), (ax - dy, ay - dx)]
+            };
+            for (x, y) in row {
+                if let Some(key) = center.offset(x, y)
+                    && !self.contains(key)
+                    && !keys.contains(&key)
+                {
+                    keys.push(key);
+                }
+            }
+        }
+        keys
+    }
+}
+
+/// The neighbouring tile `heading` points at, as (dx, dy) with y growing south
+fn heading_step(heading: f32) -> (i32, i32) {
+    // Eight directions of 45 degrees each, north first
+    let octant = (libm::floorf(heading / 45.0 + 0.5) as i32).rem_euclid(8);
+    [
+        (0, -1),
+        (1, -1),
+        (1, 0),
+        (1, 1),
+        (0, 1),
+        (-1, 1),
+        (-1, 0),
+        (-1, -1),
+    ][octant as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(x: u32, y: u32) -> TileKey {
+        TileKey::new(5, x, y)
+    }
+
+    /// Three tiles of 10 bytes in a cache that fits exactly those
+    fn full_cache() -> TileCache<u32> {
+        let mut cache = TileCache::new(30);
+        for x in 0..3 {
+            cache.insert(key(x, 0), x, 10).unwrap();
+        }
+        cache
+    }
+
+    #[test]
+    fn get_counts_as_a_use_but_peek_does_not() {
+        let mut cache = full_cache();
+        assert_eq!(cache.get(key(0, 0)), Some(&0));
+        assert_eq!(cache.peek(key(1, 0)), Some(&1));
+
+        // 1 is now the one used longest ago
+        cache.insert(key(3, 0), 3, 10).unwrap();
+        assert!(!cache.contains(key(1, 0)));
+        assert!(cache.contains(key(0, 0)) && cache.contains(key(2, 0)));
+        cache.insert(key(4, 0), 4, 10).unwrap();
+        assert!(!cache.contains(key(2, 0)));
+        cache.insert(key(5, 0), 5, 10).unwrap();
+        assert!(!cache.contains(key(0, 0)));
+
+        // Replacing a tile counts as using it too
+        let mut cache = full_cache();
+        cache.insert(key(0, 0), 10, 10).unwrap();
+        cache.insert(key(3, 0), 3, 10).unwrap();
+        assert_eq!(cache.peek(key(0, 0)), Some(&10));
+        assert!(!cache.contains(key(1, 0)));
+    }
+
+    #[test]
+    fn stays_within_the_budget() {
+        let mut cache = TileCache::new(1000);
+        let mut seed = 12345u32;
+        for i in 0..2000u32 {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let size = (seed >> 16) as usize % 300;
+            let key = key(seed % 40, 0);
+            cache.insert(key, i, size).unwrap();
+            assert!(cache.used_bytes() <= cache.budget());
+            assert!(cache.contains(key));
+            if i % 7 == 0 {
+                cache.get(key);
+            }
+        }
+
+        // The sizes add up after evicting, replacing and removing
+        let mut cache = TileCache::new(100);
+        cache.insert(key(0, 0), 0, 40).unwrap();
+        cache.insert(key(1, 0), 1, 40).unwrap();
+        cache.insert(key(0, 0), 0, 10).unwrap();
+        assert_eq!(cache.used_bytes(), 50);
+        cache.insert(key(2, 0), 2, 50).unwrap();
+        assert_eq!((cache.used_bytes(), cache.len()), (100, 3));
+        // Needs two tiles gone
+        cache.insert(key(3, 0), 3, 50).unwrap();
+        assert_eq!((cache.used_bytes(), cache.len()), (100, 2));
+        assert_eq!(cache.remove(key(3, 0)), Some(3));
+        assert_eq!(cache.remove(key(3, 0)), None);
+        assert_eq!(cache.used_bytes(), 50);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.used_bytes(), 0);
+    }
+
+    #[test]
+    fn refuses_tiles_bigger_than_the_budget() {
+        let mut cache = full_cache();
+        assert_eq!(cache.insert(key(3, 0), 3, 31), Err(3));
+        // Nothing was thrown out for it
+        assert_eq!((cache.len(), cache.used_bytes()), (3, 30));
+        assert_eq!(cache.stats().evictions, 0);
+        // Not even the tile it would have replaced
+        assert_eq!(cache.insert(key(0, 0), 9, 31), Err(9));
+        assert_eq!(cache.peek(key(0, 0)), Some(&0));
+
+        // Exactly the budget fits, on its own
+        cache.insert(key(3, 0), 3, 30).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.stats().evictions, 3);
+    }
+
+    #[test]
+    fn counts_hits_misses_and_evictions() {
+        let mut cache = full_cache();
+        assert_eq!(cache.stats(), CacheStats::default());
+        assert_eq!(cache.stats().hit_rate(), 0.0);
+
+        cache.get(key(0, 0));
+        cache.get(key(1, 0));
+        cache.get(key(9, 9));
+        // Neither peeking nor checking counts
+        cache.peek(key(9, 9));
+        cache.contains(key(9, 9));
+        cache.insert(key(3, 0), 3, 20).unwrap();
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 1,
+                evictions: 2,
+            }
+        );
+        assert!((cache.stats().hit_rate() - 2.0 / 3.0).abs() < 1e-6);
+
+        // Removing and clearing isn't evicting, and the counters keep going
+        cache.remove(key(3, 0));
+        cache.clear();
+        cache.get(key(0, 0));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 2,
+                misses: 2,
+                evictions: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn prefetches_ahead_in_every_direction() {
+        let cache = TileCache::<u32>::new(100);
+        let center = key(10, 10);
+        let offsets = |heading| {
+            cache
+                .prefetch_candidates(center, heading, 1)
+                .iter()
+                .map(|k| (k.x as i32 - 10, k.y as i32 - 10))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(offsets(0.0), [(0, -1), (-1, -1), (1, -1)]);
+        assert_eq!(offsets(45.0), [(1, -1), (1, 0), (0, -1)]);
+        assert_eq!(offsets(90.0), [(1, 0), (1, 1), (1, -1)]);
+        assert_eq!(offsets(135.0), [(1, 1), (1, 0), (0, 1)]);
+        assert_eq!(offsets(180.0), [(0, 1), (1, 1), (-1, 1)]);
+        assert_eq!(offsets(225.0), [(-1, 1), (-1, 0), (0, 1)]);
+        assert_eq!(offsets(270.0), [(-1, 0), (-1, -1), (-1, 1)]);
+        assert_eq!(offsets(315.0), [(-1, -1), (-1, 0), (0, -1)]);
+
+        // Rounded to the nearest direction, any way around
+        assert_eq!(offsets(22.4), offsets(0.0));
+        assert_eq!(offsets(22.6), offsets(45.0));
+        assert_eq!(offsets(350.0), offsets(0.0));
+        assert_eq!(offsets(-90.0), offsets(270.0));
+        assert_eq!(offsets(720.0 + 180.0), offsets(180.0));
+
+        // Further rows come after the nearer ones
+        let keys = cache.prefetch_candidates(center, 90.0, 3);
+        assert_eq!(keys.len(), 9);
+        assert_eq!(keys[3..6], [key(12, 10), key(12, 11), key(12, 9)]);
+        assert_eq!(keys[6], key(13, 10));
+    }
+
+    #[test]
+    fn prefetches_what_is_missing_on_the_map() {
+        let mut cache = TileCache::new(100);
+        cache.insert(key(10, 9), 0, 1).unwrap();
+        assert_eq!(
+            cache.prefetch_candidates(key(10, 10), 0.0, 1),
+            [key(9, 9), key(11, 9)]
+        );
+
+        // Wraps around the date line, and there's nothing past the top
+        let side = key(0, 0).tiles_per_side();
+        assert_eq!(
+            cache.prefetch_candidates(key(side - 1, 5), 90.0, 1),
+            [key(0, 5), key(0, 6), key(0, 4)]
+        );
+        assert_eq!(cache.prefetch_candidates(key(3, 0), 0.0, 2), []);
+        assert_eq!(cache.prefetch_candidates(key(3, 0), 45.0, 1), [key(4, 0)]);
+    }
+}
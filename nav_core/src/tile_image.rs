@@ -0,0 +1,317 @@
+//! Turns QOI tiles into pixels the GE can use straight away.
+//!
+//! Textures on the PSP need a power of two width, so rows are padded out to that. The padding
+//! is left transparent.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
+use tinyqoi::Qoi;
+
+/// `qoif`, magic, width, height, channels, colorspace
+const HEADER_SIZE: usize = 14;
+
+/// Every QOI file ends with seven zero bytes and a one
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// Biggest texture the GE takes
+const MAX_SIDE: u32 = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 32 bits, what the framebuffer uses
+    Abgr8888,
+    /// 16 bits, half the memory for tiles that don't need the colors
+    Rgb565,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Shorter than a header plus end marker, or the end marker is missing
+    Truncated,
+    /// Not a QOI file
+    BadHeader,
+    TooLarge {
+        width: u32,
+        height: u32,
+    },
+    /// The pixel data ran out before the image was complete
+    Corrupt,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pixels {
+    Abgr8888(Vec<u32>),
+    Rgb565(Vec<u16>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileImage {
+    pub width: u32,
+    pub height: u32,
+    /// Row length in pixels, the width rounded up to a power of two
+    pub stride: u32,
+    pub pixels: Pixels,
+}
+
+impl TileImage {
+    pub fn format(&self) -> PixelFormat {
+        match self.pixels {
+            Pixels::Abgr8888(_) => PixelFormat::Abgr8888,
+            Pixels::Rgb565(_) => PixelFormat::Rgb565,
+        }
+    }
+
+    /// What it takes up in memory, for [`crate::tile_cache::TileCache`]
+    pub fn size_bytes(&self) -> usize {
+        match &self.pixels {
+            Pixels::Abgr8888(pixels) => pixels.len() * 4,
+            Pixels::Rgb565(pixels) => pixels.len() * 2,
+        }
+    }
+
+    /// Every pixel of the image row by row, without the padding
+    pub fn colors(&self) -> impl Iterator<Item = Rgb888> + '_ {
+        let (width, stride) = (self.width as usize, self.stride as usize);
+        (0..self.height as usize).flat_map(move |y| {
+            let row = y * stride..y * stride + width;
+            let (abgr, rgb) = match &self.pixels {
+                Pixels::Abgr8888(pixels) => (&pixels[row], &[][..]),
+                Pixels::Rgb565(pixels) => (&[][..], &pixels[row]),
+            };
+            let abgr = abgr
+                .iter()
+                .map(|&pixel| Rgb888::new(pixel as u8, (pixel >> 8) as u8, (pixel >> 16) as u8));
+            let rgb = rgb.iter().map(|&pixel| {
+                let (r, g, b) = (pixel & 0x1f, (pixel >> 5) & 0x3f, pixel >> 11);
+                Rgb888::new(
+                    (r << 3 | r >> 2) as u8,
+                    (g << 2 | g >> 4) as u8,
+                    (b << 3 | b >> 2) as u8,
+                )
+            });
+            abgr.chain(rgb)
+        })
+    }
+
+    /// Start of the pixels, to hand to the GE
+    pub fn as_ptr(&self) -> *const u8 {
+        match &self.pixels {
+            Pixels::Abgr8888(pixels) => pixels.as_ptr() as *const u8,
+            Pixels::Rgb565(pixels) => pixels.as_ptr() as *const u8,
+        }
+    }
+}
+
+pub fn decode(data: &[u8], format: PixelFormat) -> Result<TileImage, DecodeError> {
+    // tinyqoi trusts the data to be complete, so check what can be checked up front
+    if data.len() < HEADER_SIZE + END_MARKER.len() || !data.ends_with(&END_MARKER) {
+        return Err(DecodeError::Truncated);
+    }
+    let qoi = Qoi::new(data).map_err(|_| DecodeError::BadHeader)?;
+    let Size { width, height } = qoi.size();
+    if width == 0 || height == 0 {
+        return Err(DecodeError::BadHeader);
+    }
+    if width > MAX_SIDE || height > MAX_SIDE {
+        return Err(DecodeError::TooLarge { width, height });
+    }
+
+    let stride = width.next_power_of_two();
+    let len = (stride * height) as usize;
+    let mut decoded = 0;
+    let pixels = match format {
+        PixelFormat::Abgr8888 => {
+            let mut pixels = vec![0u32; len];
+            for (i, color) in qoi.pixels().take((width * height) as usize).enumerate() {
+                pixels[index(i, width, stride)] = abgr8888(color);
+                decoded += 1;
+            }
+            Pixels::Abgr8888(pixels)
+        }
+        PixelFormat::Rgb565 => {
+            let mut pixels = vec![0u16; len];
+            for (i, color) in qoi.pixels().take((width * height) as usize).enumerate() {
+                pixels[index(i, width, stride)] = rgb565(color);
+                decoded += 1;
+            }
+            Pixels::Rgb565(pixels)
+        }
+    };
+    if decoded < width * height {
+        return Err(DecodeError::Corrupt);
+    }
+
+    Ok(TileImage {
+        width,
+        height,
+        stride,
+        pixels,
+    })
+}
+
+/// Position of pixel `i` of the image in the padded buffer
+fn index(i: usize, width: u32, stride: u32) -> usize {
+    let (x, y) = (i as u32 % width, i as u32 / width);
+    (y * stride + x) as usize
+}
+
+/// Red in the lowest byte, alpha opaque
+fn abgr8888(color: Rgb888) -> u32 {
+    0xff00_0000 | (color.b() as u32) << 16 | (color.g() as u32) << 8 | color.r() as u32
+}
+
+/// Red in the lowest bits, the way `GU_PSM_5650` has it
+fn rgb565(color: Rgb888) -> u16 {
+    (color.b() as u16 >> 3) << 11 | (color.g() as u16 >> 2) << 5 | color.r() as u16 >> 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A QOI file with every pixel spelled out in full, `pixels` row by row
+    fn qoi(width: u32, height: u32, pixels: &[[u8; 3]]) -> Vec<u8> {
+        let mut data = b"qoif".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[3, 0]);
+        for [r, g, b] in pixels {
+            data.extend_from_slice(&[0xfe, *r, *g, *b]);
+        }
+        data.extend_from_slice(&END_MARKER);
+        data
+    }
+
+    /// Three by two, so every row gets one pixel of padding
+    fn small() -> (Vec<[u8; 3]>, Vec<u8>) {
+        let pixels = vec![
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 255],
+            [8, 132, 248],
+            [0, 0, 0],
+        ];
+        let data = qoi(3, 2, &pixels);
+        (pixels, data)
+    }
+
+    #[test]
+    fn pads_rows_to_a_power_of_two() {
+        let (pixels, data) = small();
+        let image = decode(&data, PixelFormat::Abgr8888).unwrap();
+        assert_eq!((image.width, image.height, image.stride), (3, 2, 4));
+        assert_eq!(image.format(), PixelFormat::Abgr8888);
+        assert_eq!(image.size_bytes(), 4 * 2 * 4);
+        assert_eq!(
+            image.pixels,
+            Pixels::Abgr8888(vec![
+                0xff00_00ff,
+                0xff00_ff00,
+                0xffff_0000,
+                0,
+                0xffff_ffff,
+                0xfff8_8408,
+                0xff00_0000,
+                0,
+            ])
+        );
+        let colors = image.colors().collect::<Vec<_>>();
+        let expected = pixels
+            .iter()
+            .map(|&[r, g, b]| Rgb888::new(r, g, b))
+            .collect::<Vec<_>>();
+        assert_eq!(colors, expected);
+    }
+
+    #[test]
+    fn pads_rows_in_16_bits_too() {
+        let (pixels, data) = small();
+        let image = decode(&data, PixelFormat::Rgb565).unwrap();
+        assert_eq!((image.width, image.height, image.stride), (3, 2, 4));
+        assert_eq!(image.format(), PixelFormat::Rgb565);
+        assert_eq!(image.size_bytes(), 4 * 2 * 2);
+        assert_eq!(
+            image.pixels,
+            Pixels::Rgb565(vec![0x001f, 0x07e0, 0xf800, 0, 0xffff, 0xfc21, 0x0000, 0])
+        );
+        // Only the low bits get lost
+        for (color, [r, g, b]) in image.colors().zip(pixels) {
+            assert_eq!(color.r() >> 3, r >> 3);
+            assert_eq!(color.g() >> 2, g >> 2);
+            assert_eq!(color.b() >> 3, b >> 3);
+        }
+    }
+
+    #[test]
+    fn power_of_two_widths_need_no_padding() {
+        let data = qoi(4, 1, &[[1, 2, 3]; 4]);
+        let image = decode(&data, PixelFormat::Abgr8888).unwrap();
+        assert_eq!(image.stride, 4);
+        assert_eq!(image.pixels, Pixels::Abgr8888(vec![0xff03_0201; 4]));
+    }
+
+    #[test]
+    fn refuses_truncated_files() {
+        let (_, data) = small();
+        for len in [0, HEADER_SIZE, HEADER_SIZE + END_MARKER.len() - 1] {
+            assert_eq!(
+                decode(&data[..len], PixelFormat::Abgr8888),
+                Err(DecodeError::Truncated)
+            );
+        }
+        // Cut anywhere else, the end marker is gone
+        assert_eq!(
+            decode(&data[..data.len() - 1], PixelFormat::Abgr8888),
+            Err(DecodeError::Truncated)
+        );
+        let mut no_end = data.clone();
+        *no_end.last_mut().unwrap() = 0;
+        assert_eq!(
+            decode(&no_end, PixelFormat::Rgb565),
+            Err(DecodeError::Truncated)
+        );
+    }
+
+    #[test]
+    fn refuses_what_isnt_qoi() {
+        let (_, data) = small();
+        let mut wrong_magic = data.clone();
+        wrong_magic[..4].copy_from_slice(b"qoiv");
+        assert_eq!(
+            decode(&wrong_magic, PixelFormat::Abgr8888),
+            Err(DecodeError::BadHeader)
+        );
+
+        for (width, height) in [(0, 2), (3, 0)] {
+            assert_eq!(
+                decode(&qoi(width, height, &[]), PixelFormat::Abgr8888),
+                Err(DecodeError::BadHeader)
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_images_bigger_than_a_texture() {
+        for (width, height) in [(513, 1), (1, 513), (1 << 20, 1 << 20)] {
+            assert_eq!(
+                decode(&qoi(width, height, &[]), PixelFormat::Abgr8888),
+                Err(DecodeError::TooLarge { width, height })
+            );
+        }
+        let data = qoi(512, 1, &[[0, 0, 0]; 512]);
+        assert!(decode(&data, PixelFormat::Rgb565).is_ok());
+    }
+
+    #[test]
+    fn refuses_pixels_running_out() {
+        let (pixels, _) = small();
+        let data = qoi(3, 2, &pixels[..4]);
+        for format in [PixelFormat::Abgr8888, PixelFormat::Rgb565] {
+            assert_eq!(decode(&data, format), Err(DecodeError::Corrupt));
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! All map tiles of a region in one file, since FAT on a Memory Stick gets painfully slow with
+//! thousands of small ones.
+//!
+//! | Size               | Content                                             |
+//! |--------------------|-----------------------------------------------------|
+//! | 4                  | Magic `TPAK`                                        |
+//! | 2                  | Format version                                      |
+//! | 1                  | Lowest zoom level                                   |
+//! | 1                  | Highest zoom level                                  |
+//! | 2                  | Tile resolution in pixels                           |
+//! | 1                  | Tile content, see [`TileContent`]                   |
+//! | 1                  | Reserved                                            |
+//! | 16                 | Region min lat, min lon, max lat, max lon (deg*1e7) |
+//! | 4                  | Tile count                                          |
+//! | 20 * tile count    | Index: zoom, 3 padding, x, y, offset, length        |
+//! | ...                | Tile data, one tile after another                   |
+//!
+//! The index is sorted by zoom, x and y, so a tile is found with a binary search. The PSP
+//! reads the header and index once, then seeks to each tile as it needs it. Offsets count from
+//! the start of the file.
+
+use alloc::vec::Vec;
+
+use crate::bytes::*;
+use crate::graph::FixedCoord;
+use crate::tile::TileKey;
+
+pub const MAGIC: [u8; 4] = *b"TPAK";
+pub const VERSION: u16 = 1;
+
+/// Everything before the index
+pub const HEADER_SIZE: usize = 32;
+const INDEX_ENTRY_SIZE: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackError {
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnknownContent(u8),
+    /// Not sorted, or a tile lies outside of the file
+    BadIndex,
+}
+
+/// What the tiles are. Packs from before there was a choice have a 0 there, so QOI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TileContent {
+    /// Pre-rendered QOI images
+    Qoi = 0,
+    /// See [`crate::vector_tile`]
+    Vector = 1,
+}
+
+impl TileContent {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => Self::Qoi,
+            1 => Self::Vector,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackHeader {
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub tile_res: u16,
+    pub content: TileContent,
+    /// (min, max)
+    pub extent: (FixedCoord, FixedCoord),
+    pub tile_count: u32,
+}
+
+impl PackHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, PackError> {
+        if data.len() < HEADER_SIZE {
+            return Err(PackError::TooShort);
+        }
+        if data[0..4] != MAGIC {
+            return Err(PackError::BadMagic);
+        }
+        let version = read_u16(data, 4);
+        if version != VERSION {
+            return Err(PackError::UnsupportedVersion(version));
+        }
+        let content = read_u8(data, 10);
+        let content = TileContent::from_u8(content).ok_or(PackError::UnknownContent(content))?;
+        let coord = |offset| FixedCoord {
+            lat: read_i32(data, offset),
+            lon: read_i32(data, offset + 4),
+        };
+        Ok(Self {
+            min_zoom: read_u8(data, 6),
+            max_zoom: read_u8(data, 7),
+            tile_res: read_u16(data, 8),
+            content,
+            extent: (coord(12), coord(20)),
+            tile_count: read_u32(data, 28),
+        })
+    }
+
+    /// Bytes of index following the header
+    pub fn index_size(&self) -> usize {
+        self.tile_count as usize * INDEX_ENTRY_SIZE
+    }
+
+    /// Where the index ends and the tiles start. Can't overflow even for a bogus tile count,
+    /// so check it against the file size before reading [`Self::index_size`] bytes.
+    pub fn index_end(&self) -> u64 {
+        HEADER_SIZE as u64 + self.tile_count as u64 * INDEX_ENTRY_SIZE as u64
+    }
+}
+
+/// Where a tile's data is in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileEntry {
+    pub key: TileKey,
+    pub offset: u32,
+    pub length: u32,
+}
+
+pub struct PackIndex {
+    entries: Vec<TileEntry>,
+}
+
+impl PackIndex {
+    /// `data` is the index right after the header, `file_size` is used to check the offsets
+    pub fn parse(header: &PackHeader, data: &[u8], file_size: u64) -> Result<Self, PackError> {
+        if data.len() < header.index_size() {
+            return Err(PackError::TooShort);
+        }
+        let data_start = header.index_end();
+
+        let mut entries: Vec<TileEntry> = Vec::with_capacity(header.tile_count as usize);
+        for i in 0..header.tile_count as usize {
+            let at = i * INDEX_ENTRY_SIZE;
+            let entry = TileEntry {
+                key: TileKey::new(
+                    read_u8(data, at),
+                    read_u32(data, at + 4),
+                    read_u32(data, at + 8),
+                ),
+                offset: read_u32(data, at + 12),
+                length: read_u32(data, at + 16),
+            };
+            let end = entry.offset as u64 + entry.length as u64;
+            let in_file = entry.offset as u64 >= data_start && end <= file_size;
+            let sorted = entries.last().is_none_or(|last| last.key < entry.key);
+            if !in_file || !sorted {
+                return Err(PackError::BadIndex);
+            }
+            entries.push(entry);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn find(&self, key: TileKey) -> Option<TileEntry> {
+        self.entries
+            .binary_search_by_key(&key, |entry| entry.key)
+            .ok()
+            .map(|i| self.entries[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Zoom levels that have any tiles, lowest first
+    pub fn zoom_levels(&self) -> Vec<u8> {
+        let mut levels: Vec<u8> = self.entries.iter().map(|entry| entry.key.zoom).collect();
+        levels.dedup();
+        levels
+    }
+}
+
+/// Builds a pack from encoded tiles in any order, used by the converter
+pub fn build(
+    tile_res: u16,
+    content: TileContent,
+    extent: (FixedCoord, FixedCoord),
+    tiles: &[(TileKey, &[u8])],
+) -> Vec<u8> {
+    let mut order: Vec<usize> = (0..tiles.len()).collect();
+    order.sort_by_key(|&i| tiles[i].0);
+    let min_zoom = tiles.iter().map(|(key, _)| key.zoom).min().unwrap_or(0);
+    let max_zoom = tiles.iter().map(|(key, _)| key.zoom).max().unwrap_or(0);
+
+    let index_end = HEADER_SIZE + tiles.len() * INDEX_ENTRY_SIZE;
+    let total = index_end + tiles.iter().map(|(_, tile)| tile.len()).sum::<usize>();
+    let mut data = Vec::with_capacity(total);
+    data.extend_from_slice(&MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.push(min_zoom);
+    data.push(max_zoom);
+    data.extend_from_slice(&tile_res.to_le_bytes());
+    data.push(content as u8);
+    data.push(0);
+    for coord in [extent.0, extent.1] {
+        data.extend_from_slice(&coord.lat.to_le_bytes());
+        data.extend_from_slice(&coord.lon.to_le_bytes());
+    }
+    data.extend_from_slice(&(tiles.len() as u32).to_le_bytes());
+
+    let mut offset = index_end;
+    for &i in &order {
+        let (key, tile) = tiles[i];
+        data.push(key.zoom);
+        data.extend_from_slice(&[0; 3]);
+        data.extend_from_slice(&key.x.to_le_bytes());
+        data.extend_from_slice(&key.y.to_le_bytes());
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        data.extend_from_slice(&(tile.len() as u32).to_le_bytes());
+        offset += tile.len();
+    }
+
+    for &i in &order {
+        data.extend_from_slice(tiles[i].1);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pack() -> Vec<u8> {
+        let extent = (
+            FixedCoord::from_degrees(52.0, 13.0),
+            FixedCoord::from_degrees(53.0, 14.0),
+        );
+        build(
+            256,
+            TileContent::Vector,
+            extent,
+            &[
+                (TileKey::new(12, 2200, 1343), b"second"),
+                (TileKey::new(11, 1100, 671), b"first"),
+            ],
+        )
+    }
+
+    #[test]
+    fn reads_what_it_builds() {
+        let data = pack();
+        let header = PackHeader::parse(&data).unwrap();
+        assert_eq!((header.min_zoom, header.max_zoom), (11, 12));
+        assert_eq!(header.content, TileContent::Vector);
+        assert_eq!(header.tile_count, 2);
+        assert_eq!(
+            header.index_end(),
+            (HEADER_SIZE + 2 * INDEX_ENTRY_SIZE) as u64
+        );
+
+        let index = PackIndex::parse(&header, &data[HEADER_SIZE..], data.len() as u64).unwrap();
+        assert_eq!(index.zoom_levels(), [11, 12]);
+    }
+
+    #[test]
+    fn the_index_end_stays_in_range_for_any_tile_count() {
+        let mut data = pack();
+        data[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        let header = PackHeader::parse(&data).unwrap();
+        assert_eq!(
+            header.index_end(),
+            HEADER_SIZE as u64 + u32::MAX as u64 * INDEX_ENTRY_SIZE as u64
+        );
+        assert!(header.index_end() > data.len() as u64);
+    }
+}
@@ -0,0 +1,543 @@
+//! Follows the position along a route and decides when it has been left.
+//!
+//! GPS positions wander, especially in cities, so being away from the route once isn't enough:
+//! only after staying too far from it (or driving it the wrong way) for a while does the
+//! [`Tracker`] ask for a new route.
+
+use alloc::vec::Vec;
+
+use crate::geometry::{LocalProjection, bearing, bearing_delta, distance, project_onto_line};
+use crate::graph::{FixedCoord, Graph};
+use crate::route::Route;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackerConfig {
+    /// Further than this from the route counts as off it
+    pub off_route_distance_m: f32,
+    /// How long the position has to stay off the route before rerouting
+    pub off_route_time_ms: u32,
+    /// Driving more than this many degrees away from the route's direction counts as off it
+    pub heading_tolerance_deg: f32,
+    /// Below this speed the GPS heading is mostly noise and gets ignored
+    pub min_heading_speed_mps: f32,
+    /// How close to the end of the route counts as there
+    pub arrival_distance_m: f32,
+    /// If a reroute doesn't come (no route found, say), ask again after this long
+    pub reroute_retry_ms: u32,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            off_route_distance_m: 35.0,
+            off_route_time_ms: 4000,
+            heading_tolerance_deg: 100.0,
+            min_heading_speed_mps: 3.0,
+            arrival_distance_m: 25.0,
+            reroute_retry_ms: 10_000,
+        }
+    }
+}
+
+/// One GPS reading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackPosition {
+    pub coord: FixedCoord,
+    /// Degrees, 0 is north, clockwise
+    pub heading: f32,
+    /// Meters per second
+    pub speed: f32,
+    /// Any millisecond clock, only differences are used so it may wrap
+    pub time_ms: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackState {
+    OnRoute,
+    /// Off the route since `since_ms`, but not for long enough yet
+    Diverging {
+        since_ms: u32,
+    },
+    /// Waiting for a new route, asked for one at `requested_ms`
+    OffRoute {
+        requested_ms: u32,
+    },
+    Arrived,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackEvent {
+    None,
+    /// Route again from this position and heading, then hand the new route to
+    /// [`Tracker::set_route`]
+    Reroute {
+        from: FixedCoord,
+        heading: Option<f32>,
+    },
+    /// Got back onto the route while waiting for a new one, it isn't needed anymore
+    BackOnRoute,
+    Arrived,
+}
+
+struct RoutePoint {
+    coord: FixedCoord,
+    /// Meters from the start of the route's first edge
+    along: f32,
+}
+
+pub struct Tracker {
+    config: TrackerConfig,
+    points: Vec<RoutePoint>,
+    /// Where on the polyline the route starts and ends, the first and last edge are only
+    /// driven partially
+    start_m: f32,
+    end_m: f32,
+    /// Meters along the polyline of the last matched position
+    along_m: f32,
+    /// Polyline piece the last position was matched to
+    piece: usize,
+    distance_m: f32,
+    state: TrackState,
+}
+
+impl Tracker {
+    pub fn new(graph: &Graph, route: &Route, config: TrackerConfig) -> Self {
+        let mut tracker = Self {
+            config,
+            points: Vec::new(),
+            start_m: 0.0,
+            end_m: 0.0,
+            along_m: 0.0,
+            piece: 0,
+            distance_m: 0.0,
+            state: TrackState::OnRoute,
+        };
+        tracker.set_route(graph, route);
+        tracker
+    }
+
+    /// Starts following a (new) route from its start
+    pub fn set_route(&mut self, graph: &Graph, route: &Route) {
+        self.points.clear();
+        let edges = &route.path.edges;
+        let mut along = 0.0;
+        let mut first_length = 0.0;
+        let mut last_start = 0.0;
+        for (i, &id) in edges.iter().enumerate() {
+            let edge = graph.edge(id);
+            let edge_start = along;
+            let mut points = graph.edge_points(&edge);
+            // Edges share their end points, only the first edge keeps its first point
+            if i > 0 {
+                points.next();
+            }
+            for coord in points {
+                if let Some(prev) = self.points.last() {
+                    along += piece_length(prev.coord, coord);
+                }
+                self.points.push(RoutePoint { coord, along });
+            }
+            if i == 0 {
+                first_length = along;
+            }
+            last_start = edge_start;
+        }
+
+        self.start_m = first_length * route.start_fraction();
+        self.end_m = last_start + (along - last_start) * route.end_fraction();
+        self.along_m = self.start_m;
+        self.piece = self
+            .points
+            .iter()
+            .rposition(|p| p.along <= self.start_m)
+            .unwrap_or(0)
+            .min(self.points.len().saturating_sub(2));
+        self.distance_m = 0.0;
+        self.state = TrackState::OnRoute;
+    }
+
+    pub fn state(&self) -> TrackState {
+        self.state
+    }
+
+    /// Meters driven along the route so far
+    pub fn progress_m(&self) -> f32 {
+        (self.along_m - self.start_m).max(0.0)
+    }
+
+    pub fn remaining_m(&self) -> f32 {
+        (self.end_m - self.along_m).max(0.0)
+    }
+
+    /// Distance between the last position and the route
+    pub fn distance_m(&self) -> f32 {
+        self.distance_m
+    }
+
+    pub fn update(&mut self, position: TrackPosition) -> TrackEvent {
+        if self.state == TrackState::Arrived || self.points.len() < 2 {
+            return TrackEvent::None;
+        }
+
+        let projection = LocalProjection::new(position.coord);
+        let (piece, along, distance, piece_bearing) = self.closest_piece(&projection);
+
+        let heading_used = position.speed >= self.config.min_heading_speed_mps;
+        let wrong_way = heading_used
+            && bearing_delta(piece_bearing, position.heading).abs()
+                > self.config.heading_tolerance_deg;
+        let on_route = distance <= self.config.off_route_distance_m && !wrong_way;
+
+        // Only follow the route while on it, so getting back to it continues from where it was left
+        if on_route {
+            self.piece = piece;
+            self.along_m = along.clamp(self.start_m, self.end_m);
+        }
+        self.distance_m = distance;
+
+        if on_route && self.end_m - self.along_m <= self.config.arrival_distance_m {
+            self.state = TrackState::Arrived;
+            return TrackEvent::Arrived;
+        }
+
+        let reroute = TrackEvent::Reroute {
+            from: position.coord,
+            heading: heading_used.then_some(position.heading),
+        };
+        let now = position.time_ms;
+        match (self.state, on_route) {
+            (TrackState::OnRoute, true) => TrackEvent::None,
+            (TrackState::OnRoute, false) => {
+                self.state = TrackState::Diverging { since_ms: now };
+                TrackEvent::None
+            }
+            (TrackState::Diverging { .. }, true) => {
+                self.state = TrackState::OnRoute;
+                TrackEvent::None
+            }
+            (TrackState::Diverging { since_ms }, false) => {
+                if now.wrapping_sub(since_ms) >= self.config.off_route_time_ms {
+                    self.state = TrackState::OffRoute { requested_ms: now };
+                    reroute
+                } else {
+                    TrackEvent::None
+                }
+            }
+            (TrackState::OffRoute { .. }, true) => {
+                self.state = TrackState::OnRoute;
+                TrackEvent::BackOnRoute
+            }
+            (TrackState::OffRoute { requested_ms }, false) => {
+                if now.wrapping_sub(requested_ms) >= self.config.reroute_retry_ms {
+                    self.state = TrackState::OffRoute { requested_ms: now };
+                    reroute
+                } else {
+                    TrackEvent::None
+                }
+            }
+            (TrackState::Arrived, _) => TrackEvent::None,
+        }
+    }
+
+    /// Closest polyline piece near the last match, as (piece, meters along the polyline,
+    /// distance, bearing of the piece).
+    /// Looking only a bit back and further ahead keeps a route that passes the same
+    /// road twice from jumping to the wrong pass.
+    fn closest_piece(&self, projection: &LocalProjection) -> (usize, f32, f32, f32) {
+        const BEHIND_M: f32 = 100.0;
+        const AHEAD_M: f32 = 2000.0;
+
+        let current = self.points[self.piece].along;
+        let first = self.points[..=self.piece]
+            .iter()
+            .rposition(|p| p.along < current - BEHIND_M)
+            .unwrap_or(0);
+
+        let p = (0.0, 0.0);
+        let mut best = (self.piece, current, f32::INFINITY, 0.0);
+        for i in first..self.points.len() - 1 {
+            let (a, b) = (&self.points[i], &self.points[i + 1]);
+            if a.along > current + AHEAD_M {
+                break;
+            }
+            let (la, lb) = (projection.to_local(a.coord), projection.to_local(b.coord));
+            let (t, distance) = project_onto_line(p, la, lb);
+            if distance < best.2 {
+                let along = a.along + (b.along - a.along) * t;
+                best = (i, along, distance, bearing(la, lb));
+            }
+        }
+        best
+    }
+}
+
+fn piece_length(a: FixedCoord, b: FixedCoord) -> f32 {
+    let projection = LocalProjection::new(a);
+    distance((0.0, 0.0), projection.to_local(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::graph::fixture::{GraphFixture, edge_between};
+    use crate::graph::{Metric, Path};
+    use crate::route::Snap;
+
+    /// `x` meters east and `y` meters north of a fixed point
+    fn at(x: f64, y: f64) -> FixedCoord {
+        let meters_per_degree = 111_319.49;
+        FixedCoord::from_degrees(
+            52.5 + y / meters_per_degree,
+            13.4 + x / (meters_per_degree * libm::cos(52.5f64.to_radians())),
+        )
+    }
+
+    /// A road 1 km east, with a side street going north halfway along. The route drives the
+    /// road from end to end.
+    fn network() -> (Vec<u8>, [u32; 4]) {
+        let mut fixture = GraphFixture::new();
+        let nodes = [(0.0, 0.0), (500.0, 0.0), (1000.0, 0.0), (500.0, 800.0)].map(|(x, y)| {
+            let coord = at(x, y);
+            fixture.node(coord.lat_degrees(), coord.lon_degrees())
+        });
+        fixture.road(nodes[0], nodes[1], 50, "Main Street");
+        fixture.road(nodes[1], nodes[2], 50, "Main Street");
+        fixture.road(nodes[1], nodes[3], 30, "Side Street");
+        (fixture.build(false), nodes)
+    }
+
+    fn route(graph: &Graph, nodes: &[u32]) -> Route {
+        let edges = nodes
+            .windows(2)
+            .map(|pair| edge_between(graph, pair[0], pair[1]))
+            .collect::<Vec<_>>();
+        let snap = |edge: u32, fraction, node| Snap {
+            edge,
+            fraction,
+            distance_m: 0.0,
+            coord: graph.node_coord(node),
+        };
+        Route {
+            metric: Metric::Time,
+            start: snap(edges[0], 0.0, nodes[0]),
+            end: snap(edges[edges.len() - 1], 1.0, nodes[nodes.len() - 1]),
+            path: Path {
+                cost: 0,
+                source: nodes[0],
+                target: nodes[nodes.len() - 1],
+                edges,
+            },
+        }
+    }
+
+    fn tracker(graph: &Graph, nodes: [u32; 4]) -> Tracker {
+        let route = route(graph, &[nodes[0], nodes[1], nodes[2]]);
+        Tracker::new(graph, &route, TrackerConfig::default())
+    }
+
+    /// Feeds a recorded trace of (x, y, heading, speed) one second apart, from `start_ms` on
+    fn replay(
+        tracker: &mut Tracker,
+        start_ms: u32,
+        trace: &[(f64, f64, f32, f32)],
+    ) -> Vec<(TrackEvent, TrackState)> {
+        trace
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y, heading, speed))| {
+                let event = tracker.update(TrackPosition {
+                    coord: at(x, y),
+                    heading,
+                    speed,
+                    time_ms: start_ms.wrapping_add(i as u32 * 1000),
+                });
+                (event, tracker.state())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn follows_the_route_until_arriving() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut tracker = tracker(&graph, nodes);
+        assert_eq!(tracker.remaining_m(), tracker.end_m);
+        assert!((tracker.remaining_m() - 1000.0).abs() < 2.0);
+
+        // GPS noise a few meters to either side
+        let trace = (0..=9)
+            .map(|i| (i as f64 * 100.0, [6.0, -8.0, 3.0][i % 3], 90.0, 14.0))
+            .collect::<Vec<_>>();
+        let mut progress = 0.0;
+        for (event, state) in replay(&mut tracker, 0, &trace) {
+            assert_eq!((event, state), (TrackEvent::None, TrackState::OnRoute));
+            assert!(tracker.progress_m() >= progress);
+            progress = tracker.progress_m();
+        }
+        assert!((tracker.progress_m() - 900.0).abs() < 2.0);
+        assert!(tracker.distance_m() < 10.0);
+
+        let events = replay(&mut tracker, 10_000, &[(990.0, 2.0, 90.0, 5.0)]);
+        assert_eq!(events, [(TrackEvent::Arrived, TrackState::Arrived)]);
+        // Nothing more to say once there
+        let events = replay(&mut tracker, 11_000, &[(500.0, 300.0, 0.0, 10.0)]);
+        assert_eq!(events, [(TrackEvent::None, TrackState::Arrived)]);
+    }
+
+    #[test]
+    fn reroutes_after_leaving_the_route_for_a_while() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut tracker = tracker(&graph, nodes);
+
+        // Along the road, then north into the side street instead of straight on. The clock
+        // wraps around on the way.
+        let start = u32::MAX - 5500;
+        let mut trace = alloc::vec![(300.0, 0.0, 90.0, 12.0), (450.0, 0.0, 90.0, 12.0)];
+        trace.extend((1..=20).map(|i| (500.0, i as f64 * 25.0, 0.0, 12.0)));
+        let events = replay(&mut tracker, start, &trace);
+
+        // 25 meters up the side street is still close enough
+        assert_eq!(events[2].1, TrackState::OnRoute);
+        let diverging_since = start.wrapping_add(3000);
+        assert_eq!(
+            events[3],
+            (
+                TrackEvent::None,
+                TrackState::Diverging {
+                    since_ms: diverging_since
+                }
+            )
+        );
+        for &(event, state) in &events[4..7] {
+            assert_eq!(event, TrackEvent::None);
+            assert!(matches!(state, TrackState::Diverging { .. }));
+        }
+        let requested = diverging_since.wrapping_add(4000);
+        assert_eq!(
+            events[7],
+            (
+                TrackEvent::Reroute {
+                    from: at(500.0, 150.0),
+                    heading: Some(0.0)
+                },
+                TrackState::OffRoute {
+                    requested_ms: requested
+                }
+            )
+        );
+
+        // No new route came, so after a while it asks again
+        for &(event, _) in &events[8..17] {
+            assert_eq!(event, TrackEvent::None);
+        }
+        assert!(matches!(events[17].0, TrackEvent::Reroute { .. }));
+        assert_eq!(
+            events[17].1,
+            TrackState::OffRoute {
+                requested_ms: requested.wrapping_add(10_000)
+            }
+        );
+        // The progress stays where the route was left
+        assert!((tracker.progress_m() - 500.0).abs() < 30.0);
+        assert!(tracker.distance_m() > 400.0);
+    }
+
+    #[test]
+    fn getting_back_on_the_route_cancels_the_reroute() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut tracker = tracker(&graph, nodes);
+
+        let mut trace = alloc::vec![(400.0, 0.0, 90.0, 12.0)];
+        trace.extend((1..=6).map(|i| (500.0, 20.0 + i as f64 * 20.0, 0.0, 12.0)));
+        // Turned around and back on the main road
+        trace.extend([(500.0, 60.0, 180.0, 8.0), (520.0, 5.0, 90.0, 10.0)]);
+        trace.push((600.0, 0.0, 90.0, 12.0));
+        let events = replay(&mut tracker, 0, &trace);
+
+        assert!(matches!(events[5].0, TrackEvent::Reroute { .. }));
+        for &(event, state) in &events[6..8] {
+            assert_eq!(event, TrackEvent::None);
+            assert!(matches!(state, TrackState::OffRoute { .. }));
+        }
+        assert_eq!(events[8], (TrackEvent::BackOnRoute, TrackState::OnRoute));
+        assert_eq!(events[9], (TrackEvent::None, TrackState::OnRoute));
+        assert!((tracker.progress_m() - 600.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn short_detours_are_ignored() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut tracker = tracker(&graph, nodes);
+
+        // A couple of fixes way off, like in a street canyon
+        let trace = [
+            (100.0, 0.0, 90.0, 12.0),
+            (200.0, 60.0, 90.0, 12.0),
+            (300.0, -70.0, 90.0, 12.0),
+            (400.0, 0.0, 90.0, 12.0),
+        ];
+        let events = replay(&mut tracker, 0, &trace);
+        assert!(events.iter().all(|&(event, _)| event == TrackEvent::None));
+        assert_eq!(events[1].1, TrackState::Diverging { since_ms: 1000 });
+        assert_eq!(events[2].1, TrackState::Diverging { since_ms: 1000 });
+        assert_eq!(events[3].1, TrackState::OnRoute);
+        // The fixes off the route didn't move the position along it
+        assert!((tracker.progress_m() - 400.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn driving_the_wrong_way_counts_as_off_the_route() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut tracker = tracker(&graph, nodes);
+
+        // Standing around, the heading is noise
+        let events = replay(&mut tracker, 0, &[(300.0, 0.0, 270.0, 1.0); 6]);
+        assert!(
+            events
+                .iter()
+                .all(|&state| state == (TrackEvent::None, TrackState::OnRoute))
+        );
+
+        let trace = (0..6)
+            .map(|i| (300.0 - i as f64 * 15.0, 0.0, 270.0, 15.0))
+            .collect::<Vec<_>>();
+        let events = replay(&mut tracker, 10_000, &trace);
+        assert!(matches!(events[0].1, TrackState::Diverging { .. }));
+        assert_eq!(
+            events[4].0,
+            TrackEvent::Reroute {
+                from: at(240.0, 0.0),
+                heading: Some(270.0)
+            }
+        );
+    }
+
+    #[test]
+    fn a_new_route_starts_over() {
+        let (data, nodes) = network();
+        let graph = Graph::parse(&data).unwrap();
+        let mut tracker = tracker(&graph, nodes);
+        replay(&mut tracker, 0, &[(1000.0, 0.0, 90.0, 10.0)]);
+        assert_eq!(tracker.state(), TrackState::Arrived);
+
+        // Up the side street and back again
+        tracker.set_route(&graph, &route(&graph, &[nodes[3], nodes[1], nodes[0]]));
+        assert_eq!(tracker.state(), TrackState::OnRoute);
+        assert_eq!(tracker.progress_m(), 0.0);
+        assert!((tracker.remaining_m() - 1300.0).abs() < 3.0);
+
+        let events = replay(
+            &mut tracker,
+            1000,
+            &[(500.0, 400.0, 180.0, 10.0), (250.0, 0.0, 270.0, 10.0)],
+        );
+        assert!(events.iter().all(|&(event, _)| event == TrackEvent::None));
+        assert!((tracker.remaining_m() - 250.0).abs() < 3.0);
+    }
+}
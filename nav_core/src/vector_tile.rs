@@ -0,0 +1,510 @@
+//! Map tiles as shapes instead of pixels, so the PSP can draw them in any style and turned any
+//! way. They go into the same [`crate::tile_pack`] as raster tiles would.
+//!
+//! Coordinates are quantized to [`EXTENT`] steps per tile side, relative to the tile's top left
+//! corner. Shapes reach up to [`BUFFER`] past the edges, so strokes and fills line up with the
+//! neighbouring tiles. A tile is a list of features, drawn in the order they come in:
+//!
+//! | Size   | Content                                                          |
+//! |--------|------------------------------------------------------------------|
+//! | varint | Feature count                                                    |
+//! | 1      | Feature: geometry in the top 2 bits, class in the lower 6        |
+//! | ...    | Line and area: varint point count, varint byte length, points    |
+//! |        | Label: x, y, varint byte length, UTF-8 text                      |
+//!
+//! Varints are LEB128, every point is x and y as zigzag varints, as the difference to the
+//! point before it (the first one to 0, 0). A line's class is its [`RoadClass`], an area's its
+//! [`AreaKind`]. Areas are a single ring that closes itself, labels are drawn upright.
+
+use alloc::vec::Vec;
+
+use crate::graph::RoadClass;
+
+/// Coordinate steps per tile side
+pub const EXTENT: i32 = 4096;
+
+/// How far shapes reach past the tile edges, in the same steps
+pub const BUFFER: i32 = 256;
+
+const GEOMETRY_LINE: u8 = 0;
+const GEOMETRY_AREA: u8 = 1;
+const GEOMETRY_LABEL: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorTileError {
+    Truncated,
+    /// Unknown geometry or class
+    BadFeature,
+    NotUtf8,
+}
+
+/// What an area is, in the order they're drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum AreaKind {
+    /// Landuse without anything more specific, residential, industrial and the like
+    Generic = 0,
+    Grass = 1,
+    Water = 2,
+    Building = 3,
+}
+
+impl AreaKind {
+    pub const COUNT: usize = 4;
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Some(match value {
+            0 => Self::Generic,
+            1 => Self::Grass,
+            2 => Self::Water,
+            3 => Self::Building,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Feature<'a> {
+    Line {
+        class: RoadClass,
+        points: Points<'a>,
+    },
+    Area {
+        kind: AreaKind,
+        points: Points<'a>,
+    },
+    Label {
+        position: (i32, i32),
+        text: &'a str,
+    },
+}
+
+/// A checked tile, straight from the bytes it came in
+#[derive(Debug, Clone, Copy)]
+pub struct VectorTile<'a> {
+    /// Everything after the feature count
+    data: &'a [u8],
+    count: usize,
+}
+
+impl<'a> VectorTile<'a> {
+    /// Goes over the whole tile once, after that reading it can't fail
+    pub fn parse(data: &'a [u8]) -> Result<Self, VectorTileError> {
+        let mut reader = Reader { data, at: 0 };
+        let count = reader.varint().ok_or(VectorTileError::Truncated)? as usize;
+        let tile = Self {
+            data: &data[reader.at..],
+            count,
+        };
+
+        let mut features = tile.features();
+        for _ in 0..count {
+            let feature = features.read().ok_or(VectorTileError::Truncated)??;
+            if let Feature::Line { points, .. } | Feature::Area { points, .. } = feature {
+                // Only the byte length was looked at so far
+                let mut reader = Reader {
+                    data: points.data,
+                    at: 0,
+                };
+                let coordinates = points
+                    .remaining
+                    .checked_mul(2)
+                    .ok_or(VectorTileError::Truncated)?;
+                for _ in 0..coordinates {
+                    reader.varint().ok_or(VectorTileError::Truncated)?;
+                }
+            }
+        }
+        Ok(tile)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn features(&self) -> Features<'a> {
+        Features {
+            reader: Reader {
+                data: self.data,
+                at: 0,
+            },
+            remaining: self.count,
+        }
+    }
+}
+
+pub struct Features<'a> {
+    reader: Reader<'a>,
+    remaining: usize,
+}
+
+impl<'a> Features<'a> {
+    /// `None` when the data runs out, `Some(Err)` for a feature that makes no sense
+    fn read(&mut self) -> Option<Result<Feature<'a>, VectorTileError>> {
+        let tag = self.reader.byte()?;
+        let (geometry, class) = (tag >> 6, tag & 0x3f);
+        let feature = match geometry {
+            GEOMETRY_LINE | GEOMETRY_AREA => {
+                let points = self.reader.points()?;
+                match geometry {
+                    GEOMETRY_LINE => {
+                        RoadClass::from_u8(class).map(|class| Feature::Line { class, points })
+                    }
+                    _ => AreaKind::from_u8(class).map(|kind| Feature::Area { kind, points }),
+                }
+            }
+            GEOMETRY_LABEL => {
+                let position = (self.reader.zigzag()?, self.reader.zigzag()?);
+                let text = self.reader.bytes()?;
+                let Ok(text) = core::str::from_utf8(text) else {
+                    return Some(Err(VectorTileError::NotUtf8));
+                };
+                Some(Feature::Label { position, text })
+            }
+            _ => None,
+        };
+        Some(feature.ok_or(VectorTileError::BadFeature))
+    }
+}
+
+impl<'a> Iterator for Features<'a> {
+    type Item = Feature<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        // The tile was checked when it was parsed
+        self.read()?.ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+/// The points of a line or area, in tile coordinates
+#[derive(Debug, Clone)]
+pub struct Points<'a> {
+    data: &'a [u8],
+    remaining: usize,
+    last: (i32, i32),
+}
+
+impl Iterator for Points<'_> {
+    type Item = (i32, i32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let mut reader = Reader {
+            data: self.data,
+            at: 0,
+        };
+        let (dx, dy) = (reader.zigzag()?, reader.zigzag()?);
+        self.data = &self.data[reader.at..];
+        self.remaining -= 1;
+        self.last = (self.last.0.wrapping_add(dx), self.last.1.wrapping_add(dy));
+        Some(self.last)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for Points<'_> {}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let byte = *self.data.get(self.at)?;
+        self.at += 1;
+        Some(byte)
+    }
+
+    fn varint(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        // Longer than any u32
+        None
+    }
+
+    fn zigzag(&mut self) -> Option<i32> {
+        let value = self.varint()?;
+        Some((value >> 1) as i32 ^ -((value & 1) as i32))
+    }
+
+    /// A varint length and that many bytes
+    fn bytes(&mut self) -> Option<&'a [u8]> {
+        let length = self.varint()? as usize;
+        let bytes = self.data.get(self.at..self.at.checked_add(length)?)?;
+        self.at += length;
+        Some(bytes)
+    }
+
+    fn points(&mut self) -> Option<Points<'a>> {
+        let remaining = self.varint()? as usize;
+        Some(Points {
+            data: self.bytes()?,
+            remaining,
+            last: (0, 0),
+        })
+    }
+}
+
+/// Puts a tile together, the features end up in the order they're added
+#[derive(Debug, Clone, Default)]
+pub struct TileBuilder {
+    data: Vec<u8>,
+    count: u32,
+}
+
+impl TileBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self, class: RoadClass, points: &[(i32, i32)]) {
+        self.shape(GEOMETRY_LINE, class as u8, points);
+    }
+
+    pub fn area(&mut self, kind: AreaKind, points: &[(i32, i32)]) {
+        self.shape(GEOMETRY_AREA, kind as u8, points);
+    }
+
+    pub fn label(&mut self, position: (i32, i32), text: &str) {
+        self.data.push(GEOMETRY_LABEL << 6);
+        write_zigzag(&mut self.data, position.0);
+        write_zigzag(&mut self.data, position.1);
+        write_varint(&mut self.data, text.len() as u32);
+        self.data.extend_from_slice(text.as_bytes());
+        self.count += 1;
+    }
+
+    fn shape(&mut self, geometry: u8, class: u8, points: &[(i32, i32)]) {
+        let mut encoded = Vec::with_capacity(points.len() * 4);
+        let mut last = (0i32, 0i32);
+        for &(x, y) in points {
+            write_zigzag(&mut encoded, x.wrapping_sub(last.0));
+            write_zigzag(&mut encoded, y.wrapping_sub(last.1));
+            last = (x, y);
+        }
+        self.data.push(geometry << 6 | class);
+        write_varint(&mut self.data, points.len() as u32);
+        write_varint(&mut self.data, encoded.len() as u32);
+        self.data.extend_from_slice(&encoded);
+        self.count += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut tile = Vec::with_capacity(self.data.len() + 5);
+        write_varint(&mut tile, self.count);
+        tile.extend_from_slice(&self.data);
+        tile
+    }
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn write_zigzag(data: &mut Vec<u8>, value: i32) {
+    write_varint(data, ((value << 1) ^ (value >> 31)) as u32);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile() -> Vec<u8> {
+        let mut builder = TileBuilder::new();
+        builder.area(
+            AreaKind::Water,
+            &[(-BUFFER, -BUFFER), (EXTENT + BUFFER, 0), (EXTENT, EXTENT)],
+        );
+        builder.line(RoadClass::Primary, &[(10, 20), (4000, 20), (4000, 3000)]);
+        builder.label((2048, 100), "Hauptstraße");
+        builder.finish()
+    }
+
+    fn collect(points: &Points) -> Vec<(i32, i32)> {
+        points.clone().collect()
+    }
+
+    #[test]
+    fn reads_what_it_builds() {
+        let data = tile();
+        let tile = VectorTile::parse(&data).unwrap();
+        assert_eq!(tile.len(), 3);
+
+        let features = tile.features().collect::<Vec<_>>();
+        let Feature::Area { kind, points } = &features[0] else {
+            panic!("{:?}", features[0]);
+        };
+        assert_eq!(*kind, AreaKind::Water);
+        assert_eq!(points.len(), 3);
+        assert_eq!(
+            collect(points),
+            [(-BUFFER, -BUFFER), (EXTENT + BUFFER, 0), (EXTENT, EXTENT)]
+        );
+
+        let Feature::Line { class, points } = &features[1] else {
+            panic!("{:?}", features[1]);
+        };
+        assert_eq!(*class, RoadClass::Primary);
+        assert_eq!(collect(points), [(10, 20), (4000, 20), (4000, 3000)]);
+
+        let Feature::Label { position, text } = &features[2] else {
+            panic!("{:?}", features[2]);
+        };
+        assert_eq!((*position, *text), ((2048, 100), "Hauptstraße"));
+    }
+
+    #[test]
+    fn empty_tiles() {
+        let data = TileBuilder::new().finish();
+        let tile = VectorTile::parse(&data).unwrap();
+        assert!(tile.is_empty());
+        assert_eq!(tile.features().count(), 0);
+        assert_eq!(
+            VectorTile::parse(&[]).unwrap_err(),
+            VectorTileError::Truncated
+        );
+    }
+
+    #[test]
+    fn far_apart_points_wrap_around() {
+        let mut builder = TileBuilder::new();
+        let points = [(i32::MIN, i32::MAX), (i32::MAX, i32::MIN), (0, 0)];
+        builder.line(RoadClass::Track, &points);
+        let data = builder.finish();
+        let tile = VectorTile::parse(&data).unwrap();
+        let Some(Feature::Line { points: read, .. }) = tile.features().next() else {
+            panic!();
+        };
+        assert_eq!(collect(&read), points);
+    }
+
+    #[test]
+    fn refuses_cut_short_tiles() {
+        let data = tile();
+        for length in 0..data.len() {
+            assert_eq!(
+                VectorTile::parse(&data[..length]).unwrap_err(),
+                VectorTileError::Truncated,
+                "{length}"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_unknown_features() {
+        let data = tile();
+        // The area's tag, right after the count
+        let mut geometry = data.clone();
+        geometry[1] = 3 << 6;
+        assert_eq!(
+            VectorTile::parse(&geometry).unwrap_err(),
+            VectorTileError::BadFeature
+        );
+        let mut kind = data.clone();
+        kind[1] = GEOMETRY_AREA << 6 | AreaKind::COUNT as u8;
+        assert_eq!(
+            VectorTile::parse(&kind).unwrap_err(),
+            VectorTileError::BadFeature
+        );
+
+        let mut text = data.clone();
+        let last = text.len() - 1;
+        text[last] = 0xff;
+        assert_eq!(
+            VectorTile::parse(&text).unwrap_err(),
+            VectorTileError::NotUtf8
+        );
+    }
+
+    #[test]
+    fn refuses_more_points_than_there_are_bytes() {
+        let mut data = Vec::new();
+        write_varint(&mut data, 1);
+        data.push(GEOMETRY_LINE << 6 | RoadClass::Primary as u8);
+        // As many points as fit a varint, with the bytes of one
+        write_varint(&mut data, u32::MAX);
+        write_varint(&mut data, 2);
+        data.extend_from_slice(&[0, 0]);
+        assert_eq!(
+            VectorTile::parse(&data).unwrap_err(),
+            VectorTileError::Truncated
+        );
+
+        // Overlong varints
+        let mut data = Vec::new();
+        data.extend_from_slice(&[0xff; 6]);
+        assert_eq!(
+            VectorTile::parse(&data).unwrap_err(),
+            VectorTileError::Truncated
+        );
+    }
+
+    #[test]
+    fn never_panics_on_garbage() {
+        let data = tile();
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut random = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..20_000 {
+            let mut bytes = data.clone();
+            for _ in 0..random() % 4 + 1 {
+                let at = (random() % bytes.len().max(1) as u64) as usize;
+                match random() % 3 {
+                    0 if at < bytes.len() => bytes[at] = random() as u8,
+                    1 if at < bytes.len() => {
+                        bytes.remove(at);
+                    }
+                    _ => bytes.insert(at, random() as u8),
+                }
+            }
+            // Whatever parses reads back without running out
+            if let Ok(tile) = VectorTile::parse(&bytes) {
+                let mut count = 0;
+                for feature in tile.features() {
+                    if let Feature::Line { points, .. } | Feature::Area { points, .. } = feature {
+                        let expected = points.len();
+                        assert_eq!(points.count(), expected);
+                    }
+                    count += 1;
+                }
+                assert_eq!(count, tile.len());
+            }
+        }
+    }
+}
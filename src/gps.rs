@@ -1,63 +1,284 @@
-pub fn init_gps() {
-    unsafe {
-        psp::dprintln!(
-            "load_usb_module_acc: {}",
-            psp::sys::sceUtilityLoadUsbModule(psp::sys::UsbModule::UsbAcc)
-        );
-        psp::dprintln!(
-            "load_usb_module_gps: {}",
-            psp::sys::sceUtilityLoadUsbModule(psp::sys::UsbModule::UsbGps)
-        );
-
-        // sceUsbStart(PSP_USBBUS_DRIVERNAME,0,0)
-        // sceUsbStart("USBAccBaseDriver",0,0)
-        // sceUsbStart(PSP_USBGPS_DRIVERNAME,0,0)
-        // TODO: These all fail if they return > 0
-        //       Handle this ASAP
-        psp::sys::sceUsbStart(
-            psp::sys::USB_BUS_DRIVER_NAME.as_ptr(),
-            0,
-            core::ptr::null_mut(),
-        );
-        psp::sys::sceUsbStart("USBAccBaseDriver".as_ptr(), 0, core::ptr::null_mut());
-        psp::sys::sceUsbStart(
-            psp::sys::USB_GPS_DRIVER_NAME.as_ptr(),
-            0,
-            core::ptr::null_mut(),
-        );
-
-        // sceUsbGpsOpen()
-        psp::sys::sceUsbGpsOpen();
-
-        psp::sys::sceUsbActivate(psp::sys::USB_GPS_PID as u32);
-        psp::sys::sceUsbGpsSetInitDataLocation(1);
+use alloc::vec::Vec;
+
+use nav_core::fix::{Fix, FixError, RawGpsData, Satellite};
+use nav_core::source::gpx::{self, GpxError};
+use nav_core::source::nmea;
+use nav_core::source::{PositionSource, Replay};
+use nav_core::tracking::TrackPosition;
+
+use crate::io::{self, IoError};
+
+/// How long [`init_gps`] waits for the receiver by default
+pub const DEFAULT_INIT_TIMEOUT_MS: u32 = 10_000;
+
+/// Time between checks on whether the receiver is ready
+const STATE_POLL_INTERVAL_US: u32 = 100_000;
+
+/// What `sceUsbGpsGetState` reports once the receiver is up
+const GPS_STATE_READY: u32 = 0x3;
+
+const USB_ACC_DRIVER_NAME: &str = "USBAccBaseDriver\0";
+
+/// Which step of bringing up the GPS failed, with the kernel's error code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpsError {
+    LoadAccModule(i32),
+    LoadGpsModule(i32),
+    StartBusDriver(i32),
+    StartAccDriver(i32),
+    StartGpsDriver(i32),
+    Open(i32),
+    Activate(i32),
+    SetInitDataLocation(i32),
+    GetState(i32),
+    /// The receiver didn't become ready in time, it was last seen in `state`
+    Timeout {
+        state: u32,
+    },
+}
+
+/// Steps of [`init_gps`] in order, shutting down undoes them the other way around
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Stage {
+    Nothing,
+    AccModuleLoaded,
+    GpsModuleLoaded,
+    BusDriverStarted,
+    AccDriverStarted,
+    GpsDriverStarted,
+    Opened,
+    Activated,
+}
+
+/// The USB GPS receiver, shut down again when dropped
+pub struct UsbGps {
+    stage: Stage,
+    /// From the latest poll
+    satellites: Vec<Satellite>,
+}
+
+fn check(ret: i32, error: fn(i32) -> GpsError) -> Result<(), GpsError> {
+    if ret < 0 { Err(error(ret)) } else { Ok(()) }
+}
+
+/// Loads and starts everything the GPS receiver needs and waits up to `timeout_ms` for it
+/// to be ready. Whatever was started before a failure is shut down again.
+pub fn init_gps(timeout_ms: u32) -> Result<UsbGps, GpsError> {
+    let mut gps = UsbGps {
+        stage: Stage::Nothing,
+        satellites: Vec::new(),
+    };
+    gps.start()?;
+    gps.wait_ready(timeout_ms)?;
+    Ok(gps)
+}
+
+impl UsbGps {
+    fn start(&mut self) -> Result<(), GpsError> {
+        use psp::sys::*;
+
+        unsafe {
+            check(
+                sceUtilityLoadUsbModule(UsbModule::UsbAcc),
+                GpsError::LoadAccModule,
+            )?;
+            self.stage = Stage::AccModuleLoaded;
+            check(
+                sceUtilityLoadUsbModule(UsbModule::UsbGps),
+                GpsError::LoadGpsModule,
+            )?;
+            self.stage = Stage::GpsModuleLoaded;
+
+            check(
+                sceUsbStart(USB_BUS_DRIVER_NAME.as_ptr(), 0, core::ptr::null_mut()),
+                GpsError::StartBusDriver,
+            )?;
+            self.stage = Stage::BusDriverStarted;
+            check(
+                sceUsbStart(USB_ACC_DRIVER_NAME.as_ptr(), 0, core::ptr::null_mut()),
+                GpsError::StartAccDriver,
+            )?;
+            self.stage = Stage::AccDriverStarted;
+            check(
+                sceUsbStart(USB_GPS_DRIVER_NAME.as_ptr(), 0, core::ptr::null_mut()),
+                GpsError::StartGpsDriver,
+            )?;
+            self.stage = Stage::GpsDriverStarted;
+
+            check(sceUsbGpsOpen(), GpsError::Open)?;
+            self.stage = Stage::Opened;
+            check(sceUsbActivate(USB_GPS_PID as u32), GpsError::Activate)?;
+            self.stage = Stage::Activated;
+
+            check(
+                sceUsbGpsSetInitDataLocation(1),
+                GpsError::SetInitDataLocation,
+            )?;
+        }
+
+        Ok(())
     }
 
-    let mut init_data_location = 0u32;
-    unsafe {
-        psp::sys::sceUsbGpsGetInitDataLocation(&mut init_data_location);
+    fn wait_ready(&self, timeout_ms: u32) -> Result<(), GpsError> {
+        psp::dprintln!("Waiting for the gps...");
+        let start = time_ms();
+        loop {
+            let mut state = 0u32;
+            check(
+                unsafe { psp::sys::sceUsbGpsGetState(&mut state) },
+                GpsError::GetState,
+            )?;
+            if state == GPS_STATE_READY {
+                psp::dprintln!("GPS ready after {} ms", time_ms().wrapping_sub(start));
+                return Ok(());
+            }
+            if time_ms().wrapping_sub(start) >= timeout_ms {
+                return Err(GpsError::Timeout { state });
+            }
+            unsafe {
+                psp::sys::sceKernelDelayThread(STATE_POLL_INTERVAL_US);
+            }
+        }
     }
-    psp::dprintln!("{} (should be 1)", init_data_location);
-
-    // let usb_state = unsafe { psp::sys::sceUsbGetState() };
-    // psp::dprintln!("{:?}", usb_state);
-
-    psp::dprintln!("Waiting for the gps...");
-    // TODO: Add some sort of delay here to avoid running the PSP at 100% usage lol
-    //       Also perhaps add a timeout?
-    loop {
-        let mut gps_state = 0u32;
-        let gps_state_ret = unsafe { psp::sys::sceUsbGpsGetState(&mut gps_state) };
-
-        if gps_state == 0x3 {
-            psp::dprintln!("state: {:?}", gps_state);
-            psp::dprintln!("ret: {:?}", gps_state_ret);
-            psp::dprintln!("----------------");
-            break;
+
+    /// Closes the receiver, deactivates USB and unloads the modules again.
+    /// Failures are only logged, there's nothing left to do about them at this point.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        use psp::sys::*;
+
+        let log = |step: &str, ret: i32| {
+            if ret < 0 {
+                psp::dprintln!("GPS shutdown: {} failed: {:#x}", step, ret);
+            }
+        };
+        unsafe {
+            if self.stage >= Stage::Activated {
+                log("deactivate", sceUsbDeactivate(USB_GPS_PID as u32));
+            }
+            if self.stage >= Stage::Opened {
+                log("close", sceUsbGpsClose());
+            }
+            if self.stage >= Stage::GpsDriverStarted {
+                let ret = sceUsbStop(USB_GPS_DRIVER_NAME.as_ptr(), 0, core::ptr::null_mut());
+                log("stop gps driver", ret);
+            }
+            if self.stage >= Stage::AccDriverStarted {
+                let ret = sceUsbStop(USB_ACC_DRIVER_NAME.as_ptr(), 0, core::ptr::null_mut());
+                log("stop acc driver", ret);
+            }
+            if self.stage >= Stage::BusDriverStarted {
+                let ret = sceUsbStop(USB_BUS_DRIVER_NAME.as_ptr(), 0, core::ptr::null_mut());
+                log("stop bus driver", ret);
+            }
+            if self.stage >= Stage::GpsModuleLoaded {
+                log(
+                    "unload gps module",
+                    sceUtilityUnloadUsbModule(UsbModule::UsbGps),
+                );
+            }
+            if self.stage >= Stage::AccModuleLoaded {
+                log(
+                    "unload acc module",
+                    sceUtilityUnloadUsbModule(UsbModule::UsbAcc),
+                );
+            }
         }
+        self.stage = Stage::Nothing;
+    }
+}
+
+impl Drop for UsbGps {
+    fn drop(&mut self) {
+        self.stop();
     }
 }
 
+/// Milliseconds since boot, wraps after about 50 days
+pub fn time_ms() -> u32 {
+    (unsafe { psp::sys::sceKernelGetSystemTimeWide() } / 1000) as u32
+}
+
+/// What [`nav_core::tracking::Tracker`] needs from a fix
+pub fn track_position(fix: &Fix) -> TrackPosition {
+    fix.track_position(time_ms())
+}
+
+pub fn raw_gps_data(gps_data: &psp::sys::ScePspGpsData) -> RawGpsData {
+    RawGpsData {
+        year: gps_data.year as i32,
+        month: gps_data.month as i32,
+        date: gps_data.date as i32,
+        hour: gps_data.hour as i32,
+        minute: gps_data.minute as i32,
+        second: gps_data.second as i32,
+        hdop: gps_data.hdop,
+        latitude: gps_data.latitude,
+        longitude: gps_data.longitude,
+        altitude: gps_data.altitude,
+        speed: gps_data.speed,
+        bearing: gps_data.bearing,
+    }
+}
+
+/// Satellites in view, the receiver reports up to 24
+pub fn satellites(sat_data: &psp::sys::ScePspSatData) -> Vec<Satellite> {
+    let count = (sat_data.satellites_in_view.max(0) as usize).min(sat_data.satinfo.len());
+    sat_data.satinfo[..count]
+        .iter()
+        .map(|info| Satellite {
+            id: info.id,
+            elevation: info.elevation,
+            azimuth: info.azimuth as u16,
+            snr: info.snr,
+            used: info.good != 0,
+        })
+        .collect()
+}
+
+impl PositionSource for UsbGps {
+    fn poll(&mut self, _now_ms: u32) -> Result<Fix, FixError> {
+        let (gps_data, sat_data) = get_raw_data();
+        self.satellites = satellites(&sat_data);
+        Fix::from_raw(&raw_gps_data(&gps_data), &self.satellites)
+    }
+
+    fn satellites(&self) -> &[Satellite] {
+        &self.satellites
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    Io(IoError),
+    Gpx(GpxError),
+    /// An NMEA log without a single usable fix
+    NoFixes,
+}
+
+/// Loads a recorded track to drive around without the receiver, e.g. in an emulator.
+/// Files ending in `.gpx` are read as GPX, anything else as an NMEA log.
+/// `speed` 2.0 plays it back twice as fast as it was recorded.
+pub fn load_replay(path: &str, speed: f32) -> Result<Replay, ReplayError> {
+    let data = io::read_file(path).map_err(ReplayError::Io)?;
+    let is_gpx = path
+        .trim_end_matches('\0')
+        .to_ascii_lowercase()
+        .ends_with(".gpx");
+    let fixes = if is_gpx {
+        gpx::parse(&data).map_err(ReplayError::Gpx)?
+    } else {
+        Some(nmea::parse_log(&data))
+            .filter(|fixes| !fixes.is_empty())
+            .ok_or(ReplayError::NoFixes)?
+    };
+    psp::dprintln!("Replaying {} fixes from {}", fixes.len(), path);
+    Ok(Replay::new(fixes, speed, true))
+}
+
 pub fn get_raw_data() -> (psp::sys::ScePspGpsData, psp::sys::ScePspSatData) {
     let mut gps_data = psp::sys::ScePspGpsData::default();
     let mut sat_data = psp::sys::ScePspSatData::default();
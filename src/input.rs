@@ -0,0 +1,65 @@
+//! Reads the controller once a frame, what it means is up to [`nav_core::input::actions`].
+
+use nav_core::input::{self, Actions, Buttons, Controls, InputConfig, MenuAction};
+use psp::sys::{CtrlMode, SceCtrlData};
+
+pub struct Controller {
+    config: InputConfig,
+    previous: Controls,
+    /// When the nub last left the dead zone, while it's still out
+    pan_since_ms: Option<u32>,
+}
+
+impl Controller {
+    pub fn new(config: InputConfig) -> Self {
+        unsafe {
+            psp::sys::sceCtrlSetSamplingCycle(0);
+            psp::sys::sceCtrlSetSamplingMode(CtrlMode::Analog);
+        }
+        let mut controller = Self {
+            config,
+            previous: Controls::default(),
+            pan_since_ms: None,
+        };
+        // Buttons still held from the screen before don't count as pressed on this one
+        controller.previous = controller.sample();
+        controller
+    }
+
+    fn sample(&self) -> Controls {
+        let data = unsafe {
+            let mut data: SceCtrlData = core::mem::zeroed();
+            psp::sys::sceCtrlReadBufferPositive(&mut data, 1);
+            data
+        };
+        Controls {
+            buttons: Buttons(data.buttons.bits()),
+            analog_x: data.lx,
+            analog_y: data.ly,
+        }
+    }
+
+    /// What the controller means for the map
+    pub fn read(&mut self, now_ms: u32) -> Actions {
+        let current = self.sample();
+        let held = self
+            .pan_since_ms
+            .map_or(0, |since| now_ms.wrapping_sub(since));
+        let actions = input::actions(&self.config, &self.previous, &current, held);
+        self.pan_since_ms = match actions.is_panning() {
+            true => Some(self.pan_since_ms.unwrap_or(now_ms)),
+            false => None,
+        };
+        self.previous = current;
+        actions
+    }
+
+    /// What the controller means in a list
+    pub fn read_menu(&mut self) -> Option<MenuAction> {
+        let current = self.sample();
+        let action = input::menu_action(&self.previous, &current);
+        self.previous = current;
+        self.pan_since_ms = None;
+        action
+    }
+}
@@ -5,53 +5,178 @@ use alloc::{
     vec::Vec,
 };
 
+use psp::sys::SceUid;
+
 const IO_ERROR_NO_ENTRY: u32 = 0x80010002;
 
-static mut DATA: [u8; 16] = [0; 16];
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoError {
+    /// File/directory does not exist
+    NoEntry,
+    /// The file ended before we got everything we asked for
+    ShortRead,
+    /// Nothing more could be written, e.g. the Memory Stick is full
+    ShortWrite,
+    /// Bigger than whatever was being read can be
+    TooLarge,
+    /// Any other error code from the kernel
+    Kernel(u32),
+}
+
+impl IoError {
+    pub(crate) fn from_code(code: i32) -> Self {
+        match code as u32 {
+            IO_ERROR_NO_ENTRY => Self::NoEntry,
+            code => Self::Kernel(code),
+        }
+    }
+}
 
-extern "C" fn tile_read_ready(a: i32, b: i32, arg: *mut c_void) -> i32 {
-    psp::dprintln!("a: {a}, b: {b}, arg: {arg:?}");
-    psp::dprintln!("data: {:?}", unsafe { DATA });
-    0
+/// The kernel wants null terminated paths
+pub(crate) fn c_path(path: &str) -> String {
+    let mut c_path = path.to_string();
+    if !c_path.ends_with('\0') {
+        c_path.push('\0');
+    }
+    c_path
 }
 
-pub fn start_reading_tile(path: &str) {
+/// Reads a whole file into memory, blocking until it's done
+pub fn read_file(path: &str) -> Result<Vec<u8>, IoError> {
+    let path = c_path(path);
+
     unsafe {
         let fd = psp::sys::sceIoOpen(path.as_ptr(), psp::sys::IoOpenFlags::RD_ONLY, 0);
         if fd.0 < 0 {
-            todo!("Return error here");
+            return Err(IoError::from_code(fd.0));
         }
 
-        let cb = psp::sys::sceKernelCreateCallback(
-            "tile_read_ready".as_ptr(),
-            tile_read_ready,
-            core::ptr::null::<c_void>() as *mut c_void,
-        );
-        let e = psp::sys::sceIoSetAsyncCallback(fd, cb, core::ptr::null::<c_void>() as *mut c_void);
-        if e < 0 {
-            psp::dprintln!("e1: 0x{:x}", e);
+        let size = psp::sys::sceIoLseek(fd, 0, psp::sys::IoWhence::End);
+        psp::sys::sceIoLseek(fd, 0, psp::sys::IoWhence::Set);
+        if size < 0 {
+            psp::sys::sceIoClose(fd);
+            return Err(IoError::from_code(size as i32));
         }
-        let e = psp::sys::sceIoReadAsync(fd, DATA.as_mut_ptr() as *mut c_void, 16);
-        if e < 0 {
-            psp::dprintln!("e2: 0x{:x}", e);
+
+        let mut data = vec![0u8; size as usize];
+        let result = read_exact(fd, &mut data);
+        psp::sys::sceIoClose(fd);
+        result.map(|()| data)
+    }
+}
+
+/// Size of a file in bytes, without reading it
+pub fn file_size(path: &str) -> Result<u64, IoError> {
+    let path = c_path(path);
+    unsafe {
+        let fd = psp::sys::sceIoOpen(path.as_ptr(), psp::sys::IoOpenFlags::RD_ONLY, 0);
+        if fd.0 < 0 {
+            return Err(IoError::from_code(fd.0));
+        }
+        let size = psp::sys::sceIoLseek(fd, 0, psp::sys::IoWhence::End);
+        psp::sys::sceIoClose(fd);
+        match size < 0 {
+            true => Err(IoError::from_code(size as i32)),
+            false => Ok(size as u64),
         }
+    }
+}
+
+/// Replaces the file at `path` with `data`, creating it if needed
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), IoError> {
+    let path = c_path(path);
+    let flags = psp::sys::IoOpenFlags::WR_ONLY
+        | psp::sys::IoOpenFlags::CREAT
+        | psp::sys::IoOpenFlags::TRUNC;
+    let fd = unsafe { psp::sys::sceIoOpen(path.as_ptr(), flags, 0o777) };
+    if fd.0 < 0 {
+        return Err(IoError::from_code(fd.0));
+    }
 
-        psp::dprintln!("hi");
+    let mut written = 0;
+    let result = loop {
+        if written == data.len() {
+            break Ok(());
+        }
+        let n = unsafe {
+            psp::sys::sceIoWrite(
+                fd,
+                data[written..].as_ptr() as *const c_void,
+                data.len() - written,
+            )
+        };
+        if n < 0 {
+            break Err(IoError::from_code(n));
+        }
+        if n == 0 {
+            break Err(IoError::ShortWrite);
+        }
+        written += n as usize;
+    };
+    unsafe {
+        psp::sys::sceIoClose(fd);
+    }
+    result
+}
 
-        let mut res = 0i64;
-        // psp::sys::sceIoWaitAsync(fd, &mut res as *mut i64);
-        // psp::dprintln!("res: 0x{:x}", res);
-        psp::sys::sceIoWaitAsyncCB(fd, &mut res as *mut i64);
-        psp::dprintln!("res: 0x{:x}", res);
+/// Fills `buffer` from wherever `fd` is at, blocking until it's done
+pub(crate) fn read_exact(fd: SceUid, buffer: &mut [u8]) -> Result<(), IoError> {
+    let mut read = 0;
+    while read < buffer.len() {
+        let n = unsafe {
+            psp::sys::sceIoRead(
+                fd,
+                buffer[read..].as_mut_ptr() as *mut c_void,
+                (buffer.len() - read) as u32,
+            )
+        };
+        if n < 0 {
+            return Err(IoError::from_code(n));
+        }
+        if n == 0 {
+            return Err(IoError::ShortRead);
+        }
+        read += n as usize;
+    }
+    Ok(())
+}
 
-        psp::dprintln!("fd: {}", fd.0);
+/// Feeds a file to `f` a `buffer` full at a time, for files too big to hold at once. Returns
+/// how many bytes there were.
+pub fn read_chunks(
+    path: &str,
+    buffer: &mut [u8],
+    mut f: impl FnMut(&[u8]),
+) -> Result<u64, IoError> {
+    let path = c_path(path);
+    let fd = unsafe { psp::sys::sceIoOpen(path.as_ptr(), psp::sys::IoOpenFlags::RD_ONLY, 0) };
+    if fd.0 < 0 {
+        return Err(IoError::from_code(fd.0));
+    }
 
+    let mut total = 0;
+    let result = loop {
+        let n = unsafe {
+            psp::sys::sceIoRead(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len() as u32)
+        };
+        if n < 0 {
+            break Err(IoError::from_code(n));
+        }
+        if n == 0 {
+            break Ok(total);
+        }
+        f(&buffer[..n as usize]);
+        total += n as u64;
+    };
+    unsafe {
         psp::sys::sceIoClose(fd);
     }
+    result
 }
 
 pub fn read_dir(path: &str) -> Vec<String> {
     let mut entries = Vec::new();
+    let path = c_path(path);
 
     unsafe {
         let fd = psp::sys::sceIoDopen(path.as_ptr());
@@ -71,6 +196,7 @@ pub fn read_dir(path: &str) -> Vec<String> {
         while psp::sys::sceIoDread(fd, &mut entry) > 0 {
             let name = str::from_utf8(&entry.d_name)
                 .unwrap_or("INVALID_UTF8")
+                .trim_end_matches('\0')
                 .to_string();
             entries.push(name);
         }
@@ -7,25 +7,91 @@ extern crate alloc;
 
 use alloc::string::ToString;
 
-use crate::io::start_reading_tile;
+use nav_core::graph::FixedCoord;
+use nav_core::source::PositionSource;
 
 pub mod gps;
+pub mod input;
 pub mod io;
+pub mod navigation;
+pub mod router;
+pub mod screen;
+pub mod tile_loader;
+pub mod vector_render;
 
 psp::module!("nav_soft", 1, 1);
 
+/// Driven along when there's no GPS receiver, e.g. in an emulator
+const REPLAY: &str = "ms0:/PSP/GAME/psp_retro_nav/replay.gpx";
+/// The zoom to start at if the pack has it
+const MAP_ZOOM: u8 = 19;
+
+fn show_map<S: PositionSource>(
+    source: &mut S,
+    loader: &mut tile_loader::TileLoader,
+    navigation: Option<navigation::Navigation>,
+) {
+    let start = source.poll(gps::time_ms()).map(|fix| fix.coord);
+    let zoom_levels = loader.zoom_levels();
+    let Some(&highest) = zoom_levels.last() else {
+        psp::dprintln!("The tile pack is empty");
+        return;
+    };
+    let zoom = if zoom_levels.contains(&MAP_ZOOM) {
+        MAP_ZOOM
+    } else {
+        highest
+    };
+    // Before the first fix, show the middle of the region
+    let (min, max) = loader.header().extent;
+    let middle = FixedCoord {
+        lat: ((min.lat as i64 + max.lat as i64) / 2) as i32,
+        lon: ((min.lon as i64 + max.lon as i64) / 2) as i32,
+    };
+    let tile_size = loader.header().tile_res as u32;
+    let view = screen::map::MapView::new(zoom, tile_size, start.unwrap_or(middle));
+    screen::map::run(source, loader, view, zoom_levels, navigation);
+}
+
 fn psp_main() {
     psp::enable_home_button();
     psp::dprintln!("hi!");
 
-    // gps::init_gps();
-
-    // let path = "ms0:/PSP/GAME/\0";
-    // for entry in io::read_dir(path) {
-    //     psp::dprintln!("Entry: {entry:?}");
-    // }
-
-    start_reading_tile("ms0:/PSP/GAME/psp_retro_nav/test.qoi\0");
+    let Some(region) = screen::regions::run() else {
+        return;
+    };
+    let tiles = format!("{}/tiles.pack", region.path());
+    let mut loader = match tile_loader::TileLoader::open(&tiles) {
+        Ok(loader) => loader,
+        Err(e) => {
+            psp::dprintln!("Can't open {}: {:?}", tiles, e);
+            return;
+        }
+    };
+    // The map works without, there's just no routing then
+    let graph = format!("{}/graph.bin", region.path());
+    let navigation = match router::DeviceRouter::load(&graph) {
+        Ok(router) => Some(navigation::Navigation::new(router)),
+        Err(e) => {
+            psp::dprintln!("Can't load {}, no routing: {:?}", graph, e);
+            None
+        }
+    };
+    // The time to first fix counts from here, getting the receiver ready is part of it
+    let gps_started = gps::time_ms();
+    match gps::init_gps(gps::DEFAULT_INIT_TIMEOUT_MS) {
+        Ok(mut gps) => {
+            screen::satellites::run(&mut gps, true, gps_started);
+            show_map(&mut gps, &mut loader, navigation);
+        }
+        Err(e) => {
+            psp::dprintln!("GPS unavailable: {:?}", e);
+            match gps::load_replay(REPLAY, 1.0) {
+                Ok(mut replay) => show_map(&mut replay, &mut loader, navigation),
+                Err(e) => psp::dprintln!("No replay either: {:?}", e),
+            }
+        }
+    }
 
     psp::dprintln!("done!");
 }
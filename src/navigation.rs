@@ -0,0 +1,160 @@
+//! Keeps the position on the road and guides along a route: the [`MapMatcher`] puts fixes
+//! onto the roads, the [`Tracker`] follows them along the route, and once it decides the route
+//! was left the [`DeviceRouter`] works out a new one from where we are now.
+
+use alloc::vec::Vec;
+
+use nav_core::fix::Fix;
+use nav_core::graph::{FixedCoord, Metric};
+use nav_core::matcher::{MapMatcher, MatcherConfig};
+use nav_core::route::Route;
+use nav_core::tracking::{TrackEvent, Tracker, TrackerConfig};
+
+use crate::router::{DeviceRouter, RouterError};
+
+/// Grid of roads for the map matcher, on top of [`crate::router::ROUTER_MEMORY_BUDGET`]
+pub const MATCHER_MEMORY_BUDGET: usize = 2 * 1024 * 1024;
+
+struct Guidance {
+    route: Route,
+    tracker: Tracker,
+    /// The route's edges as one line, for drawing
+    points: Vec<FixedCoord>,
+}
+
+pub struct Navigation {
+    router: DeviceRouter,
+    /// `None` if the grid didn't fit, fixes are used as they are then
+    matcher: Option<MapMatcher>,
+    /// The last fix that was matched, and where it ended up
+    matched: Option<(Fix, Fix)>,
+    /// Fastest if the router can do that
+    metric: Metric,
+    guidance: Option<Guidance>,
+}
+
+impl Navigation {
+    pub fn new(router: DeviceRouter) -> Self {
+        let metric = match router.supports(Metric::Time) {
+            true => Metric::Time,
+            false => Metric::Distance,
+        };
+        let matcher = MapMatcher::new(
+            &router.graph(),
+            MatcherConfig::default(),
+            MATCHER_MEMORY_BUDGET,
+        )
+        .map_err(|e| psp::dprintln!("No map matching: {:?}", e))
+        .ok();
+        Self {
+            router,
+            matcher,
+            matched: None,
+            metric,
+            guidance: None,
+        }
+    }
+
+    /// `fix` moved onto the road it's most likely on, as it is when there's no road close by.
+    /// Losing the fix starts over, since the roads driven in the meantime are unknown.
+    pub fn match_fix(&mut self, fix: Option<Fix>, now_ms: u32) -> Option<Fix> {
+        let Some(matcher) = &mut self.matcher else {
+            return fix;
+        };
+        let Some(fix) = fix else {
+            matcher.reset();
+            self.matched = None;
+            return None;
+        };
+        // The source hands out the same fix until the receiver has a new one
+        if let Some((last, matched)) = self.matched
+            && last == fix
+        {
+            return Some(matched);
+        }
+
+        let matched = match matcher.update(&self.router.graph(), fix.track_position(now_ms)) {
+            Some(on_road) => Fix {
+                coord: on_road.snap.coord,
+                bearing: on_road.bearing,
+                ..fix
+            },
+            None => fix,
+        };
+        self.matched = Some((fix, matched));
+        Some(matched)
+    }
+
+    /// Routes from the position of `fix` to `destination` and starts following the route
+    pub fn start(&mut self, fix: &Fix, destination: FixedCoord) -> Result<(), RouterError> {
+        let degrees = |coord: FixedCoord| (coord.lat_degrees() as f32, coord.lon_degrees() as f32);
+        let route = self
+            .router
+            .route(degrees(fix.coord), degrees(destination), self.metric)?;
+        let graph = self.router.graph();
+        self.guidance = Some(Guidance {
+            tracker: Tracker::new(&graph, &route, TrackerConfig::default()),
+            points: route_points(&self.router, &route),
+            route,
+        });
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.guidance = None;
+    }
+
+    /// Follows `fix` along the route, and routes again when the tracker asks for it.
+    /// `now_ms` is any millisecond clock.
+    pub fn update(&mut self, fix: &Fix, now_ms: u32) -> TrackEvent {
+        let Some(guidance) = &mut self.guidance else {
+            return TrackEvent::None;
+        };
+        let event = guidance.tracker.update(fix.track_position(now_ms));
+        match event {
+            TrackEvent::Reroute { from, heading } => {
+                match self.router.reroute(from, heading, &guidance.route) {
+                    Ok(route) => {
+                        guidance.tracker.set_route(&self.router.graph(), &route);
+                        guidance.points = route_points(&self.router, &route);
+                        guidance.route = route;
+                    }
+                    // The tracker asks again in a while
+                    Err(e) => psp::dprintln!("Can't reroute: {:?}", e),
+                }
+            }
+            TrackEvent::Arrived => psp::dprintln!("Arrived"),
+            TrackEvent::None | TrackEvent::BackOnRoute => {}
+        }
+        event
+    }
+
+    /// The route being followed as a line, empty without one. The first and last edge are
+    /// included whole.
+    pub fn route_line(&self) -> &[FixedCoord] {
+        self.guidance
+            .as_ref()
+            .map_or(&[], |guidance| &guidance.points)
+    }
+
+    /// Meters left to drive, `None` without a route
+    pub fn remaining_m(&self) -> Option<f32> {
+        self.guidance
+            .as_ref()
+            .map(|guidance| guidance.tracker.remaining_m())
+    }
+}
+
+fn route_points(router: &DeviceRouter, route: &Route) -> Vec<FixedCoord> {
+    let graph = router.graph();
+    let mut points = Vec::new();
+    for (i, &id) in route.path.edges.iter().enumerate() {
+        let mut edge_points = graph.edge_points(&graph.edge(id));
+        // Edges share their end points
+        if i > 0 {
+            edge_points.next();
+        }
+        points.extend(edge_points);
+    }
+    points
+}
@@ -0,0 +1,101 @@
+//! Loads the converter's routing graph from the Memory Stick and routes on it.
+//! The actual routing lives in `nav_core::route`, so it can be tested on a PC.
+
+use alloc::vec::Vec;
+
+use nav_core::graph::{FixedCoord, Graph, GraphError, Metric};
+use nav_core::maneuver::{self, Maneuver};
+use nav_core::route::{Route, RouteError, Router};
+
+use crate::io::{self, IoError};
+
+/// Search state the router may allocate on top of the graph itself.
+/// A PSP-1000 has about 24 MB to go around, tiles need most of it.
+pub const ROUTER_MEMORY_BUDGET: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterError {
+    Io(IoError),
+    Graph(GraphError),
+    Route(RouteError),
+}
+
+impl From<IoError> for RouterError {
+    fn from(e: IoError) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<GraphError> for RouterError {
+    fn from(e: GraphError) -> Self {
+        Self::Graph(e)
+    }
+}
+
+impl From<RouteError> for RouterError {
+    fn from(e: RouteError) -> Self {
+        Self::Route(e)
+    }
+}
+
+pub struct DeviceRouter {
+    data: Vec<u8>,
+    router: Router,
+}
+
+impl DeviceRouter {
+    /// Loads e.g. `ms0:/PSP/GAME/psp_retro_nav/maps/<region>/graph.bin`
+    pub fn load(path: &str) -> Result<Self, RouterError> {
+        let data = io::read_file(path)?;
+        let router = {
+            let graph = Graph::parse(&data)?;
+            psp::dprintln!(
+                "Routing graph: {} nodes, {} edges, hierarchy: {}",
+                graph.node_count(),
+                graph.edge_count(),
+                graph.hierarchy().is_some()
+            );
+            Router::new(&graph, ROUTER_MEMORY_BUDGET)?
+        };
+
+        Ok(Self { data, router })
+    }
+
+    pub fn graph(&self) -> Graph<'_> {
+        // Already validated in `load`
+        Graph::parse_unchecked(&self.data)
+    }
+
+    pub fn supports(&self, metric: Metric) -> bool {
+        self.router.supports(metric)
+    }
+
+    /// Positions are in degrees, straight from the GPS
+    pub fn route(
+        &mut self,
+        from: (f32, f32),
+        to: (f32, f32),
+        metric: Metric,
+    ) -> Result<Route, RouterError> {
+        let graph = Graph::parse_unchecked(&self.data);
+        let from = FixedCoord::from_degrees(from.0 as f64, from.1 as f64);
+        let to = FixedCoord::from_degrees(to.0 as f64, to.1 as f64);
+        Ok(self.router.route(&graph, from, to, metric)?)
+    }
+
+    /// Routes again from the current position to the destination of `route`,
+    /// for when [`nav_core::tracking::Tracker`] says the route was left
+    pub fn reroute(
+        &mut self,
+        from: FixedCoord,
+        heading: Option<f32>,
+        route: &Route,
+    ) -> Result<Route, RouterError> {
+        let graph = Graph::parse_unchecked(&self.data);
+        Ok(self.router.reroute(&graph, from, heading, route)?)
+    }
+
+    pub fn maneuvers(&self, route: &Route) -> Vec<Maneuver> {
+        maneuver::maneuvers(&self.graph(), route)
+    }
+}
@@ -0,0 +1,586 @@
+//! The map around the current position, with an arrow for where we are and which way we're
+//! going.
+//!
+//! Tiles come in from the [`TileLoader`] while the map is already showing, until then their
+//! spot stays blank. Whatever is left over goes into loading the tiles ahead of us. Raster
+//! tiles can only be shown north up, vector tiles get drawn here and can be turned heading up
+//! and drawn in night colors. The route being driven, if any, goes on top of the tiles.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use embedded_graphics::{
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, Triangle},
+};
+use nav_core::fix::Fix;
+use nav_core::fixed_projection::{FixedProjection, SUBPIXEL_BITS};
+use nav_core::graph::FixedCoord;
+use nav_core::input::{Actions, InputConfig};
+use nav_core::source::PositionSource;
+use nav_core::tile::{self, TileKey};
+use nav_core::tile_cache::TileCache;
+use nav_core::tile_image::{self, PixelFormat, TileImage};
+use nav_core::tile_pack::TileContent;
+use nav_core::tracking::TrackEvent;
+use nav_core::vector_tile::{EXTENT, VectorTile};
+
+use super::{DoubleBuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::input::Controller;
+use crate::navigation::Navigation;
+use crate::tile_loader::{TILE_CACHE_BUDGET, TileLoader};
+use crate::vector_render::{self, Labels, Layer, TileTransform};
+
+/// Vblanks per frame, 2 makes for a steady 30 fps
+const FRAME_VBLANKS: u32 = 2;
+
+/// The receiver updates once a second, no need to ask much more often
+const POLL_INTERVAL_MS: u32 = 250;
+
+/// Slower than this the GPS bearing is mostly noise, show a dot instead of an arrow
+const MIN_ARROW_SPEED_MPS: f32 = 1.0;
+
+const BLANK: Rgb888 = Rgb888::new(224, 220, 208);
+const ARROW: Rgb888 = Rgb888::new(32, 96, 224);
+/// Arrow color while the GPS has lost the fix
+const ARROW_STALE: Rgb888 = Rgb888::new(128, 128, 128);
+const OUTLINE: Rgb888 = Rgb888::new(255, 255, 255);
+const ROUTE: Rgb888 = Rgb888::new(200, 40, 160);
+
+/// Half the screen diagonal, how far from the center a tile can show up when the map is turned
+const TURNED_REACH: i64 = 276;
+
+pub struct MapView {
+    zoom: u8,
+    /// Pixels per tile side, as the converter rendered them
+    pub tile_size: u32,
+    projection: FixedProjection,
+    /// Screen center in world pixels, with [`SUBPIXEL_BITS`] fraction bits
+    center: (i64, i64),
+    /// Degrees clockwise of the direction that's up on screen, 0 is north up
+    rotation: f32,
+}
+
+impl MapView {
+    pub fn new(zoom: u8, tile_size: u32, center: FixedCoord) -> Self {
+        let projection = FixedProjection::new();
+        Self {
+            zoom,
+            tile_size,
+            center: projection.world_pixel(center, zoom, tile_size),
+            projection,
+            rotation: 0.0,
+        }
+    }
+
+    pub fn center_on(&mut self, coord: FixedCoord) {
+        self.center = self
+            .projection
+            .world_pixel(coord, self.zoom, self.tile_size);
+    }
+
+    /// What's in the middle of the screen
+    pub fn center_coord(&self) -> FixedCoord {
+        let size = ((self.tile_size as i64) << SUBPIXEL_BITS) as f64;
+        let (x, y) = (self.center.0 as f64 / size, self.center.1 as f64 / size);
+        tile::tile_to_coord(x, y, self.zoom).into()
+    }
+
+    pub fn zoom(&self) -> u8 {
+        self.zoom
+    }
+
+    /// Zooms in or out around the screen center
+    pub fn set_zoom(&mut self, zoom: u8) {
+        let (x, y) = self.center;
+        self.center = match zoom >= self.zoom {
+            true => (x << (zoom - self.zoom), y << (zoom - self.zoom)),
+            false => (x >> (self.zoom - zoom), y >> (self.zoom - zoom)),
+        };
+        self.zoom = zoom;
+    }
+
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+
+    /// Turns the map so `heading` is up
+    pub fn set_rotation(&mut self, heading: f32) {
+        self.rotation = heading;
+    }
+
+    /// Moves the screen center by `dx` and `dy` pixels, right and down on screen
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let (dx, dy) = turn(dx, dy, self.rotation);
+        let world = ((self.tile_size as i64) << self.zoom) << SUBPIXEL_BITS;
+        let subpixels = (1 << SUBPIXEL_BITS) as f32;
+        self.center.0 = (self.center.0 + (dx * subpixels) as i64).rem_euclid(world);
+        self.center.1 = (self.center.1 + (dy * subpixels) as i64).clamp(0, world - 1);
+    }
+
+    pub fn center_tile(&self) -> TileKey {
+        let size = self.tile_size as i64;
+        let (x, y) = (
+            self.center.0 >> SUBPIXEL_BITS,
+            self.center.1 >> SUBPIXEL_BITS,
+        );
+        TileKey::new(self.zoom, (x / size) as u32, (y / size) as u32)
+    }
+
+    /// World pixel at the top left corner of the screen
+    fn origin(&self) -> (i64, i64) {
+        (
+            (self.center.0 >> SUBPIXEL_BITS) - SCREEN_WIDTH as i64 / 2,
+            (self.center.1 >> SUBPIXEL_BITS) - SCREEN_HEIGHT as i64 / 2,
+        )
+    }
+
+    pub fn to_screen(&self, coord: FixedCoord) -> Point {
+        let (x, y) = self
+            .projection
+            .world_pixel(coord, self.zoom, self.tile_size);
+        let (dx, dy) = (
+            (x >> SUBPIXEL_BITS) - (self.center.0 >> SUBPIXEL_BITS),
+            (y >> SUBPIXEL_BITS) - (self.center.1 >> SUBPIXEL_BITS),
+        );
+        let (dx, dy) = turn(dx as f32, dy as f32, -self.rotation);
+        screen_center() + Point::new(libm::roundf(dx) as i32, libm::roundf(dy) as i32)
+    }
+
+    /// Every tile sized spot on screen with its top left corner, `None` past the top or bottom
+    /// of the world
+    pub fn tile_slots(&self) -> Vec<(Option<TileKey>, Point)> {
+        let size = self.tile_size as i64;
+        let side = 1i64 << self.zoom;
+        let (left, top) = self.origin();
+        let (first_x, first_y) = (left.div_euclid(size), top.div_euclid(size));
+        let last_x = (left + SCREEN_WIDTH as i64 - 1).div_euclid(size);
+        let last_y = (top + SCREEN_HEIGHT as i64 - 1).div_euclid(size);
+
+        let mut slots = Vec::new();
+        for y in first_y..=last_y {
+            for x in first_x..=last_x {
+                let key = (0..side)
+                    .contains(&y)
+                    .then(|| TileKey::new(self.zoom, x.rem_euclid(side) as u32, y as u32));
+                let corner = Point::new((x * size - left) as i32, (y * size - top) as i32);
+                slots.push((key, corner));
+            }
+        }
+        slots
+    }
+
+    /// Every tile that can show up on screen, with its top left corner in pixels from the
+    /// screen center before turning the map
+    pub fn tiles_in_reach(&self) -> Vec<(TileKey, (f32, f32))> {
+        let size = self.tile_size as i64;
+        let side = 1i64 << self.zoom;
+        let (x, y) = (
+            self.center.0 >> SUBPIXEL_BITS,
+            self.center.1 >> SUBPIXEL_BITS,
+        );
+        let (reach_x, reach_y) = match self.rotation == 0.0 {
+            true => (SCREEN_WIDTH as i64 / 2, SCREEN_HEIGHT as i64 / 2),
+            false => (TURNED_REACH, TURNED_REACH),
+        };
+        let (first_x, last_x) = (
+            (x - reach_x).div_euclid(size),
+            (x + reach_x - 1).div_euclid(size),
+        );
+        let (first_y, last_y) = (
+            (y - reach_y).div_euclid(size),
+            (y + reach_y - 1).div_euclid(size),
+        );
+
+        let mut tiles = Vec::new();
+        for tile_y in (first_y..=last_y).filter(|tile_y| (0..side).contains(tile_y)) {
+            for tile_x in first_x..=last_x {
+                let key = TileKey::new(self.zoom, tile_x.rem_euclid(side) as u32, tile_y as u32);
+                let corner = ((tile_x * size - x) as f32, (tile_y * size - y) as f32);
+                tiles.push((key, corner));
+            }
+        }
+        tiles
+    }
+
+    pub fn visible_tiles(&self) -> Vec<TileKey> {
+        self.tiles_in_reach()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// How a vector tile with its corner at `corner` (from [`MapView::tiles_in_reach`]) gets
+    /// onto the screen
+    pub fn tile_transform(&self, corner: (f32, f32)) -> TileTransform {
+        let radians = (-self.rotation).to_radians();
+        TileTransform {
+            corner,
+            scale: self.tile_size as f32 / EXTENT as f32,
+            sin: libm::sinf(radians),
+            cos: libm::cosf(radians),
+            center: screen_center(),
+        }
+    }
+}
+
+/// Turns an offset on screen into one on the map, or the other way around with `-degrees`
+fn turn(x: f32, y: f32, degrees: f32) -> (f32, f32) {
+    let (sin, cos) = (
+        libm::sinf(degrees.to_radians()),
+        libm::cosf(degrees.to_radians()),
+    );
+    (x * cos - y * sin, x * sin + y * cos)
+}
+
+fn screen_center() -> Point {
+    Point::new(SCREEN_WIDTH as i32 / 2, SCREEN_HEIGHT as i32 / 2)
+}
+
+/// Tiles as they came out of the pack, ready to draw
+enum Tiles {
+    /// Pre-rendered, so always north up
+    Raster(TileCache<TileImage>),
+    /// Checked with [`VectorTile::parse`], but kept as they came since that's smaller
+    Vector(TileCache<Vec<u8>>),
+}
+
+impl Tiles {
+    fn new(content: TileContent) -> Self {
+        match content {
+            TileContent::Qoi => Self::Raster(TileCache::new(TILE_CACHE_BUDGET)),
+            TileContent::Vector => Self::Vector(TileCache::new(TILE_CACHE_BUDGET)),
+        }
+    }
+
+    fn contains(&self, key: TileKey) -> bool {
+        match self {
+            Self::Raster(cache) => cache.contains(key),
+            Self::Vector(cache) => cache.contains(key),
+        }
+    }
+
+    /// Decodes or checks a loaded tile and caches it, `Err` if it's broken
+    fn insert(&mut self, key: TileKey, data: Vec<u8>) -> Result<(), ()> {
+        match self {
+            Self::Raster(cache) => {
+                let image = tile_image::decode(&data, PixelFormat::Abgr8888)
+                    .map_err(|e| psp::dprintln!("tile {:?}: {:?}", key, e))?;
+                let size = image.size_bytes();
+                let _ = cache.insert(key, image, size);
+            }
+            Self::Vector(cache) => {
+                VectorTile::parse(&data).map_err(|e| psp::dprintln!("tile {:?}: {:?}", key, e))?;
+                let size = data.len();
+                let _ = cache.insert(key, data, size);
+            }
+        }
+        Ok(())
+    }
+
+    fn prefetch(&self, loader: &mut TileLoader, center: TileKey, heading: f32) {
+        match self {
+            Self::Raster(cache) => loader.prefetch(cache, center, heading),
+            Self::Vector(cache) => loader.prefetch(cache, center, heading),
+        }
+    }
+}
+
+pub struct MapScreen {
+    pub view: MapView,
+    /// Zoom levels there are tiles for, lowest first
+    zoom_levels: Vec<u8>,
+    /// Keep the position in the middle, off after panning away
+    follow: bool,
+    /// Turn the map with the direction of travel, vector tiles only
+    heading_up: bool,
+    night: bool,
+    tiles: Tiles,
+    /// Tiles that failed to load, most likely outside the converted region. Not asked for again.
+    missing: BTreeSet<TileKey>,
+    fix: Option<Fix>,
+    /// The last poll had no fix, `fix` is an old one
+    stale: bool,
+    /// The route being driven, see [`Navigation::route_line`]
+    route: Vec<FixedCoord>,
+}
+
+impl MapScreen {
+    pub fn new(view: MapView, zoom_levels: Vec<u8>, content: TileContent) -> Self {
+        Self {
+            view,
+            zoom_levels,
+            follow: true,
+            heading_up: false,
+            night: false,
+            tiles: Tiles::new(content),
+            missing: BTreeSet::new(),
+            fix: None,
+            stale: true,
+            route: Vec::new(),
+        }
+    }
+
+    /// The last fix there was, even if it's stale
+    pub fn fix(&self) -> Option<Fix> {
+        self.fix
+    }
+
+    pub fn set_route(&mut self, line: &[FixedCoord]) {
+        self.route.clear();
+        self.route.extend_from_slice(line);
+    }
+
+    pub fn update_fix(&mut self, fix: Option<Fix>) {
+        self.stale = fix.is_none();
+        if let Some(fix) = fix {
+            self.fix = Some(fix);
+            if self.follow {
+                self.view.center_on(fix.coord);
+            }
+            // Standing still the bearing is noise, keep the map as it is
+            if self.heading_up && fix.speed_mps >= MIN_ARROW_SPEED_MPS {
+                self.view.set_rotation(fix.bearing);
+            }
+        }
+    }
+
+    pub fn handle_input(&mut self, actions: &Actions) {
+        if actions.is_panning() {
+            self.follow = false;
+            self.view.pan(actions.pan.0, actions.pan.1);
+        }
+        if actions.toggle_follow {
+            self.follow = !self.follow;
+        }
+        if actions.recenter {
+            self.follow = true;
+        }
+        if actions.toggle_heading_up && matches!(self.tiles, Tiles::Vector(_)) {
+            self.heading_up = !self.heading_up;
+            let heading = self
+                .fix
+                .filter(|_| self.heading_up)
+                .map_or(0.0, |fix| fix.bearing);
+            self.view.set_rotation(heading);
+        }
+        if actions.toggle_night {
+            self.night = !self.night;
+        }
+        if self.follow
+            && let Some(fix) = self.fix
+        {
+            self.view.center_on(fix.coord);
+        }
+
+        if actions.zoom != 0
+            && let Some(i) = self.zoom_levels.iter().position(|&z| z == self.view.zoom())
+        {
+            let i =
+                (i as isize + actions.zoom as isize).clamp(0, self.zoom_levels.len() as isize - 1);
+            self.view.set_zoom(self.zoom_levels[i as usize]);
+        }
+    }
+
+    /// Takes in finished tiles and asks for the ones that are needed next
+    pub fn update_tiles(&mut self, loader: &mut TileLoader) {
+        loader.update();
+        while let Some(load) = loader.next_completed() {
+            let data = load
+                .result
+                .map_err(|e| psp::dprintln!("tile {:?}: {:?}", load.key, e));
+            if data
+                .and_then(|data| self.tiles.insert(load.key, data))
+                .is_err()
+            {
+                self.missing.insert(load.key);
+            }
+        }
+
+        let visible = self.view.visible_tiles();
+        loader.cancel_where(|key| !visible.contains(&key));
+        for &key in &visible {
+            if !self.tiles.contains(key) && !self.missing.contains(&key) {
+                loader.request(key);
+            }
+        }
+        if let Some(fix) = self.fix.filter(|fix| fix.speed_mps >= MIN_ARROW_SPEED_MPS) {
+            self.tiles
+                .prefetch(loader, self.view.center_tile(), fix.bearing);
+        }
+    }
+
+    pub fn draw<D: DrawTarget<Color = Rgb888>>(&mut self, target: &mut D) -> Result<(), D::Error> {
+        match &mut self.tiles {
+            Tiles::Raster(cache) => {
+                let size = Size::new_equal(self.view.tile_size);
+                for (key, corner) in self.view.tile_slots() {
+                    let area = Rectangle::new(corner, size);
+                    match key.and_then(|key| cache.get(key)) {
+                        Some(image) => target.fill_contiguous(&area, image.colors())?,
+                        None => target.fill_solid(&area, BLANK)?,
+                    }
+                }
+            }
+            Tiles::Vector(cache) => {
+                let palette = match self.night {
+                    true => &vector_render::NIGHT,
+                    false => &vector_render::DAY,
+                };
+                target.clear(palette.background)?;
+                // Counts as a use for the cache once per frame, the layers only peek
+                let in_reach = self
+                    .view
+                    .tiles_in_reach()
+                    .into_iter()
+                    .filter(|&(key, _)| cache.get(key).is_some())
+                    .collect::<Vec<_>>();
+                // Parsed once for all the layers
+                let tiles = in_reach
+                    .iter()
+                    .filter_map(|&(key, corner)| {
+                        let tile = VectorTile::parse(cache.peek(key)?).ok()?;
+                        Some((tile, self.view.tile_transform(corner)))
+                    })
+                    .collect::<Vec<_>>();
+                let mut labels = Labels::default();
+                for layer in Layer::ALL {
+                    for (tile, transform) in &tiles {
+                        vector_render::draw_tile(
+                            target,
+                            tile,
+                            transform,
+                            palette,
+                            layer,
+                            &mut labels,
+                        )?;
+                    }
+                }
+            }
+        }
+
+        let route_style = PrimitiveStyle::with_stroke(ROUTE, 4);
+        for pair in self.route.windows(2) {
+            let (a, b) = (self.view.to_screen(pair[0]), self.view.to_screen(pair[1]));
+            Line::new(a, b).into_styled(route_style).draw(target)?;
+        }
+
+        if let Some(fix) = self.fix {
+            let position = self.view.to_screen(fix.coord);
+            let color = if self.stale { ARROW_STALE } else { ARROW };
+            let bearing = fix.bearing - self.view.rotation();
+            draw_position(target, position, bearing, fix.speed_mps, color)?;
+        }
+        Ok(())
+    }
+}
+
+/// An arrow pointing along `bearing`, or a dot when standing still
+fn draw_position<D: DrawTarget<Color = Rgb888>>(
+    target: &mut D,
+    position: Point,
+    bearing: f32,
+    speed_mps: f32,
+    color: Rgb888,
+) -> Result<(), D::Error> {
+    let style = PrimitiveStyleBuilder::new()
+        .fill_color(color)
+        .stroke_color(OUTLINE)
+        .stroke_width(2)
+        .build();
+    if speed_mps < MIN_ARROW_SPEED_MPS {
+        return Circle::with_center(position, 14)
+            .into_styled(style)
+            .draw(target);
+    }
+
+    let (sin, cos) = (
+        libm::sinf(bearing.to_radians()),
+        libm::cosf(bearing.to_radians()),
+    );
+    // Screen y grows down, so north is -y
+    let rotate = |x: f32, y: f32| {
+        position + Point::new((x * cos - y * sin) as i32, (x * sin + y * cos) as i32)
+    };
+    let (tip, left, right, notch) = (
+        rotate(0.0, -12.0),
+        rotate(-8.0, 9.0),
+        rotate(8.0, 9.0),
+        rotate(0.0, 4.0),
+    );
+    // Two halves, so the back of the arrow gets its notch
+    Triangle::new(tip, left, notch)
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(target)?;
+    Triangle::new(tip, notch, right)
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(target)?;
+    for (a, b) in [(tip, left), (left, notch), (notch, right), (right, tip)] {
+        Line::new(a, b)
+            .into_styled(PrimitiveStyle::with_stroke(OUTLINE, 2))
+            .draw(target)?;
+    }
+    Ok(())
+}
+
+/// Shows the map following the position from `source`, for good. `zoom_levels` are the ones
+/// there are tiles for, the view's has to be one of them. With `navigation` the position is
+/// kept on the roads, and a route to the middle of the map can be asked for and followed.
+pub fn run<S: PositionSource>(
+    source: &mut S,
+    loader: &mut TileLoader,
+    view: MapView,
+    zoom_levels: Vec<u8>,
+    mut navigation: Option<Navigation>,
+) {
+    let mut framebuffer = DoubleBuffer::new();
+    let mut controller = Controller::new(InputConfig::default());
+    let mut screen = MapScreen::new(view, zoom_levels, loader.header().content);
+    let mut last_poll = crate::gps::time_ms().wrapping_sub(POLL_INTERVAL_MS);
+    loop {
+        let now = crate::gps::time_ms();
+        if now.wrapping_sub(last_poll) >= POLL_INTERVAL_MS {
+            last_poll = now;
+            let mut fix = source.poll(now).ok();
+            if let Some(navigation) = &mut navigation {
+                fix = navigation.match_fix(fix, now);
+            }
+            screen.update_fix(fix);
+            if let (Some(fix), Some(navigation)) = (fix, &mut navigation) {
+                match navigation.update(&fix, now) {
+                    TrackEvent::Reroute { .. } => screen.set_route(navigation.route_line()),
+                    TrackEvent::Arrived => {
+                        navigation.stop();
+                        screen.set_route(&[]);
+                    }
+                    TrackEvent::None | TrackEvent::BackOnRoute => {}
+                }
+            }
+        }
+
+        let actions = controller.read(now);
+        screen.handle_input(&actions);
+        if actions.navigate_here
+            && let Some(navigation) = &mut navigation
+        {
+            match screen.fix() {
+                Some(fix) => match navigation.start(&fix, screen.view.center_coord()) {
+                    Ok(()) => screen.set_route(navigation.route_line()),
+                    Err(e) => psp::dprintln!("No route: {:?}", e),
+                },
+                None => psp::dprintln!("No route without a position"),
+            }
+        }
+        screen.update_tiles(loader);
+        // The framebuffer never fails to draw
+        let _ = screen.draw(&mut framebuffer);
+
+        framebuffer.swap();
+        for _ in 1..FRAME_VBLANKS {
+            unsafe {
+                psp::sys::sceDisplayWaitVblankStart();
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Full screen views, drawn with `embedded-graphics` onto the PSP's 480x272 framebuffer
+
+use core::convert::Infallible;
+
+use embedded_graphics::{pixelcolor::Rgb888, prelude::*, primitives::Rectangle};
+use psp::sys::{self, DisplayMode, DisplayPixelFormat, DisplaySetBufSync};
+
+pub mod map;
+pub mod regions;
+pub mod satellites;
+
+pub const SCREEN_WIDTH: u32 = 480;
+pub const SCREEN_HEIGHT: u32 = 272;
+
+/// Pixels per framebuffer row, the display wants a power of two
+const BUFFER_WIDTH: usize = 512;
+
+/// Two framebuffers in VRAM. Drawing goes into the one that isn't on screen, and
+/// [`DoubleBuffer::swap`] shows it from the next vblank on, so a frame never shows up half
+/// drawn or torn.
+pub struct DoubleBuffer {
+    buffers: [*mut u32; 2],
+    /// Index of the one being drawn into
+    back: usize,
+}
+
+impl DoubleBuffer {
+    pub fn new() -> Self {
+        unsafe {
+            sys::sceDisplaySetMode(
+                DisplayMode::Lcd,
+                SCREEN_WIDTH as usize,
+                SCREEN_HEIGHT as usize,
+            );
+            // Uncached, so everything drawn is in VRAM by the time the display reads it
+            let vram = (0x4000_0000 | sys::sceGeEdramGetAddr() as usize) as *mut u32;
+            let buffers = [vram, vram.add(BUFFER_WIDTH * SCREEN_HEIGHT as usize)];
+            show(buffers[0]);
+            Self { buffers, back: 1 }
+        }
+    }
+
+    /// Shows what was drawn since the last swap and waits for the vblank it shows up on,
+    /// after that the other buffer is free to draw into
+    pub fn swap(&mut self) {
+        unsafe {
+            show(self.buffers[self.back]);
+            sys::sceDisplayWaitVblankStart();
+        }
+        self.back ^= 1;
+    }
+}
+
+impl Default for DoubleBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe fn show(buffer: *mut u32) {
+    unsafe {
+        sys::sceDisplaySetFrameBuf(
+            buffer as *const u8,
+            BUFFER_WIDTH,
+            DisplayPixelFormat::Psm8888,
+            DisplaySetBufSync::NextFrame,
+        );
+    }
+}
+
+/// What the display reads for `color` in [`DisplayPixelFormat::Psm8888`]
+fn abgr(color: Rgb888) -> u32 {
+    0xff00_0000 | (color.b() as u32) << 16 | (color.g() as u32) << 8 | color.r() as u32
+}
+
+impl OriginDimensions for DoubleBuffer {
+    fn size(&self) -> Size {
+        Size::new(SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+}
+
+impl DrawTarget for DoubleBuffer {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let buffer = self.buffers[self.back];
+        for Pixel(point, color) in pixels {
+            if let (Ok(x @ 0..SCREEN_WIDTH), Ok(y @ 0..SCREEN_HEIGHT)) =
+                (u32::try_from(point.x), u32::try_from(point.y))
+            {
+                unsafe {
+                    *buffer.add(y as usize * BUFFER_WIDTH + x as usize) = abgr(color);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// A row at a time, clearing and filling areas is most of drawing the map
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.bounding_box());
+        let Some(bottom_right) = area.bottom_right() else {
+            return Ok(());
+        };
+        let (left, right) = (area.top_left.x as usize, bottom_right.x as usize);
+        let value = abgr(color);
+        for y in area.top_left.y as usize..=bottom_right.y as usize {
+            let row = unsafe { self.buffers[self.back].add(y * BUFFER_WIDTH) };
+            for x in left..=right {
+                unsafe {
+                    *row.add(x) = value;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_solid(&self.bounding_box(), color)
+    }
+}
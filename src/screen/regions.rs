@@ -0,0 +1,377 @@
+//! Lists the converted regions on the Memory Stick, each folder in `maps/` with a
+//! `manifest.txt`, and lets the user pick the one to navigate in.
+//!
+//! Regions written for another format version are shown greyed out and can't be picked. The
+//! files of the one that is picked are checked against the manifest first, a copy cut short
+//! by pulling the Memory Stick shows up here instead of as garbage on the map. Their sizes are
+//! checked every time, their checksums only until they check out once, which is remembered in
+//! [`VERIFIED_FILE_NAME`] next to the manifest.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use embedded_graphics::{
+    mono_font::{MonoTextStyle, ascii::FONT_6X10},
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::Text,
+};
+use nav_core::input::{InputConfig, MenuAction};
+use nav_core::manifest::{self, Crc32, Manifest, ManifestError, ManifestFile};
+
+use super::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::input::Controller;
+use crate::io::{self, IoError};
+
+pub const MAPS_DIR: &str = "ms0:/PSP/GAME/psp_retro_nav/maps";
+
+/// Holds the CRC32 of the manifest whose files last checked out, a region converted again gets
+/// checked again
+pub const VERIFIED_FILE_NAME: &str = "verified.txt";
+
+const LIST_TOP: i32 = 32;
+/// Two lines of text and a gap
+const ROW_HEIGHT: i32 = 26;
+const VISIBLE_ROWS: usize = ((SCREEN_HEIGHT as i32 - LIST_TOP - 20) / ROW_HEIGHT) as usize;
+
+/// Checksums are worked out this much of a file at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+const FRAME_VBLANKS: u32 = 2;
+
+const BACKGROUND: Rgb888 = Rgb888::new(0, 0, 0);
+const TEXT: Rgb888 = Rgb888::new(220, 220, 220);
+const DETAILS: Rgb888 = Rgb888::new(150, 150, 150);
+const UNUSABLE: Rgb888 = Rgb888::new(80, 80, 80);
+const SELECTED: Rgb888 = Rgb888::new(40, 60, 110);
+const ERROR: Rgb888 = Rgb888::new(230, 80, 64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    Io(IoError),
+    Manifest(ManifestError),
+}
+
+impl From<IoError> for RegionError {
+    fn from(e: IoError) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ManifestError> for RegionError {
+    fn from(e: ManifestError) -> Self {
+        Self::Manifest(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileError {
+    Io(IoError),
+    WrongSize,
+    WrongChecksum,
+}
+
+pub struct Region {
+    /// Folder name in [`MAPS_DIR`]
+    pub folder: String,
+    pub manifest: Result<Manifest, RegionError>,
+    /// CRC32 of the manifest file, 0 without one
+    manifest_crc: u32,
+}
+
+impl Region {
+    pub fn path(&self) -> String {
+        format!("{MAPS_DIR}/{}", self.folder)
+    }
+
+    pub fn is_usable(&self) -> bool {
+        self.manifest.as_ref().is_ok_and(Manifest::is_compatible)
+    }
+
+    fn verified_path(&self) -> String {
+        format!("{}/{VERIFIED_FILE_NAME}", self.path())
+    }
+
+    fn verified_marker(&self) -> String {
+        format!("{:08x}", self.manifest_crc)
+    }
+
+    /// Whether the files checked out against this manifest before
+    fn was_verified(&self) -> bool {
+        io::read_file(&self.verified_path())
+            .is_ok_and(|marker| marker == self.verified_marker().as_bytes())
+    }
+
+    /// Checks every file the manifest lists. The checksums only the first time, `progress` gets
+    /// the bytes checked so far and in total every now and then while they're worked out.
+    pub fn verify(&self, progress: impl FnMut(u64, u64)) -> Result<(), (&ManifestFile, FileError)> {
+        let Ok(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        for file in &manifest.files {
+            let path = format!("{}/{}", self.path(), file.name);
+            let size = io::file_size(&path).map_err(|e| (file, FileError::Io(e)))?;
+            if size != file.size {
+                return Err((file, FileError::WrongSize));
+            }
+        }
+        if self.was_verified() {
+            return Ok(());
+        }
+
+        self.verify_checksums(manifest, progress)?;
+        let path = self.verified_path();
+        // Not remembering it only means checking again next time
+        if let Err(e) = io::write_file(&path, self.verified_marker().as_bytes()) {
+            psp::dprintln!("Can't write {}: {:?}", path, e);
+        }
+        Ok(())
+    }
+
+    fn verify_checksums<'a>(
+        &self,
+        manifest: &'a Manifest,
+        mut progress: impl FnMut(u64, u64),
+    ) -> Result<(), (&'a ManifestFile, FileError)> {
+        let total = manifest.files.iter().map(|file| file.size).sum();
+        let mut done = 0;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        for file in &manifest.files {
+            let mut crc = Crc32::new();
+            let path = format!("{}/{}", self.path(), file.name);
+            let size = io::read_chunks(&path, &mut buffer, |chunk| {
+                crc.update(chunk);
+                done += chunk.len() as u64;
+                progress(done, total);
+            })
+            .map_err(|e| (file, FileError::Io(e)))?;
+            if size != file.size {
+                return Err((file, FileError::WrongSize));
+            }
+            if crc.finish() != file.crc32 {
+                return Err((file, FileError::WrongChecksum));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every folder in [`MAPS_DIR`], by name
+pub fn find_regions() -> Vec<Region> {
+    let mut folders = io::read_dir(MAPS_DIR);
+    folders.retain(|name| !name.is_empty() && !name.starts_with('.'));
+    folders.sort();
+    folders
+        .into_iter()
+        .map(|folder| {
+            let path = format!("{MAPS_DIR}/{folder}/{}", manifest::FILE_NAME);
+            let data = io::read_file(&path);
+            let manifest_crc = data.as_deref().map_or(0, manifest::crc32);
+            let manifest = data
+                .map_err(RegionError::from)
+                .and_then(|data| Ok(Manifest::parse(&data)?));
+            Region {
+                folder,
+                manifest,
+                manifest_crc,
+            }
+        })
+        .collect()
+}
+
+pub struct RegionScreen {
+    pub regions: Vec<Region>,
+    selected: usize,
+    /// Why the last pick didn't work out
+    message: Option<String>,
+}
+
+impl RegionScreen {
+    pub fn new(regions: Vec<Region>) -> Self {
+        // Start on something that can actually be picked
+        let selected = regions.iter().position(Region::is_usable).unwrap_or(0);
+        Self {
+            regions,
+            selected,
+            message: None,
+        }
+    }
+
+    pub fn selected(&self) -> Option<&Region> {
+        self.regions.get(self.selected)
+    }
+
+    pub fn handle_input(&mut self, action: MenuAction) {
+        let last = self.regions.len().saturating_sub(1);
+        match action {
+            MenuAction::Up => self.selected = self.selected.saturating_sub(1),
+            MenuAction::Down => self.selected = (self.selected + 1).min(last),
+            MenuAction::Select | MenuAction::Back => return,
+        }
+        self.message = None;
+    }
+
+    pub fn draw<D: DrawTarget<Color = Rgb888>>(&self, target: &mut D) -> Result<(), D::Error> {
+        target.clear(BACKGROUND)?;
+        let style = MonoTextStyle::new(&FONT_6X10, TEXT);
+        Text::new("Choose a map", Point::new(8, 16), style).draw(target)?;
+
+        if self.regions.is_empty() {
+            let text = format!("No maps in {MAPS_DIR}");
+            Text::new(&text, Point::new(8, LIST_TOP + 10), style).draw(target)?;
+        }
+
+        // Scroll so the selection stays in view
+        let first = (self.selected + 1).saturating_sub(VISIBLE_ROWS);
+        for (row, region) in self
+            .regions
+            .iter()
+            .skip(first)
+            .take(VISIBLE_ROWS)
+            .enumerate()
+        {
+            let top = LIST_TOP + row as i32 * ROW_HEIGHT;
+            if first + row == self.selected {
+                Rectangle::new(
+                    Point::new(0, top - 2),
+                    Size::new(SCREEN_WIDTH, ROW_HEIGHT as u32 - 2),
+                )
+                .into_styled(PrimitiveStyle::with_fill(SELECTED))
+                .draw(target)?;
+            }
+            let (title, details) = describe(region);
+            let (title_color, details_color) = match region.is_usable() {
+                true => (TEXT, DETAILS),
+                false => (UNUSABLE, UNUSABLE),
+            };
+            let title_style = MonoTextStyle::new(&FONT_6X10, title_color);
+            let details_style = MonoTextStyle::new(&FONT_6X10, details_color);
+            Text::new(&title, Point::new(8, top + 8), title_style).draw(target)?;
+            Text::new(&details, Point::new(16, top + 19), details_style).draw(target)?;
+        }
+
+        if let Some(message) = &self.message {
+            let style = MonoTextStyle::new(&FONT_6X10, ERROR);
+            Text::new(message, Point::new(8, SCREEN_HEIGHT as i32 - 8), style).draw(target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Over the message line, the list stays as it was
+fn draw_progress<D: DrawTarget<Color = Rgb888>>(
+    target: &mut D,
+    done: u64,
+    total: u64,
+) -> Result<(), D::Error> {
+    let top = SCREEN_HEIGHT as i32 - 18;
+    let width = SCREEN_WIDTH - 16;
+    let filled = (done.min(total) * width as u64 / total.max(1)) as u32;
+    Rectangle::new(Point::new(0, top - 2), Size::new(SCREEN_WIDTH, 20))
+        .into_styled(PrimitiveStyle::with_fill(BACKGROUND))
+        .draw(target)?;
+    let style = MonoTextStyle::new(&FONT_6X10, TEXT);
+    Text::new("Checking map files...", Point::new(8, top + 6), style).draw(target)?;
+    Rectangle::new(Point::new(8, top + 10), Size::new(width, 6))
+        .into_styled(PrimitiveStyle::with_stroke(DETAILS, 1))
+        .draw(target)?;
+    Rectangle::new(Point::new(8, top + 10), Size::new(filled, 6))
+        .into_styled(PrimitiveStyle::with_fill(TEXT))
+        .draw(target)
+}
+
+/// Region name and a line with what's in it
+fn describe(region: &Region) -> (String, String) {
+    let manifest = match &region.manifest {
+        Ok(manifest) => manifest,
+        Err(RegionError::Io(IoError::NoEntry)) => {
+            return (
+                region.folder.clone(),
+                String::from("No manifest, convert it again"),
+            );
+        }
+        Err(e) => return (region.folder.clone(), format!("Broken manifest: {e:?}")),
+    };
+    let title = format!("{} ({})", manifest.region, region.folder);
+
+    if !manifest.is_compatible() {
+        let details = format!(
+            "Format {}, this version reads up to {}",
+            manifest.format,
+            manifest::FORMAT_VERSION
+        );
+        return (title, details);
+    }
+
+    let (min, max) = manifest.extent;
+    let mut details = format!(
+        "{:.2}..{:.2} lat {:.2}..{:.2} lon, zoom {}-{}",
+        min.lat_degrees(),
+        max.lat_degrees(),
+        min.lon_degrees(),
+        max.lon_degrees(),
+        manifest.min_zoom,
+        manifest.max_zoom,
+    );
+    if let Some(date) = manifest.source_date() {
+        details += &format!(", data {}-{:02}-{:02}", date.year, date.month, date.day);
+    }
+    details += &format!(", {} v{}", manifest.style, manifest.converter_version);
+    (title, details)
+}
+
+fn describe_file_error(file: &ManifestFile, error: FileError) -> String {
+    match error {
+        FileError::Io(e) => format!("Can't read {}: {e:?}", file.name),
+        FileError::WrongSize | FileError::WrongChecksum => {
+            format!("{} is damaged, copy the map again", file.name)
+        }
+    }
+}
+
+/// Shows the list until a region is picked and its files check out. `None` if the user backs
+/// out.
+pub fn run() -> Option<Region> {
+    let mut framebuffer = psp::embedded_graphics::Framebuffer::new();
+    let mut controller = Controller::new(InputConfig::default());
+    let mut screen = RegionScreen::new(find_regions());
+    loop {
+        match controller.read_menu() {
+            Some(MenuAction::Back) => return None,
+            Some(MenuAction::Select) => {
+                if let Some(region) = screen.selected().filter(|r| r.is_usable()) {
+                    let mut shown = None;
+                    let result = region.verify(|done, total| {
+                        // Redrawing for every chunk would slow the check down
+                        let percent = done * 100 / total.max(1);
+                        if shown != Some(percent) {
+                            shown = Some(percent);
+                            let _ = draw_progress(&mut framebuffer, done, total);
+                        }
+                    });
+                    match result {
+                        Ok(()) => return Some(screen.regions.swap_remove(screen.selected)),
+                        Err((file, error)) => {
+                            screen.message = Some(describe_file_error(file, error))
+                        }
+                    }
+                } else if let Some(region) = screen.selected() {
+                    let (_, details) = describe(region);
+                    screen.message = Some(details);
+                }
+            }
+            Some(action) => screen.handle_input(action),
+            None => {}
+        }
+        // The framebuffer never fails to draw
+        let _ = screen.draw(&mut framebuffer);
+
+        for _ in 0..FRAME_VBLANKS {
+            unsafe {
+                psp::sys::sceDisplayWaitVblankStart();
+            }
+        }
+    }
+}
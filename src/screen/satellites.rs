@@ -0,0 +1,261 @@
+//! Shows what the GPS receiver sees: a sky plot with every satellite at its elevation and
+//! azimuth, signal strength bars and how the fix is coming along.
+//!
+//! Satellites in view with decent signals but no fix means waiting on a cold start, which
+//! can take minutes. No signal at all usually means the antenna can't see the sky. Circle
+//! goes on to the map without waiting.
+
+use alloc::format;
+use alloc::string::String;
+
+use embedded_graphics::{
+    mono_font::{MonoTextStyle, ascii::FONT_6X10},
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{Circle, Line, PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+use nav_core::fix::{Fix, FixError, FixQuality, Satellite};
+use nav_core::input::{InputConfig, MenuAction};
+use nav_core::source::PositionSource;
+
+use super::SCREEN_HEIGHT;
+use crate::input::Controller;
+
+const SKY_CENTER: Point = Point::new(136, 136);
+const SKY_RADIUS: i32 = 120;
+
+const BARS_LEFT: i32 = 272;
+const BARS_BOTTOM: i32 = 250;
+const BAR_HEIGHT: i32 = 120;
+const BAR_WIDTH: i32 = 12;
+/// SNR of a full bar, in dB-Hz
+const BAR_MAX_SNR: i32 = 50;
+/// Anything weaker is hardly usable
+const WEAK_SNR: u8 = 25;
+
+/// The receiver updates once a second, no need to redraw much more often
+const REDRAW_INTERVAL_MS: u32 = 250;
+
+/// Vblanks between looking at the controller
+const FRAME_VBLANKS: u32 = 2;
+
+const BACKGROUND: Rgb888 = Rgb888::new(0, 0, 0);
+const GRID: Rgb888 = Rgb888::new(64, 64, 64);
+const TEXT: Rgb888 = Rgb888::new(220, 220, 220);
+const USED: Rgb888 = Rgb888::new(64, 200, 64);
+const SEEN: Rgb888 = Rgb888::new(200, 160, 48);
+const WEAK: Rgb888 = Rgb888::new(120, 120, 120);
+
+/// Keeps track of the time to first fix
+pub struct SatelliteScreen {
+    started_ms: u32,
+    first_fix_ms: Option<u32>,
+}
+
+impl SatelliteScreen {
+    /// `started_ms` is when the receiver was started, on the [`crate::gps::time_ms`] clock
+    pub fn new(started_ms: u32) -> Self {
+        Self {
+            started_ms,
+            first_fix_ms: None,
+        }
+    }
+
+    pub fn update(&mut self, fix: &Result<Fix, FixError>, now_ms: u32) {
+        if fix.is_ok() && self.first_fix_ms.is_none() {
+            self.first_fix_ms = Some(now_ms.wrapping_sub(self.started_ms));
+        }
+    }
+
+    pub fn draw<D: DrawTarget<Color = Rgb888>>(
+        &self,
+        target: &mut D,
+        fix: &Result<Fix, FixError>,
+        satellites: &[Satellite],
+        now_ms: u32,
+    ) -> Result<(), D::Error> {
+        target.clear(BACKGROUND)?;
+        draw_sky(target, satellites)?;
+        draw_bars(target, satellites)?;
+        self.draw_status(target, fix, satellites, now_ms)
+    }
+
+    fn draw_status<D: DrawTarget<Color = Rgb888>>(
+        &self,
+        target: &mut D,
+        fix: &Result<Fix, FixError>,
+        satellites: &[Satellite],
+        now_ms: u32,
+    ) -> Result<(), D::Error> {
+        let style = MonoTextStyle::new(&FONT_6X10, TEXT);
+        let used = satellites.iter().filter(|s| s.used).count();
+        let strong = satellites.iter().filter(|s| s.snr >= WEAK_SNR).count();
+
+        let state = match fix {
+            Ok(fix) if fix.quality == FixQuality::ThreeD => "3D fix",
+            Ok(_) => "2D fix",
+            Err(FixError::BadAccuracy) => "Fix too inaccurate",
+            // Signals but no fix yet, the receiver needs time to download the orbits
+            Err(_) if strong >= 3 => "Searching, cold start",
+            Err(_) if satellites.iter().any(|s| s.snr > 0) => "Weak signal, move to open sky",
+            Err(_) => "No signal, check antenna",
+        };
+        let accuracy = match fix {
+            Ok(fix) => format!("HDOP {:.1} (~{:.0} m)", fix.hdop, fix.accuracy_m()),
+            Err(_) => String::from("HDOP -"),
+        };
+        let ttff = match self.first_fix_ms {
+            Some(ms) => format!("First fix after {} s", ms / 1000),
+            None => format!("Waiting {} s", now_ms.wrapping_sub(self.started_ms) / 1000),
+        };
+
+        let lines = [
+            String::from(state),
+            format!("Used {used} / in view {}", satellites.len()),
+            accuracy,
+            ttff,
+            String::from("O: on to the map"),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            let position = Point::new(BARS_LEFT, 16 + i as i32 * 12);
+            Text::new(line, position, style).draw(target)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rings at 0, 30 and 60 degrees elevation, north up
+fn draw_sky<D: DrawTarget<Color = Rgb888>>(
+    target: &mut D,
+    satellites: &[Satellite],
+) -> Result<(), D::Error> {
+    let grid = PrimitiveStyle::with_stroke(GRID, 1);
+    for ring in 1..=3 {
+        let radius = SKY_RADIUS * ring / 3;
+        Circle::with_center(SKY_CENTER, radius as u32 * 2)
+            .into_styled(grid)
+            .draw(target)?;
+    }
+    let horizontal = Point::new(SKY_RADIUS, 0);
+    let vertical = Point::new(0, SKY_RADIUS);
+    Line::new(SKY_CENTER - horizontal, SKY_CENTER + horizontal)
+        .into_styled(grid)
+        .draw(target)?;
+    Line::new(SKY_CENTER - vertical, SKY_CENTER + vertical)
+        .into_styled(grid)
+        .draw(target)?;
+
+    let label_style = MonoTextStyle::new(&FONT_6X10, GRID);
+    for (label, azimuth) in [("N", 0.0), ("E", 90.0), ("S", 180.0), ("W", 270.0)] {
+        let position = sky_position(0.0, azimuth, SKY_RADIUS + 8) + Point::new(0, 3);
+        Text::with_alignment(label, position, label_style, Alignment::Center).draw(target)?;
+    }
+
+    for satellite in satellites {
+        let position = sky_position(
+            satellite.elevation as f32,
+            satellite.azimuth as f32,
+            SKY_RADIUS,
+        );
+        let color = satellite_color(satellite);
+        let style = if satellite.used {
+            PrimitiveStyle::with_fill(color)
+        } else {
+            PrimitiveStyle::with_stroke(color, 1)
+        };
+        Circle::with_center(position, 9)
+            .into_styled(style)
+            .draw(target)?;
+        let text = format!("{}", satellite.id);
+        let label = position + Point::new(0, 14);
+        Text::with_alignment(
+            &text,
+            label,
+            MonoTextStyle::new(&FONT_6X10, color),
+            Alignment::Center,
+        )
+        .draw(target)?;
+    }
+    Ok(())
+}
+
+/// Straight overhead is the center, the horizon is `radius` away
+fn sky_position(elevation: f32, azimuth: f32, radius: i32) -> Point {
+    let distance = (90.0 - elevation.clamp(0.0, 90.0)) / 90.0 * radius as f32;
+    let azimuth = azimuth.to_radians();
+    SKY_CENTER
+        + Point::new(
+            (libm::sinf(azimuth) * distance) as i32,
+            -(libm::cosf(azimuth) * distance) as i32,
+        )
+}
+
+fn draw_bars<D: DrawTarget<Color = Rgb888>>(
+    target: &mut D,
+    satellites: &[Satellite],
+) -> Result<(), D::Error> {
+    let label_style = MonoTextStyle::new(&FONT_6X10, TEXT);
+    // In the receiver's order, whatever doesn't fit next to the sky plot is left out
+    let fits = ((super::SCREEN_WIDTH as i32 - BARS_LEFT) / (BAR_WIDTH + 4)) as usize;
+    for (i, satellite) in satellites.iter().take(fits).enumerate() {
+        let left = BARS_LEFT + i as i32 * (BAR_WIDTH + 4);
+        let height = satellite.snr.min(BAR_MAX_SNR as u8) as i32 * BAR_HEIGHT / BAR_MAX_SNR;
+        Rectangle::new(
+            Point::new(left, BARS_BOTTOM - BAR_HEIGHT),
+            Size::new(BAR_WIDTH as u32, BAR_HEIGHT as u32),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(GRID, 1))
+        .draw(target)?;
+        Rectangle::new(
+            Point::new(left, BARS_BOTTOM - height),
+            Size::new(BAR_WIDTH as u32, height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(satellite_color(satellite)))
+        .draw(target)?;
+
+        let text = format!("{}", satellite.id);
+        let label = Point::new(left + BAR_WIDTH / 2, SCREEN_HEIGHT as i32 - 10);
+        Text::with_alignment(&text, label, label_style, Alignment::Center).draw(target)?;
+    }
+    Ok(())
+}
+
+fn satellite_color(satellite: &Satellite) -> Rgb888 {
+    match satellite {
+        s if s.used => USED,
+        s if s.snr >= WEAK_SNR => SEEN,
+        _ => WEAK,
+    }
+}
+
+/// Shows the screen until circle is pressed, or the receiver has a fix if `until_fix`.
+/// `started_ms` is when the receiver was started, before waiting for it to get ready.
+pub fn run<S: PositionSource>(source: &mut S, until_fix: bool, started_ms: u32) {
+    let mut framebuffer = psp::embedded_graphics::Framebuffer::new();
+    let mut controller = Controller::new(InputConfig::default());
+    let mut screen = SatelliteScreen::new(started_ms);
+    let mut last_draw: Option<u32> = None;
+    loop {
+        if controller.read_menu() == Some(MenuAction::Back) {
+            return;
+        }
+        let now = crate::gps::time_ms();
+        if last_draw.is_none_or(|last| now.wrapping_sub(last) >= REDRAW_INTERVAL_MS) {
+            last_draw = Some(now);
+            let fix = source.poll(now);
+            screen.update(&fix, now);
+            // The framebuffer never fails to draw
+            let _ = screen.draw(&mut framebuffer, &fix, source.satellites(), now);
+            if until_fix && fix.is_ok() {
+                return;
+            }
+        }
+
+        for _ in 0..FRAME_VBLANKS {
+            unsafe {
+                psp::sys::sceDisplayWaitVblankStart();
+            }
+        }
+    }
+}
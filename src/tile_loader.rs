@@ -0,0 +1,276 @@
+//! Reads map tiles out of the region's tile pack without blocking the render loop.
+//!
+//! The pack's header and index are read once when it's opened, after that every tile is a
+//! seek and a read on the same file handle using the kernel's async IO. A handle only does one
+//! thing at a time, so requests wait in a queue and [`TileLoader::update`] starts the next one
+//! once the last is done. Finished tiles come out of [`TileLoader::next_completed`].
+//!
+//! An async read can't be taken back once it's started, so cancelling one in flight only
+//! marks it: its buffer is kept until the kernel is done with it and the result is dropped.
+
+use core::ffi::c_void;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use nav_core::tile::TileKey;
+use nav_core::tile_cache::TileCache;
+use nav_core::tile_pack::{HEADER_SIZE, PackError, PackHeader, PackIndex};
+use psp::sys::{IoOpenFlags, IoWhence, SceUid};
+
+use crate::io::{self, IoError};
+
+/// Anything bigger isn't a tile, don't let a broken pack eat all memory
+const MAX_TILE_SIZE: u32 = 1024 * 1024;
+
+/// How much memory decoded tiles may take up. The PSP-1000 has 24 MiB for the app,
+/// this leaves room for the graph and the rest.
+pub const TILE_CACHE_BUDGET: usize = 8 * 1024 * 1024;
+
+/// Rows of tiles to load ahead of where we're going
+const PREFETCH_DEPTH: i32 = 2;
+
+/// `sceIoPollAsync` says the operation is still running
+const POLL_PENDING: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackOpenError {
+    Io(IoError),
+    Pack(PackError),
+}
+
+impl From<IoError> for PackOpenError {
+    fn from(e: IoError) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<PackError> for PackOpenError {
+    fn from(e: PackError) -> Self {
+        Self::Pack(e)
+    }
+}
+
+pub struct TileLoad {
+    pub key: TileKey,
+    pub result: Result<Vec<u8>, IoError>,
+}
+
+enum Step {
+    Seeking,
+    /// Bytes read so far
+    Reading(usize),
+}
+
+struct InFlight {
+    key: TileKey,
+    step: Step,
+    data: Vec<u8>,
+    cancelled: bool,
+}
+
+pub struct TileLoader {
+    fd: SceUid,
+    header: PackHeader,
+    index: PackIndex,
+    queue: VecDeque<TileKey>,
+    in_flight: Option<InFlight>,
+    completed: VecDeque<TileLoad>,
+}
+
+impl TileLoader {
+    /// Opens a pack, e.g. `ms0:/PSP/GAME/psp_retro_nav/maps/<region>/tiles.pack`. Blocks while
+    /// reading the index.
+    pub fn open(path: &str) -> Result<Self, PackOpenError> {
+        let path = io::c_path(path);
+        let fd = unsafe { psp::sys::sceIoOpen(path.as_ptr(), IoOpenFlags::RD_ONLY, 0) };
+        if fd.0 < 0 {
+            return Err(IoError::from_code(fd.0).into());
+        }
+        let close = |e: PackOpenError| {
+            unsafe {
+                psp::sys::sceIoClose(fd);
+            }
+            e
+        };
+
+        let size = unsafe { psp::sys::sceIoLseek(fd, 0, IoWhence::End) };
+        unsafe {
+            psp::sys::sceIoLseek(fd, 0, IoWhence::Set);
+        }
+        if size < 0 {
+            return Err(close(IoError::from_code(size as i32).into()));
+        }
+
+        let mut header = [0; HEADER_SIZE];
+        io::read_exact(fd, &mut header).map_err(|e| close(e.into()))?;
+        let header = PackHeader::parse(&header).map_err(|e| close(e.into()))?;
+        // A broken tile count would ask for more memory than there is
+        if header.index_end() > size as u64 {
+            return Err(close(PackError::BadIndex.into()));
+        }
+        let mut index = vec![0; header.index_size()];
+        io::read_exact(fd, &mut index).map_err(|e| close(e.into()))?;
+        let index = PackIndex::parse(&header, &index, size as u64).map_err(|e| close(e.into()))?;
+
+        Ok(Self {
+            fd,
+            header,
+            index,
+            queue: VecDeque::new(),
+            in_flight: None,
+            completed: VecDeque::new(),
+        })
+    }
+
+    pub fn header(&self) -> &PackHeader {
+        &self.header
+    }
+
+    /// Zoom levels the pack has tiles for, lowest first
+    pub fn zoom_levels(&self) -> Vec<u8> {
+        self.index.zoom_levels()
+    }
+
+    pub fn is_pending(&self, key: TileKey) -> bool {
+        self.queue.contains(&key)
+            || self
+                .in_flight
+                .as_ref()
+                .is_some_and(|request| request.key == key && !request.cancelled)
+    }
+
+    /// Queues a tile, unless it's already on its way
+    pub fn request(&mut self, key: TileKey) {
+        if let Some(request) = self.in_flight.as_mut().filter(|r| r.key == key) {
+            // Scrolled away and back before it finished
+            request.cancelled = false;
+            return;
+        }
+        if !self.queue.contains(&key) {
+            self.queue.push_back(key);
+        }
+    }
+
+    pub fn cancel(&mut self, key: TileKey) {
+        self.cancel_where(|k| k == key);
+    }
+
+    /// Cancels every request for which `cancel` says so, e.g. tiles that went off screen
+    pub fn cancel_where(&mut self, mut cancel: impl FnMut(TileKey) -> bool) {
+        self.queue.retain(|&key| !cancel(key));
+        if let Some(request) = &mut self.in_flight
+            && cancel(request.key)
+        {
+            request.cancelled = true;
+        }
+    }
+
+    /// Queues the tiles ahead of `center` in the direction of `heading` that aren't cached
+    /// yet. They go behind whatever is already waiting, so visible tiles come first.
+    pub fn prefetch<T>(&mut self, cache: &TileCache<T>, center: TileKey, heading: f32) {
+        for key in cache.prefetch_candidates(center, heading, PREFETCH_DEPTH) {
+            if self.index.find(key).is_some() {
+                self.request(key);
+            }
+        }
+    }
+
+    pub fn next_completed(&mut self) -> Option<TileLoad> {
+        self.completed.pop_front()
+    }
+
+    /// Moves the requests along, never waits on the Memory Stick
+    pub fn update(&mut self) {
+        if let Some(request) = &mut self.in_flight {
+            let Some(result) = advance(self.fd, request) else {
+                return;
+            };
+            let request = self.in_flight.take().unwrap();
+            if !request.cancelled {
+                let key = request.key;
+                let result = result.map(|()| request.data);
+                self.completed.push_back(TileLoad { key, result });
+            }
+        }
+
+        while let Some(key) = self.queue.pop_front() {
+            let result = match self.index.find(key) {
+                // Outside of the region
+                None => Err(IoError::NoEntry),
+                Some(entry) if entry.length > MAX_TILE_SIZE => Err(IoError::TooLarge),
+                Some(entry) => {
+                    let started = unsafe {
+                        psp::sys::sceIoLseekAsync(self.fd, entry.offset as i64, IoWhence::Set)
+                    };
+                    if started >= 0 {
+                        self.in_flight = Some(InFlight {
+                            key,
+                            step: Step::Seeking,
+                            data: vec![0; entry.length as usize],
+                            cancelled: false,
+                        });
+                        return;
+                    }
+                    Err(IoError::from_code(started))
+                }
+            };
+            self.completed.push_back(TileLoad { key, result });
+        }
+    }
+}
+
+impl Drop for TileLoader {
+    fn drop(&mut self) {
+        unsafe {
+            if self.in_flight.is_some() {
+                // The kernel may still be writing into the buffer
+                let mut result = 0i64;
+                psp::sys::sceIoWaitAsync(self.fd, &mut result);
+            }
+            psp::sys::sceIoClose(self.fd);
+        }
+    }
+}
+
+/// Checks on the running operation and starts the next one. `Some` once the request is done,
+/// successful or not.
+fn advance(fd: SceUid, request: &mut InFlight) -> Option<Result<(), IoError>> {
+    let mut result = 0i64;
+    let poll = unsafe { psp::sys::sceIoPollAsync(fd, &mut result) };
+    if poll == POLL_PENDING {
+        return None;
+    }
+    if poll < 0 {
+        return Some(Err(IoError::from_code(poll)));
+    }
+    if result < 0 {
+        return Some(Err(IoError::from_code(result as i32)));
+    }
+
+    // Nobody wants it anymore, don't start anything new
+    if request.cancelled {
+        return Some(Ok(()));
+    }
+
+    let read = match request.step {
+        Step::Seeking => 0,
+        Step::Reading(_) if result == 0 => return Some(Err(IoError::ShortRead)),
+        Step::Reading(read) => read + result as usize,
+    };
+    if read >= request.data.len() {
+        return Some(Ok(()));
+    }
+    request.step = Step::Reading(read);
+    let started = unsafe {
+        psp::sys::sceIoReadAsync(
+            fd,
+            request.data[read..].as_mut_ptr() as *mut c_void,
+            (request.data.len() - read) as u32,
+        )
+    };
+    if started < 0 {
+        return Some(Err(IoError::from_code(started)));
+    }
+    None
+}
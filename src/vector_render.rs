@@ -0,0 +1,252 @@
+//! Draws vector tiles, see [`nav_core::vector_tile`]. Shapes get scaled and turned on their
+//! way to the screen, so the map can be heading up and drawn in any [`Palette`].
+//!
+//! The map is drawn in [`Layer`]s over all visible tiles, areas first and labels last, so a
+//! tile's areas never cover the roads of the one next to it.
+
+use alloc::vec::Vec;
+
+use embedded_graphics::{
+    mono_font::{MonoTextStyleBuilder, ascii::FONT_6X10},
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{Polyline, PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+use nav_core::graph::RoadClass;
+use nav_core::vector_tile::{AreaKind, Feature, VectorTile};
+
+/// Road widths in pixels in [`RoadClass`] order, whatever the zoom
+const ROAD_WIDTHS: [u32; RoadClass::COUNT] = [7, 6, 5, 5, 4, 3, 3, 3, 2, 1];
+
+/// Casing on each side of a road
+const CASING_WIDTH: u32 = 1;
+
+const LABEL_FONT_WIDTH: u32 = 6;
+const LABEL_FONT_HEIGHT: u32 = 10;
+
+pub struct Palette {
+    pub background: Rgb888,
+    /// In [`AreaKind`] order
+    pub areas: [Rgb888; AreaKind::COUNT],
+    /// Outline around the roads
+    pub casing: Rgb888,
+    /// In [`RoadClass`] order
+    pub roads: [Rgb888; RoadClass::COUNT],
+    pub label: Rgb888,
+}
+
+pub const DAY: Palette = Palette {
+    background: Rgb888::new(224, 220, 208),
+    areas: [
+        Rgb888::new(214, 208, 196),
+        Rgb888::new(190, 218, 168),
+        Rgb888::new(160, 192, 228),
+        Rgb888::new(196, 186, 176),
+    ],
+    casing: Rgb888::new(140, 136, 128),
+    roads: [
+        Rgb888::new(228, 128, 72),
+        Rgb888::new(236, 164, 88),
+        Rgb888::new(248, 204, 112),
+        Rgb888::new(248, 228, 148),
+        Rgb888::new(255, 255, 255),
+        Rgb888::new(255, 255, 255),
+        Rgb888::new(255, 255, 255),
+        Rgb888::new(240, 240, 240),
+        Rgb888::new(240, 240, 240),
+        Rgb888::new(196, 172, 132),
+    ],
+    label: Rgb888::new(40, 40, 40),
+};
+
+/// Dark and low contrast, so it doesn't blind at night
+pub const NIGHT: Palette = Palette {
+    background: Rgb888::new(24, 28, 36),
+    areas: [
+        Rgb888::new(34, 38, 46),
+        Rgb888::new(28, 46, 34),
+        Rgb888::new(18, 34, 62),
+        Rgb888::new(48, 48, 58),
+    ],
+    casing: Rgb888::new(8, 8, 12),
+    roads: [
+        Rgb888::new(164, 96, 48),
+        Rgb888::new(156, 108, 56),
+        Rgb888::new(148, 124, 68),
+        Rgb888::new(128, 116, 80),
+        Rgb888::new(96, 100, 112),
+        Rgb888::new(88, 92, 104),
+        Rgb888::new(88, 92, 104),
+        Rgb888::new(76, 80, 92),
+        Rgb888::new(76, 80, 92),
+        Rgb888::new(80, 70, 56),
+    ],
+    label: Rgb888::new(196, 196, 188),
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Areas,
+    Casings,
+    Roads,
+    Labels,
+}
+
+impl Layer {
+    /// In drawing order
+    pub const ALL: [Self; 4] = [Self::Areas, Self::Casings, Self::Roads, Self::Labels];
+}
+
+/// Where a tile ends up on screen
+#[derive(Debug, Clone, Copy)]
+pub struct TileTransform {
+    /// The tile's top left corner, in pixels from the rotation center before turning
+    pub corner: (f32, f32),
+    /// Screen pixels per tile step
+    pub scale: f32,
+    /// Of the angle everything gets turned by, clockwise
+    pub sin: f32,
+    pub cos: f32,
+    /// The rotation center on screen
+    pub center: Point,
+}
+
+impl TileTransform {
+    pub fn apply(&self, (x, y): (i32, i32)) -> Point {
+        let x = self.corner.0 + x as f32 * self.scale;
+        let y = self.corner.1 + y as f32 * self.scale;
+        // Rounded, so shapes that meet at a tile edge meet on screen too
+        self.center
+            + Point::new(
+                libm::roundf(x * self.cos - y * self.sin) as i32,
+                libm::roundf(x * self.sin + y * self.cos) as i32,
+            )
+    }
+}
+
+/// Where labels went so far this frame, a label that would cover one of them is left out
+#[derive(Debug, Default)]
+pub struct Labels {
+    placed: Vec<Rectangle>,
+}
+
+impl Labels {
+    fn draw<D: DrawTarget<Color = Rgb888>>(
+        &mut self,
+        target: &mut D,
+        position: Point,
+        text: &str,
+        palette: &Palette,
+    ) -> Result<(), D::Error> {
+        let size = Size::new(
+            text.chars().count() as u32 * LABEL_FONT_WIDTH,
+            LABEL_FONT_HEIGHT,
+        );
+        let area = Rectangle::with_center(position, size);
+        let on_screen = !target.bounding_box().intersection(&area).is_zero_sized();
+        let free = self
+            .placed
+            .iter()
+            .all(|placed| placed.intersection(&area).is_zero_sized());
+        if !on_screen || !free {
+            return Ok(());
+        }
+        self.placed.push(area);
+
+        let character_style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(palette.label)
+            .background_color(palette.background)
+            .build();
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+        Text::with_text_style(text, position, character_style, text_style).draw(target)?;
+        Ok(())
+    }
+}
+
+/// Draws what of `tile` belongs to `layer`
+pub fn draw_tile<D: DrawTarget<Color = Rgb888>>(
+    target: &mut D,
+    tile: &VectorTile,
+    transform: &TileTransform,
+    palette: &Palette,
+    layer: Layer,
+    labels: &mut Labels,
+) -> Result<(), D::Error> {
+    // On screen, reused from shape to shape
+    let mut screen = Vec::new();
+    for feature in tile.features() {
+        match (layer, feature) {
+            (Layer::Areas, Feature::Area { kind, points }) => {
+                screen.clear();
+                screen.extend(points.map(|p| transform.apply(p)));
+                fill_polygon(target, &screen, palette.areas[kind as usize])?;
+            }
+            (Layer::Casings | Layer::Roads, Feature::Line { class, points }) => {
+                screen.clear();
+                screen.extend(points.map(|p| transform.apply(p)));
+                let width = ROAD_WIDTHS[class as usize];
+                let style = match layer {
+                    Layer::Casings => {
+                        PrimitiveStyle::with_stroke(palette.casing, width + 2 * CASING_WIDTH)
+                    }
+                    _ => PrimitiveStyle::with_stroke(palette.roads[class as usize], width),
+                };
+                Polyline::new(&screen).into_styled(style).draw(target)?;
+            }
+            (Layer::Labels, Feature::Label { position, text }) => {
+                labels.draw(target, transform.apply(position), text, palette)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Fills a closed outline row by row, even-odd. `embedded-graphics` only fills triangles and
+/// circles, and areas are neither.
+fn fill_polygon<D: DrawTarget<Color = Rgb888>>(
+    target: &mut D,
+    points: &[Point],
+    color: Rgb888,
+) -> Result<(), D::Error> {
+    let bounds = target.bounding_box();
+    let Some(bottom_right) = bounds.bottom_right() else {
+        return Ok(());
+    };
+    let (Some(top), Some(bottom)) = (
+        points.iter().map(|p| p.y).min(),
+        points.iter().map(|p| p.y).max(),
+    ) else {
+        return Ok(());
+    };
+
+    let mut crossings = Vec::new();
+    for y in top.max(bounds.top_left.y)..=bottom.min(bottom_right.y) {
+        // Through the middle of the row, so it never hits a point exactly
+        let scan = y as f32 + 0.5;
+        crossings.clear();
+        for (i, &a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            if (a.y as f32 <= scan) != (b.y as f32 <= scan) {
+                let t = (scan - a.y as f32) / (b.y - a.y) as f32;
+                crossings.push(a.x as f32 + t * (b.x - a.x) as f32);
+            }
+        }
+        crossings.sort_unstable_by(f32::total_cmp);
+
+        for pair in crossings.chunks_exact(2) {
+            let left = (libm::roundf(pair[0]) as i32).max(bounds.top_left.x);
+            let right = (libm::roundf(pair[1]) as i32).min(bottom_right.x + 1);
+            if right > left {
+                let span = Rectangle::new(Point::new(left, y), Size::new((right - left) as u32, 1));
+                target.fill_solid(&span, color)?;
+            }
+        }
+    }
+    Ok(())
+}